@@ -7,13 +7,30 @@ error_chain! {
     }
 
     errors {
-        HttpStatus(e: u32) {
+        DownloadFailed {
+            url: String,
+            status: u32,
+        } {
             description("http request returned an unsuccessful status code")
-            display("http request returned an unsuccessful status code: {}", e)
+            display("request for '{}' returned HTTP status {}", url, status)
         }
         FileNotFound {
             description("file not found")
         }
+        DownloadStalled(secs: u64) {
+            description("download stalled")
+            display("download stalled: no data received for {} seconds", secs)
+        }
+        TooManyRedirects(url: String) {
+            description("too many redirects")
+            display("request for '{}' was redirected too many times", url)
+        }
+        InvalidProxy {
+            description("invalid proxy url")
+        }
+        Cancelled {
+            description("download cancelled")
+        }
         BackendUnavailable(be: &'static str) {
             description("download backend unavailable")
             display("download backend '{}' unavailable", be)
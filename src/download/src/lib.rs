@@ -10,12 +10,255 @@ extern crate reqwest;
 #[macro_use]
 extern crate lazy_static;
 
-use std::path::Path;
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use url::Url;
 
 mod errors;
 pub use errors::*;
 
+/// Proxy configuration used where the environment does not set one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProxyConfig {
+    /// Proxy URL, optionally with credentials, for both HTTP and HTTPS
+    pub proxy: Option<String>,
+    /// Comma-separated hosts, as in `NO_PROXY`, that are reached directly
+    pub no_proxy: Option<String>,
+}
+
+static FALLBACK_PROXY: Mutex<ProxyConfig> = Mutex::new(ProxyConfig {
+    proxy: None,
+    no_proxy: None,
+});
+
+/// Sets the proxy configuration used when `HTTPS_PROXY`, `HTTP_PROXY`,
+/// `ALL_PROXY` or `NO_PROXY` are unset
+pub fn set_fallback_proxy(config: ProxyConfig) {
+    *FALLBACK_PROXY.lock().unwrap() = config;
+}
+
+/// The proxy to send requests for `url` through, if any
+///
+/// The scheme's `HTTPS_PROXY` or `HTTP_PROXY` variable is used, then
+/// `ALL_PROXY`, then the fallback configuration. Lowercase variable names work
+/// too. Hosts matching `NO_PROXY` are never proxied.
+pub fn proxy_for_url(url: &Url) -> Result<Option<Url>> {
+    let fallback = FALLBACK_PROXY.lock().unwrap().clone();
+    let var = |name: &str| {
+        env::var(name)
+            .or_else(|_| env::var(name.to_lowercase()))
+            .ok()
+            .filter(|v| !v.is_empty())
+    };
+
+    let scheme_var = match url.scheme() {
+        "https" => "HTTPS_PROXY",
+        "http" => "HTTP_PROXY",
+        _ => return Ok(None),
+    };
+    let proxy = match var(scheme_var)
+        .or_else(|| var("ALL_PROXY"))
+        .or(fallback.proxy)
+    {
+        Some(proxy) => proxy,
+        None => return Ok(None),
+    };
+
+    if let (Some(no_proxy), Some(host)) = (var("NO_PROXY").or(fallback.no_proxy), url.host_str()) {
+        if bypasses_proxy(&no_proxy, host) {
+            return Ok(None);
+        }
+    }
+
+    let proxy = if proxy.contains("://") {
+        proxy
+    } else {
+        format!("http://{}", proxy)
+    };
+    Url::parse(&proxy)
+        .map(Some)
+        .chain_err(|| ErrorKind::InvalidProxy)
+}
+
+/// `url` as it can be shown in messages, with any credentials and tokens in
+/// it replaced by `***`
+pub fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if !url.username().is_empty() {
+        let _ = url.set_username("***");
+    }
+    if url.password().is_some() {
+        let _ = url.set_password(Some("***"));
+    }
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| {
+                let secret = ["token", "signature", "key"]
+                    .iter()
+                    .any(|s| k.to_lowercase().contains(s));
+                let v = if secret { "***".into() } else { v.into_owned() };
+                (k.into_owned(), v)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+/// The error for a request for `url` that returned `status`
+fn status_error(url: &Url, status: u32) -> Error {
+    ErrorKind::DownloadFailed {
+        url: redact_url(url),
+        status,
+    }
+    .into()
+}
+
+fn bypasses_proxy(no_proxy: &str, host: &str) -> bool {
+    let host = host.to_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            // Ports are not considered; `host:port` entries match the host
+            let entry = match entry.rsplit_once(':') {
+                Some((host, port))
+                    if !host.contains(':') && port.bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    host.to_owned()
+                }
+                _ => entry.clone(),
+            };
+            entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+        })
+}
+
+/// A bundle of CA certificates to trust in addition to the system's, as
+/// needed behind proxies that intercept TLS, from `ELAN_CAINFO` or else
+/// `SSL_CERT_FILE`. The curl backend does not use it on Windows, see
+/// `curl::set_ca_bundle`.
+pub fn ca_bundle() -> Option<PathBuf> {
+    ["ELAN_CAINFO", "SSL_CERT_FILE"]
+        .iter()
+        .filter_map(env::var_os)
+        .find(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// How many redirects a download follows before giving up
+pub const MAX_REDIRECTS: u32 = 10;
+
+static AUTHORIZATION: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Sends the `Authorization` header `value` with downloads from `host`, or
+/// stops sending one if `None`. Redirects to another host, scheme or port
+/// are followed without it.
+pub fn set_authorization(host: &str, value: Option<String>) {
+    let mut authorization = AUTHORIZATION.lock().unwrap();
+    authorization.retain(|(h, _)| !h.eq_ignore_ascii_case(host));
+    if let Some(value) = value {
+        authorization.push((host.to_owned(), value));
+    }
+}
+
+fn authorization_for(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    AUTHORIZATION
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(h, _)| h.eq_ignore_ascii_case(host))
+        .map(|(_, value)| value.clone())
+}
+
+/// How long a download may go without receiving any data by default
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+static IDLE_TIMEOUT: Mutex<Duration> = Mutex::new(DEFAULT_IDLE_TIMEOUT);
+
+/// Sets how long a download may go without receiving any data before it is
+/// aborted with `ErrorKind::DownloadStalled`. The partial download is kept,
+/// so retrying resumes it.
+pub fn set_idle_timeout(timeout: Duration) {
+    *IDLE_TIMEOUT.lock().unwrap() = timeout;
+}
+
+fn idle_timeout() -> Duration {
+    *IDLE_TIMEOUT.lock().unwrap()
+}
+
+/// How long a request for release metadata, such as the page naming the
+/// latest release, may take by default, including connecting
+pub const DEFAULT_INDEX_TIMEOUT: Duration = Duration::from_secs(10);
+
+static INDEX_TIMEOUT: Mutex<Duration> = Mutex::new(DEFAULT_INDEX_TIMEOUT);
+
+/// Sets how long a request for release metadata may take before it is
+/// aborted. Unlike a download, such a request is small, so a slow one
+/// means a bad network rather than a large file.
+pub fn set_index_timeout(timeout: Duration) {
+    *INDEX_TIMEOUT.lock().unwrap() = timeout;
+}
+
+pub fn index_timeout() -> Duration {
+    *INDEX_TIMEOUT.lock().unwrap()
+}
+
+/// How many times a failed download is retried by default
+pub const DEFAULT_RETRIES: u32 = 0;
+
+/// How long to wait before the first retry, doubling with each one after
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+static RETRIES: Mutex<u32> = Mutex::new(DEFAULT_RETRIES);
+
+/// Sets how many times a download that failed for a reason that may go
+/// away, such as a server error or a stall, is tried again
+pub fn set_retries(retries: u32) {
+    *RETRIES.lock().unwrap() = retries;
+}
+
+fn retries() -> u32 {
+    *RETRIES.lock().unwrap()
+}
+
+thread_local!(static CANCEL_TOKEN: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) });
+
+/// Sets the token that cancels the downloads of the current thread once it
+/// is set to `true`, such as by another thread when an install running in
+/// the background is no longer wanted. It is checked whenever data arrives
+/// and with `check_cancelled` between the steps of an install.
+pub fn set_cancel_token(token: Option<Arc<AtomicBool>>) {
+    CANCEL_TOKEN.with(|t| *t.borrow_mut() = token);
+}
+
+/// Fails with `ErrorKind::Cancelled` if the cancel token of the current
+/// thread has been set
+pub fn check_cancelled() -> Result<()> {
+    CANCEL_TOKEN.with(|t| match *t.borrow() {
+        Some(ref token) if token.load(Ordering::SeqCst) => Err(ErrorKind::Cancelled.into()),
+        _ => Ok(()),
+    })
+}
+
+/// Whether trying the download again could help
+fn is_transient(e: &Error) -> bool {
+    match *e.kind() {
+        ErrorKind::DownloadFailed { status, .. } => status >= 500 || status == 429,
+        ErrorKind::FileNotFound
+        | ErrorKind::InvalidProxy
+        | ErrorKind::BackendUnavailable(_)
+        | ErrorKind::Cancelled => false,
+        _ => true,
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Backend {
     Curl,
@@ -25,12 +268,18 @@ pub enum Backend {
 #[derive(Debug, Copy, Clone)]
 pub enum Event<'a> {
     ResumingPartialDownload,
+    /// Trying the download again after it failed, for the given attempt
+    /// counting from 1. Data received before counts for nothing.
+    RetryingDownload(u32),
     /// Received the Content-Length of the to-be downloaded data.
     DownloadContentLengthReceived(u64),
     /// Received some data.
     DownloadDataReceived(&'a [u8]),
 }
 
+/// The callback of the downloads to a path, if they report their progress
+type PathCallback<'a> = Option<&'a dyn Fn(Event) -> Result<()>>;
+
 fn download_with_backend(
     backend: Backend,
     url: &Url,
@@ -43,14 +292,48 @@ fn download_with_backend(
     }
 }
 
+/// Downloads `url` to `path`, retrying as configured with `set_retries`.
+/// Without `resume_from_partial`, a retry starts over from an empty file.
+/// A cancelled download is removed, even when resuming.
 pub fn download_to_path_with_backend(
     backend: Backend,
     url: &Url,
     path: &Path,
     resume_from_partial: bool,
-    callback: Option<&dyn Fn(Event) -> Result<()>>,
+    callback: PathCallback,
+) -> Result<()> {
+    let retries = retries();
+    let mut attempt = 1;
+    loop {
+        match check_cancelled()
+            .and_then(|()| download_attempt(backend, url, path, resume_from_partial, callback))
+        {
+            Err(e @ Error(ErrorKind::Cancelled, _)) => {
+                let _ = std::fs::remove_file(path);
+                return Err(e);
+            }
+            Err(ref e) if attempt <= retries && is_transient(e) => {
+                std::thread::sleep(RETRY_DELAY * 2u32.pow(attempt - 1));
+                attempt += 1;
+                if !resume_from_partial {
+                    let _ = std::fs::remove_file(path);
+                }
+                if let Some(cb) = callback {
+                    cb(Event::RetryingDownload(attempt))?;
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+fn download_attempt(
+    backend: Backend,
+    url: &Url,
+    path: &Path,
+    resume_from_partial: bool,
+    callback: PathCallback,
 ) -> Result<()> {
-    use std::cell::RefCell;
     use std::fs::OpenOptions;
     use std::io::{Read, Seek, SeekFrom, Write};
 
@@ -106,6 +389,7 @@ pub fn download_to_path_with_backend(
 
         download_with_backend(backend, url, resume_from, &|event| {
             if let Event::DownloadDataReceived(data) = event {
+                check_cancelled()?;
                 file.borrow_mut()
                     .write_all(data)
                     .chain_err(|| "unable to write download to disk")?;
@@ -134,17 +418,83 @@ pub fn download_to_path_with_backend(
 pub mod curl {
 
     extern crate curl;
+    #[cfg(unix)]
+    extern crate openssl_probe;
 
-    use self::curl::easy::Easy;
+    use self::curl::easy::{Easy, List};
     use super::Event;
     use errors::*;
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
+    #[cfg(unix)]
+    use std::fs;
+    #[cfg(unix)]
+    use std::path::PathBuf;
     use std::str;
     use std::time::Duration;
     use url::Url;
 
     thread_local!(pub static EASY: RefCell<Easy> = RefCell::new(Easy::new()));
 
+    /// Configures `handle` with the proxy for `url`, explicitly disabling
+    /// libcurl's own proxy lookup when there is none
+    pub fn set_proxy(handle: &mut Easy, url: &Url) -> Result<()> {
+        let proxy = super::proxy_for_url(url)?;
+        handle
+            .proxy(proxy.as_ref().map_or("", |p| p.as_str()))
+            .chain_err(|| "failed to set proxy")
+    }
+
+    /// The bundle OpenSSL, and so libcurl, trusts when given none
+    #[cfg(unix)]
+    fn system_ca_bundle() -> Option<PathBuf> {
+        openssl_probe::probe().cert_file
+    }
+
+    /// Makes `handle` trust the certificates of `ca_bundle` in addition to
+    /// the system's. libcurl takes a single bundle, so it is given both
+    /// concatenated.
+    #[cfg(unix)]
+    pub fn set_ca_bundle(handle: &mut Easy) -> Result<()> {
+        let path = match super::ca_bundle() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut pem = match system_ca_bundle() {
+            Some(ref system) if *system != path => fs::read(system).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        pem.push(b'\n');
+        pem.extend(
+            fs::read(&path).chain_err(|| {
+                format!("failed to read CA certificates from '{}'", path.display())
+            })?,
+        );
+        handle
+            .ssl_cainfo_blob(&pem)
+            .chain_err(|| "failed to set CA certificates")
+    }
+
+    /// On Windows, libcurl verifies certificates against the Windows
+    /// certificate store, which a bundle given to it would replace rather
+    /// than extend. `ca_bundle` is not used there, and certificates to trust
+    /// in addition are added to the store instead.
+    #[cfg(not(unix))]
+    pub fn set_ca_bundle(_handle: &mut Easy) -> Result<()> {
+        Ok(())
+    }
+
+    /// Limits a request made with `handle` to the index timeout, which
+    /// `download` lifts again for the handle it shares
+    pub fn set_index_timeout(handle: &mut Easy) -> Result<()> {
+        let timeout = super::index_timeout();
+        handle
+            .connect_timeout(timeout)
+            .chain_err(|| "failed to set connect timeout")?;
+        handle
+            .timeout(timeout)
+            .chain_err(|| "failed to set timeout")
+    }
+
     pub fn download(
         url: &Url,
         resume_from: u64,
@@ -157,113 +507,207 @@ pub mod curl {
         // scope.
         EASY.with(|handle| {
             let mut handle = handle.borrow_mut();
+            let result = download_following_redirects(&mut handle, url, resume_from, callback);
+            // Other requests share the handle, and must not send the header
+            let _ = handle.http_headers(List::new());
+            result
+        })
+    }
 
+    /// Downloads `url`, following up to `MAX_REDIRECTS` redirects. Its
+    /// credentials are only sent while the redirects stay on the origin of
+    /// `url`, so that a token for GitHub is not passed on to the CDN its
+    /// downloads redirect to.
+    fn download_following_redirects(
+        handle: &mut Easy,
+        url: &Url,
+        resume_from: u64,
+        callback: &dyn Fn(Event) -> Result<()>,
+    ) -> Result<()> {
+        let authorization = super::authorization_for(url);
+        let mut location = url.clone();
+        let mut redirects = 0;
+        loop {
+            let mut headers = List::new();
+            let same_origin = location.origin() == url.origin();
+            if let Some(value) = authorization.as_ref().filter(|_| same_origin) {
+                headers
+                    .append(&format!("Authorization: {}", value))
+                    .chain_err(|| "failed to set authorization header")?;
+            }
             handle
-                .url(&url.to_string())
-                .chain_err(|| "failed to set url")?;
-            handle
-                .follow_location(true)
-                .chain_err(|| "failed to set follow redirects")?;
-
-            if resume_from > 0 {
-                handle
-                    .resume_from(resume_from)
-                    .chain_err(|| "setting the range header for download resumption")?;
-            } else {
-                // an error here indicates that the range header isn't supported by underlying curl,
-                // so there's nothing to "clear" - safe to ignore this error.
-                let _ = handle.resume_from(0);
+                .http_headers(headers)
+                .chain_err(|| "failed to set headers")?;
+
+            match download_once(handle, &location, resume_from, callback)? {
+                Some(next) => {
+                    redirects += 1;
+                    if redirects > super::MAX_REDIRECTS {
+                        return Err(ErrorKind::TooManyRedirects(super::redact_url(url)).into());
+                    }
+                    location = next;
+                }
+                None => return Ok(()),
             }
+        }
+    }
 
-            // Take at most 30s to connect
+    /// Makes a single request for `url`, returning where it redirects to if
+    /// it does
+    fn download_once(
+        handle: &mut Easy,
+        url: &Url,
+        resume_from: u64,
+        callback: &dyn Fn(Event) -> Result<()>,
+    ) -> Result<Option<Url>> {
+        handle.url(url.as_ref()).chain_err(|| "failed to set url")?;
+        handle
+            .follow_location(false)
+            .chain_err(|| "failed to set follow redirects")?;
+        set_proxy(handle, url)?;
+        set_ca_bundle(handle)?;
+
+        if resume_from > 0 {
             handle
-                .connect_timeout(Duration::new(30, 0))
-                .chain_err(|| "failed to set connect timeout")?;
-
-            {
-                let cberr = RefCell::new(None);
-                let mut transfer = handle.transfer();
-
-                // Data callback for libcurl which is called with data that's
-                // downloaded. We just feed it into our hasher and also write it out
-                // to disk.
-                transfer
-                    .write_function(|data| match callback(Event::DownloadDataReceived(data)) {
+                .resume_from(resume_from)
+                .chain_err(|| "setting the range header for download resumption")?;
+        } else {
+            // an error here indicates that the range header isn't supported by underlying curl,
+            // so there's nothing to "clear" - safe to ignore this error.
+            let _ = handle.resume_from(0);
+        }
+
+        // Take at most 30s to connect
+        handle
+            .connect_timeout(Duration::new(30, 0))
+            .chain_err(|| "failed to set connect timeout")?;
+
+        // A download may take as long as it keeps receiving data
+        handle
+            .timeout(Duration::new(0, 0))
+            .chain_err(|| "failed to set timeout")?;
+
+        // Abort once no data at all arrived for the idle timeout
+        let idle_timeout = super::idle_timeout();
+        handle
+            .low_speed_limit(1)
+            .chain_err(|| "failed to set idle timeout")?;
+        handle
+            .low_speed_time(idle_timeout)
+            .chain_err(|| "failed to set idle timeout")?;
+
+        {
+            let cberr = RefCell::new(None);
+            // Timeouts before the response started are connection failures
+            let responded = Cell::new(false);
+            // The body of a redirect is not the file
+            let redirecting = Cell::new(false);
+            let mut transfer = handle.transfer();
+
+            // Data callback for libcurl which is called with data that's
+            // downloaded. We just feed it into our hasher and also write it out
+            // to disk.
+            transfer
+                .write_function(|data| {
+                    if redirecting.get() {
+                        return Ok(data.len());
+                    }
+                    match callback(Event::DownloadDataReceived(data)) {
                         Ok(()) => Ok(data.len()),
                         Err(e) => {
                             *cberr.borrow_mut() = Some(e);
                             Ok(0)
                         }
-                    })
-                    .chain_err(|| "failed to set write")?;
-
-                // Listen for headers and parse out a `Content-Length` if it comes
-                // so we know how much we're downloading.
-                transfer
-                    .header_function(|header| {
-                        if let Ok(data) = str::from_utf8(header) {
-                            let prefix = "Content-Length: ";
-                            if data.starts_with(prefix) {
-                                if let Ok(s) = data[prefix.len()..].trim().parse::<u64>() {
-                                    let msg = Event::DownloadContentLengthReceived(s + resume_from);
-                                    match callback(msg) {
-                                        Ok(()) => (),
-                                        Err(e) => {
-                                            *cberr.borrow_mut() = Some(e);
-                                            return false;
-                                        }
+                    }
+                })
+                .chain_err(|| "failed to set write")?;
+
+            // Listen for headers and parse out a `Content-Length` if it comes
+            // so we know how much we're downloading.
+            transfer
+                .header_function(|header| {
+                    responded.set(true);
+                    if let Ok(data) = str::from_utf8(header) {
+                        // Each response, such as one from a proxy before the
+                        // server's, starts with its status line
+                        if data.starts_with("HTTP/") {
+                            let status = data.split_whitespace().nth(1).unwrap_or("");
+                            redirecting.set(is_redirect(status.parse().unwrap_or(0)));
+                        }
+                        let prefix = "Content-Length: ";
+                        if data.starts_with(prefix) && !redirecting.get() {
+                            if let Ok(s) = data[prefix.len()..].trim().parse::<u64>() {
+                                let msg = Event::DownloadContentLengthReceived(s + resume_from);
+                                match callback(msg) {
+                                    Ok(()) => (),
+                                    Err(e) => {
+                                        *cberr.borrow_mut() = Some(e);
+                                        return false;
                                     }
                                 }
                             }
                         }
-                        true
-                    })
-                    .chain_err(|| "failed to set header")?;
-
-                // If an error happens check to see if we had a filesystem error up
-                // in `cberr`, but we always want to punt it up.
-                transfer.perform().or_else(|e| {
-                    // If the original error was generated by one of our
-                    // callbacks, return it.
-                    match cberr.borrow_mut().take() {
-                        Some(cberr) => Err(cberr),
-                        None => {
-                            // Otherwise, return the error from curl
-                            if e.is_file_couldnt_read_file() {
-                                Err(e).chain_err(|| ErrorKind::FileNotFound)
-                            } else {
-                                Err(e).chain_err(|| "error during download")
-                            }
+                    }
+                    true
+                })
+                .chain_err(|| "failed to set header")?;
+
+            // If an error happens check to see if we had a filesystem error up
+            // in `cberr`, but we always want to punt it up.
+            transfer.perform().or_else(|e| {
+                // If the original error was generated by one of our
+                // callbacks, return it.
+                match cberr.borrow_mut().take() {
+                    Some(cberr) => Err(cberr),
+                    None => {
+                        // Otherwise, return the error from curl
+                        if e.is_file_couldnt_read_file() {
+                            Err(e).chain_err(|| ErrorKind::FileNotFound)
+                        } else if e.is_operation_timedout() && responded.get() {
+                            Err(e).chain_err(|| ErrorKind::DownloadStalled(idle_timeout.as_secs()))
+                        } else {
+                            Err(e).chain_err(|| "error during download")
                         }
                     }
-                })?;
-            }
+                }
+            })?;
+        }
 
-            // If we didn't get a 20x or 0 ("OK" for files) then return an error
-            let code = handle
-                .response_code()
-                .chain_err(|| "failed to get response code")?;
-            match code {
-                0 | 200..=299 => {}
-                _ => {
-                    return Err(ErrorKind::HttpStatus(code).into());
+        // If we didn't get a 20x or 0 ("OK" for files) then return an error
+        let code = handle
+            .response_code()
+            .chain_err(|| "failed to get response code")?;
+        match code {
+            0 | 200..=299 => Ok(None),
+            _ if is_redirect(code) => {
+                let next = handle
+                    .redirect_url()
+                    .chain_err(|| "failed to get redirect location")?
+                    .and_then(|next| Url::parse(next).ok())
+                    .filter(|next| next.scheme() == "http" || next.scheme() == "https");
+                match next {
+                    Some(next) => Ok(Some(next)),
+                    None => Err(super::status_error(url, code)),
                 }
-            };
+            }
+            _ => Err(super::status_error(url, code)),
+        }
+    }
 
-            Ok(())
-        })
+    /// Whether `status` redirects to the URL in the `Location` header, which
+    /// for a download is always fetched with `GET`
+    fn is_redirect(status: u32) -> bool {
+        matches!(status, 301 | 302 | 303 | 307 | 308)
     }
 }
 
 #[cfg(feature = "reqwest-backend")]
 pub mod reqwest_be {
-    extern crate env_proxy;
-
     use super::Event;
     use errors::*;
-    use reqwest::{header, Client, Proxy, Response};
+    use reqwest::{header, Certificate, Client, Proxy, Response};
+    use std::fs;
     use std::io;
-    use std::time::Duration;
     use url::Url;
 
     pub fn download(url: &Url, resume_from: u64, callback: &Fn(Event) -> Result<()>) -> Result<()> {
@@ -276,7 +720,7 @@ pub mod reqwest_be {
 
         if !res.status().is_success() {
             let code: u16 = res.status().into();
-            return Err(ErrorKind::HttpStatus(code as u32).into());
+            return Err(super::status_error(url, code as u32));
         }
 
         let buffer_size = 0x10000;
@@ -287,8 +731,19 @@ pub mod reqwest_be {
         }
 
         loop {
-            let bytes_read =
-                io::Read::read(&mut res, &mut buffer).chain_err(|| "error reading from socket")?;
+            let bytes_read = match io::Read::read(&mut res, &mut buffer) {
+                Ok(bytes_read) => bytes_read,
+                // The client's timeout applies to each read, so this is no
+                // data at all arriving for the idle timeout
+                Err(e)
+                    if e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    let secs = super::idle_timeout().as_secs();
+                    return Err(e).chain_err(|| ErrorKind::DownloadStalled(secs));
+                }
+                Err(e) => return Err(e).chain_err(|| "error reading from socket"),
+            };
 
             if bytes_read != 0 {
                 callback(Event::DownloadDataReceived(&buffer[0..bytes_read]))?;
@@ -299,13 +754,17 @@ pub mod reqwest_be {
     }
 
     lazy_static! {
+        // Built on first use, after `set_idle_timeout` was called
         static ref CLIENT: Client = {
             let catcher = || {
-                Client::builder()
+                let mut builder = Client::builder()
                     .gzip(false)
-                    .proxy(Proxy::custom(env_proxy))
-                    .timeout(Duration::from_secs(30))
-                    .build()
+                    .proxy(Proxy::custom(|url| super::proxy_for_url(url).ok().and_then(|p| p)))
+                    .timeout(super::idle_timeout());
+                for cert in ca_certificates() {
+                    builder = builder.add_root_certificate(cert);
+                }
+                builder.build()
             };
 
             // woah, an unwrap?!
@@ -318,8 +777,20 @@ pub mod reqwest_be {
         };
     }
 
-    fn env_proxy(url: &Url) -> Option<Url> {
-        env_proxy::for_url(url).to_url()
+    /// The certificates of `ca_bundle`, each in its own PEM block. A bundle
+    /// that can't be read adds none, leaving the certificate errors it
+    /// leads to for the download to report.
+    fn ca_certificates() -> Vec<Certificate> {
+        let pem = match super::ca_bundle().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(pem) => pem,
+            None => return Vec::new(),
+        };
+        let (begin, end) = ("-----BEGIN CERTIFICATE-----", "-----END CERTIFICATE-----");
+        pem.split_inclusive(end)
+            .filter_map(|block| block.find(begin).map(|i| &block[i..]))
+            .filter(|block| block.ends_with(end))
+            .filter_map(|block| Certificate::from_pem(block.as_bytes()).ok())
+            .collect()
     }
 
     fn request(url: &Url, resume_from: u64) -> ::reqwest::Result<Response> {
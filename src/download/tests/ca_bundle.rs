@@ -0,0 +1,123 @@
+#![cfg(feature = "curl-backend")]
+
+extern crate download;
+extern crate openssl;
+extern crate url;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+use download::Backend;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use url::Url;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+/// Serves `requests` HTTPS connections with a certificate for 127.0.0.1
+/// issued by the CA in `fixtures/ca.pem`
+fn serve(requests: usize) -> u16 {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file(fixture("server.key"), SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file(fixture("server.pem"))
+        .unwrap();
+    let acceptor = acceptor.build();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            // Fails when the client rejects the certificate
+            let mut stream = match acceptor.accept(stream.unwrap()) {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            {
+                let mut reader = BufReader::new(&mut stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\ntrusted"
+            )
+            .unwrap();
+            let _ = stream.shutdown();
+        }
+    });
+
+    port
+}
+
+fn download(url: &Url) -> download::Result<String> {
+    let path = env::temp_dir().join(format!("download-ca-test-{}", process::id()));
+    let _ = fs::remove_file(&path);
+    download::download_to_path_with_backend(Backend::Curl, url, &path, false, None)?;
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    Ok(contents)
+}
+
+fn clear_env() {
+    for var in &["HTTPS_PROXY", "ALL_PROXY", "ELAN_CAINFO", "SSL_CERT_FILE"] {
+        env::remove_var(var);
+        env::remove_var(var.to_lowercase());
+    }
+}
+
+fn assert_certificate_rejected(url: &Url) {
+    let e = download(url).unwrap_err();
+    assert!(
+        e.iter().any(|e| e.to_string().contains("certificate")),
+        "unexpected error: {}",
+        e
+    );
+}
+
+// Both cases live in one test as the CA bundle is taken from the environment.
+#[cfg(unix)]
+#[test]
+fn download_trusts_configured_ca_bundle() {
+    clear_env();
+    let port = serve(3);
+    let url = Url::parse(&format!("https://127.0.0.1:{}/lean.tar.gz", port)).unwrap();
+
+    // Without the CA, the server's certificate is rejected
+    assert_certificate_rejected(&url);
+
+    env::set_var("ELAN_CAINFO", fixture("ca.pem"));
+    assert_eq!(download(&url).unwrap(), "trusted");
+
+    // With the CA as the system's bundle, an unrelated bundle leaves it trusted
+    env::set_var("SSL_CERT_FILE", fixture("ca.pem"));
+    env::set_var("ELAN_CAINFO", fixture("other-ca.pem"));
+    assert_eq!(download(&url).unwrap(), "trusted");
+}
+
+// Windows' certificate store is used as is, so the CA is trusted only once it
+// is added there
+#[cfg(windows)]
+#[test]
+fn download_leaves_ca_bundle_to_the_certificate_store() {
+    clear_env();
+    let port = serve(1);
+    let url = Url::parse(&format!("https://127.0.0.1:{}/lean.tar.gz", port)).unwrap();
+
+    env::set_var("ELAN_CAINFO", fixture("ca.pem"));
+    assert_certificate_rejected(&url);
+}
@@ -0,0 +1,81 @@
+extern crate download;
+extern crate url;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::process;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use download::{Backend, ErrorKind};
+use url::Url;
+
+#[cfg(feature = "curl-backend")]
+#[test]
+fn stalled_curl_download_is_aborted() {
+    stalled_download_is_aborted(Backend::Curl);
+}
+
+#[cfg(feature = "reqwest-backend")]
+#[test]
+fn stalled_reqwest_download_is_aborted() {
+    stalled_download_is_aborted(Backend::Reqwest);
+}
+
+fn stalled_download_is_aborted(backend: Backend) {
+    for var in &["HTTP_PROXY", "ALL_PROXY"] {
+        env::remove_var(var);
+        env::remove_var(var.to_lowercase());
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (done_tx, done_rx) = channel::<()>();
+
+    // Sends the headers and part of the body, then hangs until the client
+    // has given up
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        {
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+        }
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\npartial"
+        )
+        .unwrap();
+        stream.flush().unwrap();
+        let _ = done_rx.recv();
+    });
+
+    download::set_idle_timeout(Duration::from_secs(1));
+
+    let path = env::temp_dir().join(format!(
+        "download-idle-test-{:?}-{}",
+        backend,
+        process::id()
+    ));
+    let _ = fs::remove_file(&path);
+    let url = Url::parse(&format!("http://127.0.0.1:{}/lean.tar.gz", port)).unwrap();
+    let start = Instant::now();
+    let result = download::download_to_path_with_backend(backend, &url, &path, true, None);
+    let elapsed = start.elapsed();
+    done_tx.send(()).unwrap();
+
+    match result.unwrap_err().0 {
+        ErrorKind::DownloadStalled(1) => {}
+        k => panic!("unexpected error: {}", k),
+    }
+    assert!(elapsed < Duration::from_secs(20), "took {:?}", elapsed);
+
+    // What did arrive is kept for resuming
+    assert_eq!(fs::read_to_string(&path).unwrap(), "partial");
+    fs::remove_file(&path).unwrap();
+}
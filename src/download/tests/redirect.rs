@@ -0,0 +1,124 @@
+#![cfg(feature = "curl-backend")]
+
+extern crate download;
+extern crate url;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use download::{Backend, ErrorKind};
+use url::Url;
+
+/// A request as the server saw it: its `Host` header, path and
+/// `Authorization` header
+type Request = (String, String, Option<String>);
+
+/// Serves `/lean.tar.gz`, redirecting `/same` to it on the same host,
+/// `/cross` to it on `localhost` and `/loop` to itself, returning its port
+/// and the requests served so far
+fn serve_redirects() -> (u16, Arc<Mutex<Vec<Request>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let log = requests.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let (mut path, mut host, mut authorization) = (String::new(), String::new(), None);
+            {
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if line.starts_with("GET ") {
+                        path = line.split_whitespace().nth(1).unwrap().to_owned();
+                    } else if let Some((name, value)) = line.split_once(':') {
+                        match &*name.to_lowercase() {
+                            "host" => host = value.trim().to_owned(),
+                            "authorization" => authorization = Some(value.trim().to_owned()),
+                            _ => {}
+                        }
+                    }
+                    line.clear();
+                }
+            }
+            let location = match &*path {
+                "/same" => Some("/lean.tar.gz".to_owned()),
+                "/cross" => Some(format!("http://localhost:{}/lean.tar.gz", port)),
+                "/loop" => Some("/loop".to_owned()),
+                _ => None,
+            };
+            log.lock().unwrap().push((host, path, authorization));
+            let response = match location {
+                Some(location) => format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 5\r\nConnection: close\r\n\r\nmoved",
+                    location
+                ),
+                None => {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nlean".to_owned()
+                }
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    (port, requests)
+}
+
+#[test]
+fn authorization_is_only_sent_to_the_same_host() {
+    for var in &["HTTP_PROXY", "ALL_PROXY"] {
+        env::remove_var(var);
+        env::remove_var(var.to_lowercase());
+    }
+    let (port, requests) = serve_redirects();
+    download::set_authorization("127.0.0.1", Some("Bearer secret".to_owned()));
+    let path = env::temp_dir().join(format!("download-redirect-test-{}", process::id()));
+    let download = |route: &str| {
+        let _ = fs::remove_file(&path);
+        requests.lock().unwrap().clear();
+        let url = Url::parse(&format!("http://127.0.0.1:{}{}", port, route)).unwrap();
+        download::download_to_path_with_backend(Backend::Curl, &url, &path, false, None)
+    };
+    let auth = Some("Bearer secret".to_owned());
+    let here = format!("127.0.0.1:{}", port);
+
+    download("/same").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "lean");
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            (here.clone(), "/same".to_owned(), auth.clone()),
+            (here.clone(), "/lean.tar.gz".to_owned(), auth.clone()),
+        ]
+    );
+
+    download("/cross").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "lean");
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            (here.clone(), "/cross".to_owned(), auth.clone()),
+            (
+                format!("localhost:{}", port),
+                "/lean.tar.gz".to_owned(),
+                None
+            ),
+        ]
+    );
+
+    match download("/loop").unwrap_err().0 {
+        ErrorKind::TooManyRedirects(url) => assert!(url.ends_with("/loop")),
+        k => panic!("unexpected error: {}", k),
+    }
+    assert_eq!(
+        requests.lock().unwrap().len() as u32,
+        download::MAX_REDIRECTS + 1
+    );
+    let _ = fs::remove_file(&path);
+    download::set_authorization("127.0.0.1", None);
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "curl-backend")]
+
+extern crate download;
+extern crate url;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use download::{Backend, ErrorKind};
+use url::Url;
+
+/// Serves a 503 to the first request and the file to all others, returning
+/// its port and the number of requests served so far
+fn serve_failing_once() -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            {
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+            }
+            let response = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            } else {
+                "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nlean"
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    (port, requests)
+}
+
+#[test]
+fn retries_only_as_often_as_configured() {
+    for var in &["HTTP_PROXY", "ALL_PROXY"] {
+        env::remove_var(var);
+        env::remove_var(var.to_lowercase());
+    }
+    let path = env::temp_dir().join(format!("download-retry-test-{}", process::id()));
+    let download = |port: u16| {
+        let _ = fs::remove_file(&path);
+        let url = Url::parse(&format!("http://127.0.0.1:{}/lean.tar.gz", port)).unwrap();
+        download::download_to_path_with_backend(Backend::Curl, &url, &path, false, None)
+    };
+
+    download::set_retries(0);
+    let (port, requests) = serve_failing_once();
+    match download(port).unwrap_err().0 {
+        ErrorKind::DownloadFailed { status, .. } => assert_eq!(status, 503),
+        k => panic!("unexpected error: {}", k),
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    download::set_retries(2);
+    let (port, requests) = serve_failing_once();
+    download(port).unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "lean");
+    let _ = fs::remove_file(&path);
+}
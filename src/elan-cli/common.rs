@@ -2,6 +2,7 @@
 
 use elan::telemetry_analysis::TelemetryAnalysis;
 use elan::{self, Cfg, Notification, Toolchain, UpdateStatus};
+use elan_dist::dist::{self, ToolchainKind};
 use elan_utils::notify::NotificationLevel;
 use elan_utils::utils;
 use errors::*;
@@ -102,13 +103,20 @@ pub fn read_line() -> Result<String> {
         .ok_or("unable to read from stdin for confirmation".into())
 }
 
-pub fn set_globals(verbose: bool) -> Result<Cfg> {
+/// With `quiet`, only errors are printed, without download progress
+pub fn set_globals(verbose: bool, quiet: bool) -> Result<Cfg> {
     use download_tracker::DownloadTracker;
     use std::cell::RefCell;
 
     let download_tracker = RefCell::new(DownloadTracker::new());
 
     Ok(Cfg::from_env(Arc::new(move |n: Notification| {
+        if quiet {
+            if let NotificationLevel::Error = n.level() {
+                err!("{}", n);
+            }
+            return;
+        }
         if download_tracker.borrow_mut().handle_notification(&n) {
             return;
         }
@@ -207,7 +215,7 @@ pub fn update_all_channels(cfg: &Cfg, self_update: bool, force_update: bool) ->
     }
 
     let setup_path = if self_update {
-        self_update::prepare_update()?
+        self_update::prepare_update(cfg.self_update_channel()?)?
     } else {
         None
     };
@@ -281,27 +289,46 @@ pub fn lean_version(toolchain: &Toolchain) -> String {
     }
 }
 
-pub fn list_toolchains(cfg: &Cfg) -> Result<()> {
-    let toolchains = cfg.list_toolchains()?;
+/// Lists the installed toolchains of `kinds`, or of any kind if empty
+pub fn list_toolchains(cfg: &Cfg, kinds: &[ToolchainKind]) -> Result<()> {
+    let toolchains: Vec<_> = cfg
+        .installed_toolchains()?
+        .into_iter()
+        .filter(|t| kinds.is_empty() || kinds.contains(&t.kind))
+        .collect();
 
     if toolchains.is_empty() {
         println!("no installed toolchains");
     } else {
-        if let Ok(Some(def_toolchain)) = cfg.find_default() {
-            for toolchain in toolchains {
-                let if_default = if def_toolchain.name() == &*toolchain {
-                    " (default)"
-                } else {
-                    ""
-                };
-                println!("{}{}", &toolchain, if_default);
-            }
-        } else {
-            for toolchain in toolchains {
-                println!("{}", &toolchain);
-            }
+        for toolchain in toolchains {
+            let if_default = if toolchain.is_default {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("{}{}", toolchain.name, if_default);
+        }
+    }
+    Ok(())
+}
+
+pub fn list_outdated_toolchains(cfg: &Cfg) -> Result<()> {
+    let mut any_outdated = false;
+    for toolchain in cfg.installed_toolchains()? {
+        let t = cfg.get_toolchain(&toolchain.name, false)?;
+        if let (Some(installed), Some(newer)) = (t.installed_release()?, t.newer_release()?) {
+            println!(
+                "{}: {} -> {}",
+                toolchain.name,
+                dist::release_tag(&installed),
+                dist::release_tag(&newer)
+            );
+            any_outdated = true;
         }
     }
+    if !any_outdated {
+        println!("all toolchains are up to date");
+    }
     Ok(())
 }
 
@@ -335,6 +362,30 @@ pub fn list_overrides(cfg: &Cfg) -> Result<()> {
     Ok(())
 }
 
+/// Shell syntax used by `elan env`
+#[derive(Copy, Clone)]
+pub enum EnvFormat {
+    Sh,
+    Fish,
+    PowerShell,
+}
+
+impl EnvFormat {
+    /// A statement setting and exporting `name` to `value`, quoted so that
+    /// it can be passed to `eval` as is
+    pub fn set_var(self, name: &str, value: &str) -> String {
+        match self {
+            EnvFormat::Sh => format!("export {}='{}';", name, value.replace('\'', r"'\''")),
+            EnvFormat::Fish => format!(
+                "set -gx {} '{}';",
+                name,
+                value.replace('\\', r"\\").replace('\'', r"\'")
+            ),
+            EnvFormat::PowerShell => format!("$Env:{} = '{}'", name, value.replace('\'', "''")),
+        }
+    }
+}
+
 pub fn version() -> &'static str {
     concat!(
         env!("CARGO_PKG_VERSION"),
@@ -21,12 +21,15 @@ pub struct DownloadTracker {
     /// Keeps track of amount of data downloaded every last few secs.
     /// Used for averaging the download speed.
     downloaded_last_few_secs: VecDeque<usize>,
-    /// Time stamp of the last second
-    last_sec: Option<f64>,
-    /// How many seconds have elapsed since the download started
-    seconds_elapsed: u32,
-    /// The terminal we write the information to.
-    term: Option<Box<term::StdoutTerminal>>,
+    /// The seconds elapsed since the download started, telling when to
+    /// display progress
+    ticks: tty::ProgressTicks,
+    /// The terminal we write the information to, stderr like all
+    /// notifications so that stdout holds only the output of commands.
+    term: Option<Box<term::StderrTerminal>>,
+    /// Whether progress is animated on a terminal, rather than printed as
+    /// occasional lines for logs, or `None` if it is not shown at all.
+    interactive: Option<bool>,
     /// Whether we displayed progress for the download or not.
     ///
     /// If the download is quick enough, we don't have time to
@@ -36,16 +39,17 @@ pub struct DownloadTracker {
 }
 
 impl DownloadTracker {
-    /// Creates a new DownloadTracker.
+    /// Creates a new DownloadTracker, showing progress as chosen with
+    /// `tty::set_progress_mode`.
     pub fn new() -> Self {
         DownloadTracker {
             content_len: None,
             total_downloaded: 0,
             downloaded_this_sec: 0,
             downloaded_last_few_secs: VecDeque::with_capacity(DOWNLOAD_TRACK_COUNT),
-            seconds_elapsed: 0,
-            last_sec: None,
-            term: term::stdout(),
+            ticks: tty::ProgressTicks::default(),
+            term: term::stderr(),
+            interactive: tty::progress_mode().interactive(tty::stderr_isatty()),
             displayed_progress: false,
         }
     }
@@ -58,15 +62,18 @@ impl DownloadTracker {
                 true
             }
             Notification::Install(In::Utils(Un::DownloadDataReceived(data))) => {
-                if tty::stdout_isatty() && self.term.is_some() {
-                    self.data_received(data.len());
-                }
+                self.data_received(data.len());
                 true
             }
             Notification::Install(In::Utils(Un::DownloadFinished)) => {
                 self.download_finished();
                 true
             }
+            Notification::Install(In::Utils(Un::RetryingDownload(_))) => {
+                // Still reported, after the progress of the failed attempt
+                self.download_finished();
+                false
+            }
             _ => false,
         }
     }
@@ -83,23 +90,17 @@ impl DownloadTracker {
         let current_time: f64 =
             (OffsetDateTime::now_utc() - OffsetDateTime::UNIX_EPOCH).as_seconds_f64();
 
-        match self.last_sec {
-            None => self.last_sec = Some(current_time),
-            Some(start) => {
-                let elapsed = current_time - start;
-                if elapsed >= 1.0 {
-                    self.seconds_elapsed += 1;
-
-                    self.display();
-                    self.last_sec = Some(current_time);
-                    if self.downloaded_last_few_secs.len() == DOWNLOAD_TRACK_COUNT {
-                        self.downloaded_last_few_secs.pop_back();
-                    }
-                    self.downloaded_last_few_secs
-                        .push_front(self.downloaded_this_sec);
-                    self.downloaded_this_sec = 0;
-                }
+        let interactive = self.interactive == Some(true);
+        if let Some(display) = self.ticks.tick(current_time, interactive) {
+            if display {
+                self.display();
+            }
+            if self.downloaded_last_few_secs.len() == DOWNLOAD_TRACK_COUNT {
+                self.downloaded_last_few_secs.pop_back();
             }
+            self.downloaded_last_few_secs
+                .push_front(self.downloaded_this_sec);
+            self.downloaded_this_sec = 0;
         }
     }
     /// Notifies self that the download has finished.
@@ -107,7 +108,11 @@ impl DownloadTracker {
         if self.displayed_progress {
             // Display the finished state
             self.display();
-            let _ = writeln!(self.term.as_mut().unwrap(), "");
+            if self.interactive == Some(true) {
+                if let Some(ref mut term) = self.term {
+                    let _ = writeln!(term);
+                }
+            }
         }
         self.prepare_for_new_download();
     }
@@ -117,12 +122,38 @@ impl DownloadTracker {
         self.total_downloaded = 0;
         self.downloaded_this_sec = 0;
         self.downloaded_last_few_secs.clear();
-        self.seconds_elapsed = 0;
-        self.last_sec = None;
+        self.ticks = tty::ProgressTicks::default();
         self.displayed_progress = false;
     }
-    /// Display the tracked download information to the terminal.
+    /// Display the tracked download information, updating the current line
+    /// on a terminal and printing a new one otherwise.
     fn display(&mut self) {
+        let progress = self.progress();
+
+        match self.interactive {
+            None => return,
+            Some(false) => {
+                eprintln!("{}", progress);
+                self.displayed_progress = true;
+                return;
+            }
+            Some(true) => {}
+        }
+        let term = match self.term {
+            Some(ref mut term) => term,
+            None => return,
+        };
+
+        let _ = write!(term, "{}", progress);
+        // delete_line() doesn't seem to clear the line properly.
+        // Instead, let's just print some whitespace to clear it.
+        let _ = write!(term, "                ");
+        let _ = term.flush();
+        let _ = term.carriage_return();
+        self.displayed_progress = true;
+    }
+    /// The tracked download information as a single line.
+    fn progress(&self) -> String {
         let total_h = HumanReadable(self.total_downloaded as f64);
         let sum = self
             .downloaded_last_few_secs
@@ -139,31 +170,13 @@ impl DownloadTracker {
                 let content_len_h = HumanReadable(content_len);
                 let remaining = content_len - self.total_downloaded as f64;
                 let eta_h = HumanReadable(remaining / speed);
-                let _ = write!(
-                    self.term.as_mut().unwrap(),
+                format!(
                     "{} / {} ({:3.0} %) {}/s ETA: {:#}",
-                    total_h,
-                    content_len_h,
-                    percent,
-                    speed_h,
-                    eta_h
-                );
-            }
-            None => {
-                let _ = write!(
-                    self.term.as_mut().unwrap(),
-                    "Total: {} Speed: {}/s",
-                    total_h,
-                    speed_h
-                );
+                    total_h, content_len_h, percent, speed_h, eta_h
+                )
             }
+            None => format!("Total: {} Speed: {}/s", total_h, speed_h),
         }
-        // delete_line() doesn't seem to clear the line properly.
-        // Instead, let's just print some whitespace to clear it.
-        let _ = write!(self.term.as_mut().unwrap(), "                ");
-        let _ = self.term.as_mut().unwrap().flush();
-        let _ = self.term.as_mut().unwrap().carriage_return();
-        self.displayed_progress = true;
     }
 }
 
@@ -1,35 +1,129 @@
-use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, Shell, SubCommand};
 use common;
+use download;
 use elan::settings::TelemetryMode;
-use elan::{command, Cfg, Toolchain};
-use elan_utils::utils;
+use elan::telemetry_analysis::parse_since;
+use elan::{self, command, Cfg, Toolchain};
+use elan_dist::dist::{self, ToolchainKind};
+use elan_dist::lockfile::{Lockfile, LOCKFILE_NAME};
+use elan_dist::release_manifest;
+use elan_utils::path_var::PathVarMode;
+use elan_utils::tty::{self, ColorChoice, OutputFormat, ProgressMode};
+use elan_utils::utils::{self, SelfUpdateChannel};
 use errors::*;
 use help::*;
 use self_update;
+use std::env;
 use std::error::Error;
-use std::io::{self, Write};
+use std::fs;
+use std::io::{self, Read, Write};
 use std::iter;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{Duration, UNIX_EPOCH};
 use term2;
+use time::OffsetDateTime;
+use wait_timeout::ChildExt;
 
 pub fn main() -> Result<()> {
     ::self_update::cleanup_self_updater()?;
 
     let ref matches = cli().get_matches();
     let verbose = matches.is_present("verbose");
-    let ref cfg = common::set_globals(verbose)?;
+    if let Some(color) = matches.value_of("color").and_then(ColorChoice::parse) {
+        tty::set_color_choice(color);
+    }
+    if let Some(format) = matches
+        .value_of("output-format")
+        .and_then(OutputFormat::parse)
+    {
+        tty::set_output_format(format);
+    }
+    if let Some(location) = matches.value_of("index-file") {
+        utils::set_release_index(utils::release_index_path(location)?);
+    }
+    let install_matches = match matches.subcommand() {
+        ("install", Some(m)) | ("update", Some(m)) => Some(m),
+        ("toolchain", Some(c)) => match c.subcommand() {
+            ("install", Some(m)) => Some(m),
+            _ => None,
+        },
+        _ => None,
+    };
+    let quiet = install_matches.is_some_and(|m| m.is_present("quiet"));
+    if let Some(mode) = install_matches
+        .and_then(|m| m.value_of("progress"))
+        .and_then(ProgressMode::parse)
+    {
+        tty::set_progress_mode(mode);
+    }
+    let mut cfg = common::set_globals(verbose, quiet)?;
+    if matches.is_present("no-auto-install") {
+        cfg.no_auto_install = true;
+    }
+    if matches.is_present("no-telemetry-color") {
+        cfg.strip_telemetry_color = true;
+    }
+    // After the settings, which these override
+    if let Some(color) = matches.value_of("color").and_then(ColorChoice::parse) {
+        tty::set_color_choice(color);
+        cfg.color = color;
+    }
+    if let Some(secs) = matches.value_of("index-timeout") {
+        let secs = secs
+            .parse()
+            .ok()
+            .filter(|&secs| secs > 0)
+            .ok_or_else(|| format!("invalid index timeout: '{}'", secs))?;
+        download::set_index_timeout(Duration::from_secs(secs));
+    }
+    let cfg = &cfg;
+    // Only where the user reads along or toolchains are installed, not for
+    // what scripts run, such as `show home`, which is how other elans are
+    // asked for their home
+    let checks_other_elans = match matches.subcommand() {
+        ("show", Some(m)) => m.subcommand_name().is_none() && m.value_of("format") != Some("name"),
+        ("install", _) | ("update", _) => true,
+        ("toolchain", Some(c)) => c.subcommand_name() == Some("install"),
+        _ => false,
+    };
+    if !quiet && checks_other_elans {
+        warn_about_other_elans(cfg);
+    }
 
     match matches.subcommand() {
-        ("show", Some(_)) => show(cfg)?,
+        ("show", Some(m)) => match m.subcommand() {
+            ("active-toolchain", Some(_)) => show_active_toolchain(cfg)?,
+            ("home", Some(m)) => show_home(cfg, m)?,
+            _ if m.value_of("format") == Some("name") => show_active_toolchain(cfg)?,
+            _ => show(cfg)?,
+        },
         ("install", Some(m)) => update(cfg, m)?,
         ("update", Some(m)) => update(cfg, m)?,
         ("uninstall", Some(m)) => toolchain_remove(cfg, m)?,
         ("default", Some(m)) => default_(cfg, m)?,
         ("toolchain", Some(c)) => match c.subcommand() {
             ("install", Some(m)) => update(cfg, m)?,
-            ("list", Some(_)) => common::list_toolchains(cfg)?,
+            ("list", Some(m)) => {
+                if m.is_present("outdated") {
+                    common::list_outdated_toolchains(cfg)?
+                } else if m.is_present("installed-only") {
+                    common::list_toolchains(cfg, &[ToolchainKind::Version, ToolchainKind::Custom])?
+                } else if m.is_present("links-only") {
+                    common::list_toolchains(cfg, &[ToolchainKind::Link])?
+                } else if m.is_present("channels-only") {
+                    common::list_toolchains(cfg, &[ToolchainKind::Channel])?
+                } else {
+                    common::list_toolchains(cfg, &[])?
+                }
+            }
             ("link", Some(m)) => toolchain_link(cfg, m)?,
+            ("rename", Some(m)) => toolchain_rename(cfg, m)?,
+            ("export", Some(m)) => toolchain_export(cfg, m)?,
+            ("import", Some(m)) => toolchain_import(cfg, m)?,
+            ("pin", Some(m)) => toolchain_pin(cfg, m)?,
+            ("unpin", Some(m)) => toolchain_unpin(cfg, m)?,
+            ("prune-downloads", Some(_)) => prune_downloads(cfg)?,
             ("uninstall", Some(m)) => toolchain_remove(cfg, m)?,
             (_, _) => unreachable!(),
         },
@@ -40,18 +134,23 @@ pub fn main() -> Result<()> {
             (_, _) => unreachable!(),
         },
         ("run", Some(m)) => run(cfg, m)?,
+        ("exec", Some(m)) => exec(cfg, m)?,
         ("which", Some(m)) => which(cfg, m)?,
+        ("set", Some(m)) => set(cfg, m)?,
+        ("get", Some(m)) => get(cfg, m)?,
+        ("why", Some(m)) => why(cfg, m)?,
+        ("env", Some(m)) => env(cfg, m)?,
         ("doc", Some(m)) => doc(cfg, m)?,
         ("man", Some(m)) => man(cfg, m)?,
         ("self", Some(c)) => match c.subcommand() {
-            ("update", Some(_)) => self_update::update()?,
+            ("update", Some(m)) => self_update_(cfg, m)?,
             ("uninstall", Some(m)) => self_uninstall(m)?,
             (_, _) => unreachable!(),
         },
         ("telemetry", Some(c)) => match c.subcommand() {
-            ("enable", Some(_)) => set_telemetry(&cfg, TelemetryMode::On)?,
-            ("disable", Some(_)) => set_telemetry(&cfg, TelemetryMode::Off)?,
-            ("analyze", Some(_)) => analyze_telemetry(&cfg)?,
+            ("enable", Some(_)) => set_telemetry(cfg, TelemetryMode::On)?,
+            ("disable", Some(_)) => set_telemetry(cfg, TelemetryMode::Off)?,
+            ("analyze", Some(m)) => analyze_telemetry(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("completions", Some(c)) => {
@@ -81,9 +180,61 @@ pub fn cli() -> App<'static, 'static> {
             .help("Enable verbose output")
             .short("v")
             .long("verbose"))
+        .arg(Arg::with_name("color")
+            .help("Whether to color the output of elan and the tools it runs; \
+                   set ELAN_NO_COLOR_INJECT to never pass `--color` on to the tools")
+            .long("color")
+            .takes_value(true)
+            .value_name("when")
+            .possible_values(&["never", "auto", "always"]))
+        .arg(Arg::with_name("no-auto-install")
+            .help("Fail instead of installing a missing toolchain needed to run a command, \
+                   as does setting ELAN_NO_AUTO_INSTALL")
+            .long("no-auto-install"))
+        .arg(Arg::with_name("no-telemetry-color")
+            .help("Strip colors from the output of lean when telemetry passes it on to \
+                   something other than a terminal, as does setting ELAN_NO_TELEMETRY_COLOR")
+            .long("no-telemetry-color"))
+        .arg(Arg::with_name("index-file")
+            .help("Resolve releases against the JSON release index at <path>, a local file \
+                   or file:// URL, instead of GitHub, as does setting ELAN_RELEASE_INDEX")
+            .long("index-file")
+            .takes_value(true)
+            .value_name("path"))
+        .arg(Arg::with_name("index-timeout")
+            .help("Give up on a request for release metadata, such as which release is the \
+                   latest, after <secs> seconds instead of 10 or 'index_timeout' in settings.toml")
+            .long("index-timeout")
+            .takes_value(true)
+            .value_name("secs"))
+        .arg(Arg::with_name("output-format")
+            .help("Write elan's own errors and warnings as GitHub Actions workflow commands, \
+                   shown as annotations, with `github`; the default when GITHUB_ACTIONS is \
+                   true unless ELAN_GITHUB_ACTIONS is 0")
+            .long("output-format")
+            .takes_value(true)
+            .value_name("format")
+            .possible_values(&["human", "github"]))
         .subcommand(SubCommand::with_name("show")
             .about("Show the active and installed toolchains")
-            .after_help(SHOW_HELP))
+            .after_help(SHOW_HELP)
+            .arg(Arg::with_name("format")
+                .help("Whether to print a summary or only the name of the active toolchain")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["human", "name"])
+                .default_value("human"))
+            .subcommand(SubCommand::with_name("active-toolchain")
+                .about("Show only the name of the active toolchain, as does '--format name'"))
+            .subcommand(SubCommand::with_name("home")
+                .about("Show only the path of the elan home directory, where toolchains and settings are kept")
+                .arg(Arg::with_name("toolchains")
+                    .help("Show the directory toolchains are installed to instead")
+                    .long("toolchains"))
+                .arg(Arg::with_name("bin")
+                    .help("Show the directory of the proxies for the Lean tools instead")
+                    .long("bin")
+                    .conflicts_with("toolchains"))))
         .subcommand(SubCommand::with_name("install")
             .about("Update Lean toolchains")
             .after_help(INSTALL_HELP)
@@ -91,14 +242,78 @@ pub fn cli() -> App<'static, 'static> {
             .arg(Arg::with_name("toolchain")
                 .help(TOOLCHAIN_ARG_HELP)
                 .required(true)
-                .multiple(true)))
+                .multiple(true))
+            .arg(Arg::with_name("locked")
+                .help(LOCKED_ARG_HELP)
+                .long("locked"))
+            .arg(Arg::with_name("default")
+                .help(DEFAULT_ARG_HELP)
+                .long("default"))
+            .arg(Arg::with_name("dry-run")
+                .help(DRY_RUN_ARG_HELP)
+                .long("dry-run"))
+            .arg(Arg::with_name("verify-only")
+                .help(VERIFY_ONLY_ARG_HELP)
+                .long("verify-only"))
+            .arg(Arg::with_name("if-needed")
+                .help(IF_NEEDED_ARG_HELP)
+                .long("if-needed"))
+            .arg(Arg::with_name("only-if-newer")
+                .help(ONLY_IF_NEWER_ARG_HELP)
+                .long("only-if-newer"))
+            .arg(Arg::with_name("print-install-dir")
+                .help(PRINT_INSTALL_DIR_ARG_HELP)
+                .long("print-install-dir"))
+            .arg(Arg::with_name("quiet")
+                .help(QUIET_ARG_HELP)
+                .short("q")
+                .long("quiet"))
+            .arg(Arg::with_name("retry")
+                .help(RETRY_ARG_HELP)
+                .long("retry")
+                .takes_value(true)
+                .value_name("n"))
+            .arg(Arg::with_name("progress")
+                .help(PROGRESS_ARG_HELP)
+                .long("progress")
+                .takes_value(true)
+                .value_name("mode")
+                .possible_values(&["auto", "plain", "none"]))
+            .arg(Arg::with_name("target")
+                .help(TARGET_ARG_HELP)
+                .long("target")
+                .takes_value(true))
+            .arg(Arg::with_name("variant")
+                .help(VARIANT_ARG_HELP)
+                .long("variant")
+                .takes_value(true)
+                .value_name("name"))
+            .arg(Arg::with_name("list-variants")
+                .help(LIST_VARIANTS_ARG_HELP)
+                .long("list-variants")
+                .conflicts_with("variant"))
+            .arg(Arg::with_name("keep-download")
+                .help(KEEP_DOWNLOAD_ARG_HELP)
+                .long("keep-download"))
+            .arg(Arg::with_name("from-cache")
+                .help(FROM_CACHE_ARG_HELP)
+                .long("from-cache"))
+            .arg(Arg::with_name("manifest")
+                .help(MANIFEST_ARG_HELP)
+                .long("manifest")
+                .takes_value(true)
+                .value_name("url|file")
+                .conflicts_with_all(&["dry-run", "list-variants", "variant", "only-if-newer"])))
         .subcommand(SubCommand::with_name("uninstall")
             .about("Uninstall Lean toolchains")
             .setting(AppSettings::Hidden) // synonym for 'toolchain uninstall'
             .arg(Arg::with_name("toolchain")
                 .help(TOOLCHAIN_ARG_HELP)
                 .required(true)
-                .multiple(true)))
+                .multiple(true))
+            .arg(Arg::with_name("no-prompt")
+                .help(NO_PROMPT_ARG_HELP)
+                .short("y")))
         .subcommand(SubCommand::with_name("update")
             .about("Update Lean toolchains and elan")
             .after_help(UPDATE_HELP)
@@ -106,6 +321,11 @@ pub fn cli() -> App<'static, 'static> {
                 .help(TOOLCHAIN_ARG_HELP)
                 .required(false)
                 .multiple(true))
+            .arg(Arg::with_name("manifest")
+                .help(MANIFEST_ARG_HELP)
+                .long("manifest")
+                .takes_value(true)
+                .value_name("url|file"))
             .arg(Arg::with_name("no-self-update")
                 .help("Don't perform self update when running the `elan` command")
                 .long("no-self-update")
@@ -114,7 +334,18 @@ pub fn cli() -> App<'static, 'static> {
             .arg(Arg::with_name("force")
                 .help("Force an update, even if some components are missing")
                 .long("force")
-                .takes_value(false)))
+                .takes_value(false))
+            .arg(Arg::with_name("retry")
+                .help(RETRY_ARG_HELP)
+                .long("retry")
+                .takes_value(true)
+                .value_name("n"))
+            .arg(Arg::with_name("progress")
+                .help(PROGRESS_ARG_HELP)
+                .long("progress")
+                .takes_value(true)
+                .value_name("mode")
+                .possible_values(&["auto", "plain", "none"])))
         .subcommand(SubCommand::with_name("default")
             .about("Set the default toolchain")
             .after_help(DEFAULT_HELP)
@@ -128,21 +359,101 @@ pub fn cli() -> App<'static, 'static> {
             .setting(AppSettings::DeriveDisplayOrder)
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .subcommand(SubCommand::with_name("list")
-                .about("List installed toolchains"))
+                .about("List installed toolchains")
+                .arg(Arg::with_name("outdated")
+                    .help("Only list tracking toolchains with a newer release, without updating them")
+                    .long("outdated"))
+                .arg(Arg::with_name("installed-only")
+                    .help("Only list exact releases and other toolchains installed as they are, \
+                           leaving out channels and links")
+                    .long("installed-only"))
+                .arg(Arg::with_name("links-only")
+                    .help("Only list toolchains linked with `elan toolchain link`")
+                    .long("links-only"))
+                .arg(Arg::with_name("channels-only")
+                    .help("Only list tracking toolchains such as `stable`")
+                    .long("channels-only"))
+                .group(ArgGroup::with_name("filter")
+                    .args(&["outdated", "installed-only", "links-only", "channels-only"])))
             .subcommand(SubCommand::with_name("install")
                 .about("Install or update a given toolchain")
                 .aliases(&["update", "add"])
+                .after_help(TOOLCHAIN_INSTALL_HELP)
                 .arg(Arg::with_name("toolchain")
                      .help(TOOLCHAIN_ARG_HELP)
                      .required(true)
-                     .multiple(true)))
+                     .multiple(true))
+                .arg(Arg::with_name("locked")
+                     .help(LOCKED_ARG_HELP)
+                     .long("locked"))
+                .arg(Arg::with_name("default")
+                     .help(DEFAULT_ARG_HELP)
+                     .long("default"))
+                .arg(Arg::with_name("dry-run")
+                     .help(DRY_RUN_ARG_HELP)
+                     .long("dry-run"))
+                .arg(Arg::with_name("verify-only")
+                     .help(VERIFY_ONLY_ARG_HELP)
+                     .long("verify-only"))
+                .arg(Arg::with_name("if-needed")
+                     .help(IF_NEEDED_ARG_HELP)
+                     .long("if-needed"))
+                .arg(Arg::with_name("only-if-newer")
+                     .help(ONLY_IF_NEWER_ARG_HELP)
+                     .long("only-if-newer"))
+                .arg(Arg::with_name("print-install-dir")
+                     .help(PRINT_INSTALL_DIR_ARG_HELP)
+                     .long("print-install-dir"))
+                .arg(Arg::with_name("quiet")
+                     .help(QUIET_ARG_HELP)
+                     .short("q")
+                     .long("quiet"))
+                .arg(Arg::with_name("retry")
+                    .help(RETRY_ARG_HELP)
+                    .long("retry")
+                    .takes_value(true)
+                    .value_name("n"))
+                .arg(Arg::with_name("progress")
+                    .help(PROGRESS_ARG_HELP)
+                    .long("progress")
+                    .takes_value(true)
+                    .value_name("mode")
+                    .possible_values(&["auto", "plain", "none"]))
+                .arg(Arg::with_name("target")
+                     .help(TARGET_ARG_HELP)
+                     .long("target")
+                     .takes_value(true))
+                .arg(Arg::with_name("variant")
+                     .help(VARIANT_ARG_HELP)
+                     .long("variant")
+                     .takes_value(true)
+                     .value_name("name"))
+                .arg(Arg::with_name("list-variants")
+                     .help(LIST_VARIANTS_ARG_HELP)
+                     .long("list-variants")
+                     .conflicts_with("variant"))
+                .arg(Arg::with_name("keep-download")
+                     .help(KEEP_DOWNLOAD_ARG_HELP)
+                     .long("keep-download"))
+                .arg(Arg::with_name("from-cache")
+                     .help(FROM_CACHE_ARG_HELP)
+                     .long("from-cache"))
+                .arg(Arg::with_name("manifest")
+                     .help(MANIFEST_ARG_HELP)
+                     .long("manifest")
+                     .takes_value(true)
+                     .value_name("url|file")
+                     .conflicts_with_all(&["dry-run", "list-variants", "variant", "only-if-newer"])))
             .subcommand(SubCommand::with_name("uninstall")
                 .about("Uninstall a toolchain")
                 .alias("remove")
                 .arg(Arg::with_name("toolchain")
                      .help(TOOLCHAIN_ARG_HELP)
                      .required(true)
-                     .multiple(true)))
+                     .multiple(true))
+                .arg(Arg::with_name("no-prompt")
+                     .help(NO_PROMPT_ARG_HELP)
+                     .short("y")))
             .subcommand(SubCommand::with_name("link")
                 .about("Create a custom toolchain by symlinking to a directory")
                 .after_help(TOOLCHAIN_LINK_HELP)
@@ -150,7 +461,44 @@ pub fn cli() -> App<'static, 'static> {
                     .help(TOOLCHAIN_ARG_HELP)
                     .required(true))
                 .arg(Arg::with_name("path")
-                    .required(true))))
+                    .required(true))
+                .arg(Arg::with_name("force")
+                    .help(LINK_FORCE_ARG_HELP)
+                    .long("force")))
+            .subcommand(SubCommand::with_name("rename")
+                .about("Rename a toolchain, updating the default and overrides that use it")
+                .arg(Arg::with_name("old")
+                    .help(TOOLCHAIN_ARG_HELP)
+                    .required(true))
+                .arg(Arg::with_name("new")
+                    .required(true)))
+            .subcommand(SubCommand::with_name("export")
+                .about("Package an installed toolchain into an archive for installing elsewhere")
+                .after_help(TOOLCHAIN_EXPORT_HELP)
+                .arg(Arg::with_name("toolchain")
+                    .help(TOOLCHAIN_ARG_HELP)
+                    .required(true))
+                .arg(Arg::with_name("archive")
+                    .required(true)))
+            .subcommand(SubCommand::with_name("import")
+                .about("Install a toolchain from an archive created by `elan toolchain export`")
+                .after_help(TOOLCHAIN_EXPORT_HELP)
+                .arg(Arg::with_name("archive")
+                    .required(true))
+                .arg(Arg::with_name("toolchain")
+                    .help("Name to install the toolchain as, instead of the name it was exported with")))
+            .subcommand(SubCommand::with_name("pin")
+                .about("Keep a tracking toolchain at its installed release when running `elan update`")
+                .arg(Arg::with_name("toolchain")
+                    .help(TOOLCHAIN_ARG_HELP)
+                    .required(true)))
+            .subcommand(SubCommand::with_name("unpin")
+                .about("Let `elan update` update a pinned toolchain again")
+                .arg(Arg::with_name("toolchain")
+                    .help(TOOLCHAIN_ARG_HELP)
+                    .required(true)))
+            .subcommand(SubCommand::with_name("prune-downloads")
+                .about("Remove partial downloads left behind by interrupted installs")))
         .subcommand(SubCommand::with_name("override")
             .about("Modify directory toolchain overrides")
             .after_help(OVERRIDE_HELP)
@@ -164,7 +512,14 @@ pub fn cli() -> App<'static, 'static> {
                 .alias("add")
                 .arg(Arg::with_name("toolchain")
                      .help(TOOLCHAIN_ARG_HELP)
-                     .required(true)))
+                     .required(true))
+                .arg(Arg::with_name("path")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Path to the directory, instead of the current one"))
+                .arg(Arg::with_name("file")
+                    .long("file")
+                    .help("Write the toolchain to the directory's `lean-toolchain` file instead")))
             .subcommand(SubCommand::with_name("unset")
                 .about("Remove the override toolchain for a directory")
                 .after_help(OVERRIDE_UNSET_HELP)
@@ -189,22 +544,79 @@ pub fn cli() -> App<'static, 'static> {
                 .required(true))
             .arg(Arg::with_name("command")
                 .required(true).multiple(true).use_delimiter(false)))
+        .subcommand(SubCommand::with_name("exec")
+            .about("Run any program, such as a build script, in the environment of a given toolchain")
+            .after_help(EXEC_HELP)
+            .setting(AppSettings::TrailingVarArg)
+            .arg(Arg::with_name("install")
+                .help("Install the requested toolchain if needed")
+                .long("install"))
+            .arg(Arg::with_name("lean-path")
+                .help(LEAN_PATH_ARG_HELP)
+                .long("lean-path")
+                .takes_value(true)
+                .value_name("mode")
+                .possible_values(&["defer", "prepend", "append"]))
+            .arg(Arg::with_name("toolchain")
+                .help(TOOLCHAIN_ARG_HELP)
+                .required(true))
+            .arg(Arg::with_name("command")
+                .required(true).multiple(true).use_delimiter(false)))
         .subcommand(SubCommand::with_name("which")
             .about("Display which binary will be run for a given command")
             .arg(Arg::with_name("command")
+                .required_unless("all"))
+            .arg(Arg::with_name("all")
+                .help("Display the binary of every command proxied by elan, or whether it is missing")
+                .long("all")
+                .conflicts_with("command")))
+        .subcommand(SubCommand::with_name("set")
+            .about("Change a setting")
+            .after_help(SET_HELP)
+            .arg(Arg::with_name("key")
+                .required(true))
+            .arg(Arg::with_name("value")
                 .required(true)))
-        /*.subcommand(SubCommand::with_name("doc")
+        .subcommand(SubCommand::with_name("get")
+            .about("Show a setting")
+            .after_help(SET_HELP)
+            .arg(Arg::with_name("key")
+                .required(true)))
+        .subcommand(SubCommand::with_name("why")
+            .about("Explain why a toolchain is in use")
+            .after_help(WHY_HELP)
+            .arg(Arg::with_name("toolchain")
+                .help(TOOLCHAIN_ARG_HELP)
+                .required(true)))
+        .subcommand(SubCommand::with_name("env")
+            .about("Print the environment variables set for a toolchain")
+            .after_help(ENV_HELP)
+            .arg(Arg::with_name("format")
+                .help("Shell syntax to print the variables in")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["sh", "fish", "powershell"]))
+            .arg(Arg::with_name("lean-path")
+                .help(LEAN_PATH_ARG_HELP)
+                .long("lean-path")
+                .takes_value(true)
+                .value_name("mode")
+                .possible_values(&["defer", "prepend", "append"]))
+            .arg(Arg::with_name("toolchain")
+                .help(TOOLCHAIN_ARG_HELP)
+                .long("toolchain")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("doc")
             .alias("docs")
             .about("Open the documentation for the current toolchain")
             .after_help(DOC_HELP)
-            .arg(Arg::with_name("book")
-                 .long("book")
-                 .help("The Rust Programming Language book"))
-            .arg(Arg::with_name("std")
-                 .long("std")
-                 .help("Standard library API documentation"))
-            .group(ArgGroup::with_name("page")
-                 .args(&["book", "std"])))*/;
+            .arg(Arg::with_name("path")
+                .long("path")
+                .help("Only print the path to the documentation"))
+            .arg(Arg::with_name("toolchain")
+                .help(TOOLCHAIN_ARG_HELP)
+                .long("toolchain")
+                .takes_value(true)));
 
     /*if cfg!(not(target_os = "windows")) {
         app = app
@@ -225,7 +637,16 @@ pub fn cli() -> App<'static, 'static> {
             .setting(AppSettings::DeriveDisplayOrder)
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .subcommand(
-                SubCommand::with_name("update").about("Download and install updates to elan"),
+                SubCommand::with_name("update")
+                    .about("Download and install updates to elan")
+                    .after_help(SELF_UPDATE_HELP)
+                    .arg(
+                        Arg::with_name("channel")
+                            .help("Which releases of elan to update to")
+                            .long("channel")
+                            .takes_value(true)
+                            .possible_values(&["stable", "beta"]),
+                    ),
             )
             .subcommand(
                 SubCommand::with_name("uninstall")
@@ -233,18 +654,29 @@ pub fn cli() -> App<'static, 'static> {
                     .arg(Arg::with_name("no-prompt").short("y")),
             ),
     )
-    /*.subcommand(SubCommand::with_name("telemetry")
-    .about("elan telemetry commands")
-    .setting(AppSettings::Hidden)
-    .setting(AppSettings::VersionlessSubcommands)
-    .setting(AppSettings::DeriveDisplayOrder)
-    .setting(AppSettings::SubcommandRequiredElseHelp)
-    .subcommand(SubCommand::with_name("enable")
-                    .about("Enable elan telemetry"))
-    .subcommand(SubCommand::with_name("disable")
-                    .about("Disable elan telemetry"))
-    .subcommand(SubCommand::with_name("analyze")
-                    .about("Analyze stored telemetry")))*/
+    .subcommand(
+        SubCommand::with_name("telemetry")
+            .about("elan telemetry commands")
+            .setting(AppSettings::Hidden)
+            .setting(AppSettings::VersionlessSubcommands)
+            .setting(AppSettings::DeriveDisplayOrder)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("enable").about("Enable elan telemetry"))
+            .subcommand(SubCommand::with_name("disable").about("Disable elan telemetry"))
+            .subcommand(
+                SubCommand::with_name("analyze")
+                    .about("Analyze stored telemetry")
+                    .arg(
+                        Arg::with_name("since")
+                            .help(
+                                "Only analyze telemetry logged since a duration ago, such as \
+                                 '3d', '12h' or '30m', or since a date (YYYY-MM-DD)",
+                            )
+                            .long("since")
+                            .takes_value(true),
+                    ),
+            ),
+    )
     .subcommand(
         SubCommand::with_name("completions")
             .about("Generate completion scripts for your shell")
@@ -275,19 +707,106 @@ fn default_(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
 }
 
 fn update(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    if let Some(n) = m.value_of("retry") {
+        let retries = n
+            .parse()
+            .map_err(|_| format!("invalid number of retries: '{}'", n))?;
+        download::set_retries(retries);
+    }
+    if m.is_present("keep-download") || m.is_present("from-cache") {
+        dist::set_archive_cache(Some(dist::ArchiveCache {
+            dir: cfg.archive_dir.clone(),
+            keep: m.is_present("keep-download"),
+            prefer: m.is_present("from-cache"),
+        }));
+    }
+    if let Some(location) = m.value_of("manifest") {
+        release_manifest::set_release_manifest(Some(location.to_owned()));
+    }
     if let Some(names) = m.values_of("toolchain") {
-        for name in names {
+        let names = toolchain_names(names)?;
+        if m.is_present("dry-run") {
+            for name in &names {
+                show_installer_url(cfg, name, m.value_of("target"), m.value_of("variant"))?;
+            }
+            return Ok(());
+        }
+        if m.is_present("list-variants") {
+            for name in &names {
+                list_variants(cfg, name, m.value_of("target"))?;
+            }
+            return Ok(());
+        }
+        if m.is_present("verify-only") {
+            return verify_toolchains(cfg, &names);
+        }
+
+        if m.is_present("default") && names.len() > 1 {
+            return Err("'--default' requires a single toolchain".into());
+        }
+
+        let lock_path = utils::current_dir()?.join(LOCKFILE_NAME);
+        let mut lock = if m.is_present("locked") {
+            Some(Lockfile::load(&lock_path)?)
+        } else {
+            None
+        };
+
+        let print_install_dir = m.is_present("print-install-dir");
+        let quiet = m.is_present("quiet");
+        for name in &names {
             let toolchain = cfg.get_toolchain(name, false)?;
 
-            let status = if !toolchain.exists() || !toolchain.is_custom() {
-                Some(toolchain.install_from_dist(m.is_present("force"))?)
+            // Leave an installed exact version alone entirely, without even
+            // logging telemetry, so that scripts can run this repeatedly, and
+            // a channel too if its latest release is the installed one
+            let up_to_date =
+                if m.is_present("only-if-newer") && toolchain.exists() && !toolchain.is_custom() {
+                    toolchain.release_to_install()?.is_none()
+                } else {
+                    m.is_present("if-needed") && toolchain.exists() && !toolchain.is_tracking()
+                };
+            if up_to_date {
+                if print_install_dir {
+                    println!("{}", toolchain.path().display());
+                }
+                if m.is_present("default")
+                    && cfg.get_default()?.as_deref() != Some(toolchain.name())
+                {
+                    toolchain.make_default()?;
+                }
+                continue;
+            }
+
+            let status = if print_install_dir && toolchain.exists() {
+                None
+            } else if !toolchain.exists() || !toolchain.is_custom() {
+                let status = toolchain.install_from_dist_locked(
+                    m.is_present("force"),
+                    lock.as_mut(),
+                    m.value_of("target"),
+                    m.value_of("variant"),
+                )?;
+                if let Some(ref lock) = lock {
+                    lock.save(&lock_path)?;
+                }
+                Some(status)
             } else {
                 None
             };
 
-            if let Some(status) = status {
-                println!("");
-                common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
+            if print_install_dir {
+                println!("{}", toolchain.path().display());
+            } else if let Some(status) = status {
+                if !quiet {
+                    println!();
+                    common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
+                }
+            }
+
+            // Only reached if the install succeeded
+            if m.is_present("default") {
+                toolchain.make_default()?;
             }
         }
     } else {
@@ -301,21 +820,132 @@ fn update(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+/// Reports the missing and modified files of each toolchain, failing if
+/// any toolchain has some
+fn verify_toolchains(cfg: &Cfg, names: &[String]) -> Result<()> {
+    let mut failed = None;
+    for name in names {
+        let toolchain = cfg.get_toolchain(name, false)?;
+        let problems = match toolchain.verify_files() {
+            Ok(problems) => problems,
+            // Installed before files were recorded, or not from a release
+            Err(elan::Error(elan::ErrorKind::NoInstalledFilesRecord(_), _)) => {
+                println!("{}: no record of installed files", toolchain.name());
+                failed = failed.or_else(|| Some(toolchain.name().to_owned()));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if problems.is_empty() {
+            println!("{}: ok", toolchain.name());
+            continue;
+        }
+        println!("{}: {} problem(s)", toolchain.name(), problems.len());
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        failed = failed.or_else(|| Some(toolchain.name().to_owned()));
+    }
+    match failed {
+        Some(name) => Err(ErrorKind::ToolchainVerificationFailed(name).into()),
+        None => Ok(()),
+    }
+}
+
+/// `names` with `-` replaced by the toolchains listed on stdin
+fn toolchain_names<'a, I: Iterator<Item = &'a str>>(names: I) -> Result<Vec<String>> {
+    let mut result = Vec::new();
+    for name in names {
+        if name == "-" {
+            let mut list = String::new();
+            io::stdin()
+                .read_to_string(&mut list)
+                .chain_err(|| "failed to read toolchains from stdin")?;
+            result.extend(utils::parse_toolchain_list(&list));
+        } else {
+            result.push(name.to_owned());
+        }
+    }
+    Ok(result)
+}
+
+fn show_installer_url(
+    cfg: &Cfg,
+    name: &str,
+    target: Option<&str>,
+    variant: Option<&str>,
+) -> Result<()> {
+    let toolchain = cfg.get_toolchain(name, false)?;
+    if toolchain.exists() && toolchain.is_custom() {
+        println!(
+            "{}: custom toolchain, nothing to download",
+            toolchain.name()
+        );
+        return Ok(());
+    }
+
+    println!("{}", toolchain.name());
+    let release = toolchain.release_url()?;
+    println!("  release:   {}", release);
+    let url = toolchain.installer_url(&release, target, variant)?;
+    let file_name = url.rsplit('/').next().unwrap_or(&url);
+    println!("  installer: {}", url);
+    println!("  file:      {}", file_name);
+
+    Ok(())
+}
+
+/// Prints the installers for `target` that `--variant` can pick from, the
+/// primary one as `(default)`
+fn list_variants(cfg: &Cfg, name: &str, target: Option<&str>) -> Result<()> {
+    let toolchain = cfg.get_toolchain(name, false)?;
+    if toolchain.exists() && toolchain.is_custom() {
+        println!("{}: custom toolchain, no variants", toolchain.name());
+        return Ok(());
+    }
+
+    println!("{}", toolchain.name());
+    let release = toolchain.release_url()?;
+    for asset in toolchain.installer_assets(&release, target)? {
+        let file_name = asset.url.rsplit('/').next().unwrap_or(&asset.url);
+        let variant = asset.variant.as_deref().unwrap_or("(default)");
+        println!("  {:<12} {}", variant, file_name);
+    }
+
+    Ok(())
+}
+
 fn run(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let ref toolchain = m.value_of("toolchain").expect("");
     let args = m.values_of("command").unwrap();
     let args: Vec<_> = args.collect();
     let cmd = cfg.create_command_for_toolchain(toolchain, m.is_present("install"), args[0])?;
 
-    Ok(command::run_command_for_dir(
-        cmd,
-        args[0],
-        &args[1..],
-        &cfg,
-    )?)
+    let exit_code = command::run_command_for_dir(cmd, args[0], &args[1..], cfg)?;
+    process::exit(exit_code)
+}
+
+fn exec(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    if m.is_present("install") && !toolchain.exists() {
+        toolchain.auto_install_from_dist()?;
+    }
+    let args: Vec<_> = m.values_of("command").unwrap().collect();
+    let lean_path = m
+        .value_of("lean-path")
+        .and_then(PathVarMode::parse)
+        .unwrap_or(cfg.lean_path);
+    let cmd = toolchain.create_exec_command(args[0], lean_path)?;
+
+    let exit_code = command::exec_command(cmd, args[0], &args[1..])?;
+    process::exit(exit_code)
 }
 
 fn which(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    if m.is_present("all") {
+        return which_all(cfg);
+    }
+
     let binary = m.value_of("command").expect("");
 
     let binary_path = cfg
@@ -329,10 +959,81 @@ fn which(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn which_all(cfg: &Cfg) -> Result<()> {
+    let (toolchain, _) = cfg.toolchain_for_dir(&utils::current_dir()?, true)?;
+    toolchain.verify()?;
+
+    let binaries = self_update::proxied_binaries()?;
+    let width = binaries.iter().map(|b| b.len()).max().unwrap_or(0);
+    for binary in &binaries {
+        let path = toolchain.binary_file(binary);
+        if utils::is_file(&path) {
+            println!("{:width$}  {}", binary, path.display(), width = width);
+        } else {
+            println!("{:width$}  missing", binary, width = width);
+        }
+    }
+
+    Ok(())
+}
+
+fn set(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let key = m.value_of("key").expect("");
+    let value = m.value_of("value").expect("");
+    Ok(cfg.set_setting(key, value)?)
+}
+
+fn get(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    println!("{}", cfg.get_setting(m.value_of("key").expect(""))?);
+    Ok(())
+}
+
+fn why(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    let references = cfg.toolchain_references(toolchain.name(), &utils::current_dir()?)?;
+
+    if references.is_empty() {
+        println!("'{}': no references", toolchain.name());
+    } else {
+        println!("'{}' is in use because:", toolchain.name());
+        for reference in references {
+            println!("  {}", reference);
+        }
+    }
+    if !toolchain.exists() {
+        warn!("toolchain '{}' is not installed", toolchain.name());
+    }
+
+    Ok(())
+}
+
+fn env(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let format = match m.value_of("format") {
+        Some("sh") => common::EnvFormat::Sh,
+        Some("fish") => common::EnvFormat::Fish,
+        Some("powershell") => common::EnvFormat::PowerShell,
+        Some(_) => unreachable!(),
+        None if cfg!(windows) => common::EnvFormat::PowerShell,
+        None => common::EnvFormat::Sh,
+    };
+    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
+    toolchain.verify()?;
+    let lean_path = m
+        .value_of("lean-path")
+        .and_then(PathVarMode::parse)
+        .unwrap_or(cfg.lean_path);
+
+    for (name, value) in toolchain.exec_env_vars(lean_path) {
+        println!("{}", format.set_var(name, &value.to_string_lossy()));
+    }
+
+    Ok(())
+}
+
 fn show(cfg: &Cfg) -> Result<()> {
     let ref cwd = utils::current_dir()?;
     let installed_toolchains = cfg.list_toolchains()?;
-    let active_toolchain = cfg.find_override_toolchain_or_default(cwd);
+    let active_toolchain = cfg.find_override_toolchain_or_default(cwd, true);
 
     let show_installed_toolchains = installed_toolchains.len() > 1;
     let show_active_toolchain = true;
@@ -406,6 +1107,131 @@ fn show(cfg: &Cfg) -> Result<()> {
     Ok(())
 }
 
+fn show_active_toolchain(cfg: &Cfg) -> Result<()> {
+    let cwd = &utils::current_dir()?;
+    // Only naming the toolchain, this must not install it
+    let (toolchain, _) = cfg.toolchain_for_dir(cwd, false)?;
+    println!("{}", toolchain.name());
+
+    Ok(())
+}
+
+/// How long another elan is given to print its home
+const OTHER_ELAN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The file of the elan home caching the homes other elans reported, each a
+/// line of the elan's modification time in seconds, its path and its home
+const OTHER_ELANS_FILE: &str = "other-elans";
+
+/// The home `binary` prints when asked with `show home`, as a wrapper may set
+/// `ELAN_HOME` for it, or `None` if it fails to within `OTHER_ELAN_TIMEOUT`
+fn reported_home(binary: &Path) -> Option<PathBuf> {
+    let mut child = Command::new(binary)
+        .args(["show", "home"])
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::null())
+        .spawn()
+        .ok()?;
+    match child.wait_timeout(OTHER_ELAN_TIMEOUT) {
+        Ok(Some(status)) if status.success() => {}
+        Ok(None) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        _ => return None,
+    }
+    let mut home = String::new();
+    child.stdout.take()?.read_to_string(&mut home).ok()?;
+    Some(PathBuf::from(home.trim_end_matches(&['\r', '\n'][..])))
+}
+
+/// Warns about elans on `PATH` that appear to use a home of their own, as
+/// they don't see this home's toolchains and settings and vice versa. A set
+/// `ELAN_HOME` is used by every elan alike, so there is nothing to warn about.
+/// The homes other elans report are cached in `OTHER_ELANS_FILE` until they
+/// are modified.
+fn warn_about_other_elans(cfg: &Cfg) {
+    if env::var_os("ELAN_HOME").is_some() {
+        return;
+    }
+    let (path, exe) = match (env::var_os("PATH"), env::current_exe()) {
+        (Some(path), Ok(exe)) => (path, exe),
+        _ => return,
+    };
+    let cache_file = cfg.elan_dir.join(OTHER_ELANS_FILE);
+    let cached = fs::read_to_string(&cache_file).unwrap_or_default();
+    let mut cache: Vec<(String, PathBuf, PathBuf)> = cached
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some((
+                fields.next()?.to_owned(),
+                PathBuf::from(fields.next()?),
+                PathBuf::from(fields.next()?),
+            ))
+        })
+        .collect();
+    let mut changed = false;
+
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    for (binary, home) in utils::other_elan_installs(&path, &exe, &cfg.elan_dir) {
+        let modified = fs::metadata(&binary)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or_else(String::new, |t| t.as_secs().to_string());
+        let home = match cache
+            .iter()
+            .find(|(m, b, _)| *b == binary && *m == modified)
+        {
+            Some((_, _, home)) => home.clone(),
+            None => {
+                // Where it can't tell, its location is all there is to go by
+                let home = reported_home(&binary).unwrap_or(home);
+                cache.retain(|(_, b, _)| *b != binary);
+                cache.push((modified, binary.clone(), home.clone()));
+                changed = true;
+                home
+            }
+        };
+        if canonical(&home) == canonical(&cfg.elan_dir) {
+            continue;
+        }
+        warn!(
+            "another elan at '{}' appears to use the elan home '{}' instead of '{}'",
+            binary.display(),
+            home.display(),
+            cfg.elan_dir.display()
+        );
+        warn!(
+            "if so, consider uninstalling one of them so they don't manage toolchains separately"
+        );
+    }
+
+    if changed {
+        let lines: String = cache
+            .iter()
+            .map(|(m, b, h)| format!("{}\t{}\t{}\n", m, b.display(), h.display()))
+            .collect();
+        let _ = fs::write(&cache_file, lines);
+    }
+}
+
+fn show_home(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let dir = if m.is_present("toolchains") {
+        cfg.toolchains_dir.clone()
+    } else if m.is_present("bin") {
+        utils::elan_bin_dir()?
+    } else {
+        cfg.elan_dir.clone()
+    };
+    println!("{}", dir.display());
+
+    Ok(())
+}
+
 fn explicit_or_dir_toolchain<'a>(cfg: &'a Cfg, m: &ArgMatches) -> Result<Toolchain<'a>> {
     let toolchain = m.value_of("toolchain");
     if let Some(toolchain) = toolchain {
@@ -414,7 +1240,7 @@ fn explicit_or_dir_toolchain<'a>(cfg: &'a Cfg, m: &ArgMatches) -> Result<Toolcha
     }
 
     let ref cwd = utils::current_dir()?;
-    let (toolchain, _) = cfg.toolchain_for_dir(cwd)?;
+    let (toolchain, _) = cfg.toolchain_for_dir(cwd, true)?;
 
     Ok(toolchain)
 }
@@ -424,14 +1250,156 @@ fn toolchain_link(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let ref path = m.value_of("path").expect("");
     let toolchain = cfg.get_toolchain(toolchain, true)?;
 
-    Ok(toolchain.install_from_dir(Path::new(path), true)?)
+    Ok(toolchain.link(Path::new(path), m.is_present("force"))?)
+}
+
+fn toolchain_rename(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let old = m.value_of("old").expect("");
+    let new = m.value_of("new").expect("");
+    let toolchain = cfg.get_toolchain(old, false)?;
+
+    Ok(toolchain.rename(new)?)
+}
+
+fn toolchain_export(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+
+    Ok(toolchain.export(Path::new(m.value_of("archive").expect("")))?)
+}
+
+fn toolchain_import(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let archive = Path::new(m.value_of("archive").expect(""));
+    let name = match m.value_of("toolchain") {
+        Some(name) => name.to_owned(),
+        None => elan::exported_name(archive)?,
+    };
+    let toolchain = cfg.get_toolchain(&name, true)?;
+
+    Ok(toolchain.import(archive)?)
+}
+
+fn toolchain_pin(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+
+    Ok(toolchain.pin()?)
+}
+
+fn toolchain_unpin(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+
+    Ok(toolchain.unpin()?)
+}
+
+fn prune_downloads(cfg: &Cfg) -> Result<()> {
+    let pruned = cfg.prune_downloads()?;
+    for path in &pruned {
+        println!("removed '{}'", path.display());
+    }
+    if pruned.is_empty() {
+        info!("no partial downloads to remove");
+    }
+    Ok(())
 }
 
 fn toolchain_remove(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let no_prompt = m.is_present("no-prompt");
+    let mut toolchains = Vec::new();
     for toolchain in m.values_of("toolchain").expect("") {
-        let toolchain = cfg.get_toolchain(toolchain, false)?;
+        toolchains.push(cfg.get_toolchain(toolchain, false)?);
+    }
+
+    // Asks before removing a toolchain that is in use, as running `lean`
+    // would then fail or install it again
+    let default = match cfg.get_default()? {
+        Some(name) => Some(cfg.get_toolchain(&name, false)?.name().to_owned()),
+        None => None,
+    };
+    let active = cfg
+        .find_override(&utils::current_dir()?, false)
+        .unwrap_or(None)
+        .map(|(toolchain, reason)| (toolchain.name().to_owned(), reason));
+    let mut in_use = false;
+    for toolchain in toolchains.iter().filter(|t| t.exists()) {
+        let name = toolchain.name();
+        if default.as_deref() == Some(name) {
+            warn!("'{}' is the default toolchain", name);
+            in_use = true;
+        }
+        if let Some((ref active, ref reason)) = active {
+            if active == name {
+                warn!("'{}' is the active toolchain ({})", name, reason);
+                in_use = true;
+            }
+        }
+        let overrides: Vec<String> = cfg.settings_file.with(|s| {
+            Ok(s.overrides
+                .iter()
+                .filter(|&(_, t)| t == name)
+                .map(|(path, _)| path.clone())
+                .collect())
+        })?;
+        for path in overrides {
+            warn!(
+                "the override for '{}' will name a toolchain that is not installed",
+                path
+            );
+        }
+    }
+    if in_use && !no_prompt && !common::confirm("\nUninstall anyway? (y/N)", false)? {
+        info!("aborting uninstallation");
+        return Ok(());
+    }
+
+    for toolchain in &toolchains {
         toolchain.remove()?;
     }
+
+    if let Some(default) = default {
+        if toolchains
+            .iter()
+            .any(|t| t.name() == default && !t.exists())
+        {
+            if no_prompt {
+                warn!(
+                    "the default toolchain '{}' is no longer installed; \
+                     choose another with `elan default <toolchain>`",
+                    default
+                );
+            } else {
+                repoint_default(cfg)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks for a toolchain to make the default instead of one just removed,
+/// clearing the default if none is given
+fn repoint_default(cfg: &Cfg) -> Result<()> {
+    let name = common::question_str(
+        "Enter a toolchain to make the default instead, or nothing to clear the default:",
+        "",
+    )?;
+    if name.is_empty() {
+        cfg.settings_file.with_mut(|s| {
+            s.default_toolchain = None;
+            Ok(())
+        })?;
+        info!("default toolchain cleared");
+        return Ok(());
+    }
+
+    let toolchain = cfg.get_toolchain(&name, false)?;
+    let status = if !toolchain.exists() || !toolchain.is_custom() {
+        Some(toolchain.install_from_dist_if_not_installed()?)
+    } else {
+        None
+    };
+    toolchain.make_default()?;
+    if let Some(status) = status {
+        println!();
+        common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
+    }
     Ok(())
 }
 
@@ -439,13 +1407,26 @@ fn override_add(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let ref toolchain = m.value_of("toolchain").expect("");
     let toolchain = cfg.get_toolchain(toolchain, false)?;
 
+    let path = match m.value_of("path") {
+        Some(path) => {
+            let path = Path::new(path);
+            utils::assert_is_directory(path)?;
+            utils::canonicalize_path(path, &|n| (cfg.notify_handler)(n.into()))
+        }
+        None => utils::current_dir()?,
+    };
+
     let status = if !toolchain.exists() || !toolchain.is_custom() {
         Some(toolchain.install_from_dist_if_not_installed()?)
     } else {
         None
     };
 
-    toolchain.make_override(&utils::current_dir()?)?;
+    if m.is_present("file") {
+        toolchain.make_toolchain_file(&path)?;
+    } else {
+        toolchain.make_override(&path)?;
+    }
 
     if let Some(status) = status {
         println!("");
@@ -501,15 +1482,14 @@ fn override_remove(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
 }
 
 fn doc(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
-    let doc_url = if m.is_present("book") {
-        "book/index.html"
-    } else if m.is_present("std") {
-        "std/index.html"
-    } else {
-        "index.html"
-    };
+    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
 
-    Ok(cfg.open_docs_for_dir(&utils::current_dir()?, doc_url)?)
+    if m.is_present("path") {
+        println!("{}", toolchain.doc_path("index.html")?.display());
+        return Ok(());
+    }
+
+    Ok(toolchain.open_docs("index.html")?)
 }
 
 fn man(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
@@ -528,6 +1508,15 @@ fn man(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn self_update_(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let channel = match m.value_of("channel") {
+        Some(channel) => SelfUpdateChannel::parse(channel).expect(""),
+        None => cfg.self_update_channel()?,
+    };
+
+    self_update::update(channel)
+}
+
 fn self_uninstall(m: &ArgMatches) -> Result<()> {
     let no_prompt = m.is_present("no-prompt");
 
@@ -541,7 +1530,11 @@ fn set_telemetry(cfg: &Cfg, t: TelemetryMode) -> Result<()> {
     }
 }
 
-fn analyze_telemetry(cfg: &Cfg) -> Result<()> {
-    let analysis = cfg.analyze_telemetry()?;
+fn analyze_telemetry(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let since = match m.value_of("since") {
+        Some(since) => Some(parse_since(since, OffsetDateTime::now_utc())?),
+        None => None,
+    };
+    let analysis = cfg.analyze_telemetry(since)?;
     common::show_telemetry(analysis)
 }
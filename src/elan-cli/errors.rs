@@ -27,6 +27,11 @@ error_chain! {
             description("toolchain is not installed")
             display("toolchain '{}' is not installed", t)
         }
+        ToolchainVerificationFailed(t: String) {
+            description("toolchain failed verification")
+            display("toolchain '{}' failed verification; run `elan toolchain uninstall {}` \
+                     and `elan toolchain install {}` to reinstall it", t, t, t)
+        }
         InfiniteRecursion {
             description("infinite recursion detected")
         }
@@ -37,6 +42,10 @@ error_chain! {
             description("elan is not installed")
             display("elan is not installed at '{}'", p.display())
         }
+        SelfUpdateChecksumMissing(url: String) {
+            description("no checksum is published for the self-update")
+            display("no checksum is published for '{}', so elan was not updated", url)
+        }
         WindowsUninstallMadness {
             description("failure during windows uninstall")
         }
@@ -9,7 +9,19 @@ pub static SHOW_HELP: &'static str = r"DISCUSSION:
     Shows the name of the active toolchain and the version of `lean`.
 
     If there are multiple toolchains installed then all installed
-    toolchains are listed as well.";
+    toolchains are listed as well.
+
+    For scripts, `elan show active-toolchain` prints nothing but the
+    name of the active toolchain:
+
+        $ lean_toolchain=$(elan show active-toolchain)
+
+    Similarly, `elan show home` prints the path of the elan home
+    directory, as set by ELAN_HOME, and `--toolchains` or `--bin` that
+    of the directory toolchains or the proxies of the Lean tools are
+    installed to:
+
+        $ export PATH=$(elan show home --bin):$PATH";
 
 pub static UPDATE_HELP: &'static str = r"DISCUSSION:
     With no toolchain specified, the `update` command updates each of
@@ -39,21 +51,87 @@ pub static TOOLCHAIN_HELP: &'static str = r"DISCUSSION:
 
         [<origin>:]<channel>[-<date>]
 
-        <channel>       = stable|nightly|<version>
+        <channel>       = stable|nightly|latest|<version>
         <date>          = YYYY-MM-DD
 
     'channel' is either a named release channel or an explicit version
-    number, such as '3.3.0'. Channel names can be optionally appended
-    with an archive date, as in 'nightly-2018-04-10', in which case
-    the toolchain is downloaded from the archive for that date.
+    number, such as '3.3.0'. A version without a patch number, such
+    as 'v4.1', tracks the newest patch release of that series, so
+    that `elan update` follows its patch updates. Channel names can
+    be optionally appended with an archive date, as in
+    'nightly-2018-04-10', in which case the toolchain is downloaded
+    from the archive for that date.
     'origin' can be used to refer to custom forks of Lean on Github;
     the default is 'leanprover/lean'. For nightly versions, '-nightly'
-    is appended to the value of 'origin'.
+    is appended to the value of 'origin'. 'latest' tracks whichever
+    of the stable and nightly releases of 'origin' was published
+    most recently, prereleases included.
 
     elan can also manage symlinked local toolchain builds, which are
     often used to for developing Lean itself. For more information see
     `elan toolchain help link`.";
 
+pub static TOOLCHAIN_INSTALL_HELP: &str = r"DISCUSSION:
+    Installing a channel such as 'stable', 'nightly' or 'v4.1' that is
+    already installed looks up its latest release and updates the
+    toolchain if it is out of date. Installing an exact version such as '3.4.2' or
+    'nightly-2021-04-01' that is already installed does nothing.
+    With '--if-needed', it does not even print or record anything, so
+    provisioning scripts can run it as often as they like:
+
+        $ elan toolchain install --if-needed leanprover/lean4:v4.1.0
+
+    '--only-if-newer' does the same, and also leaves a channel alone
+    without printing anything if its latest release is the one it was
+    last installed from, so that e.g. a nightly is only downloaded and
+    extracted once it has advanced:
+
+        $ elan toolchain install --only-if-newer nightly
+
+    With '--locked', the exact release each toolchain resolved to and
+    the checksum of the installed archive are recorded in an 'elan.lock'
+    file in the current directory. Later '--locked' installs from that
+    directory fail if a toolchain would resolve to anything else, so
+    that e.g. 'stable' is pinned to the same release for everyone using
+    the lockfile:
+
+        $ elan toolchain install --locked stable
+
+    Commit 'elan.lock' to share it; delete its entry for a toolchain to
+    update the toolchain.
+
+    Some releases provide variants of the installer for a platform,
+    such as 'lean-4.0.0-linux-nogmp.tar.zst' next to
+    'lean-4.0.0-linux.tar.zst'. '--list-variants' shows them, and
+    '--variant' installs one instead of the primary installer:
+
+        $ elan toolchain install --variant nogmp leanprover/lean4:v4.0.0
+
+    '--keep-download' keeps each downloaded archive in the 'archives'
+    directory of ELAN_HOME, or the directory 'archive_dir' in
+    settings.toml names, in a directory per release such as
+    'leanprover/lean4/v4.0.0', e.g. to serve from a mirror.
+    '--from-cache' installs from a kept archive instead of downloading
+    one if there is one. Only an exact version is found there without
+    looking up which release it is, so this works offline:
+
+        $ elan toolchain install --from-cache leanprover/lean4:v4.0.0
+
+    '--manifest' installs from the archives a JSON manifest at a URL
+    or path lists instead of from GitHub releases, for forks that
+    publish their toolchains elsewhere. It is a list of objects with
+    the 'name' of the toolchain, the 'target' triple or platform such
+    as 'darwin_aarch64' it is for, the 'url' of the archive, which may
+    be relative to the manifest, and its 'sha256'. An archive whose
+    checksum differs is not installed:
+
+        $ elan toolchain install --manifest ./manifest.json myorg/lean4:v1.0.0
+
+    A toolchain of '-' stands for the toolchains listed on stdin, one
+    per line, ignoring blank lines and '#' comments:
+
+        $ echo leanprover/lean4:v4.1.0 | elan toolchain install -";
+
 pub static TOOLCHAIN_LINK_HELP: &'static str = r"DISCUSSION:
     'toolchain' is the custom name to be assigned to the new toolchain.
 
@@ -67,7 +145,46 @@ pub static TOOLCHAIN_LINK_HELP: &'static str = r"DISCUSSION:
         $ elan override set master
 
     If you now compile a crate in the current directory, the custom
-    toolchain 'master' will be used.";
+    toolchain 'master' will be used.
+
+    Linking over a toolchain that exists fails unless '--force' is
+    given, e.g. to point a link at a build that has moved. An existing
+    link is replaced in one step, so commands running meanwhile find
+    either the old or the new directory:
+
+        $ elan toolchain link --force master <path/to/new/lean/root>";
+
+pub static TOOLCHAIN_EXPORT_HELP: &str = r"DISCUSSION:
+    `elan toolchain export` packages a complete toolchain into a
+    `.tar.gz` archive, which `elan toolchain import` installs on another
+    machine without downloading it again:
+
+        $ elan toolchain export stable stable.tar.gz
+        $ elan toolchain import stable.tar.gz
+
+    The toolchain is imported under the name it was exported with, unless
+    another one is given. Symlinks within the toolchain are stored as
+    relative links so that it can be installed anywhere, and the archive
+    is rejected if it does not contain a complete toolchain.
+
+    The archive does not record which release a channel such as `stable`
+    was last updated to, so the next `elan update` installs its latest
+    release again.";
+
+pub static SELF_UPDATE_HELP: &str = r"DISCUSSION:
+    By default elan updates itself to its latest stable release. With
+    `--channel beta`, it updates to the newest release including
+    prereleases, for trying out elan before it is released:
+
+        $ elan self update --channel beta
+
+    To follow the beta channel with every `elan self update` and
+    `elan update`, set it in `settings.toml` in the elan home directory:
+
+        self_update_channel = 'beta'
+
+    Prereleases are verified against their checksums like any other
+    release.";
 
 pub static OVERRIDE_HELP: &'static str = r"DISCUSSION:
     Overrides configure elan to use a specific toolchain when
@@ -89,6 +206,17 @@ pub static OVERRIDE_HELP: &'static str = r"DISCUSSION:
 
         $ elan override set 3.3.0
 
+    To set the override for another directory without changing into
+    it, pass `--path`:
+
+        $ elan override set nightly --path ~/projects/foo
+
+    To write the toolchain to the `lean-toolchain` file of the
+    directory instead, so that it can be shared with the project,
+    pass `--file`:
+
+        $ elan override set leanprover/lean4:v4.0.0 --file
+
     To see the active toolchain use `elan show`. To remove the
     override and use the default toolchain again, `elan override
     unset`.";
@@ -115,12 +243,70 @@ pub static RUN_HELP: &'static str = r"DISCUSSION:
 
         $ elan run nightly leanpkg build";
 
-pub static _DOC_HELP: &'static str = r"DISCUSSION:
+pub static EXEC_HELP: &str = r"DISCUSSION:
+    Runs the specified program with the environment of the given
+    toolchain, like `elan run`, but for programs that are not part of
+    the toolchain, such as build scripts. Besides putting the
+    toolchain first on `PATH`, this sets `LAKE_HOME` to the toolchain
+    and `LEAN_PATH` to the toolchain's library, unless `LEAN_PATH` is
+    set already. The arguments are passed on unchanged:
+
+        $ elan exec leanprover/lean4:v4.0.0 -- make
+
+    To combine the toolchain's library with your own `LEAN_PATH`
+    instead, pass `--lean-path append` or `--lean-path prepend`, or
+    make either the default with `elan set lean-path`.";
+
+pub static SET_HELP: &str = r"DISCUSSION:
+    Changes or shows a setting of the elan installation, checking that
+    the value is valid before 'settings.toml' is written. The settings
+    are:
+
+        auto-install  Install missing toolchains when running a command
+                      needs them: 'true' (the default) or 'false'
+        color         Whether to color output when '--color' is not
+                      given: 'auto' (the default), 'always' or 'never'
+        lean-path     How 'elan exec' adds the toolchain's library to
+                      a 'LEAN_PATH' that is set already: 'defer' (the
+                      default) leaves it alone, 'prepend' or 'append'
+        telemetry     Record telemetry: 'true' or 'false' (the default)
+
+    `elan get` shows the value in effect, and both warn when another
+    settings file, such as a project's '.elan/settings.toml', decides it
+    rather than the 'settings.toml' that `elan set` writes.
+
+    For example, to never install toolchains without being asked:
+
+        $ elan set auto-install false
+        $ elan get auto-install
+        false";
+
+pub static WHY_HELP: &str = r"DISCUSSION:
+    Lists what makes a toolchain in use: being the default toolchain,
+    directory overrides set with `elan override set`, and how the
+    current directory selects it, such as through its `lean-toolchain`
+    file. Projects in other directories are not known to elan, so a
+    toolchain with no references may still be named by one of them.";
+
+pub static ENV_HELP: &str = r"DISCUSSION:
+    Prints the environment variables elan sets when running commands
+    of the active toolchain, or of the toolchain given by `--toolchain`,
+    with `elan exec`: the toolchain first on `PATH`, `LEAN_PATH` and
+    `LAKE_HOME` among them. The output can be evaluated by a shell to
+    reproduce that environment, which is also useful to include in bug
+    reports:
+
+        $ eval $(elan env)
+
+    The default format is `sh`, or `powershell` on Windows.";
+
+pub static DOC_HELP: &str = r"DISCUSSION:
     Opens the documentation for the currently active toolchain with
-    the default browser.
+    the browser named by the `BROWSER` environment variable, or the
+    default browser if it is not set.
 
-    By default, it opens the documentation index. Use the various
-    flags to open specific pieces of documentation.";
+    With `--path`, the location of the documentation index is printed
+    instead.";
 
 pub static COMPLETIONS_HELP: &'static str = r"DISCUSSION:
     One can generate a completion script for `elan` that is
@@ -223,6 +409,66 @@ pub static COMPLETIONS_HELP: &'static str = r"DISCUSSION:
 
         PS C:\> elan completions powershell >> %USERPROFILE%\Documents\WindowsPowerShell\Microsoft.PowerShell_profile.ps1";
 
+pub static LOCKED_ARG_HELP: &str = "Pin toolchains to the releases recorded in \
+     'elan.lock', recording any that are missing";
+
+pub static DEFAULT_ARG_HELP: &str = "Set the toolchain as the default once it is installed";
+
+pub static DRY_RUN_ARG_HELP: &str = "Print the release and installer URL each toolchain \
+     resolves to, without downloading the installer";
+
+pub static VERIFY_ONLY_ARG_HELP: &str = "Check that the files of each installed toolchain \
+     are present and its binaries unmodified since it was installed, without reinstalling it";
+
+pub static PRINT_INSTALL_DIR_ARG_HELP: &str = "Print only the directory of each toolchain \
+     once it is installed. Toolchains that are already installed are not updated";
+
+pub static QUIET_ARG_HELP: &str = "Print nothing unless the install fails, \
+     for use in scripts";
+
+pub static IF_NEEDED_ARG_HELP: &str = "Do nothing at all, printing nothing, if an exact \
+     version is already installed. Channels are still updated";
+
+pub static LEAN_PATH_ARG_HELP: &str = "How to add the toolchain's library to a \
+     LEAN_PATH that is set already: 'defer' leaves it alone, 'prepend' or 'append' add it before \
+     or after the entries. Defaults to the lean-path setting";
+
+pub static ONLY_IF_NEWER_ARG_HELP: &str = "Like --if-needed, but also does nothing \
+     if a channel's latest release is the one it was last installed from";
+
+pub static VARIANT_ARG_HELP: &str = "Install a variant of the release for the \
+     platform, such as 'nogmp' for 'lean-4.0.0-linux-nogmp.tar.zst', instead of its primary \
+     installer";
+
+pub static LIST_VARIANTS_ARG_HELP: &str = "List the installers of each toolchain \
+     for the platform that '--variant' can pick, without installing anything";
+
+pub static PROGRESS_ARG_HELP: &str = "How to show download progress: 'auto', the \
+     default, updates a line on a terminal, 'plain' prints a line every few seconds as in logs, \
+     'none' shows none";
+
+pub static LINK_FORCE_ARG_HELP: &str = "Replace a toolchain of the same name, such as \
+     a link to a directory that has moved";
+
+pub static KEEP_DOWNLOAD_ARG_HELP: &str = "Keep the downloaded archive in the \
+     archive cache instead of deleting it once installed";
+
+pub static FROM_CACHE_ARG_HELP: &str = "Install from an archive in the archive cache \
+     instead of downloading one if there is one";
+
+pub static MANIFEST_ARG_HELP: &str = "Resolve and download toolchains from the \
+     archives listed in a JSON release manifest instead of GitHub releases, as does \
+     setting ELAN_RELEASE_MANIFEST";
+
+pub static RETRY_ARG_HELP: &str = "Try a download that fails with a server error or \
+     stalls again up to <n> times, waiting longer each time. 0, the default, never retries";
+
+pub static NO_PROMPT_ARG_HELP: &str = "Uninstall without asking, even the default \
+     or active toolchain";
+
+pub static TARGET_ARG_HELP: &str = "Install the release for the target triple, such as \
+     'aarch64-apple-darwin', instead of this platform. Such a toolchain cannot be run here";
+
 pub static TOOLCHAIN_ARG_HELP: &'static str = "Toolchain name, such as 'stable', 'nightly', \
      or '3.3.0'. For more information see `elan \
      help toolchain`";
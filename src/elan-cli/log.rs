@@ -1,3 +1,5 @@
+use elan_utils::notify::NotificationLevel;
+use elan_utils::tty;
 use std::fmt;
 use std::io::Write;
 use term2;
@@ -16,7 +18,22 @@ macro_rules! verbose {
     ( $ ( $ arg : tt ) * ) => ( $crate::log::verbose_fmt ( format_args ! ( $ ( $ arg ) * ) ) )
 }
 
+/// Writes `args` as the output format says, returning whether it did so
+/// rather than leaving it to be written the usual way
+fn formatted(level: NotificationLevel, args: fmt::Arguments) -> bool {
+    match tty::output_format().format_line(&level, &args.to_string()) {
+        Some(line) => {
+            let _ = writeln!(term2::stderr(), "{}", line);
+            true
+        }
+        None => false,
+    }
+}
+
 pub fn warn_fmt(args: fmt::Arguments) {
+    if formatted(NotificationLevel::Warn, args) {
+        return;
+    }
     let mut t = term2::stderr();
     let _ = t.fg(term2::color::BRIGHT_YELLOW);
     let _ = t.attr(term2::Attr::Bold);
@@ -27,6 +44,9 @@ pub fn warn_fmt(args: fmt::Arguments) {
 }
 
 pub fn err_fmt(args: fmt::Arguments) {
+    if formatted(NotificationLevel::Error, args) {
+        return;
+    }
     let mut t = term2::stderr();
     let _ = t.fg(term2::color::BRIGHT_RED);
     let _ = t.attr(term2::Attr::Bold);
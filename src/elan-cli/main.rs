@@ -14,6 +14,7 @@
 
 #![recursion_limit = "1024"]
 
+extern crate download;
 extern crate elan_dist;
 extern crate elan_utils;
 #[macro_use]
@@ -72,6 +73,9 @@ fn main() {
 }
 
 fn run_elan() -> Result<()> {
+    elan_utils::logging::init();
+    elan_utils::tty::set_output_format(elan_utils::tty::OutputFormat::from_env());
+
     // Guard against infinite proxy recursion. This mostly happens due to
     // bugs in elan.
     do_recursion_guard()?;
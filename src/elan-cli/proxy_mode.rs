@@ -1,12 +1,17 @@
 use common::set_globals;
 use elan::command::run_command_for_dir;
 use elan::Cfg;
-use elan_utils::utils;
+use elan_utils::{tty, utils};
 use errors::*;
 use job;
 use std::env;
 use std::ffi::OsString;
+use std::io;
 use std::path::PathBuf;
+use std::process;
+
+/// What running a command with no toolchain configured offers to install
+const FIRST_RUN_TOOLCHAINS: [&str; 2] = ["stable", "nightly"];
 
 pub fn main() -> Result<()> {
     ::self_update::cleanup_self_updater()?;
@@ -39,7 +44,7 @@ pub fn main() -> Result<()> {
         env::args_os().skip(2).collect()
     };
 
-    let cfg = set_globals(false)?;
+    let cfg = set_globals(false, false)?;
     direct_proxy(&cfg, arg0, toolchain, &cmd_args)?;
 
     Ok(())
@@ -47,8 +52,19 @@ pub fn main() -> Result<()> {
 
 fn direct_proxy(cfg: &Cfg, arg0: &str, toolchain: Option<&str>, args: &[OsString]) -> Result<()> {
     let cmd = match toolchain {
-        None => cfg.create_command_for_dir(&utils::current_dir()?, arg0)?,
-        Some(tc) => cfg.create_command_for_toolchain(tc, true, arg0)?,
+        None => {
+            let stdin = io::stdin();
+            cfg.create_command_for_dir_or_pick(
+                &utils::current_dir()?,
+                arg0,
+                &FIRST_RUN_TOOLCHAINS,
+                tty::stderr_isatty() && tty::stdin_isatty(),
+                stdin.lock(),
+                io::stderr(),
+            )?
+        }
+        Some(tc) => cfg.create_command_for_toolchain(tc, !cfg.no_auto_install, arg0)?,
     };
-    Ok(run_command_for_dir(cmd, arg0, args, &cfg)?)
+    let exit_code = run_command_for_dir(cmd, arg0, args, cfg)?;
+    process::exit(exit_code)
 }
@@ -32,11 +32,12 @@
 
 use common::{self, Confirm};
 use elan_dist::dist;
-use elan_utils::utils;
+use elan_utils::utils::{self, SelfUpdateChannel};
 use errors::*;
 use flate2;
 use regex::Regex;
 use same_file::Handle;
+use sha2::{Digest, Sha256};
 use std::env;
 use std::env::consts::EXE_SUFFIX;
 use std::fs;
@@ -171,7 +172,7 @@ macro_rules! pre_uninstall_msg {
     };
 }
 
-static TOOLS: &'static [&'static str] = &[
+pub static TOOLS: &[&str] = &[
     "lean",
     "leanpkg",
     "leanchecker",
@@ -180,6 +181,26 @@ static TOOLS: &'static [&'static str] = &[
     "lake",
 ];
 
+/// The binaries elan proxies: the known tools followed by any others that
+/// have been linked into `ELAN_HOME/bin`
+pub fn proxied_binaries() -> Result<Vec<String>> {
+    let mut names: Vec<String> = TOOLS.iter().map(|t| t.to_string()).collect();
+    let bin_path = utils::elan_bin_dir()?;
+    if let Ok(entries) = fs::read_dir(&bin_path) {
+        let mut extras = vec![];
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let name = name.trim_end_matches(EXE_SUFFIX).to_owned();
+            if name != "elan" && !names.contains(&name) && !extras.contains(&name) {
+                extras.push(name);
+            }
+        }
+        extras.sort();
+        names.extend(extras);
+    }
+    Ok(names)
+}
+
 static UPDATE_ROOT: &'static str = "https://github.com/leanprover/elan/releases/download";
 
 /// `ELAN_HOME` suitable for display, possibly with $HOME
@@ -473,18 +494,22 @@ fn customize_install(mut opts: InstallOpts) -> Result<InstallOpts> {
 }
 
 fn install_bins() -> Result<()> {
-    let ref bin_path = utils::elan_home()?.join("bin");
+    let bin_path = &utils::elan_bin_dir()?;
     let ref this_exe_path = utils::current_exe()?;
     let ref elan_path = bin_path.join(&format!("elan{}", EXE_SUFFIX));
 
     utils::ensure_dir_exists("bin", bin_path, &|_| {})?;
     // NB: Even on Linux we can't just copy the new binary over the (running)
-    // old binary; we must unlink it first.
-    if elan_path.exists() {
-        utils::remove_file("elan-bin", elan_path)?;
-    }
-    utils::copy_file(this_exe_path, elan_path)?;
-    utils::make_executable(elan_path)?;
+    // old binary; we must move it out of the way first. It is kept as
+    // `elan.bak` until the new binary has run, see `cleanup_self_updater`,
+    // and put back if the new one does not start.
+    utils::replace_file_with_backup("elan-bin", this_exe_path, elan_path, |path| {
+        utils::make_executable(path)?;
+        match Command::new(path).arg("--version").output() {
+            Ok(ref output) if output.status.success() => Ok(()),
+            _ => Err("the new elan executable failed to run".into()),
+        }
+    })?;
     install_proxies()
 }
 
@@ -538,7 +563,7 @@ pub fn install_proxies() -> Result<()> {
 }
 
 fn maybe_install_lean(toolchain_str: &str, verbose: bool) -> Result<()> {
-    let ref cfg = common::set_globals(verbose)?;
+    let cfg = &common::set_globals(verbose, false)?;
 
     // If there is already an install, then `toolchain_str` may not be
     // a toolchain the user actually wants. Don't do anything.  FIXME:
@@ -1189,13 +1214,13 @@ fn do_remove_from_path(methods: &[PathUpdateMethod]) -> Result<()> {
 /// (and on windows this process will not be running to do it),
 /// elan-init is stored in `ELAN_HOME`/bin, and then deleted next
 /// time elan runs.
-pub fn update() -> Result<()> {
+pub fn update(channel: SelfUpdateChannel) -> Result<()> {
     if NEVER_SELF_UPDATE {
         err!("self-update is disabled for this build of elan");
         err!("you should probably use your system package manager to update elan");
         process::exit(1);
     }
-    let setup_path = prepare_update()?;
+    let setup_path = prepare_update(channel)?;
     if let Some(ref p) = setup_path {
         let version = match get_new_elan_version(p) {
             Some(new_version) => parse_new_elan_version(new_version),
@@ -1235,7 +1260,7 @@ fn parse_new_elan_version(version: String) -> String {
     String::from(matched_version)
 }
 
-pub fn prepare_update() -> Result<Option<PathBuf>> {
+pub fn prepare_update(channel: SelfUpdateChannel) -> Result<Option<PathBuf>> {
     let ref elan_home = utils::elan_home()?;
     let ref elan_path = elan_home.join(&format!("bin/elan{}", EXE_SUFFIX));
     let ref setup_path = elan_home.join(&format!("bin/elan-init{}", EXE_SUFFIX));
@@ -1258,7 +1283,7 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
     // Download available version
     info!("checking for self-updates");
 
-    let tag = utils::fetch_latest_release_tag("leanprover/elan")?;
+    let tag = utils::fetch_channel_release_tag("leanprover/elan", channel)?;
     let available_version = &tag[1..];
 
     // If up-to-date
@@ -1281,7 +1306,9 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
 
     // Download new version
     info!("downloading self-update");
-    utils::download_file(&download_url, &archive_path, None, &|_| ())?;
+    let mut hasher = Sha256::new();
+    utils::download_file(&download_url, &archive_path, Some(&mut hasher), &|_| ())?;
+    verify_checksum(&url, hasher, tempdir.path())?;
 
     let file = fs::File::open(archive_path)?;
     if cfg!(target_os = "windows") {
@@ -1303,6 +1330,39 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
     Ok(Some(setup_path.to_owned()))
 }
 
+/// Checks a downloaded self-update against the `.sha256` file published next
+/// to it, before anything is replaced with it
+fn verify_checksum(url: &str, hasher: Sha256, tempdir: &Path) -> Result<()> {
+    let checksum_url = utils::parse_url(&format!("{}.sha256", url))?;
+    let checksum_path = tempdir.join("checksum.sha256");
+    match utils::download_file(&checksum_url, &checksum_path, None, &|_| ()) {
+        Ok(()) => {}
+        Err(elan_utils::Error(elan_utils::ErrorKind::DownloadNotExists { .. }, _)) => {
+            return Err(ErrorKind::SelfUpdateChecksumMissing(url.to_owned()).into());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let checksum = utils::read_file("checksum", &checksum_path)?;
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    let calculated = format!("{:x}", hasher.finalize());
+    if expected != calculated {
+        let e: elan_dist::Error = elan_dist::ErrorKind::ChecksumFailed {
+            url: url.to_owned(),
+            expected,
+            calculated,
+        }
+        .into();
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
 /// Tell the upgrader to replace the elan bins, then delete
 /// itself. Like with uninstallation, on Windows we're going to
 /// have to jump through hoops to make everything work right.
@@ -1362,6 +1422,13 @@ pub fn cleanup_self_updater() -> Result<()> {
         utils::remove_file("setup", setup)?;
     }
 
+    // The previous elan, kept in case the update did not start
+    let backup = utils::backup_path(&elan_home.join(format!("bin/elan{}", EXE_SUFFIX)));
+
+    if backup.exists() {
+        utils::remove_file("elan backup", &backup)?;
+    }
+
     // Transitional
     let ref old_setup = elan_home.join(&format!("bin/multilean-setup{}", EXE_SUFFIX));
 
@@ -27,19 +27,21 @@ impl Instantiable for io::Stderr {
     }
 }
 
+/// Whether to color the stream, which depends on it being a terminal unless
+/// `--color` says otherwise
 pub trait Isatty {
     fn isatty() -> bool;
 }
 
 impl Isatty for io::Stdout {
     fn isatty() -> bool {
-        tty::stdout_isatty()
+        tty::color_choice().should_color(tty::stdout_isatty())
     }
 }
 
 impl Isatty for io::Stderr {
     fn isatty() -> bool {
-        tty::stderr_isatty()
+        tty::color_choice().should_color(tty::stderr_isatty())
     }
 }
 
@@ -12,8 +12,9 @@ use temp;
 
 use std::fs::{self, File};
 use std::io::{self, Read, Seek};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
+use walkdir::WalkDir;
 use zip::ZipArchive;
 
 #[derive(Debug)]
@@ -29,21 +30,90 @@ impl<'a> TarPackage<'a> {
     }
 }
 
+fn unsafe_entry(name: &Path) -> Error {
+    ErrorKind::UnsafeArchiveEntry {
+        name: name.display().to_string(),
+    }
+    .into()
+}
+
+/// The path of the archive entry `name` relative to the destination, with the
+/// first component thrown away. Entries that would end up outside of the
+/// destination, like `pkg/../../evil`, are rejected.
+fn entry_path_without_first_dir(name: &Path) -> Result<PathBuf> {
+    let mut components = name.components();
+    // Throw away the first path component
+    components.next();
+
+    let mut relpath = PathBuf::new();
+    for component in components {
+        match component {
+            Component::Normal(part) => relpath.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if relpath.pop() => {}
+            _ => return Err(unsafe_entry(name)),
+        }
+    }
+
+    Ok(relpath)
+}
+
+/// Checks that the entry `name` is not unpacked through a symlink to
+/// `relpath` below `dest`, since an earlier entry could have pointed it
+/// anywhere
+fn check_no_symlinks(dest: &Path, relpath: &Path, name: &Path) -> Result<()> {
+    let mut path = dest.to_owned();
+    for component in relpath.components() {
+        path.push(component);
+        let is_symlink = fs::symlink_metadata(&path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_symlink {
+            return Err(unsafe_entry(name));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the symlink entry `name` at `relpath` points within the
+/// destination. Its target must be relative, with `..` only at the start,
+/// so that the directories it goes through are not symlinks themselves
+/// where it matters.
+fn check_link_target(relpath: &Path, target: &Path, name: &Path) -> Result<()> {
+    let mut resolved = relpath.parent().unwrap_or_else(|| Path::new("")).to_owned();
+    let mut leading = true;
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => {
+                leading = false;
+                resolved.push(part);
+            }
+            Component::CurDir => {}
+            Component::ParentDir if leading && resolved.pop() => {}
+            _ => return Err(unsafe_entry(name)),
+        }
+    }
+    Ok(())
+}
+
 fn unpack_without_first_dir<R: Read>(archive: &mut tar::Archive<R>, path: &Path) -> Result<()> {
     let entries = archive
         .entries()
         .chain_err(|| ErrorKind::ExtractingPackage)?;
     for entry in entries {
         let mut entry = entry.chain_err(|| ErrorKind::ExtractingPackage)?;
-        let relpath = {
+        let name = {
             let path = entry.path();
             let path = path.chain_err(|| ErrorKind::ExtractingPackage)?;
             path.into_owned()
         };
-        let mut components = relpath.components();
-        // Throw away the first path component
-        components.next();
-        let full_path = path.join(&components.as_path());
+        let relpath = entry_path_without_first_dir(&name)?;
+        check_no_symlinks(path, &relpath, &name)?;
+        let full_path = path.join(&relpath);
+        let link_name = entry
+            .link_name()
+            .chain_err(|| ErrorKind::ExtractingPackage)?
+            .map(|link| link.into_owned());
 
         // Create the full path to the entry if it does not exist already
         match full_path.parent() {
@@ -53,6 +123,20 @@ fn unpack_without_first_dir<R: Read>(archive: &mut tar::Archive<R>, path: &Path)
             _ => (),
         };
 
+        match (entry.header().entry_type(), link_name) {
+            (tar::EntryType::Symlink, Some(target)) => check_link_target(&relpath, &target, &name)?,
+            // Hard links name another entry of the archive, which `unpack`
+            // would look for relative to the working directory instead
+            (tar::EntryType::Link, Some(target)) => {
+                let target_relpath = entry_path_without_first_dir(&target)?;
+                check_no_symlinks(path, &target_relpath, &name)?;
+                fs::hard_link(path.join(target_relpath), &full_path)
+                    .chain_err(|| ErrorKind::ExtractingPackage)?;
+                continue;
+            }
+            _ => {}
+        }
+
         entry
             .unpack(&full_path)
             .chain_err(|| ErrorKind::ExtractingPackage)?;
@@ -93,11 +177,10 @@ impl<'a> ZipPackage<'a> {
             if entry.name().ends_with('/') {
                 continue; // skip directories
             }
-            let relpath = PathBuf::from(entry.name());
-            let mut components = relpath.components();
-            // Throw away the first path component
-            components.next();
-            let full_path = path.join(&components.as_path());
+            let name = PathBuf::from(entry.name());
+            let relpath = entry_path_without_first_dir(&name)?;
+            check_no_symlinks(path, &relpath, &name)?;
+            let full_path = path.join(relpath);
 
             // Create the full path to the entry if it does not exist already
             match full_path.parent() {
@@ -144,6 +227,117 @@ impl<'a> TarGzPackage<'a> {
         let file = File::open(path).chain_err(|| ErrorKind::ExtractingPackage)?;
         Self::unpack(file, into)
     }
+
+    /// Packs the contents of `src` into `dest` below a top-level directory
+    /// `root`, the layout `unpack` expects.  Symlinks are kept as such, with
+    /// absolute ones into `src` made relative so that the package can be
+    /// unpacked anywhere.
+    pub fn pack_dir(src: &Path, root: &str, dest: &Path) -> Result<()> {
+        let src = src
+            .canonicalize()
+            .chain_err(|| ErrorKind::CreatingPackage)?;
+        let file = File::create(dest).chain_err(|| ErrorKind::CreatingPackage)?;
+        let stream = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(stream);
+        builder.follow_symlinks(false);
+
+        for entry in WalkDir::new(&src).sort_by_file_name() {
+            let entry = entry.chain_err(|| ErrorKind::CreatingPackage)?;
+            let relpath = entry
+                .path()
+                .strip_prefix(&src)
+                .expect("walkdir entry outside of its root");
+            let name = Path::new(root).join(relpath);
+
+            if entry.path_is_symlink() {
+                let target =
+                    fs::read_link(entry.path()).chain_err(|| ErrorKind::CreatingPackage)?;
+                let target = relocatable_link_target(&src, relpath, &target)?;
+                let metadata = entry
+                    .path()
+                    .symlink_metadata()
+                    .chain_err(|| ErrorKind::CreatingPackage)?;
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(&metadata);
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder
+                    .append_link(&mut header, &name, &target)
+                    .chain_err(|| ErrorKind::CreatingPackage)?;
+            } else if entry.file_type().is_dir() {
+                builder
+                    .append_dir(&name, entry.path())
+                    .chain_err(|| ErrorKind::CreatingPackage)?;
+            } else {
+                builder
+                    .append_path_with_name(entry.path(), &name)
+                    .chain_err(|| ErrorKind::CreatingPackage)?;
+            }
+        }
+
+        builder
+            .into_inner()
+            .and_then(|stream| stream.finish())
+            .chain_err(|| ErrorKind::CreatingPackage)?;
+        Ok(())
+    }
+
+    /// The name of the top-level directory of the package at `path`
+    pub fn root_dir(path: &Path) -> Result<String> {
+        let file = File::open(path).chain_err(|| ErrorKind::ExtractingPackage)?;
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        let mut entries = archive
+            .entries()
+            .chain_err(|| ErrorKind::ExtractingPackage)?;
+        let entry = entries
+            .next()
+            .ok_or_else(|| Error::from(ErrorKind::ExtractingPackage))?
+            .chain_err(|| ErrorKind::ExtractingPackage)?;
+        let name = entry.path().chain_err(|| ErrorKind::ExtractingPackage)?;
+        match name.components().next() {
+            Some(Component::Normal(root)) => Ok(root.to_string_lossy().into_owned()),
+            _ => Err(ErrorKind::ExtractingPackage.into()),
+        }
+    }
+}
+
+/// The target of the symlink at `relpath` below `root`, as a path relative to
+/// the link.  Links that point outside of `root` cannot be relocated and are
+/// rejected.
+fn relocatable_link_target(root: &Path, relpath: &Path, target: &Path) -> Result<PathBuf> {
+    let unrelocatable = || ErrorKind::UnrelocatableLink {
+        name: relpath.display().to_string(),
+        target: target.to_owned(),
+    };
+    let parent = relpath.parent().unwrap_or_else(|| Path::new(""));
+
+    // Where the link points, relative to `root`
+    let resolved = if target.is_absolute() {
+        target
+            .strip_prefix(root)
+            .map_err(|_| unrelocatable())?
+            .to_owned()
+    } else {
+        parent.join(target)
+    };
+    let mut inside = PathBuf::new();
+    for component in resolved.components() {
+        match component {
+            Component::Normal(part) => inside.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if inside.pop() => {}
+            _ => return Err(unrelocatable().into()),
+        }
+    }
+
+    if !target.is_absolute() {
+        return Ok(target.to_owned());
+    }
+    let mut relative = PathBuf::new();
+    for _ in parent.components() {
+        relative.push("..");
+    }
+    Ok(relative.join(inside))
 }
 
 #[derive(Debug)]
@@ -160,3 +354,268 @@ impl<'a> TarZstdPackage<'a> {
         Self::unpack(file, into)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tempfile;
+
+    const EVIL: &str = "pkg/../../evil";
+
+    fn assert_unsafe_entry(result: Result<()>) {
+        match result {
+            Err(Error(ErrorKind::UnsafeArchiveEntry { ref name }, _)) => assert_eq!(name, EVIL),
+            r => panic!("expected an unsafe archive entry error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_entry_path_without_first_dir() {
+        let path = |s: &str| entry_path_without_first_dir(Path::new(s)).ok();
+
+        assert_eq!(path("pkg/bin/lean"), Some(PathBuf::from("bin/lean")));
+        assert_eq!(
+            path("pkg/./lib/../bin/lean"),
+            Some(PathBuf::from("bin/lean"))
+        );
+        assert_eq!(path("pkg"), Some(PathBuf::new()));
+        assert_eq!(path("pkg/.."), None);
+        assert_eq!(path(EVIL), None);
+    }
+
+    #[test]
+    fn test_tar_path_traversal() {
+        let mut data = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut data);
+            let contents = b"evil";
+            let mut header = tar::Header::new_old();
+            // `Header::set_path` refuses `..`, so write the name directly
+            header.as_old_mut().name[..EVIL.len()].copy_from_slice(EVIL.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder.append(&header, &contents[..]).unwrap();
+            builder.finish().unwrap();
+        }
+
+        let root = tempfile::tempdir().unwrap();
+        let dest = root.path().join("a").join("toolchain");
+        fs::create_dir_all(&dest).unwrap();
+
+        assert_unsafe_entry(TarPackage::unpack(Cursor::new(data), &dest));
+        assert!(!root.path().join("evil").exists());
+        assert!(!root.path().join("a").join("evil").exists());
+    }
+
+    /// A name, type, link target and contents
+    type RawEntry<'a> = (&'a str, tar::EntryType, &'a str, &'a [u8]);
+
+    /// A tar archive of `entries`, written as is since `tar::Header`
+    /// refuses unsafe names
+    fn raw_tar(entries: &[RawEntry]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut data);
+            for &(name, entry_type, link, contents) in entries {
+                let mut header = tar::Header::new_old();
+                {
+                    let old = header.as_old_mut();
+                    old.name[..name.len()].copy_from_slice(name.as_bytes());
+                    old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+                }
+                header.set_entry_type(entry_type);
+                header.set_mode(0o644);
+                header.set_size(contents.len() as u64);
+                header.set_cksum();
+                builder.append(&header, contents).unwrap();
+            }
+            builder.finish().unwrap();
+        }
+        data
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_tar_symlink_traversal() {
+        use tar::EntryType::{Link, Regular, Symlink};
+
+        let root = tempfile::tempdir().unwrap();
+        let outside = root.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        let outside = outside.to_str().unwrap().to_owned();
+        let secret = format!("{}/secret", outside);
+
+        let cases: Vec<(Vec<RawEntry>, &str)> = vec![
+            // Absolute link targets
+            (
+                vec![
+                    ("pkg/lib", Symlink, &outside, b""),
+                    ("pkg/lib/secret", Regular, "", b"evil"),
+                ],
+                "pkg/lib",
+            ),
+            // Relative ones leaving the destination
+            (
+                vec![
+                    ("pkg/lib", Symlink, "../../outside", b""),
+                    ("pkg/lib/secret", Regular, "", b"evil"),
+                ],
+                "pkg/lib",
+            ),
+            (vec![("pkg/up", Symlink, "bin/../..", b"")], "pkg/up"),
+            // Entries through a link, wherever it points
+            (
+                vec![
+                    ("pkg/bin/lean", Regular, "", b"lean"),
+                    ("pkg/lib", Symlink, "bin", b""),
+                    ("pkg/lib/secret", Regular, "", b"evil"),
+                ],
+                "pkg/lib/secret",
+            ),
+            (
+                vec![
+                    ("pkg/bin/lean", Regular, "", b"lean"),
+                    ("pkg/lib", Symlink, "bin", b""),
+                    ("pkg/lean", Link, "pkg/lib/lean", b""),
+                ],
+                "pkg/lean",
+            ),
+        ];
+        for (i, (entries, unsafe_name)) in cases.into_iter().enumerate() {
+            let dest = root.path().join(format!("a{}", i)).join("toolchain");
+            fs::create_dir_all(&dest).unwrap();
+            match TarPackage::unpack(Cursor::new(raw_tar(&entries)), &dest) {
+                Err(Error(ErrorKind::UnsafeArchiveEntry { ref name }, _)) => {
+                    assert_eq!(name, unsafe_name, "case {}", i)
+                }
+                r => panic!("case {}: expected an unsafe entry, got {:?}", i, r),
+            }
+            assert_eq!(fs::read_to_string(&secret).unwrap(), "secret");
+        }
+
+        // A hard link to a path outside is looked for inside instead
+        let dest = root.path().join("hard").join("toolchain");
+        fs::create_dir_all(&dest).unwrap();
+        let entries: Vec<RawEntry> = vec![
+            ("pkg/secret", Link, &secret, b""),
+            ("pkg/secret", Regular, "", b"evil"),
+        ];
+        assert!(TarPackage::unpack(Cursor::new(raw_tar(&entries)), &dest).is_err());
+        assert_eq!(fs::read_to_string(&secret).unwrap(), "secret");
+
+        // Links within the destination are fine
+        let dest = root.path().join("good").join("toolchain");
+        fs::create_dir_all(&dest).unwrap();
+        let entries: Vec<RawEntry> = vec![
+            ("pkg/bin/lean", Regular, "", b"lean"),
+            ("pkg/bin/lean4", Link, "pkg/bin/lean", b""),
+            ("pkg/share/lean", Symlink, "../bin/lean", b""),
+        ];
+        TarPackage::unpack(Cursor::new(raw_tar(&entries)), &dest).unwrap();
+        for lean in &["bin/lean4", "share/lean"] {
+            assert_eq!(fs::read_to_string(dest.join(lean)).unwrap(), "lean");
+        }
+    }
+
+    #[test]
+    fn test_zip_path_traversal() {
+        let mut data = Cursor::new(Vec::new());
+        {
+            let mut zip = ::zip::ZipWriter::new(&mut data);
+            zip.start_file(EVIL, ::zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(b"evil").unwrap();
+            zip.finish().unwrap();
+        }
+        data.set_position(0);
+
+        let root = tempfile::tempdir().unwrap();
+        let dest = root.path().join("a").join("toolchain");
+        fs::create_dir_all(&dest).unwrap();
+
+        assert_unsafe_entry(ZipPackage::unpack(data, &dest));
+        assert!(!root.path().join("evil").exists());
+        assert!(!root.path().join("a").join("evil").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_pack_dir_round_trip() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        use std::process::Command;
+
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::create_dir_all(src.join("lib").join("lean")).unwrap();
+        fs::create_dir_all(src.join("share")).unwrap();
+        let lean = src.join("bin").join("lean");
+        fs::write(&lean, "#!/bin/sh\necho \"Lean (version 4.0.0)\"\n").unwrap();
+        fs::set_permissions(&lean, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(src.join("lib").join("lean").join("Init.olean"), "olean").unwrap();
+        symlink("../bin/lean", src.join("share").join("relative")).unwrap();
+        symlink(
+            src.join("bin").join("lean"),
+            src.join("share").join("absolute"),
+        )
+        .unwrap();
+
+        let archive = root.path().join("toolchain.tar.gz");
+        TarGzPackage::pack_dir(&src, "stable", &archive).unwrap();
+        assert_eq!(TarGzPackage::root_dir(&archive).unwrap(), "stable");
+
+        // The source is gone, as on another machine
+        fs::remove_dir_all(&src).unwrap();
+        let dest = root.path().join("elsewhere").join("toolchain");
+        fs::create_dir_all(&dest).unwrap();
+        TarGzPackage::unpack_file(&archive, &dest).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dest.join("lib").join("lean").join("Init.olean")).unwrap(),
+            "olean"
+        );
+        let share = dest.join("share");
+        assert_eq!(
+            fs::read_link(share.join("relative")).unwrap(),
+            Path::new("../bin/lean")
+        );
+        assert_eq!(
+            fs::read_link(share.join("absolute")).unwrap(),
+            Path::new("../bin/lean")
+        );
+        for lean in &[dest.join("bin").join("lean"), share.join("absolute")] {
+            let output = Command::new(lean).output().unwrap();
+            assert!(output.status.success());
+            assert_eq!(output.stdout, b"Lean (version 4.0.0)\n");
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_pack_dir_unrelocatable_link() {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        symlink("/usr/bin/env", src.join("bin").join("env")).unwrap();
+        symlink("../../outside", src.join("bin").join("parent")).unwrap();
+
+        let archive = root.path().join("toolchain.tar.gz");
+        match TarGzPackage::pack_dir(&src, "stable", &archive) {
+            Err(Error(ErrorKind::UnrelocatableLink { ref name, .. }, _)) => {
+                assert_eq!(name, "bin/env")
+            }
+            r => panic!("expected an unrelocatable link error, got {:?}", r),
+        }
+        fs::remove_file(src.join("bin").join("env")).unwrap();
+        match TarGzPackage::pack_dir(&src, "stable", &archive) {
+            Err(Error(ErrorKind::UnrelocatableLink { ref name, .. }, _)) => {
+                assert_eq!(name, "bin/parent")
+            }
+            r => panic!("expected an unrelocatable link error, got {:?}", r),
+        }
+    }
+}
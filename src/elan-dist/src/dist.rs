@@ -1,10 +1,13 @@
 use download::DownloadCfg;
 use elan_utils::{self, utils};
 use errors::*;
+use lockfile::Lockfile;
 use manifest::Component;
-use manifestation::Manifestation;
+use manifestation::{self, Installer, Manifestation};
+pub use manifestation::{set_archive_cache, ArchiveCache, Asset};
 use notifications::Notification;
 use prefix::InstallPrefix;
+use release_manifest::{self, ReleaseManifest};
 use temp;
 
 use std::fmt;
@@ -12,6 +15,13 @@ use std::path::Path;
 
 use regex::Regex;
 
+/// Marks a toolchain installed for a platform other than the host's, holding
+/// its target triple
+pub const TARGET_FILE: &str = "elan-target";
+/// Marks a toolchain installed from a variant of the installer for its
+/// platform, holding the name of the variant
+pub const VARIANT_FILE: &str = "elan-variant";
+
 // fetch latest versions from leanprover-community, but don't break pinned origin-less versions
 const DEFAULT_ORIGIN: &str = "leanprover/lean";
 const DEFAULT_CHANNEL_ORIGIN: &str = "leanprover-community/lean";
@@ -24,14 +34,16 @@ pub struct ToolchainDesc {
     // The GitHub source repository to use (if "nightly" is specified, we append "-nightly" to this)
     // If None, we default to "leanprover/lean"
     pub origin: Option<String>,
-    // Either "nightly", "stable", an explicit version number, or a tag name
+    // Either "nightly", "stable", "latest", an explicit version number, or a tag name
     pub channel: String,
     pub date: Option<String>,
 }
 
 impl ToolchainDesc {
     pub fn from_str(name: &str) -> Result<Self> {
-        let pattern = r"^(?:([a-zA-Z0-9-]+[/][a-zA-Z0-9-]+)[:])?(?:(nightly|stable)(?:-(\d{4}-\d{2}-\d{2}))?|([a-zA-Z0-9-.]+))$";
+        // The origin is any GitHub `owner/repo`; repository names may also
+        // contain `_` and `.`
+        let pattern = r"^(?:([a-zA-Z0-9-]+[/][a-zA-Z0-9-_.]+)[:])?(?:(nightly|stable)(?:-(\d{4}-\d{2}-\d{2}))?|([a-zA-Z0-9-_.]+))$";
 
         let re = Regex::new(&pattern).unwrap();
         re.captures(name)
@@ -77,8 +89,51 @@ impl ToolchainDesc {
     }
 
     pub fn is_tracking(&self) -> bool {
-        let channels = ["nightly", "stable"];
-        channels.iter().any(|x| *x == self.channel) && self.date.is_none()
+        let channels = ["nightly", "stable", "latest"];
+        (channels.iter().any(|x| *x == self.channel) || self.minor_series().is_some())
+            && self.date.is_none()
+    }
+
+    /// The major and minor version of a channel such as `v4.1`, which tracks
+    /// the newest patch release of that series
+    pub fn minor_series(&self) -> Option<(u64, u64)> {
+        if self.date.is_some() {
+            return None;
+        }
+        let re = Regex::new(r"^v?(\d+)\.(\d+)$").unwrap();
+        let c = re.captures(&self.channel)?;
+        Some((c[1].parse().ok()?, c[2].parse().ok()?))
+    }
+}
+
+/// What an installed toolchain is, as listed by `elan toolchain list`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToolchainKind {
+    /// An exact release, such as `v4.0.0` or `nightly-2023-01-02`
+    Version,
+    /// A tracking toolchain such as `stable` or `v4.1`
+    Channel,
+    /// A directory linked with `elan toolchain link`
+    Link,
+    /// Anything else, such as a toolchain imported under a name of its own
+    Custom,
+}
+
+impl ToolchainKind {
+    /// Classifies the toolchain `name`, which `is_link` if it is a link to a
+    /// directory of its own
+    pub fn of(name: &str, is_link: bool) -> Self {
+        if is_link {
+            return ToolchainKind::Link;
+        }
+        match ToolchainDesc::from_str(name) {
+            Ok(ref desc) if desc.is_tracking() => ToolchainKind::Channel,
+            Ok(ref desc) if desc.date.is_some() => ToolchainKind::Version,
+            Ok(ref desc) if Regex::new(r"^v?\d+\.\d+").unwrap().is_match(&desc.channel) => {
+                ToolchainKind::Version
+            }
+            _ => ToolchainKind::Custom,
+        }
     }
 }
 
@@ -101,6 +156,21 @@ impl fmt::Display for ToolchainDesc {
     }
 }
 
+/// How `update_from_dist` installs a toolchain
+#[derive(Debug, Default)]
+pub struct DistOptions<'a> {
+    pub force_update: bool,
+    /// The lockfile the toolchain must resolve to the release and archive
+    /// it is locked to in, or is newly locked in to what was installed
+    pub lock: Option<&'a mut Lockfile>,
+    /// The target triple to install for instead of the host's, marked with
+    /// a `TARGET_FILE`
+    pub target: Option<&'a str>,
+    /// The variant of the installer to install instead of the primary one,
+    /// marked with a `VARIANT_FILE`
+    pub variant: Option<&'a str>,
+}
+
 // Installs or updates a toolchain from a dist server. If an initial
 // install then it will be installed with the default components. If
 // an upgrade then all the existing components will be upgraded.
@@ -113,7 +183,7 @@ pub fn update_from_dist<'a>(
     prefix: &InstallPrefix,
     add: &[Component],
     remove: &[Component],
-    force_update: bool,
+    options: DistOptions,
 ) -> Result<Option<String>> {
     let fresh_install = !prefix.path().exists();
 
@@ -124,7 +194,7 @@ pub fn update_from_dist<'a>(
         prefix,
         add,
         remove,
-        force_update,
+        options,
     );
 
     // Don't leave behind an empty / broken installation directory
@@ -143,7 +213,7 @@ pub fn update_from_dist<'a>(
 fn build_origin_name(origin: Option<&String>, version: &str) -> String {
     let repo = match origin {
         None => {
-            if version == "stable" || version == "nightly" {
+            if version == "stable" || version == "nightly" || version == "latest" {
                 DEFAULT_CHANNEL_ORIGIN
             } else {
                 DEFAULT_ORIGIN
@@ -165,9 +235,32 @@ pub fn update_from_dist_<'a>(
     prefix: &InstallPrefix,
     _add: &[Component],
     _remove: &[Component],
-    _force_update: bool,
+    options: DistOptions,
 ) -> Result<Option<String>> {
+    let DistOptions {
+        lock,
+        target,
+        variant,
+        ..
+    } = options;
+    if let Some(location) = release_manifest::release_manifest() {
+        if let Some(v) = variant {
+            return Err(format!("release manifests have no variants such as '{}'", v).into());
+        }
+        let manifest = ReleaseManifest::load(&location, &download)?;
+        return update_from_manifest(
+            &manifest,
+            download,
+            update_hash,
+            toolchain,
+            prefix,
+            lock,
+            target,
+        );
+    }
+
     let toolchain_str = toolchain.to_string();
+    let informal_target = informal_target(target)?;
     let manifestation = Manifestation::open(prefix.clone())?;
 
     let url = match toolchain_url(download, toolchain) {
@@ -188,44 +281,214 @@ pub fn update_from_dist_<'a>(
         }
     };
 
-    if let Some(hash_file) = update_hash {
-        if utils::is_file(hash_file) {
-            if let Ok(contents) = utils::read_file("update hash", hash_file) {
-                if contents == url {
-                    // Skip download, url matches
-                    return Ok(None);
-                }
-            } /*else {
-                  (self.notify_handler)(Notification::CantReadUpdateHash(hash_file));
-              }*/
-        } /*else {
-              (self.notify_handler)(Notification::NoUpdateHash(hash_file));
-          }*/
+    debug!("'{}' resolved to '{}'", toolchain_str, url);
+    if let Some(ref lock) = lock {
+        lock.verify_release(&toolchain_str, &url)?;
     }
 
+    // Without a locked checksum to compare to, download the archive anyway
+    let locked = match lock {
+        Some(ref lock) => lock.toolchains.contains_key(&toolchain_str),
+        None => true,
+    };
+    // The installed release may be for another platform
+    let same_target = match installed_target(prefix.path())? {
+        Some(t) => manifestation::triple_informal_target(&t) == Some(informal_target.clone()),
+        None => informal_target == manifestation::host_informal_target(),
+    } && installed_variant(prefix.path())?.as_deref() == variant;
+    if let Some(hash_file) = update_hash.filter(|_| locked && same_target) {
+        if is_up_to_date(toolchain, prefix.path(), hash_file, &url) {
+            debug!("'{}' is up to date, skipping download", toolchain_str);
+            return Ok(None);
+        }
+    }
+
+    // A locked archive is checked before it replaces the installed one
+    let expected = lock
+        .as_ref()
+        .and_then(|lock| lock.locked_sha256(&toolchain_str))
+        .map(|sha256| sha256.to_owned());
     match manifestation.update(
-        &build_origin_name(toolchain.origin.as_ref(), &toolchain.channel),
+        &toolchain_str,
+        &release_origin(toolchain, &url),
         &url,
+        &Installer {
+            informal_target: &informal_target,
+            variant,
+            expected: expected.as_deref(),
+        },
         &download.temp_cfg,
         download.notify_handler.clone(),
     ) {
-        Ok(()) => Ok(()),
-        e @ Err(Error(ErrorKind::Utils(elan_utils::ErrorKind::DownloadNotExists { .. }), _)) => e
-            .chain_err(|| {
+        Ok(sha256) => match target {
+            Some(t) if informal_target != manifestation::host_informal_target() => {
+                utils::write_file("target", &prefix.path().join(TARGET_FILE), t)
+                    .map_err(Error::from)
+            }
+            _ => Ok(()),
+        }
+        .and_then(|()| match variant {
+            Some(v) => utils::write_file("variant", &prefix.path().join(VARIANT_FILE), v)
+                .map_err(Error::from),
+            None => Ok(()),
+        })
+        .and_then(|()| match lock {
+            Some(lock) => lock.record(&toolchain_str, &url, &sha256),
+            None => Ok(()),
+        }),
+        Err(e @ Error(ErrorKind::Utils(elan_utils::ErrorKind::DownloadNotExists { .. }), _)) => {
+            Err(e).chain_err(|| {
                 format!(
                     "could not download nonexistent lean version `{}`",
                     toolchain_str
                 )
-            }),
+            })
+        }
         Err(e) => Err(e),
     }
     .map(|()| Some(url))
 }
 
-fn toolchain_url<'a>(download: DownloadCfg<'a>, toolchain: &ToolchainDesc) -> Result<String> {
+/// Like `update_from_dist_`, installing the archive `manifest` lists for
+/// `toolchain` on the platform of `target` instead of one from GitHub, once
+/// its checksum matches the manifest
+pub fn update_from_manifest<'a>(
+    manifest: &ReleaseManifest,
+    download: DownloadCfg<'a>,
+    update_hash: Option<&Path>,
+    toolchain: &ToolchainDesc,
+    prefix: &InstallPrefix,
+    lock: Option<&mut Lockfile>,
+    target: Option<&str>,
+) -> Result<Option<String>> {
+    let toolchain_str = toolchain.to_string();
+    let informal_target = informal_target(target)?;
+    let entry = manifest.find(toolchain, &informal_target)?;
+    debug!(
+        "'{}' resolved to '{}' in '{}'",
+        toolchain_str, entry.url, manifest.location
+    );
+    if let Some(ref lock) = lock {
+        lock.verify_release(&toolchain_str, &entry.url)?;
+        // The manifest may list another archive than the one locked
+        if let Some(locked) = lock.locked_sha256(&toolchain_str) {
+            if locked != entry.sha256 {
+                return Err(ErrorKind::ChecksumFailed {
+                    url: entry.url.clone(),
+                    expected: locked.to_owned(),
+                    calculated: entry.sha256.clone(),
+                }
+                .into());
+            }
+        }
+    }
+    // The URL differs between platforms, so it also tells whether the
+    // installed toolchain is for the same one
+    if let Some(hash_file) = update_hash {
+        if is_up_to_date(toolchain, prefix.path(), hash_file, &entry.url) {
+            debug!("'{}' is up to date, skipping download", toolchain_str);
+            return Ok(None);
+        }
+    }
+
+    let manifestation = Manifestation::open(prefix.clone())?;
+    let sha256 = manifestation.install_verified(
+        &entry.url,
+        &entry.sha256,
+        download.temp_cfg,
+        download.notify_handler,
+    )?;
+    if let Some(t) = target.filter(|_| informal_target != manifestation::host_informal_target()) {
+        utils::write_file("target", &prefix.path().join(TARGET_FILE), t)?;
+    }
+    if let Some(lock) = lock {
+        lock.record(&toolchain_str, &entry.url, &sha256)?;
+    }
+    Ok(Some(entry.url.clone()))
+}
+
+/// Whether the toolchain installed at `prefix` can be kept as is, rather
+/// than installing the release `url` that `toolchain` now resolves to. An
+/// installed exact version always is, while a channel only is if the
+/// release recorded in `update_hash` is still its latest one.
+pub fn is_up_to_date(
+    toolchain: &ToolchainDesc,
+    prefix: &Path,
+    update_hash: &Path,
+    url: &str,
+) -> bool {
+    if !utils::is_directory(prefix) {
+        return false;
+    }
+    if !toolchain.is_tracking() {
+        return true;
+    }
+    utils::read_file("update hash", update_hash)
+        .map(|installed| installed == url)
+        .unwrap_or(false)
+}
+
+/// The repository the release page `release` of `toolchain` belongs to,
+/// which for `latest` may be that of either of its tracks
+fn release_origin(toolchain: &ToolchainDesc, release: &str) -> String {
+    let re = Regex::new(r"^https://github\.com/([^/]+/[^/]+)/releases/").unwrap();
+    match re.captures(release) {
+        Some(c) => c[1].to_owned(),
+        None => build_origin_name(toolchain.origin.as_ref(), &toolchain.channel),
+    }
+}
+
+/// The most recently published release of any track of `origin`, stable or
+/// nightly, as the repository and tag it is in
+fn newest_published_release(origin: Option<&String>) -> Result<(String, String)> {
+    let mut releases = vec![];
+    for track in &["stable", "nightly"] {
+        let repo = build_origin_name(origin, track);
+        if let Some((date, tag)) = utils::fetch_newest_published_release(&repo)? {
+            releases.push((date, repo, tag));
+        }
+    }
+    let newest = releases.into_iter().max();
+    newest.map(|(_, repo, tag)| (repo, tag)).ok_or_else(|| {
+        format!(
+            "no published release found in '{}'",
+            build_origin_name(origin, "stable")
+        )
+        .into()
+    })
+}
+
+/// The release page `toolchain` currently resolves to, looking up the
+/// latest release for a tracking toolchain
+pub fn toolchain_url<'a>(download: DownloadCfg<'a>, toolchain: &ToolchainDesc) -> Result<String> {
     let origin = build_origin_name(toolchain.origin.as_ref(), toolchain.channel.as_ref());
+    if let Some((major, minor)) = toolchain.minor_series() {
+        let series = toolchain.channel.as_str();
+        (download.notify_handler)(Notification::DownloadingManifest(series));
+        let tags = utils::fetch_tags(&origin)?;
+        let release = utils::newest_patch_release(&tags, major, minor).ok_or_else(|| {
+            format!(
+                "no release of the '{}' series found in '{}'",
+                series, origin
+            )
+        })?;
+        (download.notify_handler)(Notification::DownloadedManifest(series, Some(&release)));
+        return Ok(format!(
+            "https://github.com/{}/releases/tag/{}",
+            origin, release
+        ));
+    }
     Ok(
         match (toolchain.date.as_ref(), toolchain.channel.as_str()) {
+            (None, "latest") => {
+                (download.notify_handler)(Notification::DownloadingManifest("latest"));
+                let (repo, release) = newest_published_release(toolchain.origin.as_ref())?;
+                (download.notify_handler)(Notification::DownloadedManifest(
+                    "latest",
+                    Some(&release),
+                ));
+                format!("https://github.com/{}/releases/tag/{}", repo, release)
+            }
             (None, version) if version == "stable" || version == "nightly" => {
                 (download.notify_handler)(Notification::DownloadingManifest(version));
                 let release = utils::fetch_latest_release_tag(&origin)?;
@@ -248,6 +511,468 @@ fn toolchain_url<'a>(download: DownloadCfg<'a>, toolchain: &ToolchainDesc) -> Re
     )
 }
 
+/// The installer of `toolchain` for `target`, or this platform if none is
+/// given, on its release page `release`, without downloading the installer.
+/// `variant` picks a variant of the installer instead of the primary one.
+pub fn installer_url<'a>(
+    download: DownloadCfg<'a>,
+    toolchain: &ToolchainDesc,
+    release: &str,
+    target: Option<&str>,
+    variant: Option<&str>,
+) -> Result<String> {
+    manifestation::installer_url(
+        &download,
+        &toolchain.to_string(),
+        &release_origin(toolchain, release),
+        release,
+        &informal_target(target)?,
+        variant,
+    )
+}
+
+/// The installers of `toolchain` for `target`, or this platform if none is
+/// given, on its release page `release`: the primary one first, then its
+/// variants
+pub fn installer_assets<'a>(
+    download: DownloadCfg<'a>,
+    toolchain: &ToolchainDesc,
+    release: &str,
+    target: Option<&str>,
+) -> Result<Vec<Asset>> {
+    manifestation::installer_assets(
+        &download,
+        &release_origin(toolchain, release),
+        release,
+        &informal_target(target)?,
+    )
+}
+
+/// The name release assets use for the platform of the target triple
+/// `target`, or the host platform if none is given
+pub fn informal_target(target: Option<&str>) -> Result<String> {
+    match target {
+        Some(t) => manifestation::triple_informal_target(t)
+            .ok_or_else(|| ErrorKind::UnsupportedTarget(t.to_owned()).into()),
+        None => Ok(manifestation::host_informal_target()),
+    }
+}
+
+/// The target triple the toolchain at `path` was installed for, if it was
+/// installed for a platform other than the one it was installed on
+pub fn installed_target(path: &Path) -> Result<Option<String>> {
+    let target_file = path.join(TARGET_FILE);
+    if !utils::is_file(&target_file) {
+        return Ok(None);
+    }
+    Ok(Some(utils::read_file("target", &target_file)?))
+}
+
+/// The variant of the installer the toolchain at `path` was installed from,
+/// if it was not installed from the primary one
+pub fn installed_variant(path: &Path) -> Result<Option<String>> {
+    let variant_file = path.join(VARIANT_FILE);
+    if !utils::is_file(&variant_file) {
+        return Ok(None);
+    }
+    Ok(Some(utils::read_file("variant", &variant_file)?))
+}
+
+/// Whether a toolchain installed for `target` can run on this platform
+pub fn is_host_target(target: &str) -> bool {
+    manifestation::triple_informal_target(target) == Some(manifestation::host_informal_target())
+}
+
+/// The release a toolchain installed from the release page `installed` would
+/// now update to, if there is a newer one. Only tracking toolchains can be
+/// outdated; `resolve` looks up their latest release page.
+pub fn newer_release<F>(
+    toolchain: &ToolchainDesc,
+    installed: &str,
+    resolve: F,
+) -> Result<Option<String>>
+where
+    F: FnOnce(&ToolchainDesc) -> Result<String>,
+{
+    if !toolchain.is_tracking() {
+        return Ok(None);
+    }
+    let latest = resolve(toolchain)?;
+    if latest == installed {
+        Ok(None)
+    } else {
+        Ok(Some(latest))
+    }
+}
+
+/// The release page to install `toolchain` from, or `None` if it is
+/// installed already from its latest one. `installed` is the page it was
+/// last installed from, if it is installed and that is known, and `resolve`
+/// looks up the latest release page of a channel. An installed exact version
+/// is never installed again, without looking up anything.
+pub fn release_to_install<F>(
+    toolchain: &ToolchainDesc,
+    is_installed: bool,
+    installed: Option<&str>,
+    resolve: F,
+) -> Result<Option<String>>
+where
+    F: FnOnce(&ToolchainDesc) -> Result<String>,
+{
+    match installed {
+        _ if is_installed && !toolchain.is_tracking() => Ok(None),
+        Some(installed) if is_installed => newer_release(toolchain, installed, resolve),
+        _ => resolve(toolchain).map(Some),
+    }
+}
+
+/// The tag name of a release page URL
+pub fn release_tag(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
 pub fn host_triple() -> &'static str {
     include_str!(concat!(env!("OUT_DIR"), "/target.txt"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile;
+    use url::Url;
+
+    fn tag_url(name: &str) -> String {
+        let download_dir = PathBuf::from("downloads");
+        let temp_cfg = temp::Cfg::new(PathBuf::from("tmp"), Box::new(|_| ()));
+        let download = DownloadCfg {
+            temp_cfg: &temp_cfg,
+            download_dir: &download_dir,
+            notify_handler: &|_| (),
+        };
+        toolchain_url(download, &ToolchainDesc::from_str(name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_toolchain_kind() {
+        let toolchains = [
+            ("stable", false),
+            ("leanprover/lean4:nightly", false),
+            ("leanprover/lean4:v4.1", false),
+            ("leanprover/lean4:v4.0.0", false),
+            ("leanprover/lean4:nightly-2023-01-02", false),
+            ("3.51.1", false),
+            ("local", true),
+            ("my-build", false),
+        ];
+        let only = |kind| -> Vec<&str> {
+            toolchains
+                .iter()
+                .filter(|&&(name, is_link)| ToolchainKind::of(name, is_link) == kind)
+                .map(|&(name, _)| name)
+                .collect()
+        };
+        assert_eq!(
+            only(ToolchainKind::Channel),
+            vec![
+                "stable",
+                "leanprover/lean4:nightly",
+                "leanprover/lean4:v4.1"
+            ]
+        );
+        assert_eq!(
+            only(ToolchainKind::Version),
+            vec![
+                "leanprover/lean4:v4.0.0",
+                "leanprover/lean4:nightly-2023-01-02",
+                "3.51.1"
+            ]
+        );
+        assert_eq!(only(ToolchainKind::Link), vec!["local"]);
+        assert_eq!(only(ToolchainKind::Custom), vec!["my-build"]);
+    }
+
+    #[test]
+    fn test_custom_origin() {
+        let desc = ToolchainDesc::from_str("myorg/lean4:v4.1.0-myfork").unwrap();
+        assert_eq!(desc.origin, Some("myorg/lean4".to_owned()));
+        assert_eq!(desc.channel, "v4.1.0-myfork");
+        assert_eq!(desc.date, None);
+        assert_eq!(desc.to_string(), "myorg-lean4-v4.1.0-myfork");
+
+        let desc = ToolchainDesc::from_str("my-org/lean4.fork_x:nightly-2023-01-02").unwrap();
+        assert_eq!(desc.origin, Some("my-org/lean4.fork_x".to_owned()));
+        assert_eq!(desc.channel, "nightly");
+        assert_eq!(desc.date, Some("2023-01-02".to_owned()));
+
+        assert!(ToolchainDesc::from_str("my_org/lean4:stable").is_err());
+        assert!(ToolchainDesc::from_str("myorg/lean4:v4 1").is_err());
+
+        assert_eq!(
+            tag_url("myorg/lean4:v4.1.0-myfork"),
+            "https://github.com/myorg/lean4/releases/tag/v4.1.0-myfork"
+        );
+        assert_eq!(
+            tag_url("myorg/lean4:4.1.0"),
+            "https://github.com/myorg/lean4/releases/tag/v4.1.0"
+        );
+        assert_eq!(
+            tag_url("my-org/lean4.fork_x:nightly-2023-01-02"),
+            "https://github.com/my-org/lean4.fork_x-nightly/releases/tag/nightly-2023-01-02"
+        );
+    }
+
+    #[test]
+    fn test_resolve_against_release_index() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let index = dir.path().join("releases.json");
+        fs::write(
+            &index,
+            r#"{
+                "leanprover-community/lean": {
+                    "latest": "v3.51.1",
+                    "published": { "v3.51.1": "2023-01-01T10:00:00Z" }
+                },
+                "leanprover-community/lean-nightly": {
+                    "latest": "nightly-2023-01-02",
+                    "published": { "nightly-2023-01-02": "2023-01-02T02:00:00Z" }
+                },
+                "leanprover/lean4": {
+                    "tags": ["v4.0.0", "v4.1.0", "v4.1.2", "v4.1.3-rc1"],
+                    "published": {
+                        "v4.1.2": "2023-10-01T10:00:00Z",
+                        "v4.1.3-rc1": "2023-10-05T10:00:00Z"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        // No network is used, as the test would otherwise fail here
+        utils::set_release_index(
+            utils::release_index_path(Url::from_file_path(&index).unwrap().as_ref()).unwrap(),
+        );
+
+        assert_eq!(
+            tag_url("stable"),
+            "https://github.com/leanprover-community/lean/releases/tag/v3.51.1"
+        );
+        assert_eq!(
+            tag_url("leanprover/lean4:v4.1"),
+            "https://github.com/leanprover/lean4/releases/tag/v4.1.2"
+        );
+        assert_eq!(
+            tag_url("nightly"),
+            "https://github.com/leanprover-community/lean-nightly/releases/tag/nightly-2023-01-02"
+        );
+
+        // The nightly is newer than the latest stable release
+        assert_eq!(
+            tag_url("latest"),
+            "https://github.com/leanprover-community/lean-nightly/releases/tag/nightly-2023-01-02"
+        );
+        // Prereleases count, and a track missing from the index is skipped
+        assert_eq!(
+            tag_url("leanprover/lean4:latest"),
+            "https://github.com/leanprover/lean4/releases/tag/v4.1.3-rc1"
+        );
+        assert!(ToolchainDesc::from_str("latest").unwrap().is_tracking());
+
+        // Repositories missing from the index are not looked up on GitHub
+        let missing = utils::fetch_latest_release_tag("myorg/lean4").unwrap_err();
+        assert!(missing
+            .to_string()
+            .contains("lists no releases of 'myorg/lean4'"));
+    }
+
+    #[test]
+    fn test_minor_series() {
+        let series = |name: &str| ToolchainDesc::from_str(name).unwrap().minor_series();
+        assert_eq!(series("leanprover/lean4:v4.1"), Some((4, 1)));
+        assert_eq!(series("4.12"), Some((4, 12)));
+        assert_eq!(series("v4.1.0"), None);
+        assert_eq!(series("v4.1-rc1"), None);
+        assert_eq!(series("stable"), None);
+        assert!(ToolchainDesc::from_str("v4.1").unwrap().is_tracking());
+        assert!(!ToolchainDesc::from_str("v4.1.0").unwrap().is_tracking());
+    }
+
+    #[test]
+    fn test_newer_release() {
+        let index = |desc: &ToolchainDesc| -> Result<String> {
+            let tag = match &*desc.channel {
+                "nightly" => "nightly-2023-02-01",
+                "stable" => "v4.0.0",
+                "v4.1" => "v4.1.3",
+                _ => panic!("only channels are resolved, not {}", desc),
+            };
+            Ok(format!(
+                "https://github.com/leanprover/lean4/releases/tag/{}",
+                tag
+            ))
+        };
+        let outdated = |name: &str, installed_tag: &str| {
+            let installed = format!(
+                "https://github.com/leanprover/lean4/releases/tag/{}",
+                installed_tag
+            );
+            newer_release(&ToolchainDesc::from_str(name).unwrap(), &installed, index)
+                .unwrap()
+                .map(|url| release_tag(&url).to_owned())
+        };
+
+        assert_eq!(
+            outdated("nightly", "nightly-2023-01-01"),
+            Some("nightly-2023-02-01".to_owned())
+        );
+        assert_eq!(outdated("stable", "v4.0.0"), None);
+        assert_eq!(
+            outdated("leanprover/lean4:v4.1", "v4.1.0"),
+            Some("v4.1.3".to_owned())
+        );
+        assert_eq!(outdated("4.0.0", "v4.0.0"), None);
+        assert_eq!(outdated("nightly-2023-01-01", "nightly-2023-01-01"), None);
+    }
+
+    #[test]
+    fn test_release_to_install() {
+        let release =
+            |tag: &str| format!("https://github.com/leanprover/lean4/releases/tag/{}", tag);
+        let latest = std::cell::RefCell::new(release("nightly-2023-02-01"));
+        let lookups = std::cell::Cell::new(0);
+        let to_install = |name: &str, is_installed: bool, installed: Option<&str>| {
+            release_to_install(
+                &ToolchainDesc::from_str(name).unwrap(),
+                is_installed,
+                installed,
+                |_| {
+                    lookups.set(lookups.get() + 1);
+                    Ok(latest.borrow().clone())
+                },
+            )
+            .unwrap()
+        };
+
+        // A first install, and a second one while the nightly is unchanged
+        let installed = to_install("nightly", false, None).unwrap();
+        assert_eq!(installed, release("nightly-2023-02-01"));
+        assert_eq!(to_install("nightly", true, Some(&installed)), None);
+        assert_eq!(lookups.get(), 2);
+
+        // The nightly has advanced
+        *latest.borrow_mut() = release("nightly-2023-02-02");
+        assert_eq!(
+            to_install("nightly", true, Some(&installed)),
+            Some(release("nightly-2023-02-02"))
+        );
+        // Nothing is known about what was installed
+        assert_eq!(
+            to_install("nightly", true, None),
+            Some(release("nightly-2023-02-02"))
+        );
+
+        // Exact versions are not looked up once installed
+        assert_eq!(to_install("nightly-2023-01-01", true, None), None);
+        assert_eq!(lookups.get(), 4);
+    }
+
+    #[test]
+    fn test_is_up_to_date() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let prefix = dir.path().join("toolchain");
+        let update_hash = dir.path().join("update-hash");
+        let release =
+            |tag: &str| format!("https://github.com/leanprover/lean4/releases/tag/{}", tag);
+        let up_to_date = |name: &str, url: &str| {
+            is_up_to_date(
+                &ToolchainDesc::from_str(name).unwrap(),
+                &prefix,
+                &update_hash,
+                url,
+            )
+        };
+
+        // Nothing installed yet
+        assert!(!up_to_date("nightly", &release("nightly-2023-02-01")));
+        assert!(!up_to_date("4.0.0", &release("v4.0.0")));
+
+        fs::create_dir(&prefix).unwrap();
+        fs::write(&update_hash, release("nightly-2023-01-01")).unwrap();
+
+        // A channel present but stale is updated
+        assert!(!up_to_date("nightly", &release("nightly-2023-02-01")));
+        assert!(up_to_date("nightly", &release("nightly-2023-01-01")));
+
+        // An exact version that is present is left alone, even without a
+        // matching update hash
+        assert!(up_to_date("4.0.0", &release("v4.0.0")));
+        fs::remove_file(&update_hash).unwrap();
+        assert!(up_to_date(
+            "nightly-2023-01-01",
+            &release("nightly-2023-01-01")
+        ));
+        assert!(!up_to_date("stable", &release("v4.0.0")));
+    }
+
+    #[test]
+    fn test_install_from_manifest() {
+        use component::TarGzPackage;
+        use download::file_hash;
+
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin").join("lean"), "lean").unwrap();
+        let host = manifestation::host_informal_target();
+        let archive_name = format!("lean-1.0.0-{}.tar.gz", host);
+        TarGzPackage::pack_dir(&src, "lean-1.0.0", &dir.path().join(&archive_name)).unwrap();
+        let sha256 = file_hash(&dir.path().join(&archive_name)).unwrap();
+
+        let manifest_path = dir.path().join("manifest.json");
+        let write_manifest = |sha256: &str| {
+            fs::write(
+                &manifest_path,
+                format!(
+                    r#"[{{ "name": "myorg/lean4:v1.0.0", "target": "{}", "url": "{}", "sha256": "{}" }}]"#,
+                    host, archive_name, sha256
+                ),
+            )
+            .unwrap();
+        };
+        let download_dir = dir.path().join("downloads");
+        let temp_cfg = temp::Cfg::new(dir.path().join("tmp"), Box::new(|_| ()));
+        let download = DownloadCfg {
+            temp_cfg: &temp_cfg,
+            download_dir: &download_dir,
+            notify_handler: &|_| (),
+        };
+        let toolchain = ToolchainDesc::from_str("myorg/lean4:v1.0.0").unwrap();
+        let install = |prefix: &Path| {
+            let manifest = ReleaseManifest::load(manifest_path.to_str().unwrap(), &download)?;
+            let prefix = InstallPrefix::from(prefix.to_owned());
+            update_from_manifest(&manifest, download, None, &toolchain, &prefix, None, None)
+        };
+
+        write_manifest(&"0".repeat(64));
+        let bad = dir.path().join("bad");
+        match install(&bad).unwrap_err().0 {
+            ErrorKind::ChecksumFailed { calculated, .. } => assert_eq!(calculated, sha256),
+            k => panic!("unexpected error: {}", k),
+        }
+        assert!(!bad.exists());
+
+        write_manifest(&sha256.to_uppercase());
+        let good = dir.path().join("good");
+        let url = install(&good).unwrap().unwrap();
+        assert_eq!(
+            url,
+            Url::from_file_path(dir.path().join(&archive_name))
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            fs::read_to_string(good.join("bin").join("lean")).unwrap(),
+            "lean"
+        );
+    }
+}
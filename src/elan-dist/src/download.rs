@@ -8,9 +8,92 @@ use url::Url;
 use std::fs;
 use std::ops;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 const _UPDATE_HASH_LEN: usize = 20;
 
+/// How long after it was last written to a partial download is still
+/// resumed, rather than discarded as left behind by a crash
+pub const PARTIAL_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const PARTIAL_EXT: &str = ".partial";
+
+/// Whether the stale partial downloads have been pruned by this process
+static PARTIAL_DOWNLOADS_PRUNED: AtomicBool = AtomicBool::new(false);
+
+/// The file next to a partial download recording the URL it is of
+fn partial_url_file(partial: &Path) -> PathBuf {
+    let mut name = partial.as_os_str().to_owned();
+    name.push(".url");
+    PathBuf::from(name)
+}
+
+/// Whether `path` was last written to more than `max_age` ago, or when is
+/// unknown
+fn is_older_than(path: &Path, max_age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .is_none_or(|age| age > max_age)
+}
+
+/// Whether the partial download at `partial` must not be resumed for
+/// `url`, as it is older than `max_age` or was started for another URL
+pub fn is_stale_partial(partial: &Path, url: &Url, max_age: Duration) -> bool {
+    let recorded = fs::read_to_string(partial_url_file(partial)).ok();
+    is_older_than(partial, max_age) || recorded.as_deref() != Some(url.as_str())
+}
+
+fn remove_partial(partial: &Path) -> Result<()> {
+    let url_file = partial_url_file(partial);
+    if url_file.exists() {
+        fs::remove_file(&url_file).chain_err(|| "cleaning up partial download")?;
+    }
+    if partial.exists() {
+        fs::remove_file(partial).chain_err(|| "cleaning up partial download")?;
+    }
+    Ok(())
+}
+
+/// Removes the partial downloads in `download_dir` last written to more
+/// than `max_age` ago, or all of them without `max_age`, returning their
+/// paths
+pub fn prune_partial_downloads(
+    download_dir: &Path,
+    max_age: Option<Duration>,
+) -> Result<Vec<PathBuf>> {
+    let mut pruned = Vec::new();
+    if !utils::is_directory(download_dir) {
+        return Ok(pruned);
+    }
+    let entries = fs::read_dir(download_dir).chain_err(|| "reading download directory")?;
+    for entry in entries {
+        let path = entry.chain_err(|| "reading download directory")?.path();
+        let name = path.to_string_lossy().into_owned();
+        if !path.exists() {
+            // Removed along with its partial download
+            continue;
+        }
+        if name.ends_with(".partial.url") {
+            // Left behind when the download itself was removed
+            if !Path::new(&name[..name.len() - 4]).exists() {
+                fs::remove_file(&path).chain_err(|| "cleaning up partial download")?;
+            }
+            continue;
+        }
+        if !name.ends_with(PARTIAL_EXT) {
+            continue;
+        }
+        if max_age.is_none_or(|max_age| is_older_than(&path, max_age)) {
+            remove_partial(&path)?;
+            pruned.push(path);
+        }
+    }
+    Ok(pruned)
+}
+
 #[derive(Copy, Clone)]
 pub struct DownloadCfg<'a> {
     pub temp_cfg: &'a temp::Cfg,
@@ -44,10 +127,17 @@ impl<'a> DownloadCfg<'a> {
         if target_file.exists() {
             let cached_result = file_hash(&target_file)?;
             if hash == cached_result {
+                debug!("using cached download '{}'", target_file.display());
                 (self.notify_handler)(Notification::FileAlreadyDownloaded);
                 (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
                 return Ok(File { path: target_file });
             } else {
+                debug!(
+                    "cached download '{}' has checksum {}, expected {}",
+                    target_file.display(),
+                    cached_result,
+                    hash
+                );
                 (self.notify_handler)(Notification::CachedFileChecksumFailed);
                 fs::remove_file(&target_file).chain_err(|| "cleaning up previous download")?;
             }
@@ -59,9 +149,31 @@ impl<'a> DownloadCfg<'a> {
                 .map(|s| s.to_str().unwrap_or("_"))
                 .unwrap_or("_")
                 .to_owned()
-                + ".partial",
+                + PARTIAL_EXT,
         );
 
+        // Resuming a partial download is only safe if it is of the same
+        // file, so one without a matching record is started over. Others are
+        // pruned by the first download of a command, and failing to doesn't
+        // keep this one from going ahead.
+        if !PARTIAL_DOWNLOADS_PRUNED.swap(true, Ordering::Relaxed) {
+            if let Err(e) = prune_partial_downloads(self.download_dir, Some(PARTIAL_MAX_AGE)) {
+                (self.notify_handler)(Notification::CantPrunePartialDownloads(&e));
+            }
+        }
+        if partial_file_path.exists() && is_stale_partial(&partial_file_path, url, PARTIAL_MAX_AGE)
+        {
+            (self.notify_handler)(Notification::DiscardingStalePartialDownload(
+                &partial_file_path,
+            ));
+            remove_partial(&partial_file_path)?;
+        }
+        utils::write_file(
+            "partial download url",
+            &partial_url_file(&partial_file_path),
+            url.as_str(),
+        )?;
+
         let mut hasher = Sha256::new();
 
         utils::download_file_with_resume(
@@ -85,6 +197,7 @@ impl<'a> DownloadCfg<'a> {
         } else {
             (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
             fs::rename(&partial_file_path, &target_file)?;
+            let _ = fs::remove_file(partial_url_file(&partial_file_path));
             return Ok(File { path: target_file });
         }
     }
@@ -109,7 +222,7 @@ impl<'a> DownloadCfg<'a> {
     }
 }
 
-fn file_hash(path: &Path) -> Result<String> {
+pub fn file_hash(path: &Path) -> Result<String> {
     let mut hasher = Sha256::new();
     use std::io::Read;
     let mut downloaded = fs::File::open(&path).chain_err(|| "opening already downloaded file")?;
@@ -127,3 +240,75 @@ fn file_hash(path: &Path) -> Result<String> {
 
     Ok(format!("{:x}", hasher.finalize()))
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate filetime;
+
+    use self::filetime::FileTime;
+    use super::*;
+    use std::time::SystemTime;
+    use tempfile;
+
+    #[test]
+    fn test_stale_partial_is_discarded() {
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("lean.tar.gz");
+        fs::write(&source, "lean archive").unwrap();
+        let url = Url::from_file_path(&source).unwrap();
+        let hash = file_hash(&source).unwrap();
+
+        let download_dir = root.path().join("downloads");
+        fs::create_dir_all(&download_dir).unwrap();
+        let partial = download_dir.join(format!("{}{}", hash, PARTIAL_EXT));
+        let temp_cfg = temp::Cfg::new(root.path().join("tmp"), Box::new(|_| {}));
+        let cfg = DownloadCfg {
+            temp_cfg: &temp_cfg,
+            download_dir: &download_dir,
+            notify_handler: &|_| {},
+        };
+
+        // Resuming either would not add up to the checksum
+        fs::write(&partial, "junk").unwrap();
+        assert!(is_stale_partial(&partial, &url, PARTIAL_MAX_AGE));
+        let file = cfg.download(&url, &hash).unwrap();
+        assert_eq!(fs::read_to_string(&*file).unwrap(), "lean archive");
+        fs::remove_file(&*file).unwrap();
+
+        fs::write(&partial, "junk").unwrap();
+        fs::write(partial_url_file(&partial), url.as_str()).unwrap();
+        assert!(!is_stale_partial(&partial, &url, PARTIAL_MAX_AGE));
+        let week_ago = SystemTime::now() - PARTIAL_MAX_AGE - Duration::from_secs(60);
+        filetime::set_file_mtime(&partial, FileTime::from_system_time(week_ago)).unwrap();
+        assert!(is_stale_partial(&partial, &url, PARTIAL_MAX_AGE));
+        let file = cfg.download(&url, &hash).unwrap();
+        assert_eq!(fs::read_to_string(&*file).unwrap(), "lean archive");
+        assert!(!partial.exists());
+        assert!(!partial_url_file(&partial).exists());
+    }
+
+    #[test]
+    fn test_prune_partial_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join(format!("old{}", PARTIAL_EXT));
+        let recent = dir.path().join(format!("recent{}", PARTIAL_EXT));
+        let complete = dir.path().join("complete");
+        for path in &[&old, &recent, &complete] {
+            fs::write(path, "").unwrap();
+        }
+        fs::write(partial_url_file(&old), "").unwrap();
+        fs::write(dir.path().join(format!("orphan{}.url", PARTIAL_EXT)), "").unwrap();
+        let week_ago = SystemTime::now() - PARTIAL_MAX_AGE - Duration::from_secs(60);
+        filetime::set_file_mtime(&old, FileTime::from_system_time(week_ago)).unwrap();
+
+        let pruned = prune_partial_downloads(dir.path(), Some(PARTIAL_MAX_AGE)).unwrap();
+        assert_eq!(pruned, vec![old.clone()]);
+        assert!(!partial_url_file(&old).exists());
+        assert!(recent.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        let pruned = prune_partial_downloads(dir.path(), None).unwrap();
+        assert_eq!(pruned, vec![recent]);
+        assert!(complete.exists());
+    }
+}
@@ -30,6 +30,54 @@ error_chain! {
                     expected,
                     calculated)
         }
+        LockedReleaseMismatch {
+            toolchain: String,
+            locked: String,
+            resolved: String,
+        } {
+            description("toolchain resolved to a different release than locked")
+            display("toolchain '{}' is locked to '{}' but resolved to '{}'",
+                    toolchain,
+                    locked,
+                    resolved)
+        }
+        InstallLocked(path: PathBuf) {
+            description("could not lock the toolchain directory")
+            display("could not take the install lock '{}'; another elan may be installing \
+                     the same toolchain", path.display())
+        }
+        InvalidReleaseManifest(location: String) {
+            description("invalid release manifest")
+            display("invalid release manifest '{}'", location)
+        }
+        NotInReleaseManifest {
+            toolchain: String,
+            location: String,
+        } {
+            description("toolchain missing from release manifest")
+            display("the release manifest '{}' does not list the toolchain '{}'", location, toolchain)
+        }
+        UnsupportedTarget(t: String) {
+            description("unsupported target")
+            display("no toolchains are provided for target '{}'", t)
+        }
+        UnsupportedPlatform {
+            toolchain: String,
+            target: String,
+            available: Vec<String>,
+        } {
+            description("toolchain not available for the platform")
+            display("{}", unsupported_platform_msg(toolchain, target, available))
+        }
+        UnknownVariant {
+            toolchain: String,
+            target: String,
+            variant: String,
+            available: Vec<String>,
+        } {
+            description("toolchain variant not available")
+            display("{}", unknown_variant_msg(toolchain, target, variant, available))
+        }
         ComponentConflict {
             name: String,
             path: PathBuf,
@@ -64,6 +112,27 @@ error_chain! {
         ExtractingPackage {
             description("failed to extract package")
         }
+        UnsafeArchiveEntry {
+            name: String,
+        } {
+            description("archive entry would be extracted outside of the destination")
+            display("refusing to extract archive entry '{}' outside of the destination", name)
+        }
+        CreatingPackage {
+            description("failed to create package")
+        }
+        RecordingInstalledFiles {
+            description("failed to record the installed files")
+        }
+        UnrelocatableLink {
+            name: String,
+            target: PathBuf,
+        } {
+            description("symlink points outside of the directory being packaged")
+            display("refusing to package symlink '{}', which points outside of the toolchain to '{}'",
+                    name,
+                    target.display())
+        }
         BadInstallerVersion(v: String) {
             description("unsupported installer version")
             display("unsupported installer version: {}", v)
@@ -104,6 +173,35 @@ error_chain! {
     }
 }
 
+fn unsupported_platform_msg(toolchain: &str, target: &str, available: &[String]) -> String {
+    let others = if available.is_empty() {
+        "nor for any other platform".to_owned()
+    } else {
+        format!("only for: {}", available.join(", "))
+    };
+    format!(
+        "toolchain '{}' is not available for platform '{}', {}",
+        toolchain, target, others
+    )
+}
+
+fn unknown_variant_msg(
+    toolchain: &str,
+    target: &str,
+    variant: &str,
+    available: &[String],
+) -> String {
+    let others = if available.is_empty() {
+        "which has none".to_owned()
+    } else {
+        format!("only: {}", available.join(", "))
+    };
+    format!(
+        "toolchain '{}' has no variant '{}' for platform '{}', {}",
+        toolchain, variant, target, others
+    )
+}
+
 fn component_unavailable_msg(cs: &[Component]) -> String {
     assert!(!cs.is_empty());
 
@@ -0,0 +1,234 @@
+//! `elan-files.toml`, recording the files of a toolchain when it is installed
+//! so that it can later be checked for missing or modified files, e.g.
+//!
+//! ```toml
+//! version = "1"
+//! files = ["bin/lean", "lib/lean/Init.olean"]
+//!
+//! [sha256]
+//! "bin/lean" = "..."
+//! ```
+//!
+//! Only the binaries in `bin` are hashed, as hashing the whole library of a
+//! toolchain would noticeably slow down installs.
+
+use toml;
+
+use download::file_hash;
+use elan_utils::toml_utils::*;
+use elan_utils::utils;
+use errors::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::path::{Component, Path};
+
+use walkdir::WalkDir;
+
+pub const INSTALLED_FILES_NAME: &str = "elan-files.toml";
+pub const SUPPORTED_INSTALLED_FILES_VERSIONS: [&str; 1] = ["1"];
+pub const DEFAULT_INSTALLED_FILES_VERSION: &str = "1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstalledFiles {
+    pub version: String,
+    /// Paths relative to the toolchain directory, separated by `/`
+    pub files: BTreeSet<String>,
+    /// SHA-256 of the files that are hashed, keyed by path
+    pub sha256: BTreeMap<String, String>,
+}
+
+/// Something wrong with a file of an installed toolchain
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileProblem {
+    Missing(String),
+    Modified(String),
+}
+
+impl Display for FileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileProblem::Missing(ref path) => write!(f, "missing: {}", path),
+            FileProblem::Modified(ref path) => write!(f, "modified: {}", path),
+        }
+    }
+}
+
+impl InstalledFiles {
+    /// Records the files currently in the toolchain directory `prefix`
+    pub fn record(prefix: &Path) -> Result<Self> {
+        let mut files = BTreeSet::new();
+        let mut sha256 = BTreeMap::new();
+        for entry in WalkDir::new(prefix).min_depth(1) {
+            let entry = entry.chain_err(|| ErrorKind::RecordingInstalledFiles)?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relpath = entry
+                .path()
+                .strip_prefix(prefix)
+                .expect("walkdir entry outside of its root");
+            let name = Self::key(relpath);
+            if name == INSTALLED_FILES_NAME {
+                continue;
+            }
+            if Self::is_hashed(relpath) && entry.file_type().is_file() {
+                sha256.insert(name.clone(), file_hash(entry.path())?);
+            }
+            files.insert(name);
+        }
+        Ok(InstalledFiles {
+            version: DEFAULT_INSTALLED_FILES_VERSION.to_owned(),
+            files,
+            sha256,
+        })
+    }
+
+    /// The problems with the files in `prefix`, compared with when they were
+    /// recorded. Files added since are not a problem.
+    pub fn verify(&self, prefix: &Path) -> Result<Vec<FileProblem>> {
+        let mut problems = Vec::new();
+        for name in &self.files {
+            let path = prefix.join(name);
+            if path.symlink_metadata().is_err() {
+                problems.push(FileProblem::Missing(name.clone()));
+            } else if let Some(expected) = self.sha256.get(name) {
+                if file_hash(&path)? != *expected {
+                    problems.push(FileProblem::Modified(name.clone()));
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+    fn key(relpath: &Path) -> String {
+        let components: Vec<_> = relpath
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        components.join("/")
+    }
+
+    fn is_hashed(relpath: &Path) -> bool {
+        relpath.components().next() == Some(Component::Normal("bin".as_ref()))
+    }
+
+    /// Reads the record at `path`, or `None` if there is none
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !utils::is_file(path) {
+            return Ok(None);
+        }
+        let data = utils::read_file("installed files", path)?;
+        Self::parse(&data)
+            .map(Some)
+            .chain_err(|| format!("failed to parse '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Ok(utils::write_file(
+            "installed files",
+            path,
+            &self.clone().stringify(),
+        )?)
+    }
+
+    pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let version = get_string(&mut table, "version", path)?;
+        if !SUPPORTED_INSTALLED_FILES_VERSIONS.contains(&&*version) {
+            return Err(ErrorKind::UnsupportedVersion(version).into());
+        }
+
+        let files = get_string_array(&mut table, "files", path)?
+            .into_iter()
+            .collect();
+
+        let mut sha256 = BTreeMap::new();
+        let mut sha256_table = get_table(&mut table, "sha256", path)?;
+        let path = path.to_owned() + "sha256.";
+        let names: Vec<String> = sha256_table.keys().cloned().collect();
+        for name in names {
+            let hash = get_string(&mut sha256_table, &name, &path)?;
+            sha256.insert(name, hash);
+        }
+
+        Ok(InstalledFiles {
+            version,
+            files,
+            sha256,
+        })
+    }
+    pub fn to_toml(self) -> toml::value::Table {
+        let mut result = toml::value::Table::new();
+        result.insert("version".to_owned(), toml::Value::String(self.version));
+
+        let files = self.files.into_iter().map(toml::Value::String).collect();
+        result.insert("files".to_owned(), toml::Value::Array(files));
+
+        let mut sha256 = toml::value::Table::new();
+        for (name, hash) in self.sha256 {
+            sha256.insert(name, toml::Value::String(hash));
+        }
+        result.insert("sha256".to_owned(), toml::Value::Table(sha256));
+        result
+    }
+
+    pub fn parse(data: &str) -> Result<Self> {
+        let value = toml::from_str(data).map_err(ErrorKind::Parsing)?;
+        Self::from_toml(value, "")
+    }
+
+    pub fn stringify(self) -> String {
+        toml::Value::Table(self.to_toml()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile;
+
+    #[test]
+    fn test_tampered_binary_fails_verification() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let prefix = dir.path();
+        fs::create_dir_all(prefix.join("bin")).unwrap();
+        fs::create_dir_all(prefix.join("lib").join("lean")).unwrap();
+        fs::write(prefix.join("bin").join("lean"), "lean").unwrap();
+        fs::write(prefix.join("bin").join("lake"), "lake").unwrap();
+        fs::write(prefix.join("lib").join("lean").join("Init.olean"), "olean").unwrap();
+
+        let path = prefix.join(INSTALLED_FILES_NAME);
+        InstalledFiles::record(prefix).unwrap().save(&path).unwrap();
+        let recorded = InstalledFiles::load(&path).unwrap().unwrap();
+        assert_eq!(
+            recorded.files.iter().collect::<Vec<_>>(),
+            vec!["bin/lake", "bin/lean", "lib/lean/Init.olean"]
+        );
+        assert_eq!(recorded.sha256.len(), 2);
+        assert_eq!(recorded.verify(prefix).unwrap(), vec![]);
+
+        // New files are fine, as is the record itself
+        fs::write(prefix.join("lib").join("lean").join("Extra.olean"), "").unwrap();
+        assert_eq!(recorded.verify(prefix).unwrap(), vec![]);
+
+        fs::write(prefix.join("bin").join("lean"), "evil").unwrap();
+        fs::remove_file(prefix.join("lib").join("lean").join("Init.olean")).unwrap();
+        assert_eq!(
+            recorded.verify(prefix).unwrap(),
+            vec![
+                FileProblem::Modified("bin/lean".to_owned()),
+                FileProblem::Missing("lib/lean/Init.olean".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_record() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        assert_eq!(
+            InstalledFiles::load(&dir.path().join(INSTALLED_FILES_NAME)).unwrap(),
+            None
+        );
+    }
+}
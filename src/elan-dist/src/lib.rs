@@ -11,9 +11,14 @@ extern crate walkdir;
 #[macro_use]
 extern crate error_chain;
 extern crate json;
+#[macro_use]
+extern crate log;
 extern crate sha2;
 extern crate zip;
 
+#[cfg(test)]
+extern crate tempfile;
+
 #[cfg(not(windows))]
 extern crate libc;
 #[cfg(windows)]
@@ -21,8 +26,9 @@ extern crate winapi;
 #[cfg(windows)]
 extern crate winreg;
 
+pub use component::TarGzPackage;
 pub use errors::*;
-pub use notifications::Notification;
+pub use notifications::{InstallPhase, Notification};
 
 pub mod temp;
 
@@ -31,7 +37,10 @@ pub mod config;
 pub mod dist;
 pub mod download;
 pub mod errors;
+pub mod installed_files;
+pub mod lockfile;
 pub mod manifest;
 mod manifestation;
 pub mod notifications;
 pub mod prefix;
+pub mod release_manifest;
@@ -0,0 +1,239 @@
+//! `elan.lock`, pinning toolchains to the exact release they resolved to
+//! and the checksum of the archive that was installed, e.g.
+//!
+//! ```toml
+//! version = "1"
+//!
+//! [toolchains.stable]
+//! release = "https://github.com/leanprover-community/lean/releases/tag/v3.51.1"
+//! sha256 = "..."
+//! ```
+
+use toml;
+
+use elan_utils::toml_utils::*;
+use elan_utils::utils;
+use errors::*;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const LOCKFILE_NAME: &str = "elan.lock";
+pub const SUPPORTED_LOCKFILE_VERSIONS: [&str; 1] = ["1"];
+pub const DEFAULT_LOCKFILE_VERSION: &str = "1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockEntry {
+    /// The release page the toolchain resolved to
+    pub release: String,
+    /// SHA-256 of the installed archive
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Lockfile {
+    pub version: String,
+    /// Keyed by toolchain name
+    pub toolchains: BTreeMap<String, LockEntry>,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lockfile {
+    pub fn new() -> Self {
+        Lockfile {
+            version: DEFAULT_LOCKFILE_VERSION.to_owned(),
+            toolchains: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the lockfile at `path`, or starts an empty one if there is none
+    pub fn load(path: &Path) -> Result<Self> {
+        if !utils::is_file(path) {
+            return Ok(Self::new());
+        }
+        let data = utils::read_file("lockfile", path)?;
+        Self::parse(&data).chain_err(|| format!("failed to parse lockfile '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Ok(utils::write_file(
+            "lockfile",
+            path,
+            &self.clone().stringify(),
+        )?)
+    }
+
+    /// Fails if `toolchain` is locked to a release other than `release`
+    pub fn verify_release(&self, toolchain: &str, release: &str) -> Result<()> {
+        match self.toolchains.get(toolchain) {
+            Some(entry) if entry.release != release => Err(ErrorKind::LockedReleaseMismatch {
+                toolchain: toolchain.to_owned(),
+                locked: entry.release.clone(),
+                resolved: release.to_owned(),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// SHA-256 of the archive `toolchain` is locked to, if it is locked
+    pub fn locked_sha256(&self, toolchain: &str) -> Option<&str> {
+        self.toolchains.get(toolchain).map(|e| &*e.sha256)
+    }
+
+    /// Locks `toolchain` to an installed release, failing if it is already
+    /// locked to a different release or archive
+    pub fn record(&mut self, toolchain: &str, release: &str, sha256: &str) -> Result<()> {
+        self.verify_release(toolchain, release)?;
+        if let Some(entry) = self.toolchains.get(toolchain) {
+            if entry.sha256 != sha256 {
+                return Err(ErrorKind::ChecksumFailed {
+                    url: release.to_owned(),
+                    expected: entry.sha256.clone(),
+                    calculated: sha256.to_owned(),
+                }
+                .into());
+            }
+            return Ok(());
+        }
+        self.toolchains.insert(
+            toolchain.to_owned(),
+            LockEntry {
+                release: release.to_owned(),
+                sha256: sha256.to_owned(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let version = get_string(&mut table, "version", path)?;
+        if !SUPPORTED_LOCKFILE_VERSIONS.contains(&&*version) {
+            return Err(ErrorKind::UnsupportedVersion(version).into());
+        }
+
+        let mut toolchains = BTreeMap::new();
+        let toolchains_table = get_table(&mut table, "toolchains", path)?;
+        let path = path.to_owned() + "toolchains.";
+        for (name, value) in toolchains_table {
+            let path = format!("{}{}.", path, name);
+            if let toml::Value::Table(mut t) = value {
+                let entry = LockEntry {
+                    release: get_string(&mut t, "release", &path)?,
+                    sha256: get_string(&mut t, "sha256", &path)?,
+                };
+                toolchains.insert(name, entry);
+            }
+        }
+
+        Ok(Lockfile {
+            version,
+            toolchains,
+        })
+    }
+    pub fn to_toml(self) -> toml::value::Table {
+        let mut result = toml::value::Table::new();
+        result.insert("version".to_owned(), toml::Value::String(self.version));
+
+        let mut toolchains = toml::value::Table::new();
+        for (name, entry) in self.toolchains {
+            let mut t = toml::value::Table::new();
+            t.insert("release".to_owned(), toml::Value::String(entry.release));
+            t.insert("sha256".to_owned(), toml::Value::String(entry.sha256));
+            toolchains.insert(name, toml::Value::Table(t));
+        }
+        result.insert("toolchains".to_owned(), toml::Value::Table(toolchains));
+        result
+    }
+
+    pub fn parse(data: &str) -> Result<Self> {
+        let value = toml::from_str(data).map_err(ErrorKind::Parsing)?;
+        Self::from_toml(value, "")
+    }
+
+    pub fn stringify(self) -> String {
+        toml::Value::Table(self.to_toml()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile;
+
+    const OLD: &str = "https://github.com/leanprover-community/lean/releases/tag/v3.50.0";
+    const NEW: &str = "https://github.com/leanprover-community/lean/releases/tag/v3.51.1";
+
+    #[test]
+    fn test_record_and_reload() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let path = dir.path().join(LOCKFILE_NAME);
+
+        let mut lock = Lockfile::load(&path).unwrap();
+        assert!(lock.toolchains.is_empty());
+        lock.record("stable", OLD, "abc").unwrap();
+        lock.save(&path).unwrap();
+
+        let mut lock = Lockfile::load(&path).unwrap();
+        assert_eq!(
+            lock.toolchains.get("stable"),
+            Some(&LockEntry {
+                release: OLD.to_owned(),
+                sha256: "abc".to_owned(),
+            })
+        );
+        // Reinstalling the locked release is fine
+        lock.verify_release("stable", OLD).unwrap();
+        assert_eq!(lock.locked_sha256("stable"), Some("abc"));
+        assert_eq!(lock.locked_sha256("nightly"), None);
+        lock.record("stable", OLD, "abc").unwrap();
+    }
+
+    #[test]
+    fn test_changed_resolution_fails() {
+        let mut lock = Lockfile::new();
+        lock.record("stable", OLD, "abc").unwrap();
+
+        // The channel has since moved on
+        match lock.verify_release("stable", NEW).unwrap_err().0 {
+            ErrorKind::LockedReleaseMismatch {
+                locked, resolved, ..
+            } => {
+                assert_eq!(locked, OLD);
+                assert_eq!(resolved, NEW);
+            }
+            k => panic!("unexpected error: {}", k),
+        }
+        assert!(lock.record("stable", NEW, "def").is_err());
+
+        // The release was re-uploaded with a different archive
+        match lock.record("stable", OLD, "def").unwrap_err().0 {
+            ErrorKind::ChecksumFailed { .. } => {}
+            k => panic!("unexpected error: {}", k),
+        }
+
+        // Other toolchains are unaffected
+        lock.verify_release("nightly", NEW).unwrap();
+    }
+
+    #[test]
+    fn test_type_errors_name_the_key() {
+        let message = |data: &str| match Lockfile::parse(data).unwrap_err().0 {
+            ErrorKind::Utils(e) => e.to_string(),
+            k => panic!("unexpected error: {}", k),
+        };
+        assert_eq!(
+            message("version = \"1\"\ntoolchains = 1\n"),
+            "expected type: 'table' for 'toolchains'"
+        );
+        assert_eq!(
+            message("version = \"1\"\n[toolchains.stable]\nrelease = \"r\"\nsha256 = 1\n"),
+            "expected type: 'string' for 'toolchains.stable.sha256'"
+        );
+    }
+}
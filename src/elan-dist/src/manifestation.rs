@@ -1,13 +1,66 @@
 //! Manifest a particular Lean version by installing it from a distribution server.
 
 use component::{TarGzPackage, TarZstdPackage, ZipPackage};
-use download::DownloadCfg;
-use elan_utils::utils;
+use download::{file_hash, DownloadCfg};
+use elan_utils::{self, utils};
 use errors::*;
+use installed_files::{InstalledFiles, INSTALLED_FILES_NAME};
 use notifications::*;
 use prefix::InstallPrefix;
 use temp;
 
+use elan_utils::raw;
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Toolchains are extracted next to their directory, to a directory named
+/// `.<toolchain>.extract-<elan version>-<pid>`, and only moved into place
+/// once complete
+const EXTRACT_DIR_INFIX: &str = ".extract-";
+/// How long to wait for another install of the same toolchain to finish
+/// cleaning up or moving its files into place
+const INSTALL_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where installer archives are kept once installed, so that they can be
+/// installed again without downloading them or be redistributed
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveCache {
+    /// Holds a directory per release, such as `leanprover/lean4/v4.0.0`
+    pub dir: PathBuf,
+    /// Move each downloaded archive here instead of deleting it
+    pub keep: bool,
+    /// Install from an archive here instead of downloading one if there is
+    /// one for the release, platform and variant
+    pub prefer: bool,
+}
+
+impl ArchiveCache {
+    /// Where the archives of the release page `release` of the GitHub
+    /// repository `origin` are kept
+    pub fn release_dir(&self, origin: &str, release: &str) -> PathBuf {
+        self.dir
+            .join(origin)
+            .join(release.rsplit('/').next().unwrap_or(release))
+    }
+}
+
+static ARCHIVE_CACHE: Mutex<Option<ArchiveCache>> = Mutex::new(None);
+
+/// Sets whether and where later installs keep their archives and look for
+/// kept ones, as for `elan toolchain install --keep-download`
+pub fn set_archive_cache(cache: Option<ArchiveCache>) {
+    *ARCHIVE_CACHE.lock().unwrap() = cache;
+}
+
+fn archive_cache() -> Option<ArchiveCache> {
+    ARCHIVE_CACHE.lock().unwrap().clone()
+}
+
 #[derive(Debug)]
 pub struct Manifestation {
     prefix: InstallPrefix,
@@ -19,13 +72,40 @@ impl Manifestation {
     }
 
     /// Installation using the legacy v1 manifest format
+    ///
+    /// Returns the SHA-256 of the installed archive, which must be the
+    /// installer's `expected` if given before anything is extracted.
     pub fn update(
         &self,
-        origin: &String,
-        url: &String,
+        toolchain: &str,
+        origin: &str,
+        url: &str,
+        installer: &Installer,
         temp_cfg: &temp::Cfg,
         notify_handler: &dyn Fn(Notification),
-    ) -> Result<()> {
+    ) -> Result<String> {
+        let Installer {
+            informal_target,
+            variant,
+            expected,
+        } = *installer;
+        let cache = archive_cache();
+        if let Some(cache) = cache.as_ref().filter(|c| c.prefer) {
+            let dir = cache.release_dir(origin, url);
+            let installed = self.install_cached(
+                &dir,
+                informal_target,
+                variant,
+                expected,
+                temp_cfg,
+                notify_handler,
+            )?;
+            if let Some(hash) = installed {
+                return Ok(hash);
+            }
+        }
+        let keep_in = cache.filter(|c| c.keep).map(|c| c.release_dir(origin, url));
+
         notify_handler(Notification::DownloadingComponent("lean"));
 
         use std::path::PathBuf;
@@ -36,71 +116,963 @@ impl Manifestation {
             notify_handler: notify_handler,
         };
 
-        // find correct download on HTML page (AAAAH)
-        use regex::Regex;
-        use std::fs;
-        use std::io::Read;
-        let informal_target = if cfg!(target_os = "windows") {
-            "windows"
-        } else if cfg!(target_os = "linux") {
-            "linux"
-        } else if cfg!(target_os = "macos") {
-            "darwin"
-        } else {
-            unreachable!()
+        let now = Instant::now();
+        let url = installer_url(&dlcfg, toolchain, origin, url, informal_target, variant)?;
+        notify_handler(Notification::PhaseTimed(
+            InstallPhase::Resolving,
+            now.elapsed(),
+        ));
+        debug!("installer for '{}' is '{}'", informal_target, url);
+
+        self.install_installer(&url, expected, keep_in.as_deref(), temp_cfg, notify_handler)
+    }
+
+    /// Installs the archive at `url`, failing before anything is installed
+    /// unless its SHA-256 is `sha256`
+    pub fn install_verified(
+        &self,
+        url: &str,
+        sha256: &str,
+        temp_cfg: &temp::Cfg,
+        notify_handler: &dyn Fn(Notification),
+    ) -> Result<String> {
+        notify_handler(Notification::DownloadingComponent("lean"));
+        self.install_installer(url, Some(sha256), None, temp_cfg, notify_handler)
+    }
+
+    /// Installs the `informal_target` archive of `variant` kept in the
+    /// release directory `dir` of an archive cache, if there is one
+    fn install_cached(
+        &self,
+        dir: &Path,
+        informal_target: &str,
+        variant: Option<&str>,
+        expected: Option<&str>,
+        temp_cfg: &temp::Cfg,
+        notify_handler: &dyn Fn(Notification),
+    ) -> Result<Option<String>> {
+        let archive = match cached_archive(dir, informal_target, variant)? {
+            Some(archive) => archive,
+            None => return Ok(None),
+        };
+        notify_handler(Notification::InstallingFromCache(&archive));
+        let url = Url::from_file_path(&archive)
+            .map_err(|()| format!("invalid archive path '{}'", archive.display()))?;
+        self.install_installer(url.as_str(), expected, None, temp_cfg, notify_handler)
+            .map(Some)
+    }
+
+    /// Downloads the installer at `url`, checking it has the SHA-256
+    /// `expected` if given, and extracts it into the prefix, timing each
+    /// step, then moves it to `keep_in` if given
+    fn install_installer(
+        &self,
+        url: &str,
+        expected: Option<&str>,
+        keep_in: Option<&Path>,
+        temp_cfg: &temp::Cfg,
+        notify_handler: &dyn Fn(Notification),
+    ) -> Result<String> {
+        use std::path::PathBuf;
+        let dld_dir = PathBuf::from("bogus");
+
+        // The first response from the server ends the connecting phase
+        let now = Instant::now();
+        let connected = Cell::new(None);
+        let timing_handler = |n: Notification| {
+            if let Notification::Utils(elan_utils::Notification::DownloadContentLengthReceived(_))
+            | Notification::Utils(elan_utils::Notification::DownloadDataReceived(_)) = n
+            {
+                if connected.get().is_none() {
+                    let elapsed = now.elapsed();
+                    connected.set(Some(elapsed));
+                    notify_handler(Notification::PhaseTimed(InstallPhase::Connecting, elapsed));
+                }
+            }
+            notify_handler(n)
         };
-        let informal_target = informal_target.to_owned();
-        let informal_target = if cfg!(target_arch = "x86_64") {
-            informal_target
-        } else if cfg!(target_arch = "aarch64") {
-            informal_target + "_aarch64"
-        } else {
-            unreachable!();
+        let dlcfg = DownloadCfg {
+            download_dir: &dld_dir,
+            temp_cfg,
+            notify_handler: &timing_handler,
         };
-        let url_substring = informal_target.clone() + ".";
-        let re = Regex::new(format!(r#"/{}/releases/download/[^"]+"#, origin).as_str()).unwrap();
-        let download_page_file = dlcfg.download_and_check(&url)?;
-        let mut html = String::new();
-        fs::File::open(&download_page_file as &::std::path::Path)?.read_to_string(&mut html)?;
-        let url = re
-            .find_iter(&html)
-            .map(|m| m.as_str().to_string())
-            .find(|m| m.contains(&url_substring));
-        if url.is_none() {
-            return Err(
-                format!("binary package was not provided for '{}'", informal_target).into(),
-            );
-        }
-        let url = format!("https://github.com/{}", url.unwrap());
-
-        let installer_file = dlcfg.download_and_check(&url)?;
+        let installer_file = dlcfg.download_and_check(url)?;
+        let connecting = connected.get().unwrap_or_default();
+        notify_handler(Notification::PhaseTimed(
+            InstallPhase::Downloading,
+            now.elapsed() - connecting,
+        ));
+
+        let now = Instant::now();
+        let installer_hash = file_hash(&installer_file)?;
+        notify_handler(Notification::PhaseTimed(
+            InstallPhase::Verifying,
+            now.elapsed(),
+        ));
+        debug!("installer has checksum {}", installer_hash);
+        if let Some(expected) = expected {
+            if installer_hash != expected {
+                return Err(ErrorKind::ChecksumFailed {
+                    url: url.to_owned(),
+                    expected: expected.to_owned(),
+                    calculated: installer_hash,
+                }
+                .into());
+            }
+            notify_handler(Notification::ChecksumValid(url));
+        }
+
+        // Keep any installed files if cancelled while downloading
+        utils::check_cancelled()?;
 
         let prefix = self.prefix.path();
 
         notify_handler(Notification::InstallingComponent("lean"));
 
-        // Remove old files
+        let extract_dir = extract_dir(prefix, process::id());
+        utils::ensure_dir_exists("extraction directory", &extract_dir, &|n| {
+            (notify_handler)(n.into())
+        })?;
+        let lock = || {
+            lock_install(prefix).inspect_err(|_| {
+                let _ = raw::remove_dir(&extract_dir);
+            })
+        };
+        {
+            let _lock = lock()?;
+            remove_orphaned_extractions(prefix, notify_handler)?;
+        }
+
+        // Extract new files
+        debug!(
+            "extracting '{}' to '{}'",
+            installer_file.display(),
+            extract_dir.display()
+        );
+        let now = Instant::now();
+        if let Err(e) = extract(url, &installer_file, &extract_dir) {
+            let _ = raw::remove_dir(&extract_dir);
+            return Err(e);
+        }
+        notify_handler(Notification::PhaseTimed(
+            InstallPhase::Extracting,
+            now.elapsed(),
+        ));
+
+        // Replace old files
+        let _lock = lock()?;
         if utils::is_directory(prefix) {
             utils::remove_dir("toolchain directory", prefix, &|n| {
                 (notify_handler)(n.into())
             })?;
         }
+        utils::rename_dir("toolchain directory", &extract_dir, prefix)?;
 
-        utils::ensure_dir_exists("toolchain directory", prefix, &|n| {
-            (notify_handler)(n.into())
-        })?;
+        if let Some(dir) = keep_in {
+            let kept = dir.join(url.rsplit('/').next().unwrap_or(url));
+            keep_archive(&installer_file, &kept, notify_handler)?;
+            notify_handler(Notification::KeptArchive(&kept));
+        }
 
-        // Extract new files
-        if url.ends_with(".tar.gz") {
-            TarGzPackage::unpack_file(&installer_file, prefix)?
-        } else if url.ends_with(".tar.zst") {
-            TarZstdPackage::unpack_file(&installer_file, prefix)?
-        } else if url.ends_with(".zip") {
-            ZipPackage::unpack_file(&installer_file, prefix)?
-        } else {
-            return Err(format!("unsupported archive format: {}", url).into())
+        Ok(installer_hash)
+    }
+}
+
+/// Moves the downloaded `archive` to `kept`, copying it if it is on another
+/// file system
+fn keep_archive(archive: &Path, kept: &Path, notify_handler: &dyn Fn(Notification)) -> Result<()> {
+    let dir = kept.parent().unwrap_or(kept);
+    utils::ensure_dir_exists("archive cache", dir, &|n| (notify_handler)(n.into()))?;
+    if fs::rename(archive, kept).is_ok() {
+        return Ok(());
+    }
+    // Copied under another name first, so that an archive in the cache is
+    // always complete
+    let partial = kept.with_file_name(format!(".{}.partial", process::id()));
+    utils::copy_file(archive, &partial)?;
+    utils::rename_file("archive", &partial, kept)?;
+    Ok(())
+}
+
+/// The `informal_target` archive of `variant`, or of the primary installer
+/// if none is given, in the release directory `dir` of an archive cache
+fn cached_archive(
+    dir: &Path,
+    informal_target: &str,
+    variant: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    use regex::Regex;
+
+    if !utils::is_directory(dir) {
+        return Ok(None);
+    }
+    let re = Regex::new(&format!(
+        r"-{}(?:-([A-Za-z0-9_]+))?\.(tar\.gz|tar\.zst|zip)$",
+        regex::escape(informal_target)
+    ))
+    .unwrap();
+    for entry in utils::read_dir("archive cache", dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        let matches = match name.and_then(|name| re.captures(name)) {
+            Some(cap) => cap.get(1).map(|m| m.as_str()) == variant,
+            None => false,
+        };
+        if matches && !name.unwrap_or_default().starts_with('.') && utils::is_file(&path) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Extracts the `installer` downloaded from `url` to `dir`, recording its files
+fn extract(url: &str, installer: &Path, dir: &Path) -> Result<()> {
+    if url.ends_with(".tar.gz") {
+        TarGzPackage::unpack_file(installer, dir)?
+    } else if url.ends_with(".tar.zst") {
+        TarZstdPackage::unpack_file(installer, dir)?
+    } else if url.ends_with(".zip") {
+        ZipPackage::unpack_file(installer, dir)?
+    } else {
+        return Err(format!("unsupported archive format: {}", url).into());
+    }
+    let prefix = InstallPrefix::from(dir.to_owned());
+    InstalledFiles::record(dir)?.save(&prefix.manifest_file(INSTALLED_FILES_NAME))
+}
+
+/// `.<toolchain>`, the start of the names of the files next to the toolchain
+/// directory `prefix` that belong to it
+fn hidden_name(prefix: &Path) -> String {
+    let name = prefix.file_name().unwrap_or_default();
+    format!(".{}", name.to_string_lossy())
+}
+
+fn install_lock_file(prefix: &Path) -> PathBuf {
+    prefix.with_file_name(hidden_name(prefix) + ".lock")
+}
+
+/// Takes the lock of the installs of `prefix`, which other installs of the
+/// same toolchain hold while they replace it
+fn lock_install(prefix: &Path) -> Result<raw::LockFile> {
+    let path = install_lock_file(prefix);
+    raw::lock_file(&path, INSTALL_LOCK_TIMEOUT).ok_or_else(|| ErrorKind::InstallLocked(path).into())
+}
+
+fn extract_dir_prefix(prefix: &Path) -> String {
+    format!(
+        "{}{}{}-",
+        hidden_name(prefix),
+        EXTRACT_DIR_INFIX,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Where the process `pid` extracts the toolchain for `prefix`
+fn extract_dir(prefix: &Path, pid: u32) -> PathBuf {
+    prefix.with_file_name(format!("{}{}", extract_dir_prefix(prefix), pid))
+}
+
+/// Removes the directories the toolchain for `prefix` was being extracted to
+/// by this version of elan in processes that have since been killed,
+/// returning their paths. Should be called holding the install lock of the
+/// toolchain, so that no other process is about to move one into place.
+pub fn remove_orphaned_extractions(
+    prefix: &Path,
+    notify_handler: &dyn Fn(Notification),
+) -> Result<Vec<PathBuf>> {
+    let parent = match prefix.parent() {
+        Some(parent) if utils::is_directory(parent) => parent,
+        _ => return Ok(vec![]),
+    };
+    let extract_dir_prefix = extract_dir_prefix(prefix);
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(parent)? {
+        let path = entry?.path();
+        let pid = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(&extract_dir_prefix[..]))
+            .and_then(|pid| pid.parse::<u32>().ok());
+        let orphaned = match pid {
+            Some(pid) => pid != process::id() && !raw::process_is_running(pid),
+            None => false,
+        };
+        if orphaned && utils::is_directory(&path) {
+            notify_handler(Notification::RemovingOrphanedExtraction(&path));
+            utils::remove_dir("extraction directory", &path, &|n| {
+                (notify_handler)(n.into())
+            })?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// The name release assets use for the platform `os`/`arch`, as in
+/// `std::env::consts`
+pub fn informal_target(os: &str, arch: &str) -> Option<String> {
+    let os = match os {
+        "windows" => "windows",
+        "linux" => "linux",
+        "macos" => "darwin",
+        _ => return None,
+    };
+    match arch {
+        "x86_64" => Some(os.to_owned()),
+        "aarch64" => Some(os.to_owned() + "_aarch64"),
+        _ => None,
+    }
+}
+
+/// The name release assets use for the platform of the target triple
+/// `triple`, such as `aarch64-apple-darwin`
+pub fn triple_informal_target(triple: &str) -> Option<String> {
+    let arch = match triple.split('-').next() {
+        Some("arm64") => "aarch64",
+        Some(arch) => arch,
+        None => return None,
+    };
+    let os = if triple.contains("-apple-") {
+        "macos"
+    } else if triple.contains("-linux") {
+        "linux"
+    } else if triple.contains("-windows") {
+        "windows"
+    } else {
+        return None;
+    };
+    informal_target(os, arch)
+}
+
+pub fn host_informal_target() -> String {
+    use std::env::consts::{ARCH, OS};
+    informal_target(OS, ARCH).expect("unsupported platform")
+}
+
+/// Which installer of a release `Manifestation::update` installs
+#[derive(Clone, Copy, Debug)]
+pub struct Installer<'a> {
+    pub informal_target: &'a str,
+    /// `None` for the primary installer of the platform
+    pub variant: Option<&'a str>,
+    /// The SHA-256 the installer must have, if known
+    pub expected: Option<&'a str>,
+}
+
+/// An installer on a release page
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asset {
+    /// `None` for the primary installer of its platform, else the name of
+    /// the variant, such as `nogmp` for `lean-4.0.0-linux-nogmp.tar.zst`
+    pub variant: Option<String>,
+    pub url: String,
+}
+
+/// The URL of the `informal_target` installer of `toolchain` on its release
+/// page `url`, or of its variant `variant` if given. Only the page itself is
+/// downloaded.
+pub fn installer_url(
+    dlcfg: &DownloadCfg,
+    toolchain: &str,
+    origin: &str,
+    url: &str,
+    informal_target: &str,
+    variant: Option<&str>,
+) -> Result<String> {
+    let html = download_release_page(dlcfg, url)?;
+    find_installer_url(&html, toolchain, origin, informal_target, variant)
+}
+
+/// The `informal_target` installers on the release page `url`, as in
+/// `find_assets`
+pub fn installer_assets(
+    dlcfg: &DownloadCfg,
+    origin: &str,
+    url: &str,
+    informal_target: &str,
+) -> Result<Vec<Asset>> {
+    let html = download_release_page(dlcfg, url)?;
+    Ok(find_assets(&html, origin, informal_target))
+}
+
+fn download_release_page(dlcfg: &DownloadCfg, url: &str) -> Result<String> {
+    // find correct download on HTML page (AAAAH)
+    use std::io::Read;
+    let download_page_file = dlcfg.download_and_check(url)?;
+    let mut html = String::new();
+    fs::File::open(&download_page_file as &::std::path::Path)?.read_to_string(&mut html)?;
+    Ok(html)
+}
+
+/// Like `find_asset_url`, or `find_assets` for a `variant`, but failing with
+/// the platforms or variants that are provided
+fn find_installer_url(
+    html: &str,
+    toolchain: &str,
+    origin: &str,
+    informal_target: &str,
+    variant: Option<&str>,
+) -> Result<String> {
+    if let Some(variant) = variant {
+        let assets = find_assets(html, origin, informal_target);
+        return match assets
+            .iter()
+            .find(|a| a.variant.as_deref() == Some(variant))
+        {
+            Some(asset) => Ok(asset.url.clone()),
+            None => Err(ErrorKind::UnknownVariant {
+                toolchain: toolchain.to_owned(),
+                target: informal_target.to_owned(),
+                variant: variant.to_owned(),
+                available: assets.into_iter().filter_map(|a| a.variant).collect(),
+            }
+            .into()),
+        };
+    }
+    find_asset_url(html, origin, informal_target).ok_or_else(|| {
+        ErrorKind::UnsupportedPlatform {
+            toolchain: toolchain.to_owned(),
+            target: informal_target.to_owned(),
+            available: available_targets(html, origin),
+        }
+        .into()
+    })
+}
+
+/// The platforms elan knows that the release page `html` has assets for
+fn available_targets(html: &str, origin: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    for os in &["linux", "macos", "windows"] {
+        for arch in &["x86_64", "aarch64"] {
+            if let Some(target) = informal_target(os, arch) {
+                if find_asset_url(html, origin, &target).is_some() {
+                    targets.push(target);
+                }
+            }
+        }
+    }
+    targets
+}
+
+/// The URL of the `informal_target` asset linked from the release page `html`
+/// of the GitHub repository `origin`
+fn find_asset_url(html: &str, origin: &str, informal_target: &str) -> Option<String> {
+    let url_substring = informal_target.to_owned() + ".";
+    let url = asset_paths(html, origin)
+        .into_iter()
+        .find(|m| m.contains(&url_substring));
+    url.map(|m| format!("https://github.com{}", m))
+}
+
+/// The `informal_target` installers linked from the release page `html` of
+/// the GitHub repository `origin`: the primary one first if there is one,
+/// then the variants, which are named like `lean-4.0.0-linux-nogmp.zip`
+fn find_assets(html: &str, origin: &str, informal_target: &str) -> Vec<Asset> {
+    use regex::Regex;
+
+    let mut assets: Vec<Asset> = find_asset_url(html, origin, informal_target)
+        .map(|url| Asset { variant: None, url })
+        .into_iter()
+        .collect();
+    let re = Regex::new(&format!(
+        r"-{}-([A-Za-z0-9_]+)\.(tar\.gz|tar\.zst|zip)$",
+        regex::escape(informal_target)
+    ))
+    .unwrap();
+    for path in asset_paths(html, origin) {
+        let variant = match re.captures(path) {
+            Some(cap) => cap[1].to_owned(),
+            None => continue,
+        };
+        if assets.iter().all(|a| a.variant.as_ref() != Some(&variant)) {
+            assets.push(Asset {
+                variant: Some(variant),
+                url: format!("https://github.com{}", path),
+            });
+        }
+    }
+    assets
+}
+
+/// The paths of the assets of `origin` linked from the release page `html`
+fn asset_paths<'a>(html: &'a str, origin: &str) -> Vec<&'a str> {
+    use regex::Regex;
+
+    let re = Regex::new(&format!(
+        r#"/{}/releases/download/[^"]+"#,
+        regex::escape(origin)
+    ))
+    .unwrap();
+    re.find_iter(html).map(|m| m.as_str()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elan_utils::notify::NotificationLevel;
+    use std::cell::RefCell;
+    use std::fs;
+    use tempfile;
+    use url::Url;
+
+    #[test]
+    fn test_install_phases_are_timed() {
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin").join("lean"), "lean").unwrap();
+        let archive = root.path().join("lean-4.0.0-linux.tar.gz");
+        TarGzPackage::pack_dir(&src, "lean-4.0.0-linux", &archive).unwrap();
+        let url = Url::from_file_path(&archive).unwrap().to_string();
+
+        let temp_cfg = temp::Cfg::new(root.path().join("tmp"), Box::new(|_| {}));
+        let prefix = root.path().join("toolchain");
+        let manifestation = Manifestation::open(InstallPrefix::from(prefix.clone())).unwrap();
+        let verbose = RefCell::new(Vec::new());
+        manifestation
+            .install_installer(&url, None, None, &temp_cfg, &|n| {
+                if let Notification::PhaseTimed(phase, _) = n {
+                    match n.level() {
+                        NotificationLevel::Verbose => {}
+                        _ => panic!("phase timings should only be shown with `-v`"),
+                    }
+                    verbose.borrow_mut().push((phase, n.to_string()));
+                }
+            })
+            .unwrap();
+
+        assert!(prefix.join("bin").join("lean").is_file());
+        assert!(prefix.join(INSTALLED_FILES_NAME).is_file());
+        let verbose = verbose.into_inner();
+        let phases: Vec<_> = verbose.iter().map(|&(phase, _)| phase).collect();
+        assert_eq!(
+            phases,
+            [
+                InstallPhase::Connecting,
+                InstallPhase::Downloading,
+                InstallPhase::Verifying,
+                InstallPhase::Extracting,
+            ]
+        );
+        assert!(verbose[1].1.starts_with("downloading took "));
+        assert!(verbose[1].1.ends_with('s'));
+    }
+
+    #[test]
+    fn test_cancelled_install_leaves_no_toolchain() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        // Incompressible, so that the archive arrives in many chunks
+        let mut x = 0x2545_f491_4f6c_dd1d_u64;
+        let data: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+        fs::write(src.join("bin").join("lean"), data).unwrap();
+        let archive = root.path().join("lean-4.0.0-linux.tar.gz");
+        TarGzPackage::pack_dir(&src, "lean-4.0.0-linux", &archive).unwrap();
+        let url = Url::from_file_path(&archive).unwrap().to_string();
+
+        let temp_cfg = temp::Cfg::new(root.path().join("tmp"), Box::new(|_| {}));
+        let prefix = root.path().join("toolchain");
+        let manifestation = Manifestation::open(InstallPrefix::from(prefix.clone())).unwrap();
+        let token = Arc::new(AtomicBool::new(false));
+        utils::set_cancel_token(Some(token.clone()));
+        let chunks = Cell::new(0);
+        let err = manifestation
+            .install_installer(&url, None, None, &temp_cfg, &|n| {
+                if let Notification::Utils(elan_utils::Notification::DownloadDataReceived(_)) = n {
+                    chunks.set(chunks.get() + 1);
+                    token.store(true, Ordering::SeqCst);
+                }
+            })
+            .unwrap_err();
+        utils::set_cancel_token(None);
+
+        match *err.kind() {
+            ErrorKind::Utils(elan_utils::ErrorKind::Download(ref k)) => {
+                assert_eq!(k.to_string(), "download cancelled")
+            }
+            ref k => panic!("unexpected error: {}", k),
+        }
+        assert_eq!(chunks.get(), 1);
+        assert!(!prefix.join("bin").join("lean").exists());
+        assert!(!prefix.join(INSTALLED_FILES_NAME).exists());
+        assert_eq!(fs::read_dir(root.path().join("tmp")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_orphaned_extraction_is_removed() {
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin").join("lean"), "lean").unwrap();
+        let archive = root.path().join("lean-4.0.0-linux.tar.gz");
+        TarGzPackage::pack_dir(&src, "lean-4.0.0-linux", &archive).unwrap();
+        let url = Url::from_file_path(&archive).unwrap().to_string();
+
+        // Left by an install that was killed while extracting, by a process
+        // that has exited since
+        let prefix = root.path().join("toolchains").join("lean4");
+        let mut child = process::Command::new(env!("CARGO"))
+            .arg("--version")
+            .stdout(process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        let orphan = extract_dir(&prefix, dead_pid);
+        fs::create_dir_all(orphan.join("bin")).unwrap();
+        fs::write(orphan.join("bin").join("lean"), "half").unwrap();
+        // Still being extracted by a running process
+        let running = extract_dir(&prefix.with_file_name("other"), process::id());
+        fs::create_dir_all(&running).unwrap();
+        // Not ours
+        let unrelated = prefix.with_file_name(".lean4.extract-0.0.0-1");
+        fs::create_dir_all(&unrelated).unwrap();
+
+        let temp_cfg = temp::Cfg::new(root.path().join("tmp"), Box::new(|_| {}));
+        let manifestation = Manifestation::open(InstallPrefix::from(prefix.clone())).unwrap();
+        let removed = RefCell::new(Vec::new());
+        manifestation
+            .install_installer(&url, None, None, &temp_cfg, &|n| {
+                if let Notification::RemovingOrphanedExtraction(path) = n {
+                    removed.borrow_mut().push(path.to_owned());
+                }
+            })
+            .unwrap();
+
+        assert_eq!(removed.into_inner(), vec![orphan.clone()]);
+        assert!(!orphan.exists());
+        assert!(running.is_dir());
+        assert!(unrelated.is_dir());
+        assert_eq!(
+            fs::read_to_string(prefix.join("bin").join("lean")).unwrap(),
+            "lean"
+        );
+        assert!(prefix.join(INSTALLED_FILES_NAME).is_file());
+        assert!(!extract_dir(&prefix, process::id()).exists());
+        assert!(!install_lock_file(&prefix).exists());
+    }
+
+    #[test]
+    fn test_install_fails_while_another_holds_the_lock() {
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin").join("lean"), "lean").unwrap();
+        let archive = root.path().join("lean-4.0.0-linux.tar.gz");
+        TarGzPackage::pack_dir(&src, "lean-4.0.0-linux", &archive).unwrap();
+        let url = Url::from_file_path(&archive).unwrap().to_string();
+
+        let prefix = root.path().join("toolchains").join("lean4");
+        fs::create_dir_all(root.path().join("toolchains")).unwrap();
+        // As held by a running process
+        let _lock = raw::lock_file(&install_lock_file(&prefix), Duration::from_secs(1)).unwrap();
+
+        let temp_cfg = temp::Cfg::new(root.path().join("tmp"), Box::new(|_| {}));
+        let manifestation = Manifestation::open(InstallPrefix::from(prefix.clone())).unwrap();
+        let err = manifestation
+            .install_installer(&url, None, None, &temp_cfg, &|_| {})
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::InstallLocked(ref path) => assert_eq!(*path, install_lock_file(&prefix)),
+            _ => panic!("{}", err),
+        }
+        assert!(!prefix.exists());
+        assert!(!extract_dir(&prefix, process::id()).exists());
+    }
+
+    #[test]
+    fn test_kept_archive_is_installed_from_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin").join("lean"), "lean").unwrap();
+        let archive = root.path().join("lean-4.0.0-linux.tar.gz");
+        TarGzPackage::pack_dir(&src, "lean-4.0.0-linux", &archive).unwrap();
+        let url = Url::from_file_path(&archive).unwrap().to_string();
+
+        let cache = ArchiveCache {
+            dir: root.path().join("archives"),
+            keep: true,
+            prefer: true,
+        };
+        let release_dir = cache.release_dir(
+            "leanprover/lean4",
+            "https://github.com/leanprover/lean4/releases/tag/v4.0.0",
+        );
+        assert_eq!(
+            release_dir,
+            root.path()
+                .join("archives")
+                .join("leanprover/lean4")
+                .join("v4.0.0")
+        );
+
+        let temp_cfg = temp::Cfg::new(root.path().join("tmp"), Box::new(|_| {}));
+        let first = Manifestation::open(InstallPrefix::from(root.path().join("first"))).unwrap();
+        let hash = first
+            .install_installer(&url, None, Some(&release_dir), &temp_cfg, &|_| {})
+            .unwrap();
+        let kept = release_dir.join("lean-4.0.0-linux.tar.gz");
+        assert!(kept.is_file());
+        assert_eq!(fs::read_dir(root.path().join("tmp")).unwrap().count(), 0);
+
+        // Nowhere left to download it from
+        fs::remove_file(&archive).unwrap();
+        let prefix = root.path().join("second");
+        let second = Manifestation::open(InstallPrefix::from(prefix.clone())).unwrap();
+        assert_eq!(
+            second
+                .install_cached(
+                    &release_dir,
+                    "linux",
+                    Some("nogmp"),
+                    None,
+                    &temp_cfg,
+                    &|_| {}
+                )
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            second
+                .install_cached(&release_dir, "darwin", None, None, &temp_cfg, &|_| {})
+                .unwrap(),
+            None
+        );
+        // A kept archive is checked against a lock before it is extracted
+        match second
+            .install_cached(&release_dir, "linux", None, Some("abc"), &temp_cfg, &|_| {})
+            .unwrap_err()
+            .0
+        {
+            ErrorKind::ChecksumFailed { expected, .. } => assert_eq!(expected, "abc"),
+            k => panic!("unexpected error: {}", k),
         }
+        assert!(!prefix.join("bin").exists());
+        let used = RefCell::new(Vec::new());
+        let installed = second
+            .install_cached(&release_dir, "linux", None, Some(&hash), &temp_cfg, &|n| {
+                if let Notification::InstallingFromCache(path) = n {
+                    used.borrow_mut().push(path.to_owned());
+                }
+            })
+            .unwrap();
+        assert_eq!(installed, Some(hash));
+        assert_eq!(used.into_inner(), vec![kept.clone()]);
+        assert_eq!(
+            fs::read_to_string(prefix.join("bin").join("lean")).unwrap(),
+            "lean"
+        );
+        assert!(kept.is_file());
+    }
+
+    #[test]
+    fn test_find_asset_url_custom_origin() {
+        let html = r#"
+            <a href="/myorg/lean4/releases/download/v4.1.0-myfork/lean-4.1.0-myfork-darwin.zip">
+            <a href="/myorg/lean4/releases/download/v4.1.0-myfork/lean-4.1.0-myfork-linux.tar.zst">
+            <a href="/myorgXlean4/releases/download/v4.1.0-myfork/lean-4.1.0-myfork-windows.zip">
+        "#;
+
+        assert_eq!(
+            find_asset_url(html, "myorg/lean4", "linux"),
+            Some(
+                "https://github.com/myorg/lean4/releases/download/v4.1.0-myfork/lean-4.1.0-myfork-linux.tar.zst"
+                    .to_owned()
+            )
+        );
+        assert_eq!(find_asset_url(html, "myorg/lean4", "linux_aarch64"), None);
+        assert_eq!(find_asset_url(html, "leanprover/lean4", "linux"), None);
+
+        let html = r#"<a href="/my-org/lean4.fork/releases/download/v1/lean-1-linux.tar.gz">"#;
+        assert_eq!(find_asset_url(html, "my-org/lean4xfork", "linux"), None);
+        assert!(find_asset_url(html, "my-org/lean4.fork", "linux").is_some());
+    }
+
+    #[test]
+    fn test_installer_url_for_platform() {
+        let html = r#"
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin.zip">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin_aarch64.zip">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux_aarch64.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-windows.zip">
+        "#;
+        let url = |os, arch| {
+            find_asset_url(
+                html,
+                "leanprover/lean4",
+                &informal_target(os, arch).unwrap(),
+            )
+        };
+
+        assert_eq!(
+            url("linux", "x86_64"),
+            Some(
+                "https://github.com/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            url("macos", "aarch64"),
+            Some(
+                "https://github.com/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin_aarch64.zip"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            url("windows", "x86_64"),
+            Some(
+                "https://github.com/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-windows.zip"
+                    .to_owned()
+            )
+        );
+        assert_eq!(informal_target("freebsd", "x86_64"), None);
+        assert_eq!(informal_target("linux", "riscv64"), None);
+    }
+
+    #[test]
+    fn test_missing_asset_is_unsupported_platform() {
+        let html = r#"
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin_aarch64.zip">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst.sha256">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/source.tar.gz">
+        "#;
+        let err = find_installer_url(
+            html,
+            "leanprover/lean4:v4.0.0",
+            "leanprover/lean4",
+            "windows",
+            None,
+        )
+        .unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnsupportedPlatform {
+                ref toolchain,
+                ref target,
+                ref available,
+            } => {
+                assert_eq!(toolchain, "leanprover/lean4:v4.0.0");
+                assert_eq!(target, "windows");
+                assert_eq!(available, &["linux", "darwin_aarch64"]);
+            }
+            ref k => panic!("unexpected error: {}", k),
+        }
+        assert_eq!(
+            err.to_string(),
+            "toolchain 'leanprover/lean4:v4.0.0' is not available for platform 'windows', \
+             only for: linux, darwin_aarch64"
+        );
+
+        let err = find_installer_url("", "lean4", "leanprover/lean4", "linux", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "toolchain 'lean4' is not available for platform 'linux', \
+             nor for any other platform"
+        );
+        assert!(find_installer_url(html, "lean4", "leanprover/lean4", "linux", None).is_ok());
+    }
+
+    #[test]
+    fn test_variant_selects_its_asset() {
+        let html = r#"
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux-nogmp.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux-nogmp.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux_aarch64.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux_aarch64-nogmp.zip">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux-nogmp.tar.zst.sha256">
+        "#;
+        let download = "https://github.com/leanprover/lean4/releases/download/v4.0.0/";
+        let url = |target, variant| {
+            find_installer_url(html, "lean4", "leanprover/lean4", target, variant)
+        };
+
+        assert_eq!(
+            find_assets(html, "leanprover/lean4", "linux"),
+            vec![
+                Asset {
+                    variant: None,
+                    url: download.to_owned() + "lean-4.0.0-linux.tar.zst",
+                },
+                Asset {
+                    variant: Some("nogmp".to_owned()),
+                    url: download.to_owned() + "lean-4.0.0-linux-nogmp.tar.zst",
+                },
+            ]
+        );
+        assert_eq!(
+            url("linux", None).unwrap(),
+            download.to_owned() + "lean-4.0.0-linux.tar.zst"
+        );
+        assert_eq!(
+            url("linux", Some("nogmp")).unwrap(),
+            download.to_owned() + "lean-4.0.0-linux-nogmp.tar.zst"
+        );
+        assert_eq!(
+            url("linux_aarch64", Some("nogmp")).unwrap(),
+            download.to_owned() + "lean-4.0.0-linux_aarch64-nogmp.zip"
+        );
+
+        let err = url("linux", Some("static")).unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnknownVariant { ref available, .. } => assert_eq!(available, &["nogmp"]),
+            ref k => panic!("unexpected error: {}", k),
+        }
+        assert_eq!(
+            err.to_string(),
+            "toolchain 'lean4' has no variant 'static' for platform 'linux', only: nogmp"
+        );
+    }
+
+    #[test]
+    fn test_installer_url_for_target() {
+        let html = r#"
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin.zip">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin_aarch64.zip">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst">
+            <a href="/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux_aarch64.tar.zst">
+        "#;
+        let url = |triple| {
+            find_asset_url(
+                html,
+                "leanprover/lean4",
+                &triple_informal_target(triple).unwrap(),
+            )
+            .unwrap()
+        };
 
-        Ok(())
+        assert_eq!(
+            url("aarch64-apple-darwin"),
+            "https://github.com/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-darwin_aarch64.zip"
+        );
+        assert_eq!(url("arm64-apple-darwin"), url("aarch64-apple-darwin"));
+        assert_eq!(
+            url("aarch64-unknown-linux-gnu"),
+            "https://github.com/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux_aarch64.tar.zst"
+        );
+        assert_eq!(
+            url("x86_64-unknown-linux-musl"),
+            "https://github.com/leanprover/lean4/releases/download/v4.0.0/lean-4.0.0-linux.tar.zst"
+        );
+        assert_eq!(
+            triple_informal_target("x86_64-pc-windows-msvc"),
+            Some("windows".to_owned())
+        );
+        assert_eq!(triple_informal_target("wasm32-unknown-unknown"), None);
+        assert_eq!(triple_informal_target("aarch64"), None);
     }
 }
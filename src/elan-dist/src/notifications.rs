@@ -4,6 +4,7 @@ use errors::*;
 use manifest::Component;
 use std::fmt::{self, Display};
 use std::path::Path;
+use std::time::Duration;
 use temp;
 
 #[derive(Debug)]
@@ -19,6 +20,11 @@ pub enum Notification<'a> {
     SignatureValid(&'a str),
     FileAlreadyDownloaded,
     CachedFileChecksumFailed,
+    DiscardingStalePartialDownload(&'a Path),
+    CantPrunePartialDownloads(&'a Error),
+    RemovingOrphanedExtraction(&'a Path),
+    InstallingFromCache(&'a Path),
+    KeptArchive(&'a Path),
     RollingBack,
     ExtensionNotInstalled(&'a Component),
     NonFatalError(&'a Error),
@@ -30,6 +36,32 @@ pub enum Notification<'a> {
     DownloadedManifest(&'a str, Option<&'a str>),
     DownloadingLegacyManifest,
     ManifestChecksumFailedHack,
+    PhaseTimed(InstallPhase, Duration),
+}
+
+/// The steps of installing a toolchain that are timed for verbose output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InstallPhase {
+    /// Finding the installer on the release page
+    Resolving,
+    /// Until the server starts sending the installer, including DNS and TLS
+    Connecting,
+    Downloading,
+    /// Hashing the installer
+    Verifying,
+    Extracting,
+}
+
+impl Display for InstallPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            InstallPhase::Resolving => "resolving the installer",
+            InstallPhase::Connecting => "connecting",
+            InstallPhase::Downloading => "downloading",
+            InstallPhase::Verifying => "verifying",
+            InstallPhase::Extracting => "extracting",
+        })
+    }
 }
 
 impl<'a> From<elan_utils::Notification<'a>> for Notification<'a> {
@@ -53,7 +85,8 @@ impl<'a> Notification<'a> {
             ChecksumValid(_)
             | NoUpdateHash(_)
             | FileAlreadyDownloaded
-            | DownloadingLegacyManifest => NotificationLevel::Verbose,
+            | DownloadingLegacyManifest
+            | PhaseTimed(_, _) => NotificationLevel::Verbose,
             Extracting(_, _)
             | SignatureValid(_)
             | DownloadingComponent(_)
@@ -63,11 +96,16 @@ impl<'a> Notification<'a> {
             | ManifestChecksumFailedHack
             | RollingBack
             | DownloadingManifest(_)
-            | DownloadedManifest(_, _) => NotificationLevel::Info,
+            | DownloadedManifest(_, _)
+            | DiscardingStalePartialDownload(_)
+            | RemovingOrphanedExtraction(_)
+            | InstallingFromCache(_)
+            | KeptArchive(_) => NotificationLevel::Info,
             CantReadUpdateHash(_)
             | ExtensionNotInstalled(_)
             | MissingInstalledComponent(_)
-            | CachedFileChecksumFailed => NotificationLevel::Warn,
+            | CachedFileChecksumFailed
+            | CantPrunePartialDownloads(_) => NotificationLevel::Warn,
             NonFatalError(_) => NotificationLevel::Error,
         }
     }
@@ -95,6 +133,23 @@ impl<'a> Display for Notification<'a> {
             SignatureValid(_) => write!(f, "signature valid"),
             FileAlreadyDownloaded => write!(f, "reusing previously downloaded file"),
             CachedFileChecksumFailed => write!(f, "bad checksum for cached download"),
+            DiscardingStalePartialDownload(path) => write!(
+                f,
+                "starting over instead of resuming stale partial download '{}'",
+                path.display()
+            ),
+            CantPrunePartialDownloads(e) => {
+                write!(f, "can't remove stale partial downloads: {}", e)
+            }
+            RemovingOrphanedExtraction(path) => write!(
+                f,
+                "removing '{}' left by an interrupted install",
+                path.display()
+            ),
+            InstallingFromCache(path) => {
+                write!(f, "installing from cached archive '{}'", path.display())
+            }
+            KeptArchive(path) => write!(f, "kept the archive at '{}'", path.display()),
             RollingBack => write!(f, "rolling back changes"),
             ExtensionNotInstalled(c) => {
                 write!(f, "extension '{}' was not installed", c.name())
@@ -117,6 +172,9 @@ impl<'a> Display for Notification<'a> {
             ManifestChecksumFailedHack => {
                 write!(f, "update not yet available, sorry! try again later")
             }
+            PhaseTimed(phase, duration) => {
+                write!(f, "{} took {:.2}s", phase, duration.as_secs_f64())
+            }
         }
     }
 }
@@ -0,0 +1,222 @@
+//! Release manifests, for forks that publish their toolchains somewhere
+//! other than GitHub releases. A manifest is a JSON list of the archives it
+//! provides, e.g.
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "myorg/lean4:v4.0.0",
+//!     "target": "linux",
+//!     "url": "https://example.org/lean-4.0.0-linux.tar.gz",
+//!     "sha256": "..."
+//!   }
+//! ]
+//! ```
+//!
+//! where `name` is the toolchain the archive is installed as, `target` is
+//! either a target triple or the name release assets use for a platform,
+//! such as `linux` or `darwin_aarch64`, and `url` may be relative to the
+//! manifest.
+
+use std::env;
+use std::path::Path;
+use std::sync::Mutex;
+
+use dist::ToolchainDesc;
+use download::DownloadCfg;
+use elan_utils::utils;
+use errors::*;
+use json;
+use manifestation;
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub target: String,
+    /// Where the archive is downloaded from, resolved against the manifest
+    pub url: String,
+    /// SHA-256 the archive must have
+    pub sha256: String,
+}
+
+impl ManifestEntry {
+    /// The name release assets use for the platform this archive is for
+    pub fn informal_target(&self) -> String {
+        manifestation::triple_informal_target(&self.target).unwrap_or_else(|| self.target.clone())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReleaseManifest {
+    /// The path or URL the manifest was read from
+    pub location: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl ReleaseManifest {
+    /// Reads the manifest at `location`, a path or URL, downloading it if it
+    /// is not a local file
+    pub fn load(location: &str, download: &DownloadCfg) -> Result<Self> {
+        let base = manifest_url(location)?;
+        let data = match base.to_file_path() {
+            Ok(ref path) if base.scheme() == "file" => utils::read_file("release manifest", path)?,
+            _ => {
+                let file = download.download_and_check(base.as_str())?;
+                utils::read_file("release manifest", &file)?
+            }
+        };
+        Self::parse(location, &data, &base)
+            .chain_err(|| ErrorKind::InvalidReleaseManifest(location.to_owned()))
+    }
+
+    /// Parses the manifest `data` read from `location`, resolving relative
+    /// URLs against `base`
+    pub fn parse(location: &str, data: &str, base: &Url) -> Result<Self> {
+        let value = json::parse(data).chain_err(|| "not valid JSON")?;
+        if !value.is_array() {
+            return Err("expected a list of toolchain archives".into());
+        }
+        let entries = value
+            .members()
+            .enumerate()
+            .map(|(i, entry)| {
+                let field = |name: &str| -> Result<String> {
+                    entry[name]
+                        .as_str()
+                        .map(|s| s.to_owned())
+                        .ok_or_else(|| format!("entry {} has no '{}'", i + 1, name).into())
+                };
+                let url = field("url")?;
+                let url = base
+                    .join(&url)
+                    .chain_err(|| format!("entry {} has an invalid url '{}'", i + 1, url))?;
+                Ok(ManifestEntry {
+                    name: field("name")?,
+                    target: field("target")?,
+                    url: url.to_string(),
+                    sha256: field("sha256")?.to_lowercase(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ReleaseManifest {
+            location: location.to_owned(),
+            entries,
+        })
+    }
+
+    /// The archive of `toolchain` for the platform `informal_target`
+    pub fn find(&self, toolchain: &ToolchainDesc, informal_target: &str) -> Result<&ManifestEntry> {
+        let name = toolchain.to_string();
+        let entries: Vec<&ManifestEntry> = self
+            .entries
+            .iter()
+            .filter(|e| {
+                ToolchainDesc::from_str(&e.name)
+                    .map(|desc| desc.to_string() == name)
+                    .unwrap_or(false)
+            })
+            .collect();
+        if entries.is_empty() {
+            return Err(ErrorKind::NotInReleaseManifest {
+                toolchain: name,
+                location: self.location.clone(),
+            }
+            .into());
+        }
+        match entries
+            .iter()
+            .find(|e| e.informal_target() == informal_target)
+        {
+            Some(entry) => Ok(entry),
+            None => Err(ErrorKind::UnsupportedPlatform {
+                toolchain: name,
+                target: informal_target.to_owned(),
+                available: entries.iter().map(|e| e.informal_target()).collect(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// The URL of the manifest `location`, a path or URL
+fn manifest_url(location: &str) -> Result<Url> {
+    if location.contains("://") {
+        return utils::parse_url(location).map_err(Error::from);
+    }
+    let path = env::current_dir()
+        .chain_err(|| "failed to get the current directory")?
+        .join(Path::new(location));
+    Url::from_file_path(&path)
+        .map_err(|()| ErrorKind::InvalidReleaseManifest(location.to_owned()).into())
+}
+
+static RELEASE_MANIFEST: Mutex<Option<String>> = Mutex::new(None);
+
+/// Resolves and downloads toolchains from the manifest at `location`, a
+/// path or URL, instead of GitHub releases
+pub fn set_release_manifest(location: Option<String>) {
+    *RELEASE_MANIFEST.lock().unwrap() = location;
+}
+
+/// The manifest toolchains are resolved and downloaded from, if one was set
+/// or named by `ELAN_RELEASE_MANIFEST`
+pub fn release_manifest() -> Option<String> {
+    if let Some(ref location) = *RELEASE_MANIFEST.lock().unwrap() {
+        return Some(location.clone());
+    }
+    env::var("ELAN_RELEASE_MANIFEST")
+        .ok()
+        .filter(|location| !location.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_in_manifest() {
+        let base = Url::parse("https://example.org/dist/manifest.json").unwrap();
+        let manifest = ReleaseManifest::parse(
+            "manifest.json",
+            r#"[
+                { "name": "myorg/lean4:v4.0.0", "target": "x86_64-unknown-linux-gnu",
+                  "url": "lean-4.0.0-linux.tar.gz", "sha256": "AB12" },
+                { "name": "myorg/lean4:v4.0.0", "target": "darwin_aarch64",
+                  "url": "https://mirror.example.org/lean-4.0.0-darwin_aarch64.tar.gz",
+                  "sha256": "cd34" }
+            ]"#,
+            &base,
+        )
+        .unwrap();
+        let desc = ToolchainDesc::from_str("myorg/lean4:v4.0.0").unwrap();
+
+        let linux = manifest.find(&desc, "linux").unwrap();
+        assert_eq!(
+            linux.url,
+            "https://example.org/dist/lean-4.0.0-linux.tar.gz"
+        );
+        assert_eq!(linux.sha256, "ab12");
+        assert_eq!(
+            manifest.find(&desc, "darwin_aarch64").unwrap().url,
+            "https://mirror.example.org/lean-4.0.0-darwin_aarch64.tar.gz"
+        );
+
+        match manifest.find(&desc, "windows").unwrap_err().0 {
+            ErrorKind::UnsupportedPlatform { available, .. } => {
+                assert_eq!(available, vec!["linux", "darwin_aarch64"])
+            }
+            k => panic!("unexpected error: {}", k),
+        }
+        let other = ToolchainDesc::from_str("myorg/lean4:v4.1.0").unwrap();
+        match manifest.find(&other, "linux").unwrap_err().0 {
+            ErrorKind::NotInReleaseManifest { toolchain, .. } => {
+                assert_eq!(toolchain, "myorg-lean4-v4.1.0")
+            }
+            k => panic!("unexpected error: {}", k),
+        }
+
+        assert!(ReleaseManifest::parse("m", r#"[{ "name": "x" }]"#, &base).is_err());
+        assert!(ReleaseManifest::parse("m", r#"{}"#, &base).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! The CPU architectures of binaries, read from their ELF, Mach-O or PE
+//! header, for telling a toolchain copied from another machine apart from
+//! one that is merely broken
+
+use std::env::consts;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Enough for the headers of all formats, including the PE header that the
+/// DOS stub of an `.exe` points to
+const HEADER_LEN: u64 = 4096;
+
+/// The architectures, named like `std::env::consts::ARCH`, that the binary
+/// starting with `header` has code for. Empty if it is not a binary elan
+/// knows, such as a script.
+pub fn binary_archs(header: &[u8]) -> Vec<&'static str> {
+    if header.starts_with(b"\x7fELF") {
+        let big_endian = header.get(5) == Some(&2);
+        return read_u16(header, 18, big_endian)
+            .and_then(elf_arch)
+            .into_iter()
+            .collect();
+    }
+    match read_u32(header, 0, false) {
+        Some(0xfeed_face) | Some(0xfeed_facf) => {
+            return read_u32(header, 4, false)
+                .and_then(mach_o_arch)
+                .into_iter()
+                .collect();
+        }
+        _ => {}
+    }
+    match read_u32(header, 0, true) {
+        // Universal binaries, which Java class files share a magic number
+        // with, though those give a much larger count here
+        Some(magic @ 0xcafe_babe) | Some(magic @ 0xcafe_babf) => {
+            let entry_len = if magic == 0xcafe_babe { 20 } else { 32 };
+            let count = read_u32(header, 4, true).unwrap_or(0);
+            if count == 0 || count > 32 {
+                return vec![];
+            }
+            return (0..count as usize)
+                .filter_map(|i| read_u32(header, 8 + i * entry_len, true))
+                .filter_map(mach_o_arch)
+                .collect();
+        }
+        _ => {}
+    }
+    if header.starts_with(b"MZ") {
+        return read_u32(header, 0x3c, false)
+            .map(|offset| offset as usize)
+            .filter(|&offset| header.get(offset..offset + 4) == Some(b"PE\0\0"))
+            .and_then(|offset| read_u16(header, offset + 4, false))
+            .and_then(pe_arch)
+            .into_iter()
+            .collect();
+    }
+    vec![]
+}
+
+/// Like `binary_archs`, for the binary at `path`
+pub fn read_binary_archs(path: &Path) -> io::Result<Vec<&'static str>> {
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)?;
+    Ok(binary_archs(&header))
+}
+
+/// Whether a binary with code for `archs` can run on the `host` architecture
+/// of the operating system `os`, including through the emulation that
+/// macOS and Windows provide on ARM
+pub fn can_run_on(archs: &[&str], host: &str, os: &str) -> bool {
+    archs.is_empty()
+        || archs.iter().any(|&arch| {
+            arch == host
+                || (host == "x86_64" && arch == "x86")
+                || (host == "aarch64" && os == "macos" && arch == "x86_64")
+                || (host == "aarch64" && os == "windows" && (arch == "x86_64" || arch == "x86"))
+        })
+}
+
+/// Like `can_run_on`, for this machine
+pub fn can_run_here(archs: &[&str]) -> bool {
+    can_run_on(archs, consts::ARCH, consts::OS)
+}
+
+fn elf_arch(machine: u16) -> Option<&'static str> {
+    match machine {
+        0x03 => Some("x86"),
+        0x28 => Some("arm"),
+        0x3e => Some("x86_64"),
+        0xb7 => Some("aarch64"),
+        0xf3 => Some("riscv64"),
+        _ => None,
+    }
+}
+
+fn mach_o_arch(cpu_type: u32) -> Option<&'static str> {
+    match cpu_type {
+        0x0000_0007 => Some("x86"),
+        0x0000_000c => Some("arm"),
+        0x0100_0007 => Some("x86_64"),
+        0x0100_000c => Some("aarch64"),
+        _ => None,
+    }
+}
+
+fn pe_arch(machine: u16) -> Option<&'static str> {
+    match machine {
+        0x014c => Some("x86"),
+        0x01c4 => Some("arm"),
+        0x8664 => Some("x86_64"),
+        0xaa64 => Some("aarch64"),
+        _ => None,
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    let bytes = [bytes[0], bytes[1]];
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile;
+
+    /// The start of a little-endian ELF executable for `machine`
+    fn elf(machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 64];
+        header[..4].copy_from_slice(b"\x7fELF");
+        header[4] = 2;
+        header[5] = 1;
+        header[18..20].copy_from_slice(&machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn test_binary_archs() {
+        assert_eq!(binary_archs(&elf(0xb7)), vec!["aarch64"]);
+        assert_eq!(binary_archs(&elf(0x3e)), vec!["x86_64"]);
+
+        let mut mach_o = vec![0; 32];
+        mach_o[..4].copy_from_slice(&0xfeed_facf_u32.to_le_bytes());
+        mach_o[4..8].copy_from_slice(&0x0100_000c_u32.to_le_bytes());
+        assert_eq!(binary_archs(&mach_o), vec!["aarch64"]);
+
+        let mut universal = vec![0; 48];
+        universal[..4].copy_from_slice(&0xcafe_babe_u32.to_be_bytes());
+        universal[4..8].copy_from_slice(&2_u32.to_be_bytes());
+        universal[8..12].copy_from_slice(&0x0100_0007_u32.to_be_bytes());
+        universal[28..32].copy_from_slice(&0x0100_000c_u32.to_be_bytes());
+        assert_eq!(binary_archs(&universal), vec!["x86_64", "aarch64"]);
+
+        let mut pe = vec![0; 0x90];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c..0x40].copy_from_slice(&0x80_u32.to_le_bytes());
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        pe[0x84..0x86].copy_from_slice(&0x8664_u16.to_le_bytes());
+        assert_eq!(binary_archs(&pe), vec!["x86_64"]);
+
+        assert!(binary_archs(b"#!/bin/sh\n").is_empty());
+        assert!(binary_archs(b"\x7fEL").is_empty());
+    }
+
+    #[test]
+    fn test_foreign_binary_cannot_run() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let lean = dir.path().join("lean");
+        let foreign = if consts::ARCH == "aarch64" {
+            0x3e
+        } else {
+            0xb7
+        };
+        fs::write(&lean, elf(foreign)).unwrap();
+
+        let archs = read_binary_archs(&lean).unwrap();
+        assert_eq!(archs.len(), 1);
+        assert!(!can_run_on(&archs, "riscv64", "linux"));
+        if consts::OS == "linux" {
+            assert!(!can_run_here(&archs));
+        }
+
+        assert!(can_run_on(&["aarch64"], "aarch64", "linux"));
+        assert!(can_run_on(&["x86_64"], "aarch64", "macos"));
+        assert!(!can_run_on(&["x86_64"], "aarch64", "linux"));
+        assert!(!can_run_on(&["aarch64"], "x86_64", "macos"));
+        // Nothing is known about scripts
+        assert!(can_run_on(&[], "x86_64", "linux"));
+    }
+}
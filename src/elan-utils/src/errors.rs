@@ -75,14 +75,14 @@ error_chain! {
             path: PathBuf,
         } {
             description("could not download file")
-            display("could not download file from '{}' to '{}'", url, path.display())
+            display("could not download file from '{}' to '{}'", ::download::redact_url(url), path.display())
         }
         DownloadNotExists {
             url: Url,
             path: PathBuf,
         } {
             description("could not download file")
-            display("could not download file from '{}' to '{}'", url, path.display())
+            display("could not download file from '{}' to '{}'", ::download::redact_url(url), path.display())
         }
         InvalidUrl {
             url: String,
@@ -159,5 +159,35 @@ error_chain! {
         ElanHome {
             description("couldn't find value of ELAN_HOME")
         }
+        IndexTimedOut {
+            url: String,
+            secs: u64,
+        } {
+            description("release metadata request timed out")
+            display("'{}' did not respond within {} seconds; \
+                     pass --index-timeout or set 'index_timeout' in settings.toml to wait longer", url, secs)
+        }
+        UnsupportedReleaseIndex(location: String) {
+            description("unsupported release index")
+            display("the release index must be a local file or a file:// URL, not '{}'", location)
+        }
+        InvalidReleaseIndex(path: PathBuf) {
+            description("invalid release index")
+            display("invalid release index '{}'", path.display())
+        }
+        NotInReleaseIndex {
+            path: PathBuf,
+            repo: String,
+        } {
+            description("repository not in release index")
+            display("the release index '{}' lists no releases of '{}'", path.display(), repo)
+        }
+        UnsetEnvVar {
+            var: String,
+            key: String,
+        } {
+            description("environment variable not set")
+            display("environment variable '{}' used by '{}' is not set", var, key)
+        }
     }
 }
@@ -7,9 +7,16 @@ extern crate error_chain;
 extern crate curl;
 extern crate dirs;
 extern crate download;
+#[macro_use]
+extern crate log;
 extern crate regex;
+extern crate rmp_serde;
 extern crate semver;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
 extern crate sha2;
+#[cfg(test)]
+extern crate tempfile;
 extern crate toml;
 extern crate url;
 
@@ -21,9 +28,14 @@ extern crate winreg;
 #[cfg(unix)]
 extern crate libc;
 
+pub mod arch;
 pub mod errors;
+pub mod log_format;
+pub mod logging;
 pub mod notifications;
+pub mod path_var;
 pub mod raw;
+pub mod semaphore;
 pub mod toml_utils;
 pub mod tty;
 pub mod utils;
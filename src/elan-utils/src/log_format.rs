@@ -0,0 +1,148 @@
+//! The formats telemetry events can be logged in, each event a record of a
+//! log file whose extension names its format
+//!
+//! * `json`: one JSON object per line, the format elan has always written
+//! * `msgpack`: MessagePack values, one after the other, which are about a
+//!   third smaller
+
+use std::io::{self, BufRead};
+
+use rmp_serde::{self, decode};
+use serde_json::{self, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Json,
+    MessagePack,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(LogFormat::Json),
+            "msgpack" => Some(LogFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LogFormat::Json => "json",
+            LogFormat::MessagePack => "msgpack",
+        }
+    }
+
+    /// The extension of log files in this format, before any `.gz`
+    pub fn extension(&self) -> &'static str {
+        self.as_str()
+    }
+
+    /// The format of the log file `filename`, which may be gzip-compressed,
+    /// or `None` if it is not a log file
+    pub fn of_file(filename: &str) -> Option<Self> {
+        let name = filename.trim_end_matches(".gz");
+        [LogFormat::Json, LogFormat::MessagePack]
+            .iter()
+            .cloned()
+            .find(|f| name.ends_with(&format!(".{}", f.extension())))
+    }
+
+    /// `record` as it is appended to a log file
+    pub fn encode(&self, record: &Value) -> Vec<u8> {
+        match *self {
+            LogFormat::Json => {
+                let mut data = serde_json::to_vec(record).unwrap();
+                data.push(b'\n');
+                data
+            }
+            LogFormat::MessagePack => rmp_serde::to_vec(record).unwrap(),
+        }
+    }
+
+    /// The records of a log file. Records that cannot be read, such as one
+    /// cut short by a crash while it was written, are skipped. Since
+    /// MessagePack has no line breaks to start over at, nothing after such a
+    /// record is read from it.
+    pub fn decode_all(&self, input: &mut dyn BufRead) -> io::Result<Vec<Value>> {
+        let mut records = Vec::new();
+        match *self {
+            LogFormat::Json => {
+                for line in input.split(b'\n') {
+                    if let Ok(record) = serde_json::from_slice(&line?) {
+                        records.push(record);
+                    }
+                }
+            }
+            LogFormat::MessagePack => {
+                while !input.fill_buf()?.is_empty() {
+                    match rmp_serde::from_read(&mut *input) {
+                        Ok(record) => records.push(record),
+                        Err(decode::Error::InvalidMarkerRead(e))
+                        | Err(decode::Error::InvalidDataRead(e))
+                            if e.kind() != io::ErrorKind::UnexpectedEof =>
+                        {
+                            return Err(e)
+                        }
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(i: i64) -> Value {
+        json!({
+            "log_time_s": 1_650_000_000 + i,
+            "event": {
+                "LeanRun": {
+                    "duration_ms": 1234 * i,
+                    "exit_code": -i,
+                    "errors": ["E0001", "x".repeat(40 * i as usize)],
+                }
+            },
+            "version": 1,
+            "fields": { "ratio": 0.5, "missing": null, "ok": true },
+        })
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let records: Vec<_> = (0..300).map(record).collect();
+        for &format in &[LogFormat::Json, LogFormat::MessagePack] {
+            let data: Vec<u8> = records.iter().flat_map(|r| format.encode(r)).collect();
+            let decoded = format.decode_all(&mut &data[..]).unwrap();
+            assert_eq!(decoded, records, "{:?}", format);
+            assert_eq!(LogFormat::parse(format.as_str()), Some(format));
+        }
+    }
+
+    #[test]
+    fn test_truncated_record_is_skipped() {
+        for &format in &[LogFormat::Json, LogFormat::MessagePack] {
+            let mut data = format.encode(&record(1));
+            let second = format.encode(&record(2));
+            data.extend_from_slice(&second[..second.len() / 2]);
+            let decoded = format.decode_all(&mut &data[..]).unwrap();
+            assert_eq!(decoded, vec![record(1)], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_format_of_file() {
+        assert_eq!(
+            LogFormat::of_file("log-2022-04-15.json"),
+            Some(LogFormat::Json)
+        );
+        assert_eq!(
+            LogFormat::of_file("log-2022-04-15.msgpack.gz"),
+            Some(LogFormat::MessagePack)
+        );
+        assert_eq!(LogFormat::of_file("log.lock"), None);
+    }
+}
@@ -0,0 +1,192 @@
+//! Debug logging of elan's own behavior, enabled by `ELAN_LOG`
+//!
+//! The filter takes comma-separated directives of the form `level` or
+//! `target=level`, e.g. `ELAN_LOG=debug` or `ELAN_LOG=info,elan_dist=trace`.
+//! A directive applies to its target and every module below it, with the
+//! longest matching target winning. Logs go to stderr and nothing is logged
+//! unless the variable is set.
+
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use std::env;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+pub const LOG_ENV_VAR: &str = "ELAN_LOG";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Most specific target last
+    directives: Vec<(Option<String>, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Self {
+        let mut directives = Vec::new();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (target, level) = match directive.find('=') {
+                Some(i) => (Some(&directive[..i]), &directive[i + 1..]),
+                None => match directive.parse::<LevelFilter>() {
+                    Ok(_) => (None, directive),
+                    // A bare module name enables everything in it
+                    Err(_) => (Some(directive), "trace"),
+                },
+            };
+            if let Ok(level) = level.trim().parse() {
+                directives.push((target.map(|t| t.trim().to_owned()), level));
+            }
+        }
+        directives.sort_by_key(|(target, _)| target.as_ref().map_or(0, |t| t.len()));
+        Filter { directives }
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .rev()
+            .find(|(t, _)| match t {
+                Some(t) => {
+                    target == t
+                        || (target.starts_with(t.as_str()) && target[t.len()..].starts_with("::"))
+                }
+                None => true,
+            })
+            .map_or(LevelFilter::Off, |&(_, level)| level)
+    }
+
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.level_for(target)
+    }
+
+    /// The most verbose level enabled for any target
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|&(_, level)| level)
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+}
+
+pub struct Logger {
+    filter: Filter,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Logger {
+    pub fn new(filter: Filter, out: Box<dyn Write + Send>) -> Self {
+        Logger {
+            filter,
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(
+                out,
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
+
+/// Installs the logger if `ELAN_LOG` is set
+pub fn init() {
+    let spec = match env::var(LOG_ENV_VAR) {
+        Ok(ref s) if !s.is_empty() => s.clone(),
+        _ => return,
+    };
+    let filter = Filter::parse(&spec);
+    let max_level = filter.max_level();
+    let logger = Logger::new(filter, Box::new(io::stderr()));
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logged(spec: &str) -> String {
+        let buffer = Buffer::default();
+        let logger = Logger::new(Filter::parse(spec), Box::new(buffer.clone()));
+        for &(level, target) in &[
+            (Level::Debug, "elan::config"),
+            (Level::Info, "elan_dist::dist"),
+            (Level::Trace, "elan_dist::download"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("message"))
+                    .build(),
+            );
+        }
+        let out = buffer.0.lock().unwrap();
+        String::from_utf8(out.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_logs_only_when_enabled() {
+        assert_eq!(logged(""), "");
+        assert_eq!(logged("warn"), "");
+        assert_eq!(
+            logged("debug"),
+            "[DEBUG elan::config] message\n[INFO elan_dist::dist] message\n"
+        );
+        assert_eq!(
+            logged("off,elan_dist=trace"),
+            "[INFO elan_dist::dist] message\n[TRACE elan_dist::download] message\n"
+        );
+        assert_eq!(
+            logged("trace,elan_dist::download=off"),
+            "[DEBUG elan::config] message\n[INFO elan_dist::dist] message\n"
+        );
+        // Targets match whole module path segments only
+        assert_eq!(logged("elan_d=trace"), "");
+        assert_eq!(logged("elan"), "[DEBUG elan::config] message\n");
+    }
+
+    #[test]
+    fn test_max_level() {
+        assert_eq!(Filter::parse("").max_level(), LevelFilter::Off);
+        assert_eq!(
+            Filter::parse("warn,elan::config=debug").max_level(),
+            LevelFilter::Debug
+        );
+        assert_eq!(Filter::parse("bogus=loud").max_level(), LevelFilter::Off);
+    }
+}
@@ -20,6 +20,8 @@ pub enum Notification<'a> {
     DownloadFinished,
     NoCanonicalPath(&'a Path),
     ResumingPartialDownload,
+    /// Trying a failed download again, for the given attempt
+    RetryingDownload(u32),
     UsingCurl,
     UsingReqwest,
     UsingHyperDeprecated,
@@ -39,6 +41,7 @@ impl<'a> Notification<'a> {
             | ResumingPartialDownload
             | UsingCurl
             | UsingReqwest => NotificationLevel::Verbose,
+            RetryingDownload(_) => NotificationLevel::Info,
             UsingHyperDeprecated | NoCanonicalPath(_) => NotificationLevel::Warn,
         }
     }
@@ -62,6 +65,9 @@ impl<'a> Display for Notification<'a> {
             DownloadFinished => write!(f, "download finished"),
             NoCanonicalPath(path) => write!(f, "could not canonicalize path: '{}'", path.display()),
             ResumingPartialDownload => write!(f, "resuming partial download"),
+            RetryingDownload(attempt) => {
+                write!(f, "download failed, trying again (attempt {})", attempt)
+            }
             UsingCurl => write!(f, "downloading with curl"),
             UsingReqwest => write!(f, "downloading with reqwest"),
             UsingHyperDeprecated => f.write_str(
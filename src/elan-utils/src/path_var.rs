@@ -0,0 +1,95 @@
+//! How a directory of a toolchain is added to a path-like variable, such as
+//! `LEAN_PATH`, that the user may have set already
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathVarMode {
+    /// Only set the variable if the user has not
+    Defer,
+    /// Put the toolchain's directories before the user's
+    Prepend,
+    /// Put the toolchain's directories after the user's
+    Append,
+}
+
+impl PathVarMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "defer" => Some(PathVarMode::Defer),
+            "prepend" => Some(PathVarMode::Prepend),
+            "append" => Some(PathVarMode::Append),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PathVarMode::Defer => "defer",
+            PathVarMode::Prepend => "prepend",
+            PathVarMode::Append => "append",
+        }
+    }
+}
+
+/// The value to set a variable whose current value is `old` to so that it
+/// includes `dirs` as `mode` says, or `None` if it is to be left alone or
+/// the result cannot be joined
+pub fn combined_path(
+    mode: PathVarMode,
+    old: Option<&OsStr>,
+    dirs: Vec<PathBuf>,
+) -> Option<OsString> {
+    let old: Vec<PathBuf> = match old {
+        Some(old) if !old.is_empty() => env::split_paths(old).collect(),
+        _ => vec![],
+    };
+    let parts = match mode {
+        PathVarMode::Defer if !old.is_empty() => return None,
+        PathVarMode::Defer => dirs,
+        PathVarMode::Prepend => dirs.into_iter().chain(old).collect(),
+        PathVarMode::Append => old.into_iter().chain(dirs).collect(),
+    };
+    env::join_paths(parts).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combined_path() {
+        let lib = PathBuf::from("toolchain").join("lib").join("lean");
+        let user = env::join_paths(["mine", "theirs"]).unwrap();
+        let split = |value: OsString| env::split_paths(&value).collect::<Vec<_>>();
+
+        let appended = combined_path(PathVarMode::Append, Some(&user), vec![lib.clone()]);
+        assert_eq!(
+            split(appended.unwrap()),
+            vec![PathBuf::from("mine"), PathBuf::from("theirs"), lib.clone()]
+        );
+        let prepended = combined_path(PathVarMode::Prepend, Some(&user), vec![lib.clone()]);
+        assert_eq!(
+            split(prepended.unwrap()),
+            vec![lib.clone(), PathBuf::from("mine"), PathBuf::from("theirs")]
+        );
+        assert_eq!(
+            combined_path(PathVarMode::Defer, Some(&user), vec![lib.clone()]),
+            None
+        );
+
+        for &mode in &[
+            PathVarMode::Defer,
+            PathVarMode::Prepend,
+            PathVarMode::Append,
+        ] {
+            for old in &[None, Some(OsStr::new(""))] {
+                let value = combined_path(mode, *old, vec![lib.clone()]);
+                assert_eq!(split(value.unwrap()), vec![lib.clone()]);
+            }
+            assert_eq!(PathVarMode::parse(mode.as_str()), Some(mode));
+        }
+    }
+}
@@ -8,9 +8,11 @@ use std::fmt;
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::Path;
-use std::process::{Command, ExitStatus};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Output, Stdio};
 use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use rand::random;
 
@@ -132,6 +134,94 @@ pub fn append_file(dest: &Path, line: &str) -> io::Result<()> {
     Ok(())
 }
 
+/// Exclusive access to something shared between processes, for as long as
+/// this lives
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The process recorded as holding the lock file at `path`, if it has
+/// written its id yet
+fn lock_holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Creates the lock file at `path`, waiting up to `timeout` for another
+/// process to remove it. The lock records the id of its holder, so that one
+/// left behind by a process that has died is taken over. Locks are meant to
+/// be held briefly, so `None` is returned on timeout or when the file cannot
+/// be created at all, leaving it to the caller whether to go ahead
+/// regardless.
+pub fn lock_file(path: &Path, timeout: Duration) -> Option<LockFile> {
+    let start = Instant::now();
+    loop {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(mut file) => {
+                let lock = LockFile {
+                    path: path.to_owned(),
+                };
+                return match write!(file, "{}", process::id()) {
+                    Ok(()) => Some(lock),
+                    Err(_) => None,
+                };
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(_) => return None,
+        }
+
+        match lock_holder(path) {
+            // Checked again right before removing it, in case another
+            // process took over the stale lock meanwhile
+            Some(pid) if !process_is_running(pid) && lock_holder(path) == Some(pid) => {
+                let _ = fs::remove_file(path);
+            }
+            _ if start.elapsed() >= timeout => return None,
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+/// Whether the process with id `pid` is still running, such as the one that
+/// left a file or directory behind. Processes elan is not allowed to inspect
+/// count as running.
+#[cfg(unix)]
+pub fn process_is_running(pid: u32) -> bool {
+    let signalled = unsafe { libc::kill(pid as libc::pid_t, 0) == 0 };
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+pub fn process_is_running(pid: u32) -> bool {
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::shared::winerror::ERROR_ACCESS_DENIED;
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+    const STILL_ACTIVE: DWORD = 259;
+
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if handle.is_null() {
+            return GetLastError() == ERROR_ACCESS_DENIED;
+        }
+        let mut code = 0;
+        let running = GetExitCodeProcess(handle, &mut code) == 0 || code == STILL_ACTIVE;
+        CloseHandle(handle);
+        running
+    }
+}
+
 pub fn tee_file<W: io::Write>(path: &Path, w: &mut W) -> io::Result<()> {
     let mut file = fs::OpenOptions::new().read(true).open(path)?;
 
@@ -306,6 +396,192 @@ pub fn cmd_status(cmd: &mut Command) -> CommandResult<()> {
     })
 }
 
+/// Runs `cmd` without input, capturing its output instead of inheriting ours
+pub fn cmd_output(cmd: &mut Command) -> io::Result<Output> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+}
+
+/// Passes on what is written to it without ANSI escape sequences such as
+/// colors, for output that is going somewhere other than a terminal.
+/// Sequences may be split across writes.
+pub struct StripAnsi<W> {
+    inner: W,
+    state: AnsiState,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AnsiState {
+    Text,
+    /// After an `ESC`
+    Escape,
+    /// In a control sequence, `ESC [` up to its final byte
+    Control,
+    /// In an operating system command, `ESC ]` up to `BEL` or `ESC \`
+    Command,
+    /// After an `ESC` in an operating system command
+    CommandEscape,
+}
+
+impl<W: Write> StripAnsi<W> {
+    pub fn new(inner: W) -> Self {
+        StripAnsi {
+            inner,
+            state: AnsiState::Text,
+        }
+    }
+}
+
+impl<W: Write> Write for StripAnsi<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut text = Vec::with_capacity(buf.len());
+        for &b in buf {
+            self.state = match (self.state, b) {
+                (AnsiState::Text, 0x1b) => AnsiState::Escape,
+                (AnsiState::Text, _) => {
+                    text.push(b);
+                    AnsiState::Text
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Control,
+                (AnsiState::Escape, b']') => AnsiState::Command,
+                (AnsiState::Escape, _) => AnsiState::Text,
+                (AnsiState::Control, 0x40..=0x7e) => AnsiState::Text,
+                (AnsiState::Control, _) => AnsiState::Control,
+                (AnsiState::Command, 0x07) => AnsiState::Text,
+                (AnsiState::Command, 0x1b) => AnsiState::CommandEscape,
+                (AnsiState::Command, _) => AnsiState::Command,
+                (AnsiState::CommandEscape, b'\\') => AnsiState::Text,
+                (AnsiState::CommandEscape, _) => AnsiState::Command,
+            };
+        }
+        self.inner.write_all(&text)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies the lines of `reader` to `out`, passing each to `scan` as well.
+/// Once writing fails, such as when `out` is a closed pipe, the remaining
+/// lines are only scanned. Returns the error that stopped the copy, if any.
+pub fn tee_lines<R, W, F>(mut reader: R, out: &mut W, mut scan: F) -> Option<io::Error>
+where
+    R: io::BufRead,
+    W: Write,
+    F: FnMut(&[u8]),
+{
+    let mut write_error = None;
+    let mut line = Vec::new();
+    // Lines are read as bytes as they need not be valid UTF-8
+    while let Ok(n) = reader.read_until(b'\n', &mut line) {
+        if n == 0 {
+            break;
+        }
+        if write_error.is_none() {
+            if let Err(e) = out.write_all(&line).and_then(|()| out.flush()) {
+                write_error = Some(e);
+            }
+        }
+        scan(&line);
+        line.clear();
+    }
+    write_error
+}
+
+/// How a command waited for by `wait_forwarding_stderr` finished
+#[derive(Debug)]
+pub struct ForwardedExit {
+    /// 1 if the command was killed by a signal
+    pub exit_code: i32,
+    /// When the command exited, before its stderr was forwarded
+    pub exited_at: Instant,
+    /// Bytes appended to the captured stderr after the command exited, by
+    /// processes it left behind. These are forwarded without being scanned.
+    pub late_output: u64,
+}
+
+/// Waits for `child`, then copies the stderr it wrote to `captured` to
+/// `out` with `tee_lines`. Without a capture file, `child` is assumed to
+/// have written to `out` directly.
+pub fn wait_forwarding_stderr<W, F>(
+    child: &mut Child,
+    captured: Option<fs::File>,
+    out: &mut W,
+    scan: F,
+) -> io::Result<ForwardedExit>
+where
+    W: Write,
+    F: FnMut(&[u8]),
+{
+    let status = child.wait()?;
+    let exited_at = Instant::now();
+    let late_output = match captured {
+        Some(file) => forward_captured(file, out, scan),
+        None => 0,
+    };
+    Ok(ForwardedExit {
+        exit_code: status.code().unwrap_or(1),
+        exited_at,
+        late_output,
+    })
+}
+
+/// Returns the number of bytes forwarded without scanning
+fn forward_captured<W, F>(file: fs::File, out: &mut W, scan: F) -> u64
+where
+    W: Write,
+    F: FnMut(&[u8]),
+{
+    use std::io::{BufReader, Read};
+
+    // Processes spawned by the child may have inherited the capture
+    // file and still be appending to it, so only scan what had been
+    // written by the time the child exited. The file is read without
+    // moving the offset they write at, which would have them overwrite
+    // what was not forwarded yet.
+    let captured_len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut reader = ReadAt {
+        file: &file,
+        offset: 0,
+    };
+    let write_error = tee_lines(BufReader::new((&mut reader).take(captured_len)), out, scan);
+
+    // Still pass on anything written in the meantime, just without
+    // scanning it, unless `out` is gone
+    if write_error.is_none() {
+        io::copy(&mut reader, out).unwrap_or(0)
+    } else {
+        0
+    }
+}
+
+/// Reads a file from `offset` on, independently of the file's own offset
+struct ReadAt<'a> {
+    file: &'a fs::File,
+    offset: u64,
+}
+
+impl<'a> io::Read for ReadAt<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        let n = {
+            use std::os::unix::fs::FileExt;
+            self.file.read_at(buf, self.offset)?
+        };
+        // Unlike on Unix, this moves the file's own offset
+        #[cfg(windows)]
+        let n = {
+            use std::os::windows::fs::FileExt;
+            self.file.seek_read(buf, self.offset)?
+        };
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
 pub fn remove_dir(path: &Path) -> io::Result<()> {
     if fs::symlink_metadata(path)?.file_type().is_symlink() {
         if cfg!(windows) {
@@ -356,11 +632,53 @@ pub fn find_cmd<'a>(cmds: &[&'a str]) -> Option<&'a str> {
     cmds.into_iter().map(|&s| s).filter(|&s| has_cmd(s)).next()
 }
 
+/// The commands listed in a `$BROWSER` value, each with `path` substituted
+/// for `%s` or, if there is no `%s`, appended as the last argument
+fn browser_commands(browser: &str, path: &Path) -> Vec<(String, Vec<OsString>)> {
+    let separator = if cfg!(windows) { ';' } else { ':' };
+    browser
+        .split(separator)
+        .filter_map(|entry| {
+            let mut words = entry.split_whitespace();
+            let program = words.next()?.to_owned();
+            let mut substituted = false;
+            let mut args: Vec<OsString> = words
+                .map(|word| {
+                    if word.contains("%s") {
+                        substituted = true;
+                        word.replace("%s", &path.to_string_lossy()).into()
+                    } else {
+                        word.into()
+                    }
+                })
+                .collect();
+            if !substituted {
+                args.push(path.into());
+            }
+            Some((program, args))
+        })
+        .collect()
+}
+
 pub fn open_browser(path: &Path) -> io::Result<bool> {
+    use std::process::Stdio;
+
+    if let Ok(browser) = env::var("BROWSER") {
+        for (program, args) in browser_commands(&browser, path) {
+            let spawned = Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            if spawned.is_ok() {
+                return Ok(true);
+            }
+        }
+    }
+
     #[cfg(not(windows))]
     fn inner(path: &Path) -> io::Result<bool> {
-        use std::process::Stdio;
-
         let commands = [
             "xdg-open",
             "open",
@@ -480,3 +798,233 @@ pub mod windows {
         inner(s.as_ref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_file_of_a_dead_holder_is_taken_over() {
+        use tempfile;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        let mut child = Command::new(env!("CARGO"))
+            .arg("--version")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+
+        fs::write(&path, dead_pid.to_string()).unwrap();
+        let lock = lock_file(&path, Duration::from_secs(10)).unwrap();
+        assert_eq!(lock_holder(&path), Some(process::id()));
+
+        // Still held by a running process, however old the lock
+        assert!(lock_file(&path, Duration::from_millis(50)).is_none());
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_browser_commands() {
+        let path = Path::new("/elan/toolchains/stable/share/doc/lean/html/index.html");
+        let page = OsString::from(path);
+
+        assert_eq!(
+            browser_commands("firefox --new-window", path),
+            vec![(
+                "firefox".to_owned(),
+                vec!["--new-window".into(), page.clone()]
+            )]
+        );
+        assert_eq!(
+            browser_commands("open-url --page=%s", path),
+            vec![(
+                "open-url".to_owned(),
+                vec![format!("--page={}", path.display()).into()]
+            )]
+        );
+        if !cfg!(windows) {
+            assert_eq!(
+                browser_commands("w3m::lynx", path),
+                vec![
+                    ("w3m".to_owned(), vec![page.clone()]),
+                    ("lynx".to_owned(), vec![page.clone()]),
+                ]
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cmd_output() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf lean; cat; printf oops >&2; exit 3"]);
+        let output = cmd_output(&mut cmd).unwrap();
+        assert_eq!(output.stdout, b"lean");
+        assert_eq!(output.stderr, b"oops");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_forwarding_stderr() {
+        use tempfile;
+
+        let captured = tempfile::tempfile().unwrap();
+        let mut child = Command::new("sh")
+            .args(["-c", "echo 'oops [E0003]' >&2; exit 3"])
+            .stderr(Stdio::from(captured.try_clone().unwrap()))
+            .spawn()
+            .unwrap();
+        let mut out = Vec::new();
+        let mut lines = Vec::new();
+        let exit = wait_forwarding_stderr(&mut child, Some(captured), &mut out, |l| {
+            lines.push(l.to_owned())
+        })
+        .unwrap();
+
+        assert_eq!(exit.exit_code, 3);
+        assert_eq!(exit.late_output, 0);
+        assert_eq!(out, b"oops [E0003]\n");
+        assert_eq!(lines, [&b"oops [E0003]\n"[..]]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_late_output_of_grandchild() {
+        use std::thread;
+        use std::time::Duration;
+        use tempfile;
+
+        let dir = tempfile::Builder::new()
+            .prefix("elan-late")
+            .tempdir()
+            .unwrap();
+        let written = dir.path().join("written");
+        let captured = tempfile::tempfile().unwrap();
+        // The grandchild keeps the capture file open after the child exits
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                "(sleep 0.2; echo 'late [E0004]' >&2; touch \"$1\") & echo 'early [E0001]' >&2",
+                "sh",
+            ])
+            .arg(&written)
+            .stderr(Stdio::from(captured.try_clone().unwrap()))
+            .spawn()
+            .unwrap();
+        let mut out = Vec::new();
+        let mut lines = Vec::new();
+        let exit = wait_forwarding_stderr(&mut child, Some(captured), &mut out, |l| {
+            lines.push(l.to_owned());
+            // Let the grandchild write while the child's output is scanned
+            for _ in 0..100 {
+                if written.exists() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        })
+        .unwrap();
+
+        assert_eq!(exit.exit_code, 0);
+        assert_eq!(lines, [&b"early [E0001]\n"[..]]);
+        assert_eq!(out, b"early [E0001]\nlate [E0004]\n");
+        assert_eq!(exit.late_output, b"late [E0004]\n".len() as u64);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_forwarding_stripped_of_color() {
+        use tempfile;
+
+        let captured = tempfile::tempfile().unwrap();
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                r"printf '\033[31merror\033[0m: oops \033[1m[E0003]\033[0m\n\033]0;title\007done\n' >&2",
+            ])
+            .stderr(Stdio::from(captured.try_clone().unwrap()))
+            .spawn()
+            .unwrap();
+        let mut out = StripAnsi::new(Vec::new());
+        let mut codes = Vec::new();
+        wait_forwarding_stderr(&mut child, Some(captured), &mut out, |l| {
+            let l = String::from_utf8_lossy(l);
+            if let Some(i) = l.find("[E") {
+                codes.push(l[i..i + 7].to_owned());
+            }
+        })
+        .unwrap();
+
+        assert_eq!(out.inner, b"error: oops [E0003]\ndone\n");
+        assert_eq!(codes, ["[E0003]"]);
+    }
+
+    #[test]
+    fn test_strip_ansi_split_writes() {
+        let mut out = StripAnsi::new(Vec::new());
+        for chunk in &[&b"a\x1b"[..], b"[3", b"8;5;1mb\x1b", b"]8;;x\x1b", b"\\c"] {
+            out.write_all(chunk).unwrap();
+        }
+        assert_eq!(out.inner, b"abc");
+    }
+
+    /// Takes at most `chunk` bytes per write and fails once `limit` bytes
+    /// have been written, like a slow terminal or a pipe closed by its reader
+    struct ShortWriter {
+        written: Vec<u8>,
+        chunk: usize,
+        limit: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() >= self.limit {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+            }
+            let n = buf.len().min(self.chunk);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tee_lines_short_writes() {
+        let input = "error: unknown identifier [E0001]\nsecond line\nno newline";
+        let mut out = ShortWriter {
+            written: Vec::new(),
+            chunk: 3,
+            limit: usize::MAX,
+        };
+        let mut lines = Vec::new();
+        let error = tee_lines(input.as_bytes(), &mut out, |l| lines.push(l.to_owned()));
+
+        assert!(error.is_none());
+        assert_eq!(out.written, input.as_bytes());
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_tee_lines_broken_pipe() {
+        let input = "first line\nsecond line [E0002]\n";
+        let mut out = ShortWriter {
+            written: Vec::new(),
+            chunk: 4,
+            limit: 4,
+        };
+        let mut lines = Vec::new();
+        let error = tee_lines(input.as_bytes(), &mut out, |l| lines.push(l.to_owned()));
+
+        assert_eq!(error.map(|e| e.kind()), Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(out.written, b"firs");
+        // Everything is still scanned
+        assert_eq!(lines, [&b"first line\n"[..], &b"second line [E0002]\n"[..]]);
+    }
+}
@@ -0,0 +1,108 @@
+//! A counting semaphore, for limiting how many threads of a process do
+//! something at once
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Returns its permit to the semaphore when dropped
+pub struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// At least one permit is always available
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until a permit is available and takes it
+    pub fn acquire(&self) -> SemaphoreGuard<'_> {
+        let mut available = self.lock();
+        while *available == 0 {
+            available = self
+                .released
+                .wait(available)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= 1;
+        SemaphoreGuard { semaphore: self }
+    }
+
+    // A panic while holding the lock cannot leave the count inconsistent
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.available.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        *self.semaphore.lock() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raw;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile;
+
+    #[test]
+    fn test_concurrent_appends() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let log = dir.path().join("log.json");
+        let lock = dir.path().join("log.lock");
+
+        for &permits in &[1, 3] {
+            let _ = fs::remove_file(&log);
+            let semaphore = Arc::new(Semaphore::new(permits));
+            let holders = Arc::new(AtomicUsize::new(0));
+            let max_holders = Arc::new(AtomicUsize::new(0));
+
+            let threads: Vec<_> = (0..16)
+                .map(|i| {
+                    let (semaphore, holders, max_holders) =
+                        (semaphore.clone(), holders.clone(), max_holders.clone());
+                    let (log, lock) = (log.clone(), lock.clone());
+                    thread::spawn(move || {
+                        let _permit = semaphore.acquire();
+                        let held = holders.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_holders.fetch_max(held, Ordering::SeqCst);
+                        {
+                            let _lock = raw::lock_file(&lock, Duration::from_secs(10));
+                            raw::append_file(&log, &format!("event {}", i)).unwrap();
+                        }
+                        thread::sleep(Duration::from_millis(5));
+                        holders.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for t in threads {
+                t.join().unwrap();
+            }
+
+            assert!(max_holders.load(Ordering::SeqCst) <= permits);
+            let mut events: Vec<_> = fs::read_to_string(&log)
+                .unwrap()
+                .lines()
+                .map(|l| l.to_owned())
+                .collect();
+            events.sort();
+            events.dedup();
+            assert_eq!(events.len(), 16);
+            assert!(!lock.exists());
+        }
+    }
+}
@@ -55,6 +55,22 @@ pub fn get_opt_bool(table: &mut toml::value::Table, key: &str, path: &str) -> Re
     }
 }
 
+pub fn get_opt_integer(
+    table: &mut toml::value::Table,
+    key: &str,
+    path: &str,
+) -> Result<Option<i64>> {
+    if let Ok(v) = get_value(table, key, path) {
+        if let toml::Value::Integer(i) = v {
+            Ok(Some(i))
+        } else {
+            Err(ErrorKind::ExpectedType("integer", path.to_owned() + key).into())
+        }
+    } else {
+        Ok(None)
+    }
+}
+
 pub fn get_table(
     table: &mut toml::value::Table,
     key: &str,
@@ -86,3 +102,90 @@ pub fn get_array(
         Ok(toml::value::Array::new())
     }
 }
+
+pub fn get_string_array(
+    table: &mut toml::value::Table,
+    key: &str,
+    path: &str,
+) -> Result<Vec<String>> {
+    get_array(table, key, path)?
+        .into_iter()
+        .map(|v| match v {
+            toml::Value::String(s) => Ok(s),
+            _ => Err(ErrorKind::ExpectedType("string", path.to_owned() + key).into()),
+        })
+        .collect()
+}
+
+/// Merges `overlay` into `base`, with the keys of `overlay` taking
+/// precedence. Tables are merged key by key, all other values replaced.
+pub fn merge_tables(base: &mut toml::value::Table, overlay: toml::value::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(s: &str) -> toml::value::Table {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_merge_tables() {
+        let mut settings = table(
+            r#"
+                version = "12"
+                proxy = "http://system.example:3128"
+                no_proxy = "localhost"
+                lean_args = ["-DmaxHeartbeats=400000"]
+            "#,
+        );
+        let layers = [
+            r#"
+                version = "12"
+                default_toolchain = "stable"
+                proxy = "http://user.example:3128"
+
+                [overrides]
+                "/home/user/old" = "3.4.2"
+            "#,
+            r#"
+                proxy = "http://project.example:3128"
+                lean_args = []
+
+                [overrides]
+                "/home/user/project" = "nightly"
+            "#,
+        ];
+        for layer in &layers {
+            merge_tables(&mut settings, table(layer));
+        }
+
+        assert_eq!(
+            settings,
+            table(
+                r#"
+                    version = "12"
+                    default_toolchain = "stable"
+                    proxy = "http://project.example:3128"
+                    no_proxy = "localhost"
+                    lean_args = []
+
+                    [overrides]
+                    "/home/user/old" = "3.4.2"
+                    "/home/user/project" = "nightly"
+                "#
+            )
+        );
+    }
+}
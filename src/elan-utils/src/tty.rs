@@ -1,3 +1,16 @@
+use notify::NotificationLevel;
+use raw;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Set to keep elan from ever adding `--color` to the arguments of a child
+pub const NO_COLOR_INJECT_ENV_VAR: &str = "ELAN_NO_COLOR_INJECT";
+/// Set to `1` to format elan's diagnostics as GitHub Actions workflow
+/// commands, or to `0` not to even when running in GitHub Actions
+pub const GITHUB_ACTIONS_ENV_VAR: &str = "ELAN_GITHUB_ACTIONS";
+
 // Copied from rustc. atty crate did not work as expected
 #[cfg(unix)]
 pub fn stderr_isatty() -> bool {
@@ -44,3 +57,455 @@ pub fn stdout_isatty() -> bool {
         GetConsoleMode(handle, &mut out) != 0
     }
 }
+
+#[cfg(unix)]
+pub fn stdin_isatty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) != 0 }
+}
+
+#[cfg(windows)]
+pub fn stdin_isatty() -> bool {
+    type DWORD = u32;
+    type BOOL = i32;
+    type HANDLE = *mut u8;
+    const STD_INPUT_HANDLE: DWORD = -10i32 as DWORD;
+    extern "system" {
+        fn GetStdHandle(which: DWORD) -> HANDLE;
+        fn GetConsoleMode(hConsoleHandle: HANDLE, lpMode: *mut DWORD) -> BOOL;
+    }
+    unsafe {
+        let handle = GetStdHandle(STD_INPUT_HANDLE);
+        let mut out = 0;
+        GetConsoleMode(handle, &mut out) != 0
+    }
+}
+
+/// Whether to color output, as chosen with `elan --color` or the `color`
+/// setting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorChoice {
+    Never,
+    Auto,
+    Always,
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "never" => Some(ColorChoice::Never),
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ColorChoice::Never => "never",
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+        }
+    }
+
+    /// Whether to color output to a stream, `isatty` being whether it is a
+    /// terminal
+    pub fn should_color(self, isatty: bool) -> bool {
+        match self {
+            ColorChoice::Never => false,
+            ColorChoice::Auto => isatty,
+            ColorChoice::Always => true,
+        }
+    }
+
+    /// The arguments to add to `args` of a child whose stderr is captured,
+    /// so that it colors its output as if it were writing to our stderr.
+    /// A `--color` already in `args` is left alone.
+    pub fn child_color_args<S: AsRef<OsStr>>(
+        self,
+        stderr_isatty: bool,
+        args: &[S],
+    ) -> Vec<&'static str> {
+        let has_color_args = args.iter().any(|e| {
+            let e = e.as_ref().to_str().unwrap_or("");
+            e.starts_with("--color")
+        });
+        if !has_color_args && self.should_color(stderr_isatty) {
+            vec!["--color", "always"]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Like `ColorChoice::child_color_args`, but adding nothing at all unless
+/// `inject`, whatever the choice and terminal
+pub fn injected_color_args<S: AsRef<OsStr>>(
+    choice: ColorChoice,
+    inject: bool,
+    stderr_isatty: bool,
+    args: &[S],
+) -> Vec<&'static str> {
+    if inject {
+        choice.child_color_args(stderr_isatty, args)
+    } else {
+        vec![]
+    }
+}
+
+/// How download progress is shown, as chosen with `--progress`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgressMode {
+    /// Updating one line on a terminal, and occasional lines otherwise
+    Auto,
+    /// Occasional lines, as in logs, even on a terminal
+    Plain,
+    None,
+}
+
+impl ProgressMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(ProgressMode::Auto),
+            "plain" => Some(ProgressMode::Plain),
+            "none" => Some(ProgressMode::None),
+            _ => None,
+        }
+    }
+
+    /// Whether progress is shown by updating a line in place, `isatty`
+    /// being whether it goes to a terminal, or `None` if it is not shown
+    pub fn interactive(&self, isatty: bool) -> Option<bool> {
+        match *self {
+            ProgressMode::Auto => Some(isatty),
+            ProgressMode::Plain => Some(false),
+            ProgressMode::None => None,
+        }
+    }
+}
+
+/// When progress is not updated in place, it is printed at most this often,
+/// in seconds
+const NON_INTERACTIVE_INTERVAL: u32 = 10;
+
+/// Counts the seconds of a download from the times its data arrives, to
+/// tell at which of them its progress is shown
+#[derive(Clone, Debug, Default)]
+pub struct ProgressTicks {
+    /// Time stamp of the last second
+    last_sec: Option<f64>,
+    /// How many seconds have elapsed since the download started
+    seconds_elapsed: u32,
+}
+
+impl ProgressTicks {
+    /// Notes data arriving at `now`, in seconds. Returns `None` unless
+    /// another second has elapsed, and then whether progress shown as
+    /// `interactive` would be updated, which is every second on a terminal
+    /// and only every few seconds otherwise.
+    pub fn tick(&mut self, now: f64, interactive: bool) -> Option<bool> {
+        match self.last_sec {
+            None => {
+                self.last_sec = Some(now);
+                None
+            }
+            Some(start) if now - start >= 1.0 => {
+                self.seconds_elapsed += 1;
+                self.last_sec = Some(now);
+                let interval = if interactive {
+                    1
+                } else {
+                    NON_INTERACTIVE_INTERVAL
+                };
+                Some(self.seconds_elapsed.is_multiple_of(interval))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// How elan writes its own diagnostics, as chosen with `--output-format`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    /// Errors and warnings as GitHub Actions workflow commands, which the
+    /// runner shows as annotations
+    Github,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "human" => Some(OutputFormat::Human),
+            "github" => Some(OutputFormat::Github),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OutputFormat::Human => "human",
+            OutputFormat::Github => "github",
+        }
+    }
+
+    /// The format when none is chosen: `github` if `ELAN_GITHUB_ACTIONS` is
+    /// `1`, or if it is unset and `GITHUB_ACTIONS` is `true` as in GitHub
+    /// Actions runners
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// `from_env` with the environment's variables as looked up by `var`
+    fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> Self {
+        let github = match var(GITHUB_ACTIONS_ENV_VAR).as_deref() {
+            Some("1") => true,
+            Some("0") => false,
+            _ => var("GITHUB_ACTIONS").as_deref() == Some("true"),
+        };
+        if github {
+            OutputFormat::Github
+        } else {
+            OutputFormat::Human
+        }
+    }
+
+    /// The line to write `message` of `level` as, or `None` if it is
+    /// written the usual way
+    pub fn format_line(&self, level: &NotificationLevel, message: &str) -> Option<String> {
+        let command = match (*self, level) {
+            (OutputFormat::Github, &NotificationLevel::Error) => "error",
+            (OutputFormat::Github, &NotificationLevel::Warn) => "warning",
+            _ => return None,
+        };
+        let message = message
+            .replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A");
+        Some(format!("::{}::{}", command, message))
+    }
+}
+
+/// Asks `question` on `output`, listing `choices`, and reads the answer
+/// from `input`: the number or name of a choice, or nothing for the first.
+/// `None` if the answer is anything else, such as `n`, or input ends, and
+/// without asking at all unless `interactive`, so that scripts never wait
+/// for an answer.
+pub fn pick<'a, R: BufRead, W: Write>(
+    interactive: bool,
+    question: &str,
+    choices: &[&'a str],
+    mut input: R,
+    mut output: W,
+) -> io::Result<Option<&'a str>> {
+    if !interactive || choices.is_empty() {
+        return Ok(None);
+    }
+    writeln!(output, "{}", question)?;
+    for (i, choice) in choices.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        writeln!(output, "{}) {}{}", i + 1, choice, default)?;
+    }
+    write!(output, "enter a number, or anything else to cancel: ")?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(Some(choices[0]));
+    }
+    Ok(choices
+        .iter()
+        .enumerate()
+        .find(|&(i, choice)| answer == (i + 1).to_string() || answer == *choice)
+        .map(|(_, &choice)| choice))
+}
+
+/// Whether `ELAN_NO_COLOR_INJECT` is set
+pub fn color_injection_disabled_by_env() -> bool {
+    env::var_os(NO_COLOR_INJECT_ENV_VAR)
+        .and_then(raw::if_not_empty)
+        .is_some()
+}
+
+static COLOR_CHOICE: AtomicUsize = AtomicUsize::new(ColorChoice::Auto as usize);
+
+pub fn set_color_choice(choice: ColorChoice) {
+    COLOR_CHOICE.store(choice as usize, Ordering::SeqCst);
+}
+
+pub fn color_choice() -> ColorChoice {
+    match COLOR_CHOICE.load(Ordering::SeqCst) {
+        n if n == ColorChoice::Never as usize => ColorChoice::Never,
+        n if n == ColorChoice::Always as usize => ColorChoice::Always,
+        _ => ColorChoice::Auto,
+    }
+}
+
+static PROGRESS_MODE: AtomicUsize = AtomicUsize::new(ProgressMode::Auto as usize);
+
+pub fn set_progress_mode(mode: ProgressMode) {
+    PROGRESS_MODE.store(mode as usize, Ordering::SeqCst);
+}
+
+pub fn progress_mode() -> ProgressMode {
+    match PROGRESS_MODE.load(Ordering::SeqCst) {
+        n if n == ProgressMode::Plain as usize => ProgressMode::Plain,
+        n if n == ProgressMode::None as usize => ProgressMode::None,
+        _ => ProgressMode::Auto,
+    }
+}
+
+static OUTPUT_FORMAT: AtomicUsize = AtomicUsize::new(OutputFormat::Human as usize);
+
+pub fn set_output_format(format: OutputFormat) {
+    OUTPUT_FORMAT.store(format as usize, Ordering::SeqCst);
+}
+
+pub fn output_format() -> OutputFormat {
+    match OUTPUT_FORMAT.load(Ordering::SeqCst) {
+        n if n == OutputFormat::Github as usize => OutputFormat::Github,
+        _ => OutputFormat::Human,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_color_args() {
+        let none: &[&str] = &[];
+        assert!(ColorChoice::Never.child_color_args(true, none).is_empty());
+        assert!(ColorChoice::Never.child_color_args(false, none).is_empty());
+        assert_eq!(
+            ColorChoice::Auto.child_color_args(true, none),
+            vec!["--color", "always"]
+        );
+        assert!(ColorChoice::Auto.child_color_args(false, none).is_empty());
+        assert_eq!(
+            ColorChoice::Always.child_color_args(false, none),
+            vec!["--color", "always"]
+        );
+
+        // An explicit choice for the child wins
+        assert!(ColorChoice::Always
+            .child_color_args(true, &["--color=never", "Main.lean"])
+            .is_empty());
+        assert!(ColorChoice::Auto
+            .child_color_args(true, &["--color", "never"])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_no_color_injection() {
+        let none: &[&str] = &[];
+        for &choice in &[ColorChoice::Never, ColorChoice::Auto, ColorChoice::Always] {
+            assert!(injected_color_args(choice, false, true, none).is_empty());
+            assert!(injected_color_args(choice, false, false, none).is_empty());
+            assert_eq!(
+                injected_color_args(choice, true, true, none),
+                choice.child_color_args(true, none)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let choices = ["stable", "nightly"];
+        let pick_with = |interactive: bool, answer: &str| {
+            let mut output = Vec::new();
+            let picked = pick(
+                interactive,
+                "Install?",
+                &choices,
+                answer.as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+            (picked, String::from_utf8(output).unwrap())
+        };
+
+        // Nothing is asked or read without a terminal
+        assert_eq!(pick_with(false, "1\n"), (None, String::new()));
+
+        let (picked, output) = pick_with(true, "\n");
+        assert_eq!(picked, Some("stable"));
+        assert!(output.starts_with("Install?\n1) stable (default)\n2) nightly\n"));
+        assert_eq!(pick_with(true, "2\n").0, Some("nightly"));
+        assert_eq!(pick_with(true, " nightly \r\n").0, Some("nightly"));
+        assert_eq!(pick_with(true, "n\n").0, None);
+        assert_eq!(pick_with(true, "3\n").0, None);
+        assert_eq!(pick_with(true, "").0, None);
+    }
+
+    #[test]
+    fn test_progress_mode() {
+        for &isatty in &[true, false] {
+            assert_eq!(ProgressMode::None.interactive(isatty), None);
+            assert_eq!(ProgressMode::Plain.interactive(isatty), Some(false));
+            assert_eq!(ProgressMode::Auto.interactive(isatty), Some(isatty));
+        }
+        assert_eq!(ProgressMode::parse("plain"), Some(ProgressMode::Plain));
+        assert_eq!(ProgressMode::parse("bar"), None);
+    }
+
+    #[test]
+    fn test_progress_ticks() {
+        // A minute of data arriving every millisecond
+        let shown = |interactive| {
+            let mut ticks = ProgressTicks::default();
+            let mut seconds = 0;
+            let mut shown = 0;
+            for ms in 0..60_000 {
+                match ticks.tick(f64::from(ms) / 1000.0, interactive) {
+                    Some(true) => {
+                        seconds += 1;
+                        shown += 1;
+                    }
+                    Some(false) => seconds += 1,
+                    None => {}
+                }
+            }
+            (seconds, shown)
+        };
+        assert_eq!(shown(true), (59, 59));
+        assert_eq!(shown(false), (59, 5));
+    }
+
+    #[test]
+    fn test_github_output_format() {
+        let from_vars = |elan: Option<&str>, github: Option<&str>| {
+            OutputFormat::from_vars(|name| match name {
+                GITHUB_ACTIONS_ENV_VAR => elan.map(str::to_owned),
+                "GITHUB_ACTIONS" => github.map(str::to_owned),
+                _ => None,
+            })
+        };
+        let format = from_vars(None, Some("true"));
+        assert_eq!(format, OutputFormat::Github);
+        assert_eq!(
+            format.format_line(
+                &NotificationLevel::Error,
+                "could not download 'lean4:stable'\n100% failed"
+            ),
+            Some("::error::could not download 'lean4:stable'%0A100%25 failed".to_owned())
+        );
+        assert_eq!(
+            format.format_line(&NotificationLevel::Warn, "no toolchain"),
+            Some("::warning::no toolchain".to_owned())
+        );
+        assert_eq!(format.format_line(&NotificationLevel::Info, "done"), None);
+        assert_eq!(
+            OutputFormat::Human.format_line(&NotificationLevel::Error, "failed"),
+            None
+        );
+
+        assert_eq!(from_vars(Some("0"), Some("true")), OutputFormat::Human);
+        assert_eq!(from_vars(Some("1"), None), OutputFormat::Github);
+        assert_eq!(from_vars(None, None), OutputFormat::Human);
+        assert_eq!(OutputFormat::parse("github"), Some(OutputFormat::Github));
+    }
+}
@@ -2,15 +2,21 @@ use dirs;
 use errors::*;
 use notifications::Notification;
 use raw;
+use regex::{self, Regex};
+use semaphore::Semaphore;
+use serde_json;
 use sha2::{Digest, Sha256};
 use std::cmp::Ord;
+use std::collections::BTreeMap;
 use std::env;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Command};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use url::Url;
 #[cfg(windows)]
 use winreg;
@@ -47,6 +53,17 @@ pub fn write_file(name: &'static str, path: &Path, contents: &str) -> Result<()>
     })
 }
 
+/// Like `write_file`, but writes to a temporary file next to `path` first
+/// and renames it over `path`, so that readers never see a partial file
+pub fn write_file_atomic(name: &'static str, path: &Path, contents: &str) -> Result<()> {
+    let file_name = path.file_name().map_or("".into(), |n| n.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{}.tmp", file_name));
+    write_file(name, &tmp, contents)?;
+    rename_file(name, &tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
 pub fn append_file(name: &'static str, path: &Path, line: &str) -> Result<()> {
     raw::append_file(path, line).chain_err(|| ErrorKind::WritingFile {
         name: name,
@@ -139,12 +156,16 @@ pub fn download_file_with_resume(
     notify_handler: &dyn Fn(Notification),
 ) -> Result<()> {
     use download::ErrorKind as DEK;
+    let limiter = download_limiter();
+    let _permit = limiter.acquire();
     match download_file_(url, path, hasher, resume_from_partial, notify_handler) {
         Ok(_) => Ok(()),
+        Err(e @ Error(ErrorKind::Download(DEK::Cancelled), _)) => Err(e),
         Err(e) => {
-            println!("{:?}", e);
             let is_client_error = match e.kind() {
-                &ErrorKind::Download(DEK::HttpStatus(400..=499)) => true,
+                &ErrorKind::Download(DEK::DownloadFailed {
+                    status: 400..=499, ..
+                }) => true,
                 &ErrorKind::Download(DEK::FileNotFound) => true,
                 _ => false,
             };
@@ -165,6 +186,34 @@ pub fn download_file_with_resume(
     }
 }
 
+/// How many files are downloaded at once by default
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+static DOWNLOAD_LIMITER: Mutex<Option<Arc<Semaphore>>> = Mutex::new(None);
+
+/// Sets how many files the threads of this process download at once, such
+/// as when installing several toolchains in parallel. Downloads beyond that
+/// wait for one of the others to finish.
+pub fn set_max_concurrent_downloads(n: usize) {
+    *DOWNLOAD_LIMITER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(Semaphore::new(n)));
+}
+
+fn download_limiter() -> Arc<Semaphore> {
+    DOWNLOAD_LIMITER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(|| Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_DOWNLOADS)))
+        .clone()
+}
+
+pub use download::set_cancel_token;
+
+/// Fails with `download::ErrorKind::Cancelled` if the downloads of this
+/// thread have been cancelled, for stopping an install between its steps
+pub fn check_cancelled() -> Result<()> {
+    Ok(::download::check_cancelled()?)
+}
+
 static DEPRECATED_HYPER_WARNED: AtomicBool = AtomicBool::new(false);
 
 fn download_file_(
@@ -202,8 +251,17 @@ fn download_file_(
                 notify_handler(Notification::DownloadDataReceived(data));
             }
             Event::ResumingPartialDownload => {
+                debug!("resuming partial download at '{}'", path.display());
                 notify_handler(Notification::ResumingPartialDownload);
             }
+            Event::RetryingDownload(attempt) => {
+                // The data is received again, from the partial download if
+                // resuming
+                if let Some(ref mut h) = *hasher.borrow_mut() {
+                    **h = Sha256::new();
+                }
+                notify_handler(Notification::RetryingDownload(attempt));
+            }
         }
 
         Ok(())
@@ -223,8 +281,15 @@ fn download_file_(
         (Backend::Curl, Notification::UsingCurl)
     };
     notify_handler(notification);
+    debug!(
+        "downloading '{}' to '{}' with {:?}",
+        ::download::redact_url(url),
+        path.display(),
+        backend
+    );
     download_to_path_with_backend(backend, url, path, resume_from_partial, Some(callback))?;
 
+    debug!("finished downloading '{}'", path.display());
     notify_handler(Notification::DownloadFinished);
 
     Ok(())
@@ -270,6 +335,34 @@ pub fn symlink_dir(src: &Path, dest: &Path, notify_handler: &dyn Fn(Notification
     })
 }
 
+/// Points the link `dest` at `src` like `symlink_dir`, but replacing the
+/// link already at `dest` in one step where the platform can, so that
+/// there is always a link at `dest`
+pub fn replace_symlink_dir(
+    src: &Path,
+    dest: &Path,
+    notify_handler: &dyn Fn(Notification),
+) -> Result<()> {
+    notify_handler(Notification::LinkingDirectory(src, dest));
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let link = dest.with_file_name(format!(".{}.link-{}", name, process::id()));
+    let linked = raw::symlink_dir(src, &link).and_then(|()| {
+        if fs::rename(&link, dest).is_ok() {
+            return Ok(());
+        }
+        // A junction cannot be renamed over another on Windows
+        raw::remove_dir(dest)?;
+        fs::rename(&link, dest)
+    });
+    if linked.is_err() {
+        let _ = raw::remove_dir(&link);
+    }
+    linked.chain_err(|| ErrorKind::LinkingDirectory {
+        src: PathBuf::from(src),
+        dest: PathBuf::from(dest),
+    })
+}
+
 pub fn hard_or_symlink_file(src: &Path, dest: &Path) -> Result<()> {
     if hardlink_file(src, dest).is_err() {
         symlink_file(src, dest)?;
@@ -319,6 +412,39 @@ pub fn copy_file(src: &Path, dest: &Path) -> Result<()> {
         .map(|_| ())
 }
 
+/// `path` with `.bak` appended
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// Replaces `dest` with a copy of `src`, moving the previous `dest` to its
+/// `backup_path`. If copying or `verify` fails, the previous file is put back.
+pub fn replace_file_with_backup<F>(
+    name: &'static str,
+    src: &Path,
+    dest: &Path,
+    verify: F,
+) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let backup = backup_path(dest);
+    let had_previous = dest.exists();
+    if had_previous {
+        rename_file(name, dest, &backup)?;
+    }
+
+    let result = copy_file(src, dest).and_then(|()| verify(dest));
+    if result.is_err() && had_previous {
+        let _ = fs::remove_file(dest);
+        rename_file(name, &backup, dest)?;
+    }
+
+    result
+}
+
 pub fn remove_dir(
     name: &'static str,
     path: &Path,
@@ -417,6 +543,49 @@ pub fn elan_home() -> Result<PathBuf> {
     elan_home.or(user_home).ok_or(ErrorKind::ElanHome.into())
 }
 
+/// The directory of `elan_home()` the proxies of the Lean tools are
+/// installed to
+pub fn elan_bin_dir() -> Result<PathBuf> {
+    Ok(elan_home()?.join("bin"))
+}
+
+/// The elans on `path` other than `current_exe` that look installed with a
+/// home other than `home`, as each binary and the home guessed from its
+/// location. A binary is guessed to belong to a home if it is in the home's
+/// `bin` directory next to its `toolchains` or `settings.toml`; binaries
+/// anywhere else are not counted.
+pub fn other_elan_installs(
+    path: &OsStr,
+    current_exe: &Path,
+    home: &Path,
+) -> Vec<(PathBuf, PathBuf)> {
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let current_exe = canonical(current_exe);
+    let home = canonical(home);
+    let mut found: Vec<(PathBuf, PathBuf)> = vec![];
+    for dir in env::split_paths(path) {
+        let binary = dir.join(format!("elan{}", env::consts::EXE_SUFFIX));
+        if !is_file(&binary) || canonical(&binary) == current_exe {
+            continue;
+        }
+        let other_home = match dir.parent() {
+            Some(other_home) if dir.file_name() == Some(OsStr::new("bin")) => other_home,
+            _ => continue,
+        };
+        let is_home = is_directory(other_home.join("toolchains"))
+            || is_file(other_home.join("settings.toml"));
+        if is_home
+            && canonical(other_home) != home
+            && !found
+                .iter()
+                .any(|(_, h)| canonical(h) == canonical(other_home))
+        {
+            found.push((binary, other_home.to_owned()));
+        }
+    }
+    found
+}
+
 pub fn format_path_for_display(path: &str) -> String {
     let unc_present = path.find(r"\\?\");
 
@@ -463,6 +632,285 @@ pub fn string_from_winreg_value(val: &winreg::RegValue) -> Option<String> {
     }
 }
 
+/// Whether `arg0`, as a proxy was invoked with, runs the command `name`.
+/// `arg0` may be a path and may end in `.exe`, and its case is ignored if
+/// `os` is Windows, so that `/usr/bin/lean` and `LEAN.EXE` both run `lean`.
+pub fn is_command_on(arg0: &str, name: &str, os: &str) -> bool {
+    let windows = os == "windows";
+    let file_name = match arg0.rfind(|c| c == '/' || (windows && c == '\\')) {
+        Some(i) => &arg0[i + 1..],
+        None => arg0,
+    };
+    let exe = file_name.len() > 4
+        && file_name.is_char_boundary(file_name.len() - 4)
+        && if windows {
+            file_name[file_name.len() - 4..].eq_ignore_ascii_case(".exe")
+        } else {
+            file_name.ends_with(".exe")
+        };
+    let stem = if exe {
+        &file_name[..file_name.len() - 4]
+    } else {
+        file_name
+    };
+    if windows {
+        stem.eq_ignore_ascii_case(name)
+    } else {
+        stem == name
+    }
+}
+
+/// Like `is_command_on`, for this machine
+pub fn is_command(arg0: &str, name: &str) -> bool {
+    is_command_on(arg0, name, env::consts::OS)
+}
+
+/// Concatenates the global default arguments, the toolchain's default
+/// arguments and the user's arguments, in increasing precedence. A
+/// `-Dname=value` option is dropped if a later layer sets `name` again.
+pub fn merge_default_args<S: AsRef<OsStr>>(
+    global: &[String],
+    toolchain: &[String],
+    user: &[S],
+) -> Vec<OsString> {
+    fn option_name(arg: &OsStr) -> Option<&str> {
+        let option = arg.to_str()?.strip_prefix("-D")?;
+        option.find('=').map(|i| &option[..i])
+    }
+
+    let layers: Vec<Vec<&OsStr>> = vec![
+        global.iter().map(OsStr::new).collect(),
+        toolchain.iter().map(OsStr::new).collect(),
+        user.iter().map(|a| a.as_ref()).collect(),
+    ];
+    let mut result = vec![];
+    for (i, layer) in layers.iter().enumerate() {
+        for &arg in layer {
+            if let Some(name) = option_name(arg) {
+                let overridden = layers[i + 1..]
+                    .iter()
+                    .flatten()
+                    .any(|&a| option_name(a) == Some(name));
+                if overridden {
+                    continue;
+                }
+            }
+            result.push(arg.to_owned());
+        }
+    }
+    result
+}
+
+/// Replaces each `@file` argument with the arguments listed in `file`, as
+/// compilers do for response files. An argument naming a file that can't be
+/// read is passed on unchanged.
+pub fn expand_response_files<S: AsRef<OsStr>>(args: &[S]) -> Vec<OsString> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        let arg = arg.as_ref();
+        let contents = match arg.to_str() {
+            Some(s) if s.len() > 1 && s.starts_with('@') => fs::read_to_string(&s[1..]).ok(),
+            _ => None,
+        };
+        match contents {
+            Some(contents) => expanded.extend(
+                parse_response_file(&contents)
+                    .into_iter()
+                    .map(OsString::from),
+            ),
+            None => expanded.push(arg.to_owned()),
+        }
+    }
+    expanded
+}
+
+/// Splits a response file into arguments. Arguments are separated by any
+/// whitespace, including newlines, and may be quoted with `"` or `'`. A
+/// backslash escapes a following quote, backslash or whitespace character
+/// outside of single quotes and is kept as is otherwise, so that Windows
+/// paths need no escaping.
+fn parse_response_file(contents: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => arg.push(c),
+            (_, '\\') => {
+                in_arg = true;
+                match chars.peek() {
+                    Some(&next)
+                        if next == '"' || next == '\'' || next == '\\' || next.is_whitespace() =>
+                    {
+                        arg.push(next);
+                        chars.next();
+                    }
+                    _ => arg.push(c),
+                }
+            }
+            (Some(_), c) => arg.push(c),
+            (None, '"') | (None, '\'') => {
+                in_arg = true;
+                quote = Some(c);
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(arg.clone());
+                    arg.clear();
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+
+    args
+}
+
+/// The milliseconds from `start` to `end`. Only the monotonic clock is
+/// used, so adjustments of the system clock such as by NTP never affect
+/// the result. An `end` before `start` counts as no time, and durations too
+/// long for a `u64` saturate.
+pub fn elapsed_ms(start: Instant, end: Instant) -> u64 {
+    use std::convert::TryFrom;
+
+    u64::try_from(end.saturating_duration_since(start).as_millis()).unwrap_or(u64::MAX)
+}
+
+/// `value` with each `${VAR}` replaced by the variable as looked up by
+/// `var`, failing if it is not set. `key` names the value in the error. A
+/// `$` not followed by a complete `${...}` is kept as is.
+pub fn expand_env_vars<F: Fn(&str) -> Option<String>>(
+    value: &str,
+    key: &str,
+    var: F,
+) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        let expansion = var(name).ok_or_else(|| ErrorKind::UnsetEnvVar {
+            var: name.to_owned(),
+            key: key.to_owned(),
+        })?;
+        result.push_str(&rest[..start]);
+        result.push_str(&expansion);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The regex finding the error codes in the output of `lean`, unless
+/// `telemetry_error_pattern` overrides it
+pub const DEFAULT_ERROR_CODE_PATTERN: &str = r"\[(?P<error>E.{4})\]";
+
+/// The regex for `pattern`, or the default one if there is no `pattern` or
+/// it does not compile, along with why it did not
+pub fn error_code_regex(pattern: Option<&str>) -> (Regex, Option<regex::Error>) {
+    let default = || Regex::new(DEFAULT_ERROR_CODE_PATTERN).expect("invalid default pattern");
+    match pattern.map(Regex::new) {
+        Some(Ok(re)) => (re, None),
+        Some(Err(e)) => (default(), Some(e)),
+        None => (default(), None),
+    }
+}
+
+/// The error codes matched by `re` in `line`, each being the `error` group
+/// or else the whole match
+pub fn error_codes(re: &Regex, line: &str) -> Vec<String> {
+    re.captures_iter(line)
+        .filter_map(|caps| caps.name("error").or_else(|| caps.get(0)))
+        .map(|m| m.as_str().to_owned())
+        .collect()
+}
+
+/// The toolchains listed in `contents`, one per line. Blank lines and
+/// anything after a `#` are ignored.
+pub fn parse_toolchain_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+        .collect()
+}
+
+/// The contents of a `lean-toolchain` file naming `toolchain`, keeping the
+/// lines after the first of the `existing` file
+pub fn toolchain_file_contents(existing: Option<&str>, toolchain: &str) -> String {
+    let mut contents = format!("{}\n", toolchain);
+    if let Some(rest) = existing.and_then(|e| e.find('\n').map(|i| &e[i + 1..])) {
+        contents.push_str(rest);
+    }
+    contents
+}
+
+/// The nearest `lean-toolchain` file in `dir` or above and the toolchain it
+/// names, if that is not `toolchain`, as when the directory override of
+/// `dir` takes precedence over a file naming something else
+pub fn conflicting_toolchain_file(dir: &Path, toolchain: &str) -> Option<(PathBuf, String)> {
+    // Names that map to the same toolchain directory are the same
+    let dir_name = |name: &str| name.replace("/", "--").replace(":", "---");
+    let file = dir
+        .ancestors()
+        .map(|d| d.join("lean-toolchain"))
+        .find(|file| is_file(file))?;
+    let name = fs::read_to_string(&file)
+        .ok()?
+        .lines()
+        .next()?
+        .trim()
+        .to_owned();
+    if name.is_empty() || dir_name(&name) == dir_name(toolchain) {
+        None
+    } else {
+        Some((file, name))
+    }
+}
+
+/// The oldest elan a project supports, given by a line
+/// `elan-version = <version>` after the toolchain name in its
+/// `lean-toolchain` file
+pub fn required_elan_version(contents: &str) -> Option<&str> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key.trim() == "elan-version" => {
+                    Some(value.trim().trim_matches('"'))
+                }
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Whether elan `running` is at least `required`, or `None` if `required`
+/// is not a version
+pub fn satisfies_elan_version(running: &str, required: &str) -> Option<bool> {
+    use semver::Version;
+
+    let required = Version::parse(required.trim_start_matches('v')).ok()?;
+    Some(Version::parse(running).map_or(true, |running| running >= required))
+}
+
 pub fn toolchain_sort<T: AsRef<str>>(v: &mut Vec<T>) {
     use semver::{Identifier, Version};
 
@@ -497,17 +945,42 @@ pub fn toolchain_sort<T: AsRef<str>>(v: &mut Vec<T>) {
     });
 }
 
-// fetch from HTML page instead of Github API to avoid rate limit
-pub fn fetch_latest_release_tag(repo_slug: &str) -> Result<String> {
-    use regex::Regex;
+/// Which releases of elan itself `elan self update` installs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelfUpdateChannel {
+    Stable,
+    /// Prereleases as well, whichever is newest
+    Beta,
+}
 
-    let latest_url = format!("https://github.com/{}/releases/latest", repo_slug);
+impl SelfUpdateChannel {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "stable" => Some(SelfUpdateChannel::Stable),
+            "beta" => Some(SelfUpdateChannel::Beta),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SelfUpdateChannel::Stable => "stable",
+            SelfUpdateChannel::Beta => "beta",
+        }
+    }
+}
+
+fn fetch_page(page_url: &str) -> Result<String> {
+    let url = parse_url(page_url)?;
 
     let mut data = Vec::new();
-    ::download::curl::EASY.with(|handle| {
+    ::download::curl::EASY.with(|handle| -> Result<()> {
         let mut handle = handle.borrow_mut();
-        handle.url(&latest_url).unwrap();
+        handle.url(page_url).unwrap();
         handle.follow_location(true).unwrap();
+        ::download::curl::set_proxy(&mut handle, &url)?;
+        ::download::curl::set_ca_bundle(&mut handle)?;
+        ::download::curl::set_index_timeout(&mut handle)?;
         {
             let mut transfer = handle.transfer();
             transfer
@@ -516,22 +989,497 @@ pub fn fetch_latest_release_tag(repo_slug: &str) -> Result<String> {
                     Ok(new_data.len())
                 })
                 .unwrap();
-            transfer.perform().unwrap();
+            transfer.perform().map_err(|e| {
+                if e.is_operation_timedout() {
+                    Error::from(ErrorKind::IndexTimedOut {
+                        url: page_url.to_owned(),
+                        secs: ::download::index_timeout().as_secs(),
+                    })
+                } else {
+                    Error::with_chain(e, format!("failed to fetch '{}'", page_url))
+                }
+            })?;
         }
-    });
-    let redirect =
-        ::std::str::from_utf8(&data).chain_err(|| "failed to decode release tag response")?;
+        Ok(())
+    })?;
+    String::from_utf8(data).chain_err(|| "failed to decode release tag response")
+}
+
+static RELEASE_INDEX: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Resolves releases against the index at `path` instead of GitHub, like
+/// `ELAN_RELEASE_INDEX`
+pub fn set_release_index(path: PathBuf) {
+    *RELEASE_INDEX.lock().unwrap_or_else(|e| e.into_inner()) = Some(path);
+}
+
+/// The release index that toolchains are resolved against instead of
+/// GitHub, if one was set or named by `ELAN_RELEASE_INDEX`. Such an index is
+/// a JSON snapshot of the releases of some repositories, e.g.
+///
+/// ```json
+/// {
+///   "leanprover/lean4": { "latest": "v4.0.0", "tags": ["v4.0.0", "v4.1.0-rc1"] }
+/// }
+/// ```
+///
+/// where `latest` defaults to the newest version among `tags` that is not a
+/// prerelease. An entry may also map tags to when they were published, as
+/// UTC RFC 3339 timestamps, in `"published": { "v4.0.0":
+/// "2023-09-08T10:00:00Z" }`, for resolving the `latest` channel.
+pub fn release_index() -> Result<Option<PathBuf>> {
+    if let Some(ref path) = *RELEASE_INDEX.lock().unwrap_or_else(|e| e.into_inner()) {
+        return Ok(Some(path.clone()));
+    }
+    match env::var("ELAN_RELEASE_INDEX") {
+        Ok(ref location) if !location.is_empty() => release_index_path(location).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The file a release index `location`, a path or `file://` URL, names
+pub fn release_index_path(location: &str) -> Result<PathBuf> {
+    if !location.contains("://") {
+        return Ok(PathBuf::from(location));
+    }
+    Url::parse(location)
+        .ok()
+        .filter(|url| url.scheme() == "file")
+        .and_then(|url| url.to_file_path().ok())
+        .ok_or_else(|| ErrorKind::UnsupportedReleaseIndex(location.to_owned()).into())
+}
+
+/// The releases of a repository listed in a release index
+#[derive(Debug, PartialEq)]
+pub struct IndexedReleases {
+    pub latest: Option<String>,
+    pub tags: Vec<String>,
+    pub published: BTreeMap<String, String>,
+}
+
+impl IndexedReleases {
+    /// The entry of `repo_slug` in the release index at `path`
+    pub fn load(path: &Path, repo_slug: &str) -> Result<Self> {
+        debug!(
+            "reading releases of '{}' from '{}'",
+            repo_slug,
+            path.display()
+        );
+        let data = read_file("release index", path)?;
+        let index: serde_json::Value = serde_json::from_str(&data)
+            .chain_err(|| ErrorKind::InvalidReleaseIndex(path.to_owned()))?;
+        let repo = index
+            .get(repo_slug)
+            .ok_or_else(|| ErrorKind::NotInReleaseIndex {
+                path: path.to_owned(),
+                repo: repo_slug.to_owned(),
+            })?;
+        let string = |v: &serde_json::Value| v.as_str().map(|s| s.to_owned());
+        Ok(IndexedReleases {
+            latest: repo.get("latest").and_then(string),
+            tags: repo
+                .get("tags")
+                .and_then(|tags| tags.as_array())
+                .map(|tags| tags.iter().filter_map(string).collect())
+                .unwrap_or_default(),
+            published: repo
+                .get("published")
+                .and_then(|published| published.as_object())
+                .map(|published| {
+                    published
+                        .iter()
+                        .filter_map(|(tag, date)| Some((tag.clone(), string(date)?)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The newest release on `channel`
+    pub fn newest(&self, channel: SelfUpdateChannel) -> Option<String> {
+        let newest = newest_version(self.tags.iter().map(|t| &**t), channel);
+        match channel {
+            SelfUpdateChannel::Stable => self.latest.clone().or(newest),
+            SelfUpdateChannel::Beta => newest.or_else(|| self.latest.clone()),
+        }
+    }
+
+    /// The publish date and tag of the most recently published release,
+    /// prereleases included
+    pub fn newest_published(&self) -> Option<(String, String)> {
+        self.published
+            .iter()
+            .map(|(tag, date)| (date.clone(), tag.clone()))
+            .max()
+    }
+}
+
+/// The newest release of `repo_slug` on `channel` in the release index at
+/// `path`
+fn indexed_release_tag(path: &Path, repo_slug: &str, channel: SelfUpdateChannel) -> Result<String> {
+    IndexedReleases::load(path, repo_slug)?
+        .newest(channel)
+        .ok_or_else(|| {
+            format!(
+                "the release index '{}' lists no {} release of '{}'",
+                path.display(),
+                channel.as_str(),
+                repo_slug
+            )
+            .into()
+        })
+}
+
+// fetch from HTML page instead of Github API to avoid rate limit
+pub fn fetch_latest_release_tag(repo_slug: &str) -> Result<String> {
+    if let Some(path) = release_index()? {
+        return indexed_release_tag(&path, repo_slug, SelfUpdateChannel::Stable);
+    }
+    let latest_url = format!("https://github.com/{}/releases/latest", repo_slug);
+    let redirect = fetch_page(&latest_url)?;
     let re = Regex::new(r#"/tag/([-a-z0-9.]+)"#).unwrap();
     let capture = re.captures(&redirect);
     match capture {
-        Some(cap) => Ok(cap.get(1).unwrap().as_str().to_string()),
+        Some(cap) => {
+            let tag = cap.get(1).unwrap().as_str();
+            debug!("latest release of '{}' is '{}'", repo_slug, tag);
+            Ok(tag.to_string())
+        }
         None => Err("failed to parse latest release tag".into()),
     }
 }
 
+/// The tag of the newest release of `repo_slug` on `channel`. Prereleases
+/// are found through the release feed, as `releases/latest` never points
+/// to one.
+pub fn fetch_channel_release_tag(repo_slug: &str, channel: SelfUpdateChannel) -> Result<String> {
+    if channel == SelfUpdateChannel::Stable {
+        return fetch_latest_release_tag(repo_slug);
+    }
+    if let Some(path) = release_index()? {
+        return indexed_release_tag(&path, repo_slug, channel);
+    }
+
+    let feed_url = format!("https://github.com/{}/releases.atom", repo_slug);
+    let feed = fetch_page(&feed_url)?;
+    match latest_release_in_feed(&feed, channel) {
+        Some(tag) => {
+            debug!(
+                "latest {} release of '{}' is '{}'",
+                channel.as_str(),
+                repo_slug,
+                tag
+            );
+            Ok(tag)
+        }
+        None => Err(format!(
+            "failed to find a {} release of '{}'",
+            channel.as_str(),
+            repo_slug
+        )
+        .into()),
+    }
+}
+
+/// The publish date and tag of the most recently published release of
+/// `repo_slug`, whether it is a prerelease or not, or `None` if it has none
+pub fn fetch_newest_published_release(repo_slug: &str) -> Result<Option<(String, String)>> {
+    if let Some(path) = release_index()? {
+        return match IndexedReleases::load(&path, repo_slug) {
+            Ok(releases) => Ok(releases.newest_published()),
+            Err(Error(ErrorKind::NotInReleaseIndex { .. }, _)) => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+    let feed_url = format!("https://github.com/{}/releases.atom", repo_slug);
+    let newest = newest_published_in_feed(&fetch_page(&feed_url)?);
+    if let Some((ref date, ref tag)) = newest {
+        debug!(
+            "newest release of '{}' is '{}', published {}",
+            repo_slug, tag, date
+        );
+    }
+    Ok(newest)
+}
+
+/// The tags of `repo_slug`, read from the refs its git server advertises as
+/// these list every tag without counting against the GitHub API rate limit
+pub fn fetch_tags(repo_slug: &str) -> Result<Vec<String>> {
+    if let Some(path) = release_index()? {
+        return Ok(IndexedReleases::load(&path, repo_slug)?.tags);
+    }
+    let refs_url = format!(
+        "https://github.com/{}.git/info/refs?service=git-upload-pack",
+        repo_slug
+    );
+    Ok(tags_in_ref_advertisement(&fetch_page(&refs_url)?))
+}
+
+/// The tags listed in the git ref advertisement `refs`, without the peeled
+/// `^{}` entries of annotated tags
+pub fn tags_in_ref_advertisement(refs: &str) -> Vec<String> {
+    let re = Regex::new(r"refs/tags/([^\s^]+)(\^\{\})?").unwrap();
+    re.captures_iter(refs)
+        .filter(|cap| cap.get(2).is_none())
+        .map(|cap| cap[1].to_owned())
+        .collect()
+}
+
+/// The tag of the highest release `v<major>.<minor>.<patch>` among `tags`.
+/// Prereleases such as `v4.1.0-rc1` are never picked.
+pub fn newest_patch_release<T: AsRef<str>>(tags: &[T], major: u64, minor: u64) -> Option<String> {
+    use semver::Version;
+
+    tags.iter()
+        .map(|tag| tag.as_ref())
+        .filter(|tag| tag.starts_with('v'))
+        .filter_map(|tag| Some((Version::parse(&tag[1..]).ok()?, tag)))
+        .filter(|(version, _)| {
+            version.major == major && version.minor == minor && !version.is_prerelease()
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag.to_owned())
+}
+
+/// The tag of the highest version linked from the release feed `feed` on
+/// `channel`, where only the beta channel includes prereleases such as
+/// `v1.5.0-rc1`. Tags that are not versions are ignored.
+pub fn latest_release_in_feed(feed: &str, channel: SelfUpdateChannel) -> Option<String> {
+    let re = Regex::new(r#"/releases/tag/(v?[-A-Za-z0-9.+]+)"#).unwrap();
+    newest_version(
+        re.captures_iter(feed)
+            .map(|cap| cap.get(1).unwrap().as_str()),
+        channel,
+    )
+}
+
+/// The publish date and tag of the most recently updated entry of the
+/// release feed `feed`
+pub fn newest_published_in_feed(feed: &str) -> Option<(String, String)> {
+    let tag = Regex::new(r#"/releases/tag/([^"/<>\s]+)""#).unwrap();
+    let updated = Regex::new(r"<updated>([^<]+)</updated>").unwrap();
+    feed.split("<entry>")
+        .skip(1)
+        .filter_map(|entry| {
+            let date = updated.captures(entry)?[1].trim().to_owned();
+            Some((date, tag.captures(entry)?[1].to_owned()))
+        })
+        .max()
+}
+
+/// The tag of the highest version among `tags` on `channel`, ignoring tags
+/// that are not versions
+fn newest_version<'a, I>(tags: I, channel: SelfUpdateChannel) -> Option<String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    use semver::Version;
+
+    tags.filter_map(|tag| {
+        let version = Version::parse(tag.trim_start_matches('v')).ok()?;
+        Some((version, tag.to_owned()))
+    })
+    .filter(|(version, _)| channel == SelfUpdateChannel::Beta || !version.is_prerelease())
+    .max_by(|(a, _), (b, _)| a.cmp(b))
+    .map(|(_, tag)| tag)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile;
+
+    #[test]
+    fn test_expand_env_vars() {
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/user".to_owned()),
+            "PORT" => Some("3128".to_owned()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_env_vars("${HOME}/.local/bin", "bin_dir", var).unwrap(),
+            "/home/user/.local/bin"
+        );
+        assert_eq!(
+            expand_env_vars("http://proxy:${PORT}/${HOME}", "proxy", var).unwrap(),
+            "http://proxy:3128//home/user"
+        );
+        assert_eq!(
+            expand_env_vars("$HOME ${", "proxy", var).unwrap(),
+            "$HOME ${"
+        );
+
+        match expand_env_vars("${ELAN_UNSET}/bin", "bin_dir", var)
+            .unwrap_err()
+            .0
+        {
+            ErrorKind::UnsetEnvVar { var, key } => {
+                assert_eq!(var, "ELAN_UNSET");
+                assert_eq!(key, "bin_dir");
+            }
+            k => panic!("unexpected error: {}", k),
+        }
+    }
+
+    #[test]
+    fn test_conflicting_toolchain_file() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let project = dir.path().join("project");
+        let sub = project.join("src");
+        fs::create_dir_all(&sub).unwrap();
+        assert_eq!(conflicting_toolchain_file(&sub, "stable"), None);
+
+        let file = project.join("lean-toolchain");
+        fs::write(&file, "leanprover/lean4:v4.1.0\nelan-version = 4.0\n").unwrap();
+        assert_eq!(
+            conflicting_toolchain_file(&sub, "stable"),
+            Some((file.clone(), "leanprover/lean4:v4.1.0".to_owned()))
+        );
+        assert_eq!(
+            conflicting_toolchain_file(&project, "leanprover/lean4:v4.1.0"),
+            None
+        );
+        assert_eq!(
+            conflicting_toolchain_file(&sub, "leanprover--lean4---v4.1.0"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_write_toolchain_file() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let path = dir.path().join("lean-toolchain");
+        let write = |existing: Option<&str>, toolchain: &str| {
+            write_file_atomic(
+                "toolchain",
+                &path,
+                &toolchain_file_contents(existing, toolchain),
+            )
+            .unwrap();
+            fs::read_to_string(&path).unwrap()
+        };
+
+        assert_eq!(
+            write(None, "leanprover/lean4:v4.0.0"),
+            "leanprover/lean4:v4.0.0\n"
+        );
+        let existing = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            write(Some(&existing), "leanprover/lean4:v4.1.0"),
+            "leanprover/lean4:v4.1.0\n"
+        );
+        assert_eq!(write(Some("nightly"), "stable"), "stable\n");
+        assert_eq!(
+            write(Some("nightly\nelan-version = 1.4.0\n"), "stable"),
+            "stable\nelan-version = 1.4.0\n"
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_error_code_regex() {
+        let line = "error: [E0001] and [E0002], custom E-42";
+
+        let (re, invalid) = error_code_regex(None);
+        assert!(invalid.is_none());
+        assert_eq!(error_codes(&re, line), vec!["E0001", "E0002"]);
+
+        let (re, invalid) = error_code_regex(Some(r"E-\d+"));
+        assert!(invalid.is_none());
+        assert_eq!(error_codes(&re, line), vec!["E-42"]);
+
+        // An invalid pattern is reported, and the default used instead
+        let (re, invalid) = error_code_regex(Some(r"\[(?P<error>E"));
+        assert!(invalid.is_some());
+        assert_eq!(re.as_str(), DEFAULT_ERROR_CODE_PATTERN);
+        assert_eq!(error_codes(&re, line), vec!["E0001", "E0002"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_error_codes_of_captured_run() {
+        let output = raw::cmd_output(Command::new("sh").args([
+            "-c",
+            "echo 'a.lean:1:0: error [E0001]' >&2; echo ok; echo 'a.lean:2:0: error [E0042]' >&2",
+        ]))
+        .unwrap();
+        let (re, _) = error_code_regex(None);
+        assert_eq!(
+            error_codes(&re, &String::from_utf8_lossy(&output.stderr)),
+            vec!["E0001", "E0042"]
+        );
+        assert!(error_codes(&re, &String::from_utf8_lossy(&output.stdout)).is_empty());
+    }
+
+    #[test]
+    fn test_elapsed_ms() {
+        use std::time::Duration;
+
+        let start = Instant::now();
+        let end = start + Duration::from_millis(1500);
+        assert_eq!(elapsed_ms(start, end), 1500);
+
+        // Instants taken out of order, as a wall clock that was set back
+        // would give, count as no time rather than underflowing
+        assert_eq!(elapsed_ms(end, start), 0);
+        if let Some(far) = start.checked_add(Duration::from_secs(u64::MAX / 100)) {
+            assert_eq!(elapsed_ms(start, far), u64::MAX);
+        }
+    }
+
+    #[test]
+    fn test_newest_patch_release() {
+        // A ref advertisement as served by a git server, with the peeled
+        // entries of annotated tags
+        let refs = "001e# service=git-upload-pack\n\
+            0000\
+            003f1111111111111111111111111111111111111111 refs/heads/master\n\
+            003f2222222222222222222222222222222222222222 refs/tags/v4.0.0\n\
+            003f3333333333333333333333333333333333333333 refs/tags/v4.1.0\n\
+            00424444444444444444444444444444444444444444 refs/tags/v4.1.0^{}\n\
+            003f5555555555555555555555555555555555555555 refs/tags/v4.1.3\n\
+            00436666666666666666666666666666666666666666 refs/tags/v4.1.4-rc1\n\
+            003f7777777777777777777777777777777777777777 refs/tags/v4.2.0\n\
+            00458888888888888888888888888888888888888888 refs/tags/nightly-2023-09-01\n\
+            0000";
+        let tags = tags_in_ref_advertisement(refs);
+        assert_eq!(
+            tags,
+            [
+                "v4.0.0",
+                "v4.1.0",
+                "v4.1.3",
+                "v4.1.4-rc1",
+                "v4.2.0",
+                "nightly-2023-09-01"
+            ]
+        );
+
+        assert_eq!(newest_patch_release(&tags, 4, 1), Some("v4.1.3".to_owned()));
+        assert_eq!(newest_patch_release(&tags, 4, 2), Some("v4.2.0".to_owned()));
+        assert_eq!(newest_patch_release(&tags, 4, 3), None);
+        assert_eq!(
+            newest_patch_release(&["v4.1.9", "v4.1.10"], 4, 1),
+            Some("v4.1.10".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_required_elan_version() {
+        assert_eq!(required_elan_version("stable\n"), None);
+        // The toolchain itself is never taken for the requirement
+        assert_eq!(required_elan_version("elan-version = 1.0.0"), None);
+        assert_eq!(
+            required_elan_version("leanprover/lean4:nightly\nelan-version = \"1.5.0\"\n"),
+            Some("1.5.0")
+        );
+
+        assert_eq!(satisfies_elan_version("1.4.2", "1.5.0"), Some(false));
+        assert_eq!(satisfies_elan_version("1.4.2-pre", "1.4.2"), Some(false));
+        assert_eq!(satisfies_elan_version("1.5.0", "v1.5.0"), Some(true));
+        assert_eq!(satisfies_elan_version("2.0.0", "1.5.0"), Some(true));
+        assert_eq!(satisfies_elan_version("1.4.2", "newest"), None);
+    }
 
     #[test]
     fn test_toochain_sort() {
@@ -559,4 +1507,336 @@ mod tests {
 
         assert_eq!(expected, v);
     }
+
+    #[test]
+    fn test_replace_symlink_dir() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+        for (src, lean) in &[(&old, "old lean"), (&new, "new lean")] {
+            fs::create_dir_all(src.join("bin")).unwrap();
+            fs::write(src.join("bin").join("lean"), lean).unwrap();
+        }
+        let link = dir.path().join("toolchains").join("mine");
+        fs::create_dir_all(link.parent().unwrap()).unwrap();
+        symlink_dir(&old, &link, &|_| {}).unwrap();
+
+        replace_symlink_dir(&new, &link, &|_| {}).unwrap();
+        assert_eq!(
+            fs::read_to_string(link.join("bin").join("lean")).unwrap(),
+            "new lean"
+        );
+        // Nothing is left next to the link, and the old directory is kept
+        assert_eq!(fs::read_dir(link.parent().unwrap()).unwrap().count(), 1);
+        assert!(old.join("bin").join("lean").is_file());
+    }
+
+    #[test]
+    fn test_is_command() {
+        assert!(is_command_on("lean", "lean", "linux"));
+        assert!(is_command_on("lean.exe", "lean", "linux"));
+        assert!(is_command_on("/usr/bin/lean", "lean", "linux"));
+        assert!(is_command_on("../bin/lean", "lean", "macos"));
+        assert!(!is_command_on("LEAN", "lean", "linux"));
+        assert!(!is_command_on("/usr/bin/lean/lake", "lean", "linux"));
+        assert!(!is_command_on("leanc", "lean", "linux"));
+        assert!(!is_command_on("", "lean", "linux"));
+
+        assert!(is_command_on("LEAN.EXE", "lean", "windows"));
+        assert!(is_command_on("Lean", "lean", "windows"));
+        assert!(is_command_on("C:\\elan\\bin\\lean.Exe", "lean", "windows"));
+        assert!(is_command_on("C:/elan/bin/LEAN.EXE", "lean", "windows"));
+        assert!(!is_command_on("C:\\elan\\bin\\lake.exe", "lean", "windows"));
+        assert!(!is_command_on(".exe", "", "windows"));
+    }
+
+    #[test]
+    fn test_expand_response_files() {
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        let rsp = dir.path().join("args.rsp");
+        fs::write(
+            &rsp,
+            "-DmaxHeartbeats=0 \"My File.lean\"\n\n  'it''s \"quoted\"'\r\n-o out\\ dir C:\\lean\\a.olean\n\"\"\n",
+        )
+        .unwrap();
+
+        let rsp_arg = format!("@{}", rsp.display());
+        let missing_arg = format!("@{}", dir.path().join("missing.rsp").display());
+        let args = expand_response_files(&["--make", &rsp_arg, "@", &missing_arg]);
+
+        let expected: Vec<OsString> = vec![
+            "--make",
+            "-DmaxHeartbeats=0",
+            "My File.lean",
+            "its \"quoted\"",
+            "-o",
+            "out dir",
+            "C:\\lean\\a.olean",
+            "",
+            "@",
+            &missing_arg,
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_parse_toolchain_list() {
+        let list = "# generated\nleanprover/lean4:v4.1.0\n\n  stable  \r\nnightly # for CI\n   \n";
+        assert_eq!(
+            parse_toolchain_list(list),
+            vec!["leanprover/lean4:v4.1.0", "stable", "nightly"]
+        );
+        assert!(parse_toolchain_list("").is_empty());
+    }
+
+    #[test]
+    fn test_replace_file_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("new");
+        let dest = dir.path().join("elan");
+        fs::write(&src, "new").unwrap();
+        fs::write(&dest, "old").unwrap();
+
+        let result = replace_file_with_backup("test", &src, &dest, |_| Err("bad".into()));
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        assert!(!backup_path(&dest).exists());
+
+        replace_file_with_backup("test", &src, &dest, |path| {
+            assert_eq!(fs::read_to_string(path).unwrap(), "new");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert_eq!(fs::read_to_string(backup_path(&dest)).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_merge_default_args() {
+        let global = vec![
+            "-DwarningAsError=false".to_owned(),
+            "-Dmaxheartbeats=1".to_owned(),
+        ];
+        let toolchain = vec!["-DmaxHeartbeats=2".to_owned(), "-j4".to_owned()];
+        let strs = |args: Vec<OsString>| -> Vec<String> {
+            args.into_iter().map(|a| a.into_string().unwrap()).collect()
+        };
+
+        assert_eq!(
+            strs(merge_default_args(&global, &toolchain, &["Main.lean"])),
+            vec![
+                "-DwarningAsError=false",
+                "-Dmaxheartbeats=1",
+                "-DmaxHeartbeats=2",
+                "-j4",
+                "Main.lean"
+            ]
+        );
+
+        // Later layers override earlier settings of the same option
+        assert_eq!(
+            strs(merge_default_args(
+                &global,
+                &["-DwarningAsError=true".to_owned()],
+                &["-DwarningAsError=false", "-Dmaxheartbeats=5", "Main.lean"]
+            )),
+            vec!["-DwarningAsError=false", "-Dmaxheartbeats=5", "Main.lean"]
+        );
+        let none: &[&str] = &[];
+        assert_eq!(
+            strs(merge_default_args(
+                &global,
+                &["-DwarningAsError=true".to_owned()],
+                none
+            )),
+            vec!["-Dmaxheartbeats=1", "-DwarningAsError=true"]
+        );
+    }
+
+    #[test]
+    fn test_latest_release_in_feed() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <entry>
+    <link rel="alternate" type="text/html" href="https://github.com/leanprover/elan/releases/tag/v1.5.0-rc1"/>
+  </entry>
+  <entry>
+    <link rel="alternate" type="text/html" href="https://github.com/leanprover/elan/releases/tag/v1.4.10"/>
+  </entry>
+  <entry>
+    <link rel="alternate" type="text/html" href="https://github.com/leanprover/elan/releases/tag/v1.4.9"/>
+  </entry>
+  <entry>
+    <link rel="alternate" type="text/html" href="https://github.com/leanprover/elan/releases/tag/nightly"/>
+  </entry>
+</feed>"#;
+
+        assert_eq!(
+            latest_release_in_feed(feed, SelfUpdateChannel::Beta),
+            Some("v1.5.0-rc1".to_owned())
+        );
+        assert_eq!(
+            latest_release_in_feed(feed, SelfUpdateChannel::Stable),
+            Some("v1.4.10".to_owned())
+        );
+
+        // Once the release is out, beta follows it too
+        let feed = feed.replace("v1.4.10", "v1.5.0");
+        assert_eq!(
+            latest_release_in_feed(&feed, SelfUpdateChannel::Beta),
+            Some("v1.5.0".to_owned())
+        );
+        assert_eq!(latest_release_in_feed("", SelfUpdateChannel::Beta), None);
+    }
+
+    #[test]
+    fn test_newest_published_in_feed() {
+        let feed = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+  <updated>2023-09-01T00:00:00Z</updated>
+  <entry>
+    <updated>2023-08-30T12:00:00Z</updated>
+    <link rel="alternate" type="text/html" href="https://github.com/leanprover/lean4/releases/tag/v4.0.0"/>
+  </entry>
+  <entry>
+    <updated>2023-08-31T12:00:00Z</updated>
+    <link rel="alternate" type="text/html" href="https://github.com/leanprover/lean4/releases/tag/v4.1.0-rc1"/>
+  </entry>
+</feed>"#;
+
+        assert_eq!(
+            newest_published_in_feed(feed),
+            Some(("2023-08-31T12:00:00Z".to_owned(), "v4.1.0-rc1".to_owned()))
+        );
+        assert_eq!(newest_published_in_feed(""), None);
+    }
+
+    #[test]
+    fn test_downloads_wait_for_their_turn() {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
+        use std::time::Duration;
+
+        for var in &["HTTP_PROXY", "ALL_PROXY"] {
+            env::remove_var(var);
+            env::remove_var(var.to_lowercase());
+        }
+        // Serves every request after a while, counting how many are served
+        // at once
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let serving = Arc::new(AtomicUsize::new(0));
+        let max_serving = Arc::new(AtomicUsize::new(0));
+        let (serving2, max_serving2) = (serving.clone(), max_serving.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (serving, max_serving) = (serving2.clone(), max_serving2.clone());
+                thread::spawn(move || {
+                    {
+                        let mut reader = BufReader::new(&stream);
+                        let mut line = String::new();
+                        while reader.read_line(&mut line).unwrap() > 2 {
+                            line.clear();
+                        }
+                    }
+                    let now = serving.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_serving.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(100));
+                    serving.fetch_sub(1, Ordering::SeqCst);
+                    let response =
+                        "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nlean";
+                    stream.write_all(response.as_bytes()).unwrap();
+                });
+            }
+        });
+
+        let dir = tempfile::Builder::new().prefix("elan").tempdir().unwrap();
+        set_max_concurrent_downloads(1);
+        let downloads: Vec<_> = (0..3)
+            .map(|i| {
+                let path = dir.path().join(format!("lean-{}.tar.gz", i));
+                thread::spawn(move || {
+                    let url = Url::parse(&format!("http://127.0.0.1:{}/{}", port, i)).unwrap();
+                    download_file(&url, &path, None, &|_| ()).unwrap();
+                    fs::read_to_string(&path).unwrap()
+                })
+            })
+            .collect();
+        for download in downloads {
+            assert_eq!(download.join().unwrap(), "lean");
+        }
+        assert_eq!(max_serving.load(Ordering::SeqCst), 1);
+        set_max_concurrent_downloads(DEFAULT_MAX_CONCURRENT_DOWNLOADS);
+    }
+
+    #[test]
+    fn test_other_elan_installs() {
+        let dir = tempfile::Builder::new()
+            .prefix("elan-installs")
+            .tempdir()
+            .unwrap();
+        let binary = |home: &str| {
+            let bin = dir.path().join(home).join("bin");
+            fs::create_dir_all(&bin).unwrap();
+            let binary = bin.join(format!("elan{}", env::consts::EXE_SUFFIX));
+            fs::write(&binary, "").unwrap();
+            binary
+        };
+        let ours = binary("ours");
+        fs::create_dir(dir.path().join("ours").join("toolchains")).unwrap();
+        let theirs = binary("theirs");
+        fs::write(dir.path().join("theirs").join("settings.toml"), "").unwrap();
+        // Not in a home, e.g. from a package manager
+        binary("packaged");
+        let path = env::join_paths(
+            ["ours", "theirs", "packaged", "theirs"]
+                .iter()
+                .map(|home| dir.path().join(home).join("bin")),
+        )
+        .unwrap();
+
+        assert_eq!(
+            other_elan_installs(&path, &ours, &dir.path().join("ours")),
+            vec![(theirs.clone(), dir.path().join("theirs"))]
+        );
+        assert_eq!(
+            other_elan_installs(&path, &theirs, &dir.path().join("theirs")),
+            vec![(ours, dir.path().join("ours"))]
+        );
+        assert_eq!(
+            other_elan_installs(&path, &theirs, &dir.path().join("ours")),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_index_request_times_out() {
+        use std::net::TcpListener;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        for var in &["HTTP_PROXY", "ALL_PROXY"] {
+            env::remove_var(var);
+            env::remove_var(var.to_lowercase());
+        }
+        // Accepts connections but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let _streams: Vec<_> = listener.incoming().collect();
+        });
+
+        ::download::set_index_timeout(Duration::from_secs(1));
+        let start = Instant::now();
+        let err = fetch_page(&format!("http://127.0.0.1:{}/releases/latest", port)).unwrap_err();
+        assert!(start.elapsed() < ::download::DEFAULT_IDLE_TIMEOUT / 3);
+        match *err.kind() {
+            ErrorKind::IndexTimedOut { secs, .. } => assert_eq!(secs, 1),
+            ref k => panic!("unexpected error: {}", k),
+        }
+    }
 }
@@ -1,115 +1,221 @@
 use regex::Regex;
-use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
-use std::process::{self, Command, Stdio};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{self, Command, Output, Stdio};
 use std::time::Instant;
 use tempfile::tempfile;
 
 use elan_utils;
+use elan_utils::raw;
+use elan_utils::tty;
+use elan_utils::utils;
 use errors::*;
 use notifications::*;
-use telemetry::{Telemetry, TelemetryEvent};
+use telemetry::TelemetryEvent;
 use Cfg;
 
+/// Runs `cmd` with `args`, returning its exit code for the caller to exit
+/// with. Without telemetry, `cmd` replaces this process on Unix, so that
+/// this only returns if it fails to start.
 pub fn run_command_for_dir<S: AsRef<OsStr>>(
     cmd: Command,
     arg0: &str,
     args: &[S],
     cfg: &Cfg,
-) -> Result<()> {
-    if (arg0 == "lean" || arg0 == "lean.exe") && cfg.telemetry_enabled()? {
-        return telemetry_lean(cmd, arg0, args, cfg);
+) -> Result<i32> {
+    let args = command_args(&cmd, arg0, args, cfg)?;
+
+    let is_lean = utils::is_command(arg0, "lean");
+    let telemetry = is_lean && cfg.telemetry_enabled()?;
+    debug!(
+        "running {:?} with arguments {:?}{}",
+        cmd.get_program(),
+        args,
+        if telemetry { " and telemetry" } else { "" }
+    );
+    if telemetry {
+        return telemetry_lean(cmd, arg0, &args, cfg);
     }
 
-    exec_command_for_dir_without_telemetry(cmd, arg0, args)
+    exec_command_for_dir_without_telemetry(cmd, arg0, &args, &*cfg.notify_handler)
 }
 
-fn telemetry_lean<S: AsRef<OsStr>>(
+/// Runs `cmd` with `args` as given, without telemetry or any of the
+/// arguments elan adds to the commands of a toolchain, returning its exit
+/// code. On Unix, `cmd` replaces this process.
+pub fn exec_command<S: AsRef<OsStr>>(cmd: Command, arg0: &str, args: &[S]) -> Result<i32> {
+    debug!(
+        "executing {:?} with arguments {:?}",
+        cmd.get_program(),
+        args.iter().map(|a| a.as_ref()).collect::<Vec<_>>()
+    );
+    exec_command_for_dir_without_telemetry(cmd, arg0, args, &|_| ())
+}
+
+/// Runs `cmd` with `args` like `run_command_for_dir`, but returns its output
+/// and exit status instead of passing on the terminal. No telemetry is
+/// recorded.
+pub fn run_command_capture<S: AsRef<OsStr>>(
     mut cmd: Command,
-    arg0: &str,
     args: &[S],
     cfg: &Cfg,
-) -> Result<()> {
-    #[cfg(unix)]
-    fn file_as_stdio(file: &File) -> Stdio {
-        use std::os::unix::io::{AsRawFd, FromRawFd};
-        unsafe { Stdio::from_raw_fd(file.as_raw_fd()) }
-    }
+) -> Result<Output> {
+    let arg0 = Path::new(cmd.get_program())
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_owned();
+    let args = command_args(&cmd, &arg0, args, cfg)?;
+    debug!(
+        "capturing {:?} with arguments {:?}",
+        cmd.get_program(),
+        args
+    );
+    cmd.args(&args);
+
+    elan_utils::raw::cmd_output(&mut cmd).map_err(|e| command_error(&cmd, &arg0, e))
+}
 
-    #[cfg(windows)]
-    fn file_as_stdio(file: &File) -> Stdio {
-        use std::os::windows::io::{AsRawHandle, FromRawHandle};
-        unsafe { Stdio::from_raw_handle(file.as_raw_handle()) }
+/// The output of a command run with `run_command_capture_errors`
+#[derive(Debug)]
+pub struct CapturedRun {
+    pub output: Output,
+    /// The error codes in its stderr, in order, as telemetry records them
+    pub error_codes: Vec<String>,
+}
+
+/// Like `run_command_capture`, but also returns the error codes of lean in
+/// the output, found with the same pattern as telemetry uses
+pub fn run_command_capture_errors<S: AsRef<OsStr>>(
+    cmd: Command,
+    args: &[S],
+    cfg: &Cfg,
+) -> Result<CapturedRun> {
+    let re = error_code_regex(cfg)?;
+    let output = run_command_capture(cmd, args, cfg)?;
+    let error_codes = utils::error_codes(&re, &String::from_utf8_lossy(&output.stderr));
+    Ok(CapturedRun {
+        output,
+        error_codes,
+    })
+}
+
+/// The regex finding error codes, from `telemetry_error_pattern` if set. A
+/// bad custom pattern must not get in the way of running lean, so it is
+/// only warned about.
+fn error_code_regex(cfg: &Cfg) -> Result<Regex> {
+    let pattern = cfg
+        .settings_file
+        .with(|s| Ok(s.telemetry_error_pattern.clone()))?;
+    let (re, invalid) = utils::error_code_regex(pattern.as_deref());
+    if let (Some(pattern), Some(e)) = (pattern.as_ref(), invalid) {
+        (cfg.notify_handler)(Notification::InvalidTelemetryErrorPattern(pattern, &e));
     }
+    Ok(re)
+}
+
+/// `args` for `lean` with response files expanded and the default arguments
+/// from the settings added, and those of other commands as given, since
+/// they may mean something else by `@`
+fn command_args<S: AsRef<OsStr>>(
+    cmd: &Command,
+    arg0: &str,
+    args: &[S],
+    cfg: &Cfg,
+) -> Result<Vec<OsString>> {
+    let is_lean = utils::is_command(arg0, "lean");
+    Ok(if is_lean {
+        let args = elan_utils::utils::expand_response_files(args);
+        let toolchain = toolchain_name(cmd).unwrap_or("").to_owned();
+        let (global, toolchain) = cfg.settings_file.with(|s| {
+            Ok((
+                s.lean_args.clone(),
+                s.toolchain_lean_args
+                    .get(&toolchain)
+                    .cloned()
+                    .unwrap_or_default(),
+            ))
+        })?;
+        elan_utils::utils::merge_default_args(&global, &toolchain, &args)
+    } else {
+        args.iter().map(|a| a.as_ref().to_owned()).collect()
+    })
+}
 
+/// Runs lean and records its run time, exit code and the error codes it
+/// printed, returning the exit code
+fn telemetry_lean<S: AsRef<OsStr>>(
+    mut cmd: Command,
+    arg0: &str,
+    args: &[S],
+    cfg: &Cfg,
+) -> Result<i32> {
     let now = Instant::now();
 
     cmd.args(args);
 
-    let has_color_args = args.iter().any(|e| {
-        let e = e.as_ref().to_str().unwrap_or("");
-        e.starts_with("--color")
-    });
-
-    if stderr_isatty() && !has_color_args {
-        cmd.arg("--color");
-        cmd.arg("always");
-    }
-
-    let mut cmd_err_file = tempfile().unwrap();
-    let cmd_err_stdio = file_as_stdio(&cmd_err_file);
+    // In passive mode lean writes to our stderr directly, so its output is
+    // ordered exactly as without telemetry, but no error codes are recorded.
+    let passive = env::var_os("ELAN_TELEMETRY_PASSIVE").is_some();
+
+    let color_args = if passive {
+        vec![]
+    } else {
+        tty::injected_color_args(cfg.color, cfg.inject_color, stderr_isatty(), args)
+    };
+    let color_forced = !color_args.is_empty();
+    cmd.args(color_args);
+
+    let re = error_code_regex(cfg)?;
+
+    // Without somewhere to capture stderr, fall back to passive mode rather
+    // than failing the run. The child gets its own handle to the file so
+    // that dropping `cmd` does not close ours.
+    let capture = if passive {
+        None
+    } else {
+        tempfile()
+            .and_then(|file| Ok((file.try_clone()?, file)))
+            .ok()
+    };
+    let (cmd_err_stdio, cmd_err_file) = match capture {
+        Some((handle, file)) => (Stdio::from(handle), Some(file)),
+        None => (Stdio::inherit(), None),
+    };
 
     // FIXME rust-lang/rust#32254. It's not clear to me
     // when and why this is needed.
-    let mut cmd = cmd
+    let mut child = cmd
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(cmd_err_stdio)
         .spawn()
-        .unwrap();
-
-    let status = cmd.wait();
-
-    let duration = now.elapsed();
-
-    let ms = (duration.as_secs() as u64 * 1000) + (duration.subsec_nanos() as u64 / 1000 / 1000);
+        .map_err(|e| command_error(&cmd, arg0, e))?;
+
+    let mut errors: Vec<String> = Vec::new();
+    let stderr = io::stderr();
+    // Codes are still scanned for in the colored output
+    let mut out: Box<dyn Write> = if cfg.strip_telemetry_color && !stderr_isatty() {
+        Box::new(raw::StripAnsi::new(stderr.lock()))
+    } else {
+        Box::new(stderr.lock())
+    };
+    let status = raw::wait_forwarding_stderr(&mut child, cmd_err_file, &mut out, |line| {
+        // A single line may list several error codes
+        errors.extend(utils::error_codes(&re, &String::from_utf8_lossy(line)));
+    });
 
-    let t = Telemetry::new(cfg.elan_dir.join("telemetry"));
+    let ms = match status {
+        Ok(ref exit) => utils::elapsed_ms(now, exit.exited_at),
+        Err(_) => utils::elapsed_ms(now, Instant::now()),
+    };
 
     match status {
-        Ok(status) => {
-            let exit_code = status.code().unwrap_or(1);
-
-            let re = Regex::new(r"\[(?P<error>E.{4})\]").unwrap();
-
-            let mut buffer = String::new();
-            // Chose a HashSet instead of a Vec to avoid calls to sort() and dedup().
-            // The HashSet should be faster if there are a lot of errors, too.
-            let mut errors: Vec<String> = Vec::new();
-
-            let stderr = io::stderr();
-            let mut handle = stderr.lock();
-
-            cmd_err_file.seek(SeekFrom::Start(0)).unwrap();
-
-            let mut buffered_stderr = BufReader::new(cmd_err_file);
-
-            while buffered_stderr.read_line(&mut buffer).unwrap() > 0 {
-                let b = buffer.to_owned();
-                buffer.clear();
-                let _ = handle.write(b.as_bytes());
-
-                if let Some(caps) = re.captures(&b) {
-                    if caps.len() > 0 {
-                        errors.push(
-                            caps.name("error")
-                                .map(|m| m.as_str())
-                                .unwrap_or("")
-                                .to_owned(),
-                        );
-                    }
-                };
+        Ok(exit) => {
+            if exit.late_output > 0 {
+                (cfg.notify_handler)(Notification::TelemetryLateOutput(exit.late_output));
             }
 
             let e = if errors.is_empty() {
@@ -118,29 +224,33 @@ fn telemetry_lean<S: AsRef<OsStr>>(
                 Some(errors)
             };
 
+            (cfg.notify_handler)(Notification::CommandFinished {
+                tool: arg0,
+                exit_code: exit.exit_code,
+                duration_ms: ms,
+            });
+
             let te = TelemetryEvent::LeanRun {
                 duration_ms: ms,
-                exit_code: exit_code,
+                exit_code: exit.exit_code,
                 errors: e,
+                color_forced,
             };
 
-            let _ = t.log_telemetry(te).map_err(|xe| {
-                (cfg.notify_handler)(Notification::TelemetryCleanupError(&xe));
-            });
+            log_telemetry(cfg, te);
 
-            process::exit(exit_code);
+            Ok(exit.exit_code)
         }
         Err(e) => {
             let exit_code = e.raw_os_error().unwrap_or(1);
             let te = TelemetryEvent::LeanRun {
                 duration_ms: ms,
-                exit_code: exit_code,
+                exit_code,
                 errors: None,
+                color_forced,
             };
 
-            let _ = t.log_telemetry(te).map_err(|xe| {
-                (cfg.notify_handler)(Notification::TelemetryCleanupError(&xe));
-            });
+            log_telemetry(cfg, te);
 
             Err(e).chain_err(|| elan_utils::ErrorKind::RunningCommand {
                 name: OsStr::new(arg0).to_owned(),
@@ -149,31 +259,83 @@ fn telemetry_lean<S: AsRef<OsStr>>(
     }
 }
 
+/// Records `event`, only reporting any failure as telemetry must never
+/// get in the way of running lean
+fn log_telemetry(cfg: &Cfg, event: TelemetryEvent) {
+    if let Err(e) = cfg.telemetry().and_then(|t| t.log_telemetry(event)) {
+        (cfg.notify_handler)(Notification::TelemetryError(&e));
+    }
+}
+
+/// Runs `cmd`, sending `Notification::CommandFinished` to `notify_handler`
+/// where it returns, which it does not on Unix unless it fails to start
 fn exec_command_for_dir_without_telemetry<S: AsRef<OsStr>>(
     mut cmd: Command,
     arg0: &str,
     args: &[S],
-) -> Result<()> {
+    notify_handler: &dyn Fn(Notification),
+) -> Result<i32> {
     cmd.args(args);
 
     // FIXME rust-lang/rust#32254. It's not clear to me
     // when and why this is needed.
     cmd.stdin(process::Stdio::inherit());
 
-    return exec(&mut cmd).chain_err(|| elan_utils::ErrorKind::RunningCommand {
-        name: OsStr::new(arg0).to_owned(),
-    });
+    return exec(&mut cmd, arg0, notify_handler).map_err(|e| command_error(&cmd, arg0, e));
 
     #[cfg(unix)]
-    fn exec(cmd: &mut Command) -> io::Result<()> {
+    fn exec(cmd: &mut Command, _: &str, _: &dyn Fn(Notification)) -> io::Result<i32> {
         use std::os::unix::prelude::*;
         Err(cmd.exec())
     }
 
     #[cfg(windows)]
-    fn exec(cmd: &mut Command) -> io::Result<()> {
+    fn exec(
+        cmd: &mut Command,
+        arg0: &str,
+        notify_handler: &dyn Fn(Notification),
+    ) -> io::Result<i32> {
+        let now = Instant::now();
         let status = cmd.status()?;
-        process::exit(status.code().unwrap());
+        let exit_code = status.code().unwrap();
+        notify_handler(Notification::CommandFinished {
+            tool: arg0,
+            exit_code,
+            duration_ms: utils::elapsed_ms(now, Instant::now()),
+        });
+        Ok(exit_code)
+    }
+}
+
+/// The toolchain `cmd` was created for
+fn toolchain_name(cmd: &Command) -> Option<&str> {
+    cmd.get_envs()
+        .find(|&(name, _)| name == "ELAN_TOOLCHAIN")
+        .and_then(|(_, value)| value)
+        .and_then(|value| value.to_str())
+}
+
+/// The error for `cmd` failing to start, with guidance for the common causes
+fn command_error(cmd: &Command, arg0: &str, e: io::Error) -> Error {
+    let program = Path::new(cmd.get_program());
+    let toolchain = toolchain_name(cmd);
+    let kind = match e.kind() {
+        // Distinguish a missing binary from one whose interpreter is missing
+        io::ErrorKind::NotFound if !program.exists() => toolchain
+            .map(|toolchain| ErrorKind::BinaryNotFound(toolchain.to_owned(), arg0.to_owned())),
+        io::ErrorKind::PermissionDenied => Some(ErrorKind::BinaryNotExecutable(program.to_owned())),
+        _ => None,
+    };
+
+    let e = Error::with_chain(
+        e,
+        ErrorKind::Utils(elan_utils::ErrorKind::RunningCommand {
+            name: OsStr::new(arg0).to_owned(),
+        }),
+    );
+    match kind {
+        Some(kind) => Error::with_chain(e, kind),
+        None => e,
     }
 }
 
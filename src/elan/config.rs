@@ -1,20 +1,32 @@
 use std::env;
 use std::fmt::{self, Display};
-use std::io;
+use std::io::{self, BufRead, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
 
+use elan_dist::dist::{self, ToolchainDesc, ToolchainKind};
 use elan_dist::temp;
-use elan_utils::utils;
+use elan_utils::path_var::PathVarMode;
+use elan_utils::semaphore::Semaphore;
+use elan_utils::tty::{self, ColorChoice};
+use elan_utils::utils::{self, SelfUpdateChannel};
 use errors::*;
 use notifications::*;
-use settings::{Settings, SettingsFile, TelemetryMode};
+use settings::{self, SettingKey, Settings, SettingsFile, TelemetryMode};
+use telemetry::{Telemetry, TelemetryTransform};
 use telemetry_analysis::*;
-use toolchain::{Toolchain, UpdateStatus};
+use toolchain::{name_from_dir_name, Toolchain, UpdateStatus};
 
 use toml;
 
+/// Set once a directory override hiding a toolchain file was warned about,
+/// as the toolchain may be looked up more than once per command
+static OVERRIDE_CONFLICT_WARNED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Debug)]
 pub enum OverrideReason {
     Environment,
@@ -48,30 +60,128 @@ impl Display for OverrideReason {
     }
 }
 
+/// A reason a toolchain is in use, as returned by `Cfg::toolchain_references`
+#[derive(Debug)]
+pub enum ToolchainReference {
+    Default,
+    /// A directory override in the settings
+    Override(PathBuf),
+    /// How the current directory selects the toolchain, other than through
+    /// a directory override
+    Active(OverrideReason),
+}
+
+impl Display for ToolchainReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        match *self {
+            ToolchainReference::Default => write!(f, "it is the default toolchain"),
+            ToolchainReference::Override(ref path) => {
+                write!(f, "directory override for '{}'", path.display())
+            }
+            ToolchainReference::Active(ref reason) => {
+                write!(f, "active in the current directory ({})", reason)
+            }
+        }
+    }
+}
+
+/// An installed toolchain, as returned by `Cfg::installed_toolchains`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolchainInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_default: bool,
+    pub kind: ToolchainKind,
+}
+
 pub struct Cfg {
     pub elan_dir: PathBuf,
     pub settings_file: SettingsFile,
     pub toolchains_dir: PathBuf,
     pub update_hash_dir: PathBuf,
     pub download_dir: PathBuf,
+    /// Where installer archives are kept when asked to, see
+    /// `dist::ArchiveCache`
+    pub archive_dir: PathBuf,
     pub temp_cfg: temp::Cfg,
     //pub gpg_key: Cow<'static, str>,
     pub env_override: Option<String>,
     pub notify_handler: Arc<dyn Fn(Notification)>,
+    pub telemetry_transform: Option<TelemetryTransform>,
+    /// Shared by the telemetry writers of this process
+    pub telemetry_limiter: Option<Arc<Semaphore>>,
+    /// Whether elan and the tools it runs color their output
+    pub color: ColorChoice,
+    /// Whether `color` may be passed on to commands as `--color`
+    pub inject_color: bool,
+    /// Whether a missing toolchain that is needed to run a command is an
+    /// error instead of being installed
+    pub no_auto_install: bool,
+    /// Whether the output of `lean` recorded by telemetry is passed on
+    /// without ANSI escape sequences when stderr is not a terminal
+    pub strip_telemetry_color: bool,
+    /// How `elan exec` adds the toolchain's library to `LEAN_PATH` unless
+    /// told otherwise
+    pub lean_path: PathVarMode,
 }
 
 impl Cfg {
     pub fn from_env(notify_handler: Arc<dyn Fn(Notification)>) -> Result<Self> {
-        // Set up the elan home directory
-        let elan_dir = utils::elan_home()?;
+        Cfg::from_home(utils::elan_home()?, notify_handler)
+    }
 
+    /// The configuration of the elan home `elan_dir`, regardless of
+    /// `ELAN_HOME`, such as for embedders keeping a home of their own
+    pub fn from_home(elan_dir: PathBuf, notify_handler: Arc<dyn Fn(Notification)>) -> Result<Self> {
+        // Set up the elan home directory
         utils::ensure_dir_exists("home", &elan_dir, &|n| notify_handler(n.into()))?;
 
-        let settings_file = SettingsFile::new(elan_dir.join("settings.toml"));
+        let settings_file =
+            SettingsFile::discover(elan_dir.join("settings.toml"), &utils::current_dir()?);
+        if let Some((file, keys)) = settings_file.ignored_project_settings()? {
+            for key in &keys {
+                notify_handler(Notification::IgnoredProjectSetting(&file, key));
+            }
+        }
+        for file in settings_file.ignored_system_toolchains()? {
+            notify_handler(Notification::IgnoredSystemToolchains(&file));
+        }
+
+        // Environment variables take precedence over these, see
+        // `download::proxy_for_url`
+        let proxy = settings_file.with(|s| {
+            Ok(download::ProxyConfig {
+                proxy: s.proxy.clone(),
+                no_proxy: s.no_proxy.clone(),
+            })
+        })?;
+        download::set_fallback_proxy(proxy);
+        // Only sent to github.com, not to the CDN its downloads redirect to
+        if let Some(token) = env::var("ELAN_GITHUB_TOKEN").ok().filter(|t| !t.is_empty()) {
+            download::set_authorization("github.com", Some(format!("Bearer {}", token)));
+        }
+
+        if let Some(secs) = settings_file.with(|s| Ok(s.download_idle_timeout))? {
+            download::set_idle_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = settings_file.with(|s| Ok(s.index_timeout))? {
+            download::set_index_timeout(Duration::from_secs(secs));
+        }
+        if let Some(n) = settings_file.with(|s| Ok(s.max_concurrent_downloads))? {
+            utils::set_max_concurrent_downloads(n);
+        }
+
+        let telemetry_limiter = settings_file
+            .with(|s| Ok(s.telemetry_concurrency))?
+            .map(|n| Arc::new(Semaphore::new(n)));
 
         let toolchains_dir = elan_dir.join("toolchains");
         let update_hash_dir = elan_dir.join("update-hashes");
         let download_dir = elan_dir.join("downloads");
+        let archive_dir = match settings_file.with(|s| Ok(s.archive_dir.clone()))? {
+            Some(dir) => elan_dir.join(dir),
+            None => elan_dir.join("archives"),
+        };
 
         // GPG key
         /*let gpg_key = ""; if let Some(path) = env::var_os("ELAN_GPG_KEY")
@@ -86,6 +196,27 @@ impl Cfg {
             .ok()
             .and_then(utils::if_not_empty);
 
+        let inject_color = !tty::color_injection_disabled_by_env()
+            && !settings_file.with(|s| Ok(s.no_color_inject))?;
+
+        let no_auto_install = env::var("ELAN_NO_AUTO_INSTALL")
+            .ok()
+            .and_then(utils::if_not_empty)
+            .is_some()
+            || !settings_file.with(|s| Ok(s.auto_install))?;
+
+        // `--color` is applied again once the settings are read
+        if let Some(color) = settings_file.with(|s| Ok(s.color))? {
+            tty::set_color_choice(color);
+        }
+
+        let lean_path = settings_file.with(|s| Ok(s.lean_path))?;
+
+        let strip_telemetry_color = env::var("ELAN_NO_TELEMETRY_COLOR")
+            .ok()
+            .and_then(utils::if_not_empty)
+            .is_some();
+
         let notify_clone = notify_handler.clone();
         let temp_cfg = temp::Cfg::new(
             elan_dir.join("tmp"),
@@ -98,10 +229,18 @@ impl Cfg {
             toolchains_dir: toolchains_dir,
             update_hash_dir: update_hash_dir,
             download_dir: download_dir,
+            archive_dir,
             temp_cfg: temp_cfg,
             //gpg_key: gpg_key,
             notify_handler: notify_handler,
             env_override: env_override,
+            telemetry_transform: None,
+            telemetry_limiter,
+            color: tty::color_choice(),
+            inject_color,
+            no_auto_install,
+            strip_telemetry_color,
+            lean_path,
         })
     }
 
@@ -141,7 +280,7 @@ impl Cfg {
     }
 
     pub fn which_binary(&self, path: &Path, binary: &str) -> Result<Option<PathBuf>> {
-        if let Some((toolchain, _)) = self.find_override_toolchain_or_default(path)? {
+        if let Some((toolchain, _)) = self.find_override_toolchain_or_default(path, true)? {
             Ok(Some(toolchain.binary_file(binary)))
         } else {
             Ok(None)
@@ -153,10 +292,13 @@ impl Cfg {
             .settings_file
             .with(|s| Ok(s.default_toolchain.clone()))?;
 
+        debug!("default toolchain is {:?}", opt_name);
         if let Some(name) = opt_name {
+            // The toolchain may have been removed since it was made the
+            // default
             let toolchain = self
                 .verify_toolchain(&name)
-                .chain_err(|| ErrorKind::ToolchainNotInstalled(name.to_string()))?;
+                .chain_err(|| ErrorKind::DefaultToolchainNotInstalled(name.to_string()))?;
 
             Ok(Some(toolchain))
         } else {
@@ -164,7 +306,13 @@ impl Cfg {
         }
     }
 
-    pub fn find_override(&self, path: &Path) -> Result<Option<(Toolchain, OverrideReason)>> {
+    /// The toolchain an override selects for `path`, installing it first if
+    /// `install` and it is missing, as running a command in `path` does
+    pub fn find_override(
+        &self,
+        path: &Path,
+        install: bool,
+    ) -> Result<Option<(Toolchain<'_>, OverrideReason)>> {
         let mut override_ = None;
 
         // First check ELAN_TOOLCHAIN
@@ -183,6 +331,13 @@ impl Cfg {
             })?;
         }
 
+        match override_ {
+            Some((ref name, ref reason)) => {
+                debug!("'{}' uses '{}' ({})", path.display(), name, reason)
+            }
+            None => debug!("no override applies to '{}'", path.display()),
+        }
+
         if let Some((name, reason)) = override_ {
             // This is hackishly using the error chain to provide a bit of
             // extra context about what went wrong. The CLI will display it
@@ -221,9 +376,23 @@ impl Cfg {
             match self.get_toolchain(&name, false) {
                 Ok(toolchain) => {
                     if toolchain.exists() {
+                        debug!(
+                            "'{}' is installed at '{}'",
+                            name,
+                            toolchain.path().display()
+                        );
                         Ok(Some((toolchain, reason)))
+                    } else if !install {
+                        Ok(Some((toolchain, reason)))
+                    } else if self.no_auto_install {
+                        Err(Error::from(reason_err))
+                            .chain_err(|| ErrorKind::ToolchainNotInstalled(name.to_string()))
                     } else {
-                        toolchain.install_from_dist(false)?;
+                        (self.notify_handler)(Notification::AutoInstallingToolchain(
+                            &name, &reason,
+                        ));
+                        toolchain.auto_install_from_dist()?;
+                        (self.notify_handler)(Notification::AutoInstalledToolchain(&name));
                         Ok(Some((toolchain, reason)))
                     }
                 }
@@ -248,6 +417,13 @@ impl Cfg {
         while let Some(d) = dir {
             // First check the override database
             if let Some(name) = settings.dir_override(d, notify) {
+                if let Some((file, file_name)) = utils::conflicting_toolchain_file(d, &name) {
+                    if !OVERRIDE_CONFLICT_WARNED.swap(true, Ordering::Relaxed) {
+                        notify(Notification::OverrideHidesToolchainFile(
+                            d, &name, &file, &file_name,
+                        ));
+                    }
+                }
                 let reason = OverrideReason::OverrideDB(d.to_owned());
                 return Ok(Some((name, reason)));
             }
@@ -255,6 +431,7 @@ impl Cfg {
             // Then look for 'lean-toolchain'
             let toolchain_file = d.join("lean-toolchain");
             if let Ok(s) = utils::read_file("toolchain file", &toolchain_file) {
+                check_elan_version(&toolchain_file, &s)?;
                 if let Some(s) = s.lines().next() {
                     let toolchain_name = s.trim();
                     let reason = OverrideReason::ToolchainFile(toolchain_file);
@@ -305,9 +482,10 @@ impl Cfg {
     pub fn find_override_toolchain_or_default(
         &self,
         path: &Path,
+        install: bool,
     ) -> Result<Option<(Toolchain, Option<OverrideReason>)>> {
         Ok(
-            if let Some((toolchain, reason)) = self.find_override(path)? {
+            if let Some((toolchain, reason)) = self.find_override(path, install)? {
                 Some((toolchain, Some(reason)))
             } else {
                 self.find_default()?.map(|toolchain| (toolchain, None))
@@ -315,29 +493,114 @@ impl Cfg {
         )
     }
 
+    /// Everything that makes the toolchain `name` in use: being the default,
+    /// directory overrides, and how `cwd` selects a toolchain. Other
+    /// directories with a `lean-toolchain` file are not known to elan.
+    pub fn toolchain_references(&self, name: &str, cwd: &Path) -> Result<Vec<ToolchainReference>> {
+        let name = self.get_toolchain(name, false)?.name().to_owned();
+        let mut references = Vec::new();
+
+        if let Some(default) = self.get_default()? {
+            if self.get_toolchain(&default, false)?.name() == name {
+                references.push(ToolchainReference::Default);
+            }
+        }
+
+        let overrides: Vec<PathBuf> = self.settings_file.with(|s| {
+            Ok(s.overrides
+                .iter()
+                .filter(|&(_, toolchain)| *toolchain == name)
+                .map(|(path, _)| PathBuf::from(path))
+                .collect())
+        })?;
+        references.extend(overrides.into_iter().map(ToolchainReference::Override));
+
+        // A broken `lean-toolchain` file does not make any toolchain in use
+        match self.find_override(cwd, false).unwrap_or(None) {
+            Some((_, OverrideReason::OverrideDB(_))) | None => {}
+            Some((toolchain, reason)) => {
+                if toolchain.name() == name {
+                    references.push(ToolchainReference::Active(reason));
+                }
+            }
+        }
+
+        Ok(references)
+    }
+
     pub fn get_default(&self) -> Result<Option<String>> {
         self.settings_file.with(|s| Ok(s.default_toolchain.clone()))
     }
 
+    /// Which releases of elan `elan self update` installs, unless told
+    /// otherwise
+    pub fn self_update_channel(&self) -> Result<SelfUpdateChannel> {
+        self.settings_file.with(|s| Ok(s.self_update_channel))
+    }
+
+    /// Removes all partial downloads, returning their paths
+    pub fn prune_downloads(&self) -> Result<Vec<PathBuf>> {
+        Ok(elan_dist::download::prune_partial_downloads(
+            &self.download_dir,
+            None,
+        )?)
+    }
+
     pub fn list_toolchains(&self) -> Result<Vec<String>> {
-        // de-sanitize toolchain file names (best effort...)
-        fn insane(s: String) -> String {
-            s.replace("---", ":").replace("--", "/")
-        }
         if utils::is_directory(&self.toolchains_dir) {
             let mut toolchains: Vec<_> = utils::read_dir("toolchains", &self.toolchains_dir)?
                 .filter_map(io::Result::ok)
                 .filter(|e| e.file_type().map(|f| !f.is_file()).unwrap_or(false))
                 .filter_map(|e| e.file_name().into_string().ok())
-                .map(insane)
+                // Toolchains still being extracted
+                .filter(|s| !s.starts_with('.'))
+                .map(|s| name_from_dir_name(&s))
                 .collect();
+            toolchains.extend(self.system_toolchain_names()?);
 
             utils::toolchain_sort(&mut toolchains);
+            toolchains.dedup();
 
             Ok(toolchains)
         } else {
-            Ok(Vec::new())
+            self.system_toolchain_names()
+        }
+    }
+
+    fn system_toolchain_names(&self) -> Result<Vec<String>> {
+        self.settings_file
+            .with(|s| Ok(s.system_toolchains.keys().cloned().collect()))
+    }
+
+    /// The installed toolchains in the order of `list_toolchains`, for
+    /// programmatic use
+    pub fn installed_toolchains(&self) -> Result<Vec<ToolchainInfo>> {
+        let default = self.get_default()?;
+
+        self.list_toolchains()?
+            .into_iter()
+            .map(|name| {
+                let toolchain = self.get_toolchain(&name, false)?;
+                Ok(ToolchainInfo {
+                    path: toolchain.path().to_owned(),
+                    is_default: default.as_ref() == Some(&name),
+                    kind: ToolchainKind::of(&name, toolchain.exists() && toolchain.is_custom()),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the toolchain `name` is completely installed, checked without
+    /// any network I/O. A name that is neither an installed toolchain nor a
+    /// valid toolchain spec is an error.
+    pub fn is_toolchain_installed(&self, name: &str) -> Result<bool> {
+        let toolchain = self.get_toolchain(name, false)?;
+        if toolchain.exists() {
+            return Ok(toolchain.is_complete());
         }
+        ToolchainDesc::from_str(name)?;
+        Ok(false)
     }
 
     pub fn update_all_channels(
@@ -350,9 +613,26 @@ impl Cfg {
         let toolchains = toolchains.into_iter();
         let toolchains = toolchains.map(|n| (n.clone(), self.get_toolchain(&n, true)));
 
-        // Filter out toolchains that don't track a release channel
-        let toolchains =
-            toolchains.filter(|&(_, ref t)| t.as_ref().map(|t| t.is_tracking()).unwrap_or(false));
+        // Filter out toolchains that don't track a release channel or are
+        // system toolchains, which are updated by whoever installed them
+        let toolchains = toolchains.filter(|(_, t)| {
+            t.as_ref()
+                .map(|t| t.is_tracking() && !t.is_read_only())
+                .unwrap_or(false)
+        });
+
+        // Filter out toolchains pinned with `elan toolchain pin`
+        let pinned = self.settings_file.with(|s| Ok(s.pinned.clone()))?;
+        let toolchains = toolchains.filter(|(n, _)| match pinned.get(n) {
+            Some(release) => {
+                (self.notify_handler)(Notification::SkippingPinnedToolchain(
+                    n,
+                    dist::release_tag(release),
+                ));
+                false
+            }
+            None => true,
+        });
 
         // Update toolchains and collect the results
         let toolchains = toolchains.map(|(n, t)| {
@@ -370,17 +650,74 @@ impl Cfg {
         Ok(toolchains.collect())
     }
 
-    pub fn toolchain_for_dir(&self, path: &Path) -> Result<(Toolchain, Option<OverrideReason>)> {
-        self.find_override_toolchain_or_default(path)
+    /// The toolchain used in `path`, which is installed first if `install`
+    /// and it is missing. Queries that don't run anything pass `false`.
+    pub fn toolchain_for_dir(
+        &self,
+        path: &Path,
+        install: bool,
+    ) -> Result<(Toolchain<'_>, Option<OverrideReason>)> {
+        self.find_override_toolchain_or_default(path, install)
             .and_then(|r| r.ok_or(ErrorKind::NoDefaultToolchain.into()))
     }
 
     pub fn create_command_for_dir(&self, path: &Path, binary: &str) -> Result<Command> {
-        let (ref toolchain, _) = self.toolchain_for_dir(path)?;
+        let (ref toolchain, _) = self.toolchain_for_dir(path, true)?;
 
         toolchain.create_command(binary)
     }
 
+    /// Like `create_command_for_dir`, but when no toolchain is configured
+    /// at all, offers to install one of `choices` and make it the default
+    /// as `pick_default_toolchain` does
+    pub fn create_command_for_dir_or_pick<R: BufRead, W: Write>(
+        &self,
+        path: &Path,
+        binary: &str,
+        choices: &[&str],
+        interactive: bool,
+        input: R,
+        output: W,
+    ) -> Result<Command> {
+        match self.create_command_for_dir(path, binary) {
+            Err(Error(ErrorKind::NoDefaultToolchain, state)) => {
+                match self.pick_default_toolchain(choices, interactive, input, output)? {
+                    Some(toolchain) => toolchain.create_command(binary),
+                    None => Err(Error(ErrorKind::NoDefaultToolchain, state)),
+                }
+            }
+            cmd => cmd,
+        }
+    }
+
+    /// Asks on `output` which of `choices` to install and make the default,
+    /// reading the answer from `input`, and returns it once installed, or
+    /// `None` if declined or not `interactive`
+    pub fn pick_default_toolchain<R: BufRead, W: Write>(
+        &self,
+        choices: &[&str],
+        interactive: bool,
+        input: R,
+        output: W,
+    ) -> Result<Option<Toolchain<'_>>> {
+        let picked = tty::pick(
+            interactive,
+            "no default toolchain is configured. install one and make it the default?",
+            choices,
+            input,
+            output,
+        )
+        .chain_err(|| "failed to ask for a toolchain to install")?;
+        let name = match picked {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let toolchain = self.get_toolchain(name, false)?;
+        toolchain.install_from_dist_if_not_installed()?;
+        toolchain.make_default()?;
+        Ok(Some(toolchain))
+    }
+
     pub fn create_command_for_toolchain(
         &self,
         toolchain: &str,
@@ -389,22 +726,49 @@ impl Cfg {
     ) -> Result<Command> {
         let ref toolchain = self.get_toolchain(toolchain, false)?;
         if install_if_missing && !toolchain.exists() {
-            toolchain.install_from_dist(false)?;
+            toolchain.auto_install_from_dist()?;
         }
 
         toolchain.create_command(binary)
     }
 
     pub fn doc_path_for_dir(&self, path: &Path, relative: &str) -> Result<PathBuf> {
-        let (toolchain, _) = self.toolchain_for_dir(path)?;
+        let (toolchain, _) = self.toolchain_for_dir(path, true)?;
         toolchain.doc_path(relative)
     }
 
     pub fn open_docs_for_dir(&self, path: &Path, relative: &str) -> Result<()> {
-        let (toolchain, _) = self.toolchain_for_dir(path)?;
+        let (toolchain, _) = self.toolchain_for_dir(path, true)?;
         toolchain.open_docs(relative)
     }
 
+    /// The value of the setting `elan get` knows as `name`
+    pub fn get_setting(&self, name: &str) -> Result<String> {
+        let key = settings::setting_key(name)?;
+        self.notify_if_setting_shadowed(key)?;
+        self.settings_file.with(|s| Ok(key.get(s)))
+    }
+
+    /// Changes the setting `elan set` knows as `name` to `value`, checking
+    /// that it is valid first
+    pub fn set_setting(&self, name: &str, value: &str) -> Result<()> {
+        let key = settings::setting_key(name)?;
+        self.settings_file.with_mut(|s| key.set(s, value))?;
+        (self.notify_handler)(Notification::SetSetting(key.name, value));
+        self.notify_if_setting_shadowed(key)
+    }
+
+    /// Tells when a settings file other than the one in `ELAN_HOME`, which
+    /// `elan set` writes, decides the setting `key`
+    fn notify_if_setting_shadowed(&self, key: &SettingKey) -> Result<()> {
+        if let Some(layer) = self.settings_file.layer_setting(key.key)? {
+            if layer != self.settings_file.path() {
+                (self.notify_handler)(Notification::SettingShadowed(key.name, &layer));
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_telemetry(&self, telemetry_enabled: bool) -> Result<()> {
         if telemetry_enabled {
             self.enable_telemetry()
@@ -444,8 +808,33 @@ impl Cfg {
         })
     }
 
-    pub fn analyze_telemetry(&self) -> Result<TelemetryAnalysis> {
+    /// Registers a transform applied to all telemetry before it is logged
+    pub fn set_telemetry_transform(&mut self, transform: TelemetryTransform) {
+        self.telemetry_transform = Some(transform);
+    }
+
+    /// The telemetry log writer, honouring `compress_telemetry`,
+    /// `telemetry_format` and the registered transform
+    pub fn telemetry(&self) -> Result<Telemetry> {
+        let compress = self.settings_file.with(|s| Ok(s.compress_telemetry))?;
+        let format = self.settings_file.with(|s| Ok(s.telemetry_format))?;
+        let mut telemetry = Telemetry::new(self.elan_dir.join("telemetry"), compress);
+        telemetry.set_format(format);
+        if let Some(ref transform) = self.telemetry_transform {
+            telemetry.set_transform(transform.clone());
+        }
+        if let Some(ref limiter) = self.telemetry_limiter {
+            telemetry.set_limiter(limiter.clone());
+        }
+        Ok(telemetry)
+    }
+
+    /// Analyzes the telemetry logged at or after `since`, or all of it
+    pub fn analyze_telemetry(&self, since: Option<OffsetDateTime>) -> Result<TelemetryAnalysis> {
         let mut t = TelemetryAnalysis::new(self.elan_dir.join("telemetry"));
+        if let Some(since) = since {
+            t.set_since(since);
+        }
 
         let events = t.import_telemery()?;
         t.analyze_telemetry_events(&events)?;
@@ -453,3 +842,22 @@ impl Cfg {
         Ok(t)
     }
 }
+
+/// Fails if the toolchain file `path` asks for a newer elan than this one
+fn check_elan_version(path: &Path, contents: &str) -> Result<()> {
+    let required = match utils::required_elan_version(contents) {
+        Some(required) => required,
+        None => return Ok(()),
+    };
+    let running = env!("CARGO_PKG_VERSION");
+    match utils::satisfies_elan_version(running, required) {
+        Some(true) => Ok(()),
+        Some(false) => Err(ErrorKind::ElanVersionTooOld(
+            path.to_owned(),
+            required.to_owned(),
+            running.to_owned(),
+        )
+        .into()),
+        None => Err(ErrorKind::InvalidElanVersion(path.to_owned(), required.to_owned()).into()),
+    }
+}
@@ -23,9 +23,42 @@ error_chain! {
             description("toolchain is not installed")
             display("toolchain '{}' is not installed", t)
         }
+        ReadOnlyToolchain(t: String) {
+            description("toolchain is read-only")
+            display("toolchain '{}' is a system toolchain, which elan does not modify", t)
+        }
+        ToolchainAlreadyExists(t: String) {
+            description("toolchain already exists")
+            display("toolchain '{}' already exists", t)
+        }
+        ToolchainLinkExists(t: String) {
+            description("toolchain already exists")
+            display("toolchain '{}' already exists; pass `--force` to link it to the new directory instead", t)
+        }
+        ExportingToolchain(t: String) {
+            description("failed to export toolchain")
+            display("failed to export toolchain '{}'", t)
+        }
+        InvalidToolchainArchive(path: PathBuf) {
+            description("archive does not contain a toolchain")
+            display("'{}' is not a toolchain archive created by `elan toolchain export`", path.display())
+        }
+        NoInstalledFilesRecord(t: String) {
+            description("toolchain has no record of its installed files")
+            display("toolchain '{}' has no record of its installed files to verify against; \
+                     only toolchains installed from a release by this version of elan or newer have one", t)
+        }
+        ToolchainNotTracking(t: String) {
+            description("toolchain does not track a release channel")
+            display("toolchain '{}' does not track a release channel and is never updated", t)
+        }
         NoDefaultToolchain {
             description("no default toolchain configured. run `elan default stable` to install & configure the latest Lean 3 community release.")
         }
+        DefaultToolchainNotInstalled(t: String) {
+            description("default toolchain is not installed")
+            display("default toolchain '{}' is not installed. run `elan default <toolchain>` to choose another one, or `elan default {}` to reinstall it", t, t)
+        }
         OverrideToolchainNotInstalled(t: String) {
             description("override toolchain is not installed")
             display("override toolchain '{}' is not installed", t)
@@ -34,6 +67,27 @@ error_chain! {
             description("toolchain does not contain binary")
             display("toolchain '{}' does not have the binary `{}`", t, bin)
         }
+        ForeignToolchain(t: String, target: String) {
+            description("toolchain was installed for another platform")
+            display("toolchain '{}' was installed for '{}' and cannot run on this platform; \
+                     reinstall it without `--target` to use it here", t, target)
+        }
+        ToolchainArchMismatch {
+            expected: String,
+            found: String,
+        } {
+            description("toolchain was built for another architecture")
+            display("toolchain binary was built for '{}' and cannot run on this '{}' machine; \
+                     it may have been copied from another machine, reinstall it to use it here", found, expected)
+        }
+        BinaryNotExecutable(path: PathBuf) {
+            description("binary is not executable")
+            display("'{}' is not executable; you may need to run `chmod +x '{}'`", path.display(), path.display())
+        }
+        ToolchainDocsNotInstalled(t: String) {
+            description("toolchain does not contain documentation")
+            display("toolchain '{}' does not have documentation installed", t)
+        }
         NeedMetadataUpgrade {
             description("elan's metadata is out of date. run `elan self upgrade-data`")
         }
@@ -71,13 +125,69 @@ error_chain! {
         TelemetryCleanupError {
             description("unable to remove old telemetry files")
         }
+        TelemetryWriteError {
+            description("unable to write telemetry log")
+        }
         TelemetryAnalysisError {
             description("error analyzing telemetry files")
         }
+        InvalidTelemetrySince(s: String) {
+            description("invalid telemetry cutoff")
+            display("invalid value '{}' for '--since': expected a duration such as '3d', '12h' or '30m', or a date in the form 'YYYY-MM-DD'", s)
+        }
         InvalidLeanpkgFile(path: PathBuf, error: toml::de::Error) {
             description("couldn't parse 'leanpkg.toml'")
             display("couldn't parse '{}': '{}'", path.display(), error)
         }
+        InvalidDownloadIdleTimeout(secs: i64) {
+            description("invalid 'download_idle_timeout' setting")
+            display("invalid 'download_idle_timeout' setting: expected a positive number of seconds instead of {}", secs)
+        }
+        InvalidIndexTimeout(secs: i64) {
+            description("invalid 'index_timeout' setting")
+            display("invalid 'index_timeout' setting: expected a positive number of seconds instead of {}", secs)
+        }
+        InvalidMaxConcurrentDownloads(n: i64) {
+            description("invalid 'max_concurrent_downloads' setting")
+            display("invalid 'max_concurrent_downloads' setting: expected a positive number of downloads instead of {}", n)
+        }
+        InvalidTelemetryConcurrency(n: i64) {
+            description("invalid 'telemetry_concurrency' setting")
+            display("invalid 'telemetry_concurrency' setting: expected a positive number of writers instead of {}", n)
+        }
+        InvalidTelemetryFormat(format: String) {
+            description("invalid 'telemetry_format' setting")
+            display("invalid 'telemetry_format' setting: expected 'json' or 'msgpack' instead of '{}'", format)
+        }
+        InvalidColor(color: String) {
+            description("invalid 'color' setting")
+            display("invalid 'color' setting: expected 'auto', 'always' or 'never' instead of '{}'", color)
+        }
+        InvalidLeanPathMode(mode: String) {
+            description("invalid 'lean_path' setting")
+            display("invalid 'lean_path' setting: expected 'defer', 'prepend' or 'append' instead of '{}'", mode)
+        }
+        UnknownSetting(name: String, known: String) {
+            description("unknown setting")
+            display("unknown setting '{}', expected one of: {}", name, known)
+        }
+        InvalidSettingValue(name: &'static str, value: String, expected: &'static str) {
+            description("invalid setting value")
+            display("invalid value '{}' for '{}': expected {}", value, name, expected)
+        }
+        InvalidSelfUpdateChannel(channel: String) {
+            description("invalid 'self_update_channel' setting")
+            display("invalid 'self_update_channel' setting: expected 'stable' or 'beta' instead of '{}'", channel)
+        }
+        ElanVersionTooOld(path: PathBuf, required: String, running: String) {
+            description("elan is too old for this project")
+            display("the toolchain file at '{}' requires elan {} or newer, but this is elan {}; \
+                     run `elan self update` to update it", path.display(), required, running)
+        }
+        InvalidElanVersion(path: PathBuf, v: String) {
+            description("invalid 'elan-version' value")
+            display("invalid 'elan-version' value in '{}': expected a version such as '1.5.0' instead of '{}'", path.display(), v)
+        }
         InvalidLeanVersion(path: PathBuf, t: &'static str) {
             description("invalid 'package.lean_version' value")
             display("invalid 'package.lean_version' value in '{}': expected string instead of {}", path.display(), t)
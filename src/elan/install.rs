@@ -1,7 +1,7 @@
 //! Installation and upgrade of both distribution-managed and local
 //! toolchains
 
-use elan_dist::dist;
+use elan_dist::dist::{self, DistOptions};
 use elan_dist::download::DownloadCfg;
 use elan_dist::prefix::InstallPrefix;
 use elan_dist::Notification;
@@ -9,16 +9,16 @@ use elan_utils::utils;
 use errors::Result;
 use std::path::Path;
 
-#[derive(Copy, Clone)]
 pub enum InstallMethod<'a> {
     Copy(&'a Path),
     Link(&'a Path),
-    // bool is whether to force an update
+    // A directory on the same file system, such as a temporary one
+    Move(&'a Path),
     Dist(
         &'a dist::ToolchainDesc,
         Option<&'a Path>,
         DownloadCfg<'a>,
-        bool,
+        DistOptions<'a>,
     ),
 }
 
@@ -43,7 +43,11 @@ impl<'a> InstallMethod<'a> {
                 utils::symlink_dir(src, &path, &|n| notify_handler(n.into()))?;
                 Ok(true)
             }
-            InstallMethod::Dist(toolchain, update_hash, dl_cfg, force_update) => {
+            InstallMethod::Move(src) => {
+                utils::rename_dir("toolchain", src, path)?;
+                Ok(true)
+            }
+            InstallMethod::Dist(toolchain, update_hash, dl_cfg, options) => {
                 let prefix = &InstallPrefix::from(path.to_owned());
                 let maybe_new_hash = dist::update_from_dist(
                     dl_cfg,
@@ -52,7 +56,7 @@ impl<'a> InstallMethod<'a> {
                     prefix,
                     &[],
                     &[],
-                    force_update,
+                    options,
                 )?;
 
                 if let Some(hash) = maybe_new_hash {
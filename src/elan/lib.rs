@@ -1,7 +1,9 @@
 #![recursion_limit = "1024"]
 
+extern crate download;
 extern crate elan_dist;
 extern crate elan_utils;
+extern crate flate2;
 #[macro_use]
 extern crate error_chain;
 extern crate itertools;
@@ -11,6 +13,8 @@ extern crate url;
 extern crate serde_derive;
 #[cfg(unix)]
 extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate serde_json;
 extern crate tempfile;
 extern crate time;
@@ -1,7 +1,9 @@
 use std::fmt::{self, Display};
 use std::path::{Path, PathBuf};
 
+use config::OverrideReason;
 use errors::*;
+use settings::PROJECT_SETTINGS;
 
 use elan_dist::{self, temp};
 use elan_utils;
@@ -15,14 +17,23 @@ pub enum Notification<'a> {
 
     SetDefaultToolchain(&'a str),
     SetOverrideToolchain(&'a Path, &'a str),
+    WroteToolchainFile(&'a Path, &'a str),
     LookingForToolchain(&'a str),
     ToolchainDirectory(&'a Path, &'a str),
     UpdatingToolchain(&'a str),
     InstallingToolchain(&'a str),
     InstalledToolchain(&'a str),
+    AutoInstallingToolchain(&'a str, &'a OverrideReason),
+    AutoInstalledToolchain(&'a str),
     UsingExistingToolchain(&'a str),
     UninstallingToolchain(&'a str),
     UninstalledToolchain(&'a str),
+    RenamedToolchain(&'a str, &'a str),
+    ExportedToolchain(&'a str, &'a Path),
+    ImportedToolchain(&'a str),
+    PinnedToolchain(&'a str, &'a str),
+    UnpinnedToolchain(&'a str),
+    SkippingPinnedToolchain(&'a str, &'a str),
     ToolchainNotInstalled(&'a str),
     UpdateHashMatches,
     UpgradingMetadata(&'a str, &'a str),
@@ -33,8 +44,29 @@ pub enum Notification<'a> {
     UpgradeRemovesToolchains,
     MissingFileDuringSelfUninstall(PathBuf),
     SetTelemetry(&'a str),
+    SetSetting(&'a str, &'a str),
 
-    TelemetryCleanupError(&'a Error),
+    TelemetryError(&'a Error),
+    TelemetryLateOutput(u64),
+    InvalidTelemetryErrorPattern(&'a str, &'a regex::Error),
+    /// A directory override, for the directory and with the toolchain
+    /// given, that wins over a toolchain file naming another one
+    OverrideHidesToolchainFile(&'a Path, &'a str, &'a Path, &'a str),
+    /// A key of a project's settings file that a project may not set
+    IgnoredProjectSetting(&'a Path, &'a str),
+    /// The `system_toolchains` of a settings file that may not register
+    /// any
+    IgnoredSystemToolchains(&'a Path),
+    /// A setting of `elan set` decided by another settings file than the
+    /// one it writes
+    SettingShadowed(&'a str, &'a Path),
+    /// A command run through elan exited, for embedders to hook. Not sent
+    /// where the command replaces elan, as without telemetry on Unix.
+    CommandFinished {
+        tool: &'a str,
+        exit_code: i32,
+        duration_ms: u64,
+    },
 }
 
 impl<'a> From<elan_dist::Notification<'a>> for Notification<'a> {
@@ -68,18 +100,36 @@ impl<'a> Notification<'a> {
             | ReadMetadataVersion(_)
             | InstalledToolchain(_)
             | UpdateHashMatches
-            | TelemetryCleanupError(_) => NotificationLevel::Verbose,
+            | TelemetryError(_)
+            | TelemetryLateOutput(_)
+            | CommandFinished { .. } => NotificationLevel::Verbose,
             SetDefaultToolchain(_)
             | SetOverrideToolchain(_, _)
+            | WroteToolchainFile(_, _)
             | UsingExistingToolchain(_)
+            | AutoInstallingToolchain(_, _)
+            | AutoInstalledToolchain(_)
             | UninstallingToolchain(_)
             | UninstalledToolchain(_)
+            | RenamedToolchain(_, _)
+            | ExportedToolchain(_, _)
+            | ImportedToolchain(_)
+            | PinnedToolchain(_, _)
+            | UnpinnedToolchain(_)
+            | SkippingPinnedToolchain(_, _)
             | ToolchainNotInstalled(_)
             | UpgradingMetadata(_, _)
             | MetadataUpgradeNotNeeded(_)
-            | SetTelemetry(_) => NotificationLevel::Info,
+            | SetTelemetry(_)
+            | SetSetting(_, _) => NotificationLevel::Info,
             NonFatalError(_) => NotificationLevel::Error,
-            UpgradeRemovesToolchains | MissingFileDuringSelfUninstall(_) => NotificationLevel::Warn,
+            UpgradeRemovesToolchains
+            | MissingFileDuringSelfUninstall(_)
+            | InvalidTelemetryErrorPattern(_, _)
+            | OverrideHidesToolchainFile(_, _, _, _)
+            | IgnoredProjectSetting(_, _)
+            | IgnoredSystemToolchains(_)
+            | SettingShadowed(_, _) => NotificationLevel::Warn,
         }
     }
 }
@@ -100,14 +150,37 @@ impl<'a> Display for Notification<'a> {
                     name
                 )
             }
+            WroteToolchainFile(path, name) => {
+                write!(f, "toolchain file '{}' set to '{}'", path.display(), name)
+            }
             LookingForToolchain(name) => write!(f, "looking for installed toolchain '{}'", name),
             ToolchainDirectory(path, _) => write!(f, "toolchain directory: '{}'", path.display()),
             UpdatingToolchain(name) => write!(f, "updating existing install for '{}'", name),
             InstallingToolchain(name) => write!(f, "installing toolchain '{}'", name),
             InstalledToolchain(name) => write!(f, "toolchain '{}' installed", name),
+            AutoInstallingToolchain(name, reason) => write!(
+                f,
+                "toolchain '{}' ({}) is not installed yet, installing it now",
+                name, reason
+            ),
+            AutoInstalledToolchain(name) => {
+                write!(f, "toolchain '{}' installed, continuing", name)
+            }
             UsingExistingToolchain(name) => write!(f, "using existing install for '{}'", name),
             UninstallingToolchain(name) => write!(f, "uninstalling toolchain '{}'", name),
             UninstalledToolchain(name) => write!(f, "toolchain '{}' uninstalled", name),
+            RenamedToolchain(old, new) => write!(f, "toolchain '{}' renamed to '{}'", old, new),
+            ExportedToolchain(name, path) => {
+                write!(f, "toolchain '{}' exported to '{}'", name, path.display())
+            }
+            ImportedToolchain(name) => write!(f, "toolchain '{}' imported", name),
+            PinnedToolchain(name, tag) => write!(f, "toolchain '{}' pinned to '{}'", name, tag),
+            UnpinnedToolchain(name) => write!(f, "toolchain '{}' unpinned", name),
+            SkippingPinnedToolchain(name, tag) => write!(
+                f,
+                "skipping toolchain '{}', which is pinned to '{}'",
+                name, tag
+            ),
             ToolchainNotInstalled(name) => write!(f, "no toolchain installed for '{}'", name),
             UpdateHashMatches => {
                 write!(f, "toolchain is already up to date")
@@ -141,7 +214,56 @@ impl<'a> Display for Notification<'a> {
                 )
             }
             SetTelemetry(telemetry_status) => write!(f, "telemetry set to '{}'", telemetry_status),
-            TelemetryCleanupError(e) => write!(f, "unable to remove old telemetry files: '{}'", e),
+            SetSetting(name, value) => write!(f, "'{}' set to '{}'", name, value),
+            TelemetryError(e) => write!(f, "unable to record telemetry: '{}'", e),
+            TelemetryLateOutput(len) => write!(
+                f,
+                "{} bytes of output were written after lean exited and were not scanned for error codes",
+                len
+            ),
+            InvalidTelemetryErrorPattern(pattern, e) => write!(
+                f,
+                "invalid telemetry_error_pattern '{}', using the default: {}",
+                pattern, e
+            ),
+            OverrideHidesToolchainFile(dir, name, file, file_name) => write!(
+                f,
+                "using '{}' from the directory override for '{}' rather than '{}' from '{}', \
+                 as overrides take precedence; run `elan override unset` to use the file",
+                name,
+                dir.display(),
+                file_name,
+                file.display()
+            ),
+            IgnoredProjectSetting(file, key) => write!(
+                f,
+                "ignoring '{}' in '{}', as a project's settings may only set {}",
+                key,
+                file.display(),
+                PROJECT_SETTINGS.join(", ")
+            ),
+            IgnoredSystemToolchains(file) => write!(
+                f,
+                "ignoring 'system_toolchains' in '{}', as only the system settings \
+                 and those of the elan home directory may register system toolchains",
+                file.display()
+            ),
+            SettingShadowed(name, file) => write!(
+                f,
+                "'{}' is set in '{}', which takes precedence over the settings \
+                 `elan set` changes",
+                name,
+                file.display()
+            ),
+            CommandFinished {
+                tool,
+                exit_code,
+                duration_ms,
+            } => write!(
+                f,
+                "'{}' exited with code {} after {}ms",
+                tool, exit_code, duration_ms
+            ),
         }
     }
 }
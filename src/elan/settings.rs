@@ -1,31 +1,106 @@
+use elan_utils::log_format::LogFormat;
+use elan_utils::path_var::PathVarMode;
+use elan_utils::tty::ColorChoice;
 use errors::*;
 use notifications::*;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
 use toml;
 use toml_utils::*;
-use utils;
+use utils::{self, SelfUpdateChannel};
 
 pub const SUPPORTED_METADATA_VERSIONS: [&'static str; 2] = ["2", "12"];
 pub const DEFAULT_METADATA_VERSION: &'static str = "12";
 
+/// The settings in `ELAN_HOME`, read together with the settings files that
+/// layer over them. From least to most specific these are the system
+/// defaults in `/etc/elan/settings.toml`, the `settings.toml` in
+/// `ELAN_HOME`, the user's `$XDG_CONFIG_HOME/elan/settings.toml` (or
+/// `~/.config/elan/settings.toml`) and the `.elan/settings.toml` of the
+/// nearest enclosing project. Keys of more specific files win and tables
+/// are merged key by key.
+///
+/// Only the file in `ELAN_HOME` is ever written, so `with_mut` sees only
+/// its own settings. A project, which may be anything that was cloned,
+/// may only set the keys of `PROJECT_SETTINGS`, and only the system
+/// defaults and `ELAN_HOME` may register `system_toolchains`, which elan
+/// trusts not to change.
 #[derive(Clone, Debug, PartialEq)]
 pub struct SettingsFile {
     path: PathBuf,
+    /// Least specific first, including `path`
+    layers: Vec<PathBuf>,
+    /// The project's layer, if any
+    project: Option<PathBuf>,
+    /// Keys of the project's layer that were left out of `merged`
+    ignored: RefCell<Vec<String>>,
+    /// Other layers whose `system_toolchains` were left out of `merged`
+    ignored_system_toolchains: RefCell<Vec<PathBuf>>,
     cache: RefCell<Option<Settings>>,
+    merged: RefCell<Option<Settings>>,
 }
 
+/// The settings a project's `.elan/settings.toml` may set, which only
+/// change how elan downloads and prints rather than what it runs. Proxies
+/// are left out, as they would send downloads through a server of the
+/// project's choosing
+pub const PROJECT_SETTINGS: &[&str] = &[
+    "download_idle_timeout",
+    "index_timeout",
+    "max_concurrent_downloads",
+    "color",
+];
+
 impl SettingsFile {
     pub fn new(path: PathBuf) -> Self {
         SettingsFile {
+            layers: vec![path.clone()],
             path: path,
+            project: None,
+            ignored: RefCell::new(Vec::new()),
+            ignored_system_toolchains: RefCell::new(Vec::new()),
             cache: RefCell::new(None),
+            merged: RefCell::new(None),
         }
     }
+    /// Layers the settings files found for `cwd` over and under `path`
+    pub fn discover(path: PathBuf, cwd: &Path) -> Self {
+        let mut layers = Vec::new();
+        layers.extend(system_settings_file());
+        layers.push(path.clone());
+        layers.extend(user_settings_file());
+        let project = project_settings_file(&path, cwd);
+        layers.extend(project.clone());
+        SettingsFile {
+            layers,
+            project,
+            ..Self::new(path)
+        }
+    }
+    /// The project's settings file and the keys of it that were ignored,
+    /// as they are not among `PROJECT_SETTINGS`
+    pub fn ignored_project_settings(&self) -> Result<Option<(PathBuf, Vec<String>)>> {
+        self.read_merged_settings()?;
+        let ignored = self.ignored.borrow();
+        Ok(self
+            .project
+            .clone()
+            .filter(|_| !ignored.is_empty())
+            .map(|project| (project, ignored.clone())))
+    }
+    /// The settings files other than the system defaults and the one in
+    /// `ELAN_HOME` whose `system_toolchains` were ignored
+    pub fn ignored_system_toolchains(&self) -> Result<Vec<PathBuf>> {
+        self.read_merged_settings()?;
+        Ok(self.ignored_system_toolchains.borrow().clone())
+    }
     fn write_settings(&self) -> Result<()> {
         let s = self.cache.borrow().as_ref().unwrap().clone();
-        utils::write_file("settings", &self.path, &s.stringify())?;
+        utils::write_file_atomic("settings", &self.path, &s.stringify())?;
+        *self.merged.borrow_mut() = None;
         Ok(())
     }
     fn read_settings(&self) -> Result<()> {
@@ -47,11 +122,86 @@ impl SettingsFile {
         }
         Ok(())
     }
-    pub fn with<T, F: FnOnce(&Settings) -> Result<T>>(&self, f: F) -> Result<T> {
+    fn read_merged_settings(&self) -> Result<()> {
         self.read_settings()?;
+        if self.merged.borrow().is_some() {
+            return Ok(());
+        }
+        let mut merged = if self.layers.len() == 1 {
+            self.cache.borrow().clone().unwrap()
+        } else {
+            let mut table = toml::value::Table::new();
+            let system = system_settings_file();
+            self.ignored_system_toolchains.borrow_mut().clear();
+            for layer in &self.layers {
+                let mut layer_table = match self.layer_table(layer)? {
+                    Some(table) => table,
+                    None => continue,
+                };
+                if Some(layer) == self.project.as_ref() {
+                    let ignored: Vec<String> = layer_table
+                        .keys()
+                        .filter(|key| !PROJECT_SETTINGS.contains(&key.as_str()))
+                        .cloned()
+                        .collect();
+                    for key in &ignored {
+                        layer_table.remove(key);
+                    }
+                    *self.ignored.borrow_mut() = ignored;
+                } else if *layer != self.path
+                    && Some(layer) != system.as_ref()
+                    && layer_table.remove("system_toolchains").is_some()
+                {
+                    self.ignored_system_toolchains
+                        .borrow_mut()
+                        .push(layer.clone());
+                }
+                merge_tables(&mut table, layer_table);
+            }
+            Settings::from_toml(table, "")?
+        };
+        merged.expand_env_vars()?;
+        *self.merged.borrow_mut() = Some(merged);
+        Ok(())
+    }
+    /// The keys `layer` sets, or `None` if there is no such file
+    fn layer_table(&self, layer: &Path) -> Result<Option<toml::value::Table>> {
+        if layer == self.path {
+            self.read_settings()?;
+            return Ok(Some(self.cache.borrow().clone().unwrap().to_toml()));
+        }
+        if !utils::is_file(layer) {
+            return Ok(None);
+        }
+        let content = utils::read_file("settings", layer)?;
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|e| Error::from(ErrorKind::ParsingSettings(e)))
+            .chain_err(|| format!("failed to parse settings file '{}'", layer.display()))
+    }
+    /// The most specific settings file that sets `key`, if any
+    pub fn layer_setting(&self, key: &str) -> Result<Option<PathBuf>> {
+        for layer in self.layers.iter().rev() {
+            if Some(layer) == self.project.as_ref() && !PROJECT_SETTINGS.contains(&key) {
+                continue;
+            }
+            if let Some(table) = self.layer_table(layer)? {
+                if table.contains_key(key) {
+                    return Ok(Some(layer.clone()));
+                }
+            }
+        }
+        Ok(None)
+    }
+    /// The settings file in `ELAN_HOME`, the only one that is written
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn with<T, F: FnOnce(&Settings) -> Result<T>>(&self, f: F) -> Result<T> {
+        self.read_merged_settings()?;
 
         // Settings can no longer be None so it's OK to unwrap
-        f(self.cache.borrow().as_ref().unwrap())
+        f(self.merged.borrow().as_ref().unwrap())
     }
     pub fn with_mut<T, F: FnOnce(&mut Settings) -> Result<T>>(&self, f: F) -> Result<T> {
         self.read_settings()?;
@@ -63,6 +213,117 @@ impl SettingsFile {
     }
 }
 
+#[cfg(unix)]
+fn system_settings_file() -> Option<PathBuf> {
+    Some(PathBuf::from("/etc/elan/settings.toml"))
+}
+
+#[cfg(windows)]
+fn system_settings_file() -> Option<PathBuf> {
+    env::var_os("PROGRAMDATA").map(|p| PathBuf::from(p).join("elan").join("settings.toml"))
+}
+
+fn user_settings_file() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => utils::home_dir()?.join(".config"),
+    };
+    Some(config_dir.join("elan").join("settings.toml"))
+}
+
+/// The `.elan/settings.toml` nearest to `cwd`, unless that is the settings
+/// file of `ELAN_HOME` itself, as for a `~/.elan` above the project
+fn project_settings_file(home_settings: &Path, cwd: &Path) -> Option<PathBuf> {
+    let home_settings = fs::canonicalize(home_settings).ok();
+    cwd.ancestors()
+        .map(|d| d.join(".elan").join("settings.toml"))
+        .find(|p| utils::is_file(p))
+        .filter(|p| fs::canonicalize(p).ok() != home_settings)
+}
+
+/// A setting that `elan set` can change and `elan get` show, by the name
+/// they know it by
+pub struct SettingKey {
+    pub name: &'static str,
+    /// Its key in `settings.toml`
+    pub key: &'static str,
+    /// The values it takes, as shown in errors
+    pub values: &'static str,
+    get: fn(&Settings) -> String,
+    /// Sets the setting from a value, or `None` if the value is invalid
+    set: fn(&mut Settings, &str) -> Option<()>,
+}
+
+impl SettingKey {
+    pub fn get(&self, settings: &Settings) -> String {
+        (self.get)(settings)
+    }
+
+    pub fn set(&self, settings: &mut Settings, value: &str) -> Result<()> {
+        (self.set)(settings, value).ok_or_else(|| {
+            ErrorKind::InvalidSettingValue(self.name, value.to_owned(), self.values).into()
+        })
+    }
+}
+
+pub static SETTING_KEYS: [SettingKey; 4] = [
+    SettingKey {
+        name: "auto-install",
+        key: "auto_install",
+        values: "'true' or 'false'",
+        get: |s| s.auto_install.to_string(),
+        set: |s, v| {
+            s.auto_install = v.parse().ok()?;
+            Some(())
+        },
+    },
+    SettingKey {
+        name: "color",
+        key: "color",
+        values: "'auto', 'always' or 'never'",
+        get: |s| s.color.unwrap_or(ColorChoice::Auto).as_str().to_owned(),
+        set: |s, v| {
+            s.color = Some(ColorChoice::parse(v)?);
+            Some(())
+        },
+    },
+    SettingKey {
+        name: "lean-path",
+        key: "lean_path",
+        values: "'defer', 'prepend' or 'append'",
+        get: |s| s.lean_path.as_str().to_owned(),
+        set: |s, v| {
+            s.lean_path = PathVarMode::parse(v)?;
+            Some(())
+        },
+    },
+    SettingKey {
+        name: "telemetry",
+        key: "telemetry",
+        values: "'true' or 'false'",
+        get: |s| (s.telemetry == TelemetryMode::On).to_string(),
+        set: |s, v| {
+            s.telemetry = if v.parse().ok()? {
+                TelemetryMode::On
+            } else {
+                TelemetryMode::Off
+            };
+            Some(())
+        },
+    },
+];
+
+/// The setting `elan set` and `elan get` know as `name`
+pub fn setting_key(name: &str) -> Result<&'static SettingKey> {
+    SETTING_KEYS
+        .iter()
+        .find(|key| key.name == name)
+        .ok_or_else(|| {
+            let names: Vec<_> = SETTING_KEYS.iter().map(|key| key.name).collect();
+            ErrorKind::UnknownSetting(name.to_owned(), names.join(", ")).into()
+        })
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TelemetryMode {
     On,
@@ -75,6 +336,46 @@ pub struct Settings {
     pub default_toolchain: Option<String>,
     pub overrides: BTreeMap<String, String>,
     pub telemetry: TelemetryMode,
+    pub compress_telemetry: bool,
+    /// What new telemetry events are written as
+    pub telemetry_format: LogFormat,
+    /// How many telemetry events one process may write at once
+    pub telemetry_concurrency: Option<usize>,
+    /// Regex finding the error codes in the output of `lean`, with the code
+    /// in its `error` group if it has one
+    pub telemetry_error_pattern: Option<String>,
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    /// Seconds a download may go without receiving data
+    pub download_idle_timeout: Option<u64>,
+    /// Seconds a request for release metadata may take
+    pub index_timeout: Option<u64>,
+    /// How many files one process may download at once
+    pub max_concurrent_downloads: Option<usize>,
+    /// Where `elan toolchain install --keep-download` keeps archives,
+    /// relative to `ELAN_HOME` unless absolute
+    pub archive_dir: Option<PathBuf>,
+    /// Arguments passed to every `lean` before the toolchain's own and the
+    /// user's arguments
+    pub lean_args: Vec<String>,
+    /// Arguments passed to `lean` of a toolchain, keyed by toolchain name
+    pub toolchain_lean_args: BTreeMap<String, Vec<String>>,
+    /// Release pages of tracking toolchains that `elan update` leaves alone,
+    /// keyed by toolchain name
+    pub pinned: BTreeMap<String, String>,
+    /// Toolchains installed outside of elan, such as by an administrator,
+    /// that elan uses but never modifies, keyed by toolchain name
+    pub system_toolchains: BTreeMap<String, PathBuf>,
+    /// Which releases of elan `elan self update` installs
+    pub self_update_channel: SelfUpdateChannel,
+    /// Never add `--color` to the arguments of commands run through elan
+    pub no_color_inject: bool,
+    /// Install missing toolchains when running a command needs them
+    pub auto_install: bool,
+    /// Whether to color output when `--color` is not given
+    pub color: Option<ColorChoice>,
+    /// How `elan exec` adds the toolchain's library to `LEAN_PATH`
+    pub lean_path: PathVarMode,
 }
 
 impl Default for Settings {
@@ -84,6 +385,25 @@ impl Default for Settings {
             default_toolchain: None,
             overrides: BTreeMap::new(),
             telemetry: TelemetryMode::Off,
+            compress_telemetry: false,
+            telemetry_format: LogFormat::Json,
+            telemetry_concurrency: None,
+            telemetry_error_pattern: None,
+            proxy: None,
+            no_proxy: None,
+            download_idle_timeout: None,
+            index_timeout: None,
+            max_concurrent_downloads: None,
+            archive_dir: None,
+            lean_args: Vec::new(),
+            toolchain_lean_args: BTreeMap::new(),
+            pinned: BTreeMap::new(),
+            system_toolchains: BTreeMap::new(),
+            self_update_channel: SelfUpdateChannel::Stable,
+            no_color_inject: false,
+            auto_install: true,
+            color: None,
+            lean_path: PathVarMode::Defer,
         }
     }
 }
@@ -124,6 +444,22 @@ impl Settings {
         self.overrides.get(&key).map(|s| s.clone())
     }
 
+    /// Points the default toolchain, all overrides and the pin that use `old`
+    /// at `new`
+    pub fn rename_toolchain(&mut self, old: &str, new: &str) {
+        if self.default_toolchain.as_ref().map(|s| &s[..]) == Some(old) {
+            self.default_toolchain = Some(new.to_owned());
+        }
+        for toolchain in self.overrides.values_mut() {
+            if toolchain == old {
+                *toolchain = new.to_owned();
+            }
+        }
+        if let Some(release) = self.pinned.remove(old) {
+            self.pinned.insert(new.to_owned(), release);
+        }
+    }
+
     pub fn parse(data: &str) -> Result<Self> {
         let value = toml::from_str(data).map_err(ErrorKind::ParsingSettings)?;
         Self::from_toml(value, "")
@@ -146,8 +482,109 @@ impl Settings {
             } else {
                 TelemetryMode::Off
             },
+            compress_telemetry: get_opt_bool(&mut table, "compress_telemetry", path)?
+                .unwrap_or(false),
+            telemetry_format: Self::get_telemetry_format(&mut table, path)?,
+            telemetry_concurrency: Self::get_telemetry_concurrency(&mut table, path)?,
+            telemetry_error_pattern: get_opt_string(&mut table, "telemetry_error_pattern", path)?,
+            proxy: get_opt_string(&mut table, "proxy", path)?,
+            no_proxy: get_opt_string(&mut table, "no_proxy", path)?,
+            download_idle_timeout: Self::get_download_idle_timeout(&mut table, path)?,
+            index_timeout: Self::get_index_timeout(&mut table, path)?,
+            max_concurrent_downloads: Self::get_max_concurrent_downloads(&mut table, path)?,
+            archive_dir: get_opt_string(&mut table, "archive_dir", path)?.map(PathBuf::from),
+            lean_args: get_string_array(&mut table, "lean_args", path)?,
+            toolchain_lean_args: Self::table_to_toolchain_lean_args(&mut table, path)?,
+            pinned: Self::table_to_pinned(&mut table, path)?,
+            system_toolchains: Self::table_to_system_toolchains(&mut table, path)?,
+            self_update_channel: Self::get_self_update_channel(&mut table, path)?,
+            no_color_inject: get_opt_bool(&mut table, "no_color_inject", path)?.unwrap_or(false),
+            auto_install: get_opt_bool(&mut table, "auto_install", path)?.unwrap_or(true),
+            color: Self::get_color(&mut table, path)?,
+            lean_path: Self::get_lean_path(&mut table, path)?,
         })
     }
+    /// Expands `${VAR}` in the values that name locations. Only the settings
+    /// read through `with` are expanded, so that `with_mut` writes the
+    /// references back as they were.
+    fn expand_env_vars(&mut self) -> Result<()> {
+        let var = |name: &str| env::var(name).ok();
+        if let Some(ref mut proxy) = self.proxy {
+            *proxy = utils::expand_env_vars(proxy, "proxy", var)?;
+        }
+        if let Some(ref mut dir) = self.archive_dir {
+            let expanded = utils::expand_env_vars(&dir.to_string_lossy(), "archive_dir", var)?;
+            *dir = PathBuf::from(expanded);
+        }
+        Ok(())
+    }
+    fn get_self_update_channel(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<SelfUpdateChannel> {
+        match get_opt_string(table, "self_update_channel", path)? {
+            Some(s) => SelfUpdateChannel::parse(&s)
+                .ok_or_else(|| ErrorKind::InvalidSelfUpdateChannel(s).into()),
+            None => Ok(SelfUpdateChannel::Stable),
+        }
+    }
+    fn get_color(table: &mut toml::value::Table, path: &str) -> Result<Option<ColorChoice>> {
+        match get_opt_string(table, "color", path)? {
+            Some(s) => ColorChoice::parse(&s)
+                .map(Some)
+                .ok_or_else(|| ErrorKind::InvalidColor(s).into()),
+            None => Ok(None),
+        }
+    }
+    fn get_lean_path(table: &mut toml::value::Table, path: &str) -> Result<PathVarMode> {
+        match get_opt_string(table, "lean_path", path)? {
+            Some(s) => {
+                PathVarMode::parse(&s).ok_or_else(|| ErrorKind::InvalidLeanPathMode(s).into())
+            }
+            None => Ok(PathVarMode::Defer),
+        }
+    }
+    fn get_telemetry_format(table: &mut toml::value::Table, path: &str) -> Result<LogFormat> {
+        match get_opt_string(table, "telemetry_format", path)? {
+            Some(s) => {
+                LogFormat::parse(&s).ok_or_else(|| ErrorKind::InvalidTelemetryFormat(s).into())
+            }
+            None => Ok(LogFormat::Json),
+        }
+    }
+    fn get_telemetry_concurrency(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<Option<usize>> {
+        match get_opt_integer(table, "telemetry_concurrency", path)? {
+            Some(n) if n <= 0 => Err(ErrorKind::InvalidTelemetryConcurrency(n).into()),
+            n => Ok(n.map(|n| n as usize)),
+        }
+    }
+    fn get_download_idle_timeout(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<Option<u64>> {
+        match get_opt_integer(table, "download_idle_timeout", path)? {
+            Some(secs) if secs <= 0 => Err(ErrorKind::InvalidDownloadIdleTimeout(secs).into()),
+            secs => Ok(secs.map(|s| s as u64)),
+        }
+    }
+    fn get_index_timeout(table: &mut toml::value::Table, path: &str) -> Result<Option<u64>> {
+        match get_opt_integer(table, "index_timeout", path)? {
+            Some(secs) if secs <= 0 => Err(ErrorKind::InvalidIndexTimeout(secs).into()),
+            secs => Ok(secs.map(|s| s as u64)),
+        }
+    }
+    fn get_max_concurrent_downloads(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<Option<usize>> {
+        match get_opt_integer(table, "max_concurrent_downloads", path)? {
+            Some(n) if n <= 0 => Err(ErrorKind::InvalidMaxConcurrentDownloads(n).into()),
+            n => Ok(n.map(|n| n as usize)),
+        }
+    }
     pub fn to_toml(self) -> toml::value::Table {
         let mut result = toml::value::Table::new();
 
@@ -163,6 +600,115 @@ impl Settings {
         let telemetry = self.telemetry == TelemetryMode::On;
         result.insert("telemetry".to_owned(), toml::Value::Boolean(telemetry));
 
+        if self.compress_telemetry {
+            result.insert("compress_telemetry".to_owned(), toml::Value::Boolean(true));
+        }
+
+        if self.telemetry_format != LogFormat::Json {
+            result.insert(
+                "telemetry_format".to_owned(),
+                toml::Value::String(self.telemetry_format.as_str().to_owned()),
+            );
+        }
+
+        if let Some(n) = self.telemetry_concurrency {
+            result.insert(
+                "telemetry_concurrency".to_owned(),
+                toml::Value::Integer(n as i64),
+            );
+        }
+
+        if let Some(v) = self.telemetry_error_pattern {
+            result.insert("telemetry_error_pattern".to_owned(), toml::Value::String(v));
+        }
+
+        if let Some(v) = self.proxy {
+            result.insert("proxy".to_owned(), toml::Value::String(v));
+        }
+
+        if let Some(v) = self.no_proxy {
+            result.insert("no_proxy".to_owned(), toml::Value::String(v));
+        }
+
+        if let Some(v) = self.download_idle_timeout {
+            result.insert(
+                "download_idle_timeout".to_owned(),
+                toml::Value::Integer(v as i64),
+            );
+        }
+
+        if let Some(v) = self.index_timeout {
+            result.insert("index_timeout".to_owned(), toml::Value::Integer(v as i64));
+        }
+
+        if let Some(n) = self.max_concurrent_downloads {
+            result.insert(
+                "max_concurrent_downloads".to_owned(),
+                toml::Value::Integer(n as i64),
+            );
+        }
+
+        if let Some(v) = self.archive_dir {
+            result.insert(
+                "archive_dir".to_owned(),
+                toml::Value::String(v.to_string_lossy().into_owned()),
+            );
+        }
+
+        if !self.lean_args.is_empty() {
+            result.insert("lean_args".to_owned(), Self::args_to_array(self.lean_args));
+        }
+
+        if !self.toolchain_lean_args.is_empty() {
+            let mut table = toml::value::Table::new();
+            for (k, v) in self.toolchain_lean_args {
+                table.insert(k, Self::args_to_array(v));
+            }
+            result.insert("toolchain_lean_args".to_owned(), toml::Value::Table(table));
+        }
+
+        if !self.pinned.is_empty() {
+            let pinned = Self::overrides_to_table(self.pinned);
+            result.insert("pinned".to_owned(), toml::Value::Table(pinned));
+        }
+
+        if !self.system_toolchains.is_empty() {
+            let mut table = toml::value::Table::new();
+            for (k, v) in self.system_toolchains {
+                table.insert(k, toml::Value::String(v.to_string_lossy().into_owned()));
+            }
+            result.insert("system_toolchains".to_owned(), toml::Value::Table(table));
+        }
+
+        if self.self_update_channel != SelfUpdateChannel::Stable {
+            result.insert(
+                "self_update_channel".to_owned(),
+                toml::Value::String(self.self_update_channel.as_str().to_owned()),
+            );
+        }
+
+        if self.no_color_inject {
+            result.insert("no_color_inject".to_owned(), toml::Value::Boolean(true));
+        }
+
+        if !self.auto_install {
+            result.insert("auto_install".to_owned(), toml::Value::Boolean(false));
+        }
+
+        if let Some(color) = self.color {
+            result.insert(
+                "color".to_owned(),
+                toml::Value::String(color.as_str().to_owned()),
+            );
+        }
+
+        if self.lean_path != PathVarMode::Defer {
+            result.insert(
+                "lean_path".to_owned(),
+                toml::Value::String(self.lean_path.as_str().to_owned()),
+            );
+        }
+
         result
     }
 
@@ -182,6 +728,61 @@ impl Settings {
         Ok(result)
     }
 
+    fn table_to_pinned(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut pinned_table = get_table(table, "pinned", path)?;
+        let path = path.to_owned() + "pinned.";
+
+        let mut result = BTreeMap::new();
+        let toolchains: Vec<String> = pinned_table.keys().cloned().collect();
+        for toolchain in toolchains {
+            let release = get_string(&mut pinned_table, &toolchain, &path)?;
+            result.insert(toolchain, release);
+        }
+
+        Ok(result)
+    }
+
+    fn table_to_system_toolchains(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<BTreeMap<String, PathBuf>> {
+        let mut system_table = get_table(table, "system_toolchains", path)?;
+        let path = path.to_owned() + "system_toolchains.";
+
+        let mut result = BTreeMap::new();
+        let toolchains: Vec<String> = system_table.keys().cloned().collect();
+        for toolchain in toolchains {
+            let dir = get_string(&mut system_table, &toolchain, &path)?;
+            result.insert(toolchain, PathBuf::from(dir));
+        }
+
+        Ok(result)
+    }
+
+    fn table_to_toolchain_lean_args(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<BTreeMap<String, Vec<String>>> {
+        let mut result = BTreeMap::new();
+        let mut args_table = get_table(table, "toolchain_lean_args", path)?;
+        let path = path.to_owned() + "toolchain_lean_args.";
+
+        let toolchains: Vec<String> = args_table.keys().cloned().collect();
+        for toolchain in toolchains {
+            let args = get_string_array(&mut args_table, &toolchain, &path)?;
+            result.insert(toolchain, args);
+        }
+
+        Ok(result)
+    }
+
+    fn args_to_array(args: Vec<String>) -> toml::Value {
+        toml::Value::Array(args.into_iter().map(toml::Value::String).collect())
+    }
+
     fn overrides_to_table(overrides: BTreeMap<String, String>) -> toml::value::Table {
         let mut result = toml::value::Table::new();
         for (k, v) in overrides {
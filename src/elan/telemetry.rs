@@ -1,14 +1,32 @@
+use elan_utils::log_format::LogFormat;
+use elan_utils::raw;
+use elan_utils::semaphore::Semaphore;
 use errors::*;
-
-use std::fs;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json;
+use time::OffsetDateTime;
+use utils;
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+/// Durations are measured on the monotonic clock and so are unaffected by
+/// changes to the system clock, unlike the time each event is logged at.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum TelemetryEvent {
     LeanRun {
         duration_ms: u64,
         exit_code: i32,
         errors: Option<Vec<String>>,
+        /// Whether elan added `--color always` to the arguments, which
+        /// changes what the error codes are scanned for in
+        #[serde(default)]
+        color_forced: bool,
     },
     ToolchainUpdate {
         toolchain: String,
@@ -19,38 +37,164 @@ pub enum TelemetryEvent {
         target: String,
         success: bool,
     },
+    /// A toolchain was installed or updated from a release
+    Download {
+        toolchain: String,
+        bytes: u64,
+        duration_ms: u64,
+        /// Whether running a command installed the toolchain, rather than
+        /// the user asking for it
+        auto: bool,
+    },
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct LogMessage {
+    /// Wall-clock time, which is only as reliable as the system clock and
+    /// may go backwards when it is adjusted
     log_time_s: i64,
     event: TelemetryEvent,
     version: i32,
+    /// Added by a `TelemetryTransform`, such as a machine ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<String, String>,
 }
 
 impl LogMessage {
     pub fn get_event(&self) -> TelemetryEvent {
         self.event.clone()
     }
+
+    pub fn set_event(&mut self, event: TelemetryEvent) {
+        self.event = event;
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(|v| &**v)
+    }
+
+    pub fn set_field(&mut self, name: &str, value: &str) {
+        self.fields.insert(name.to_owned(), value.to_owned());
+    }
+
+    /// When the event was logged, in seconds since the Unix epoch
+    pub fn get_log_time(&self) -> i64 {
+        self.log_time_s
+    }
 }
 
-#[derive(Debug)]
+/// Called with each message before it is logged, returning it, possibly
+/// scrubbed or enriched, or `None` to drop it
+pub type TelemetryTransform = Arc<dyn Fn(LogMessage) -> Option<LogMessage> + Send + Sync>;
+
 pub struct Telemetry {
     telemetry_dir: PathBuf,
+    compress: bool,
+    format: LogFormat,
+    transform: Option<TelemetryTransform>,
+    limiter: Option<Arc<Semaphore>>,
 }
 
-#[allow(dead_code)]
 const LOG_FILE_VERSION: i32 = 1;
 const MAX_TELEMETRY_FILES: usize = 100;
+/// Taken while writing, so that processes take turns as well, as an event
+/// written in several parts could otherwise be interleaved with another
+const LOCK_FILE: &str = "log.lock";
+/// How long to wait for other processes before writing regardless
+const LOCK_TIMEOUT_MS: u64 = 500;
+
+/// Whether `filename` is a telemetry log file, in any format and compressed
+/// or not
+pub fn is_log_file(filename: &str) -> bool {
+    filename.starts_with("log") && LogFormat::of_file(filename).is_some()
+}
 
 impl Telemetry {
-    pub fn new(telemetry_dir: PathBuf) -> Telemetry {
+    /// If `compress` is set, events are written to gzip-compressed log files
+    pub fn new(telemetry_dir: PathBuf, compress: bool) -> Telemetry {
         Telemetry {
             telemetry_dir: telemetry_dir,
+            compress,
+            format: LogFormat::Json,
+            transform: None,
+            limiter: None,
         }
     }
 
-    pub fn log_telemetry(&self, _event: TelemetryEvent) -> Result<()> {
+    /// Events of days already logged in another format stay in their files
+    pub fn set_format(&mut self, format: LogFormat) {
+        self.format = format;
+    }
+
+    pub fn set_transform(&mut self, transform: TelemetryTransform) {
+        self.transform = Some(transform);
+    }
+
+    /// Makes writers take turns, with at most as many writing at once as
+    /// `limiter` has permits
+    pub fn set_limiter(&mut self, limiter: Arc<Semaphore>) {
+        self.limiter = Some(limiter);
+    }
+
+    pub fn log_telemetry(&self, event: TelemetryEvent) -> Result<()> {
+        let current_time = OffsetDateTime::now_utc();
+        let ln = LogMessage {
+            log_time_s: current_time.unix_timestamp(),
+            event,
+            version: LOG_FILE_VERSION,
+            fields: BTreeMap::new(),
+        };
+        let ln = match self.transform {
+            Some(ref transform) => match transform(ln) {
+                Some(ln) => ln,
+                None => return Ok(()),
+            },
+            None => ln,
+        };
+
+        let record = self.format.encode(&serde_json::to_value(&ln).unwrap());
+
+        let filename = format!(
+            "log-{}-{:02}-{:02}.{}{}",
+            current_time.year(),
+            current_time.month() as u8,
+            current_time.day(),
+            self.format.extension(),
+            if self.compress { ".gz" } else { "" }
+        );
+        let path = self.telemetry_dir.join(&filename);
+
+        // The directory is created when telemetry is enabled, but may have
+        // been removed since
+        utils::ensure_dir_exists("telemetry", &self.telemetry_dir, &|_| ())
+            .chain_err(|| ErrorKind::TelemetryWriteError)?;
+
+        let _permit = self.limiter.as_ref().map(|l| l.acquire());
+        let _lock = raw::lock_file(
+            &self.telemetry_dir.join(LOCK_FILE),
+            Duration::from_millis(LOCK_TIMEOUT_MS),
+        );
+
+        // Check for the telemetry file. If it doesn't exist, it's a new day.
+        // If it is a new day, then attempt to clean the telemetry directory.
+        if !raw::is_file(&path) {
+            self.clean_telemetry_dir()?;
+        }
+
+        // Concatenated gzip members form a valid gzip file, so each event
+        // can be appended on its own, like the records of both formats.
+        (|| -> ::std::io::Result<()> {
+            let file = OpenOptions::new().append(true).create(true).open(&path)?;
+            if self.compress {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                encoder.write_all(&record)?;
+                encoder.finish().map(|_| ())
+            } else {
+                (&file).write_all(&record)
+            }
+        })()
+        .chain_err(|| ErrorKind::TelemetryWriteError)?;
+
         Ok(())
     }
 
@@ -61,11 +205,10 @@ impl Telemetry {
 
         let mut telemetry_files: Vec<PathBuf> = Vec::new();
 
-        for c in contents {
-            let x = c.unwrap();
-            let filename = x.path().file_name().unwrap().to_str().unwrap().to_owned();
-            if filename.starts_with("log") && filename.ends_with("json") {
-                telemetry_files.push(x.path());
+        for c in contents.filter_map(|c| c.ok()) {
+            let path = c.path();
+            if path.file_name().and_then(|f| f.to_str()).map(is_log_file) == Some(true) {
+                telemetry_files.push(path);
             }
         }
 
@@ -1,17 +1,56 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::path::PathBuf;
 
+use flate2::read::MultiGzDecoder;
 use serde_json;
+use time::{Date, Duration, Month, OffsetDateTime};
 
+use elan_utils::log_format::LogFormat;
 use errors::*;
-use telemetry::{LogMessage, TelemetryEvent};
+use telemetry::{self, LogMessage, TelemetryEvent};
+
+/// Parses the cutoff of `--since`, either a duration before `now` such as
+/// `3d`, `12h` or `30m`, or a UTC date in the form `YYYY-MM-DD`
+pub fn parse_since(since: &str, now: OffsetDateTime) -> Result<OffsetDateTime> {
+    let invalid = || Error::from(ErrorKind::InvalidTelemetrySince(since.to_owned()));
+
+    let unit = since.chars().last().ok_or_else(invalid)?;
+    let duration = since[..since.len() - unit.len_utf8()]
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| match unit {
+            'd' => Some(Duration::days(n.into())),
+            'h' => Some(Duration::hours(n.into())),
+            'm' => Some(Duration::minutes(n.into())),
+            _ => None,
+        });
+    if let Some(duration) = duration {
+        return Ok(now - duration);
+    }
+
+    let parts: Vec<_> = since.split('-').collect();
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+    let year = parts[0].parse().map_err(|_| invalid())?;
+    let month = parts[1]
+        .parse::<u8>()
+        .ok()
+        .and_then(|m| Month::try_from(m).ok())
+        .ok_or_else(invalid)?;
+    let day = parts[2].parse().map_err(|_| invalid())?;
+    let date = Date::from_calendar_date(year, month, day).map_err(|_| invalid())?;
+    Ok(date.midnight().assume_utc())
+}
 
 pub struct TelemetryAnalysis {
     telemetry_dir: PathBuf,
+    since: Option<OffsetDateTime>,
     rustc_statistics: RustcStatistics,
     rustc_success_statistics: RustcStatistics,
     rustc_error_statistics: RustcStatistics,
@@ -110,12 +149,18 @@ impl TelemetryAnalysis {
     pub fn new(telemetry_dir: PathBuf) -> TelemetryAnalysis {
         TelemetryAnalysis {
             telemetry_dir: telemetry_dir,
+            since: None,
             rustc_statistics: RustcStatistics::new(),
             rustc_success_statistics: RustcStatistics::new(),
             rustc_error_statistics: RustcStatistics::new(),
         }
     }
 
+    /// Only import events logged at or after `since`
+    pub fn set_since(&mut self, since: OffsetDateTime) {
+        self.since = Some(since);
+    }
+
     pub fn import_telemery(&mut self) -> Result<Vec<TelemetryEvent>> {
         let mut events: Vec<TelemetryEvent> = Vec::new();
         let contents = self
@@ -129,7 +174,7 @@ impl TelemetryAnalysis {
             let x = c.unwrap();
             let filename = x.path().file_name().unwrap().to_str().unwrap().to_owned();
 
-            if filename.starts_with("log") && filename.ends_with("json") {
+            if telemetry::is_log_file(&filename) {
                 telemetry_files.push(x.path());
                 match self.read_telemetry_file(x.path()) {
                     Ok(y) => events.extend(y),
@@ -144,16 +189,29 @@ impl TelemetryAnalysis {
     fn read_telemetry_file(&self, path: PathBuf) -> Result<Vec<TelemetryEvent>> {
         let mut events: Vec<TelemetryEvent> = Vec::new();
 
+        let filename = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+        let format = LogFormat::of_file(filename).unwrap_or(LogFormat::Json);
         let f = File::open(&path).chain_err(|| ErrorKind::TelemetryAnalysisError)?;
 
-        let file = BufReader::new(&f);
+        let mut file: Box<dyn BufRead> = if path.extension() == Some("gz".as_ref()) {
+            Box::new(BufReader::new(MultiGzDecoder::new(f)))
+        } else {
+            Box::new(BufReader::new(f))
+        };
 
-        for line in file.lines() {
-            let l = line.unwrap();
-            let log_message_result = serde_json::from_str(&l);
+        let records = format
+            .decode_all(&mut file)
+            .chain_err(|| ErrorKind::TelemetryAnalysisError)?;
+        for record in records {
+            let log_message_result = serde_json::from_value(record);
 
             if log_message_result.is_ok() {
                 let log_message: LogMessage = log_message_result.unwrap();
+                if let Some(since) = self.since {
+                    if log_message.get_log_time() < since.unix_timestamp() {
+                        continue;
+                    }
+                }
                 let event: TelemetryEvent = log_message.get_event();
                 events.push(event);
             }
@@ -185,6 +243,7 @@ impl TelemetryAnalysis {
                     duration_ms,
                     ref exit_code,
                     ref errors,
+                    ..
                 } => {
                     self.rustc_statistics.rustc_execution_count += 1;
                     rustc_durations.push(duration_ms);
@@ -233,6 +292,7 @@ impl TelemetryAnalysis {
                         updated_toolchains_with_errors.push(toolchain.to_owned());
                     }
                 }
+                TelemetryEvent::Download { .. } => {}
             }
         }
 
@@ -1,22 +1,32 @@
 use config::Cfg;
 use elan_dist;
-use elan_dist::dist::ToolchainDesc;
+use elan_dist::dist::{self, Asset, DistOptions, ToolchainDesc};
 use elan_dist::download::DownloadCfg;
+use elan_dist::installed_files::{FileProblem, InstalledFiles, INSTALLED_FILES_NAME};
+use elan_dist::lockfile::Lockfile;
 use elan_dist::manifest::Component;
+use elan_dist::TarGzPackage;
+use elan_utils;
+use elan_utils::arch;
+use elan_utils::path_var::{self, PathVarMode};
 use elan_utils::utils;
 use env_var;
 use errors::*;
 use install::{self, InstallMethod};
 use notifications::*;
 use telemetry;
-use telemetry::{Telemetry, TelemetryEvent};
+use telemetry::TelemetryEvent;
 
+use std::cell::Cell;
 use std::env;
 use std::env::consts::EXE_SUFFIX;
 use std::ffi::OsStr;
 use std::ffi::OsString;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::rc::Rc;
+use std::time::Instant;
 
 /// A fully resolved reference to a toolchain which may or may not exist
 pub struct Toolchain<'a> {
@@ -26,6 +36,10 @@ pub struct Toolchain<'a> {
     path: PathBuf,
     telemetry: telemetry::Telemetry,
     dist_handler: Box<dyn Fn(elan_dist::Notification) + 'a>,
+    /// Bytes received by downloads through `dist_handler`
+    downloaded: Rc<Cell<u64>>,
+    /// Whether this is a system toolchain, which is never modified
+    read_only: bool,
 }
 
 /// Used by the `list_component` function
@@ -42,21 +56,60 @@ pub enum UpdateStatus {
     Unchanged,
 }
 
+/// De-sanitizes the name of a toolchain directory (best effort...)
+pub fn name_from_dir_name(dir_name: &str) -> String {
+    dir_name.replace("---", ":").replace("--", "/")
+}
+
+/// The name of the toolchain packaged by `Toolchain::export` into `archive`
+pub fn exported_name(archive: &Path) -> Result<String> {
+    let root = TarGzPackage::root_dir(archive)
+        .chain_err(|| ErrorKind::InvalidToolchainArchive(archive.to_owned()))?;
+    Ok(name_from_dir_name(&root))
+}
+
+/// Checks that `src` has the files of a toolchain
+fn validate_layout(src: &Path) -> Result<()> {
+    let mut pathbuf = PathBuf::from(src);
+
+    pathbuf.push("bin");
+    utils::assert_is_directory(&pathbuf)?;
+    pathbuf.push(format!("lean{}", EXE_SUFFIX));
+    utils::assert_is_file(&pathbuf)?;
+    Ok(())
+}
+
 impl<'a> Toolchain<'a> {
     pub fn from(cfg: &'a Cfg, name: &str) -> Result<Self> {
         //We need to replace ":" and "/" with "-" in the toolchain name in order to make a name which is a valid
         //name for a directory.
         let dir_name = name.replace("/", "--").replace(":", "---");
 
-        let path = cfg.toolchains_dir.join(&dir_name[..]);
+        let system_path = cfg
+            .settings_file
+            .with(|s| Ok(s.system_toolchains.get(name).cloned()))?;
+        let read_only = system_path.is_some();
+        let path = system_path.unwrap_or_else(|| cfg.toolchains_dir.join(&dir_name[..]));
+        let downloaded = Rc::new(Cell::new(0));
+        let counter = downloaded.clone();
 
         Ok(Toolchain {
             cfg: cfg,
             name: name.to_owned(),
             dir_name: dir_name,
             path: path.clone(),
-            telemetry: Telemetry::new(cfg.elan_dir.join("telemetry")),
-            dist_handler: Box::new(move |n| (cfg.notify_handler)(n.into())),
+            telemetry: cfg.telemetry()?,
+            dist_handler: Box::new(move |n| {
+                if let elan_dist::Notification::Utils(
+                    elan_utils::Notification::DownloadDataReceived(data),
+                ) = n
+                {
+                    counter.set(counter.get() + data.len() as u64);
+                }
+                (cfg.notify_handler)(n.into())
+            }),
+            downloaded,
+            read_only,
         })
     }
     pub fn name(&self) -> &str {
@@ -69,7 +122,6 @@ impl<'a> Toolchain<'a> {
         &self.path
     }
     fn is_symlink(&self) -> bool {
-        use std::fs;
         fs::symlink_metadata(&self.path)
             .map(|m| m.file_type().is_symlink())
             .unwrap_or(false)
@@ -82,12 +134,41 @@ impl<'a> Toolchain<'a> {
     }
     pub fn is_custom(&self) -> bool {
         assert!(self.exists());
-        self.is_symlink()
+        self.read_only || self.is_symlink()
+    }
+    /// Whether this is a system toolchain registered in the
+    /// `system_toolchains` setting, which elan uses but never modifies
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    /// Whether the toolchain has the files `install_from_dir` requires of a
+    /// toolchain
+    pub fn is_complete(&self) -> bool {
+        utils::is_file(self.binary_file("lean"))
+    }
+    /// The target triple the toolchain was installed for with `--target`, if
+    /// it cannot run on this platform
+    pub fn foreign_target(&self) -> Result<Option<String>> {
+        Ok(dist::installed_target(&self.path)?.filter(|t| !dist::is_host_target(t)))
     }
     pub fn verify(&self) -> Result<()> {
         Ok(utils::assert_is_directory(&self.path)?)
     }
+    /// The files that are missing or were modified since the toolchain was
+    /// installed from a release
+    pub fn verify_files(&self) -> Result<Vec<FileProblem>> {
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+        match InstalledFiles::load(&self.path.join(INSTALLED_FILES_NAME))? {
+            Some(record) => Ok(record.verify(&self.path)?),
+            None => Err(ErrorKind::NoInstalledFilesRecord(self.name.to_owned()).into()),
+        }
+    }
     pub fn remove(&self) -> Result<()> {
+        if self.read_only {
+            return Err(ErrorKind::ReadOnlyToolchain(self.name.to_owned()).into());
+        }
         if self.exists() || self.is_symlink() {
             (self.cfg.notify_handler)(Notification::UninstallingToolchain(&self.name));
         } else {
@@ -95,15 +176,128 @@ impl<'a> Toolchain<'a> {
             return Ok(());
         }
         if let Some(update_hash) = self.update_hash()? {
-            utils::remove_file("update hash", &update_hash)?;
+            // Toolchains that were not installed from a release have none
+            if utils::is_file(&update_hash) {
+                utils::remove_file("update hash", &update_hash)?;
+            }
         }
         let result = install::uninstall(&self.path, &|n| (self.cfg.notify_handler)(n.into()));
         if !self.exists() {
+            self.cfg.settings_file.with_mut(|s| {
+                s.pinned.remove(&self.name);
+                Ok(())
+            })?;
             (self.cfg.notify_handler)(Notification::UninstalledToolchain(&self.name));
         }
         Ok(result?)
     }
+    /// Keeps a tracking toolchain at its installed release when running
+    /// `elan update`
+    pub fn pin(&self) -> Result<()> {
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+        if !self.is_tracking() {
+            return Err(ErrorKind::ToolchainNotTracking(self.name.to_owned()).into());
+        }
+        let release = self
+            .installed_release()?
+            .ok_or_else(|| Error::from(ErrorKind::ToolchainNotInstalled(self.name.to_owned())))?;
+        self.cfg.settings_file.with_mut(|s| {
+            s.pinned.insert(self.name.clone(), release.clone());
+            Ok(())
+        })?;
+        (self.cfg.notify_handler)(Notification::PinnedToolchain(
+            &self.name,
+            dist::release_tag(&release),
+        ));
+        Ok(())
+    }
+    /// Lets `elan update` update the toolchain again
+    pub fn unpin(&self) -> Result<()> {
+        let unpinned = self
+            .cfg
+            .settings_file
+            .with_mut(|s| Ok(s.pinned.remove(&self.name).is_some()))?;
+        if unpinned {
+            (self.cfg.notify_handler)(Notification::UnpinnedToolchain(&self.name));
+        }
+        Ok(())
+    }
+    /// The release page the toolchain is pinned to, if any
+    pub fn pinned_release(&self) -> Result<Option<String>> {
+        self.cfg
+            .settings_file
+            .with(|s| Ok(s.pinned.get(&self.name).cloned()))
+    }
+    /// Moves the toolchain to `new_name`, updating the default toolchain,
+    /// any overrides and the pin that refer to it
+    pub fn rename(&self, new_name: &str) -> Result<()> {
+        if self.read_only {
+            return Err(ErrorKind::ReadOnlyToolchain(self.name.to_owned()).into());
+        }
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+        let new = Toolchain::from(self.cfg, new_name)?;
+        if new.exists() {
+            return Err(ErrorKind::ToolchainAlreadyExists(new.name.to_owned()).into());
+        }
+
+        let update_hash = self.update_hash()?;
+        utils::rename_dir("toolchain", &self.path, &new.path)?;
+
+        let renamed = self.cfg.settings_file.with_mut(|s| {
+            s.rename_toolchain(&self.name, &new.name);
+            Ok(())
+        });
+        if let Err(e) = renamed {
+            let _ = utils::rename_dir("toolchain", &new.path, &self.path);
+            return Err(e);
+        }
+
+        if let (Some(old_hash), Some(new_hash)) = (update_hash, new.update_hash()?) {
+            if utils::is_file(&old_hash) {
+                utils::rename_file("update hash", &old_hash, &new_hash)?;
+            }
+        }
+
+        (self.cfg.notify_handler)(Notification::RenamedToolchain(&self.name, &new.name));
+        Ok(())
+    }
+    /// Packages the toolchain into the `.tar.gz` archive `dest`, for
+    /// installing it on another machine with `import`. A linked toolchain
+    /// is packaged with the contents of the directory it links to.
+    pub fn export(&self, dest: &Path) -> Result<()> {
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+        if let Err(e) = TarGzPackage::pack_dir(&self.path, &self.dir_name, dest) {
+            let _ = fs::remove_file(dest);
+            return Err(e).chain_err(|| ErrorKind::ExportingToolchain(self.name.to_owned()));
+        }
+        (self.cfg.notify_handler)(Notification::ExportedToolchain(&self.name, dest));
+        Ok(())
+    }
+    /// Installs the toolchain from an archive created by `export`, which
+    /// must contain a complete toolchain
+    pub fn import(&self, archive: &Path) -> Result<()> {
+        if self.exists() {
+            return Err(ErrorKind::ToolchainAlreadyExists(self.name.to_owned()).into());
+        }
+        let invalid = || ErrorKind::InvalidToolchainArchive(archive.to_owned());
+        let dir = self.cfg.temp_cfg.new_directory()?;
+        TarGzPackage::unpack_file(archive, &dir).chain_err(invalid)?;
+        validate_layout(&dir).chain_err(invalid)?;
+
+        self.install(InstallMethod::Move(&dir))?;
+        (self.cfg.notify_handler)(Notification::ImportedToolchain(&self.name));
+        Ok(())
+    }
     fn install(&self, install_method: InstallMethod) -> Result<UpdateStatus> {
+        if self.read_only {
+            return Err(ErrorKind::ReadOnlyToolchain(self.name.to_owned()).into());
+        }
         let exists = self.exists();
         if exists {
             (self.cfg.notify_handler)(Notification::UpdatingToolchain(&self.name));
@@ -154,27 +348,87 @@ impl<'a> Toolchain<'a> {
     }
 
     pub fn install_from_dist(&self, force_update: bool) -> Result<UpdateStatus> {
+        self.install_from_dist_locked(force_update, None, None, None)
+    }
+
+    /// Like `install_from_dist`, for a toolchain needed to run a command
+    /// that is not installed, which telemetry records separately
+    pub fn auto_install_from_dist(&self) -> Result<UpdateStatus> {
+        if self.cfg.telemetry_enabled()? {
+            return self.install_from_dist_with_telemetry(false, None, None, None, true);
+        }
+        self.install_from_dist_inner(false, None, None, None)
+    }
+
+    /// Like `install_from_dist`, but verifies the resolved release against
+    /// `lock` and records it there if the toolchain is not locked yet, and
+    /// installs the release for the target triple `target` if given, from
+    /// the variant `variant` of its installer if given
+    pub fn install_from_dist_locked(
+        &self,
+        force_update: bool,
+        lock: Option<&mut Lockfile>,
+        target: Option<&str>,
+        variant: Option<&str>,
+    ) -> Result<UpdateStatus> {
         if self.cfg.telemetry_enabled()? {
-            return self.install_from_dist_with_telemetry(force_update);
+            return self.install_from_dist_with_telemetry(
+                force_update,
+                lock,
+                target,
+                variant,
+                false,
+            );
         }
-        self.install_from_dist_inner(force_update)
+        self.install_from_dist_inner(force_update, lock, target, variant)
     }
 
-    pub fn install_from_dist_inner(&self, force_update: bool) -> Result<UpdateStatus> {
+    pub fn install_from_dist_inner(
+        &self,
+        force_update: bool,
+        lock: Option<&mut Lockfile>,
+        target: Option<&str>,
+        variant: Option<&str>,
+    ) -> Result<UpdateStatus> {
         let update_hash = self.update_hash()?;
         self.install(InstallMethod::Dist(
             &self.desc()?,
             update_hash.as_ref().map(|p| &**p),
             self.download_cfg(),
-            force_update,
+            DistOptions {
+                force_update,
+                lock,
+                target,
+                variant,
+            },
         ))
     }
 
-    pub fn install_from_dist_with_telemetry(&self, force_update: bool) -> Result<UpdateStatus> {
-        let result = self.install_from_dist_inner(force_update);
+    pub fn install_from_dist_with_telemetry(
+        &self,
+        force_update: bool,
+        lock: Option<&mut Lockfile>,
+        target: Option<&str>,
+        variant: Option<&str>,
+        auto: bool,
+    ) -> Result<UpdateStatus> {
+        let now = Instant::now();
+        self.downloaded.set(0);
+        let result = self.install_from_dist_inner(force_update, lock, target, variant);
 
         match result {
             Ok(us) => {
+                if let UpdateStatus::Installed | UpdateStatus::Updated = us {
+                    let te = TelemetryEvent::Download {
+                        toolchain: self.name().to_string(),
+                        bytes: self.downloaded.get(),
+                        duration_ms: utils::elapsed_ms(now, Instant::now()),
+                        auto,
+                    };
+                    if let Err(e) = self.telemetry.log_telemetry(te) {
+                        (self.cfg.notify_handler)(Notification::TelemetryError(&e));
+                    }
+                }
                 let te = TelemetryEvent::ToolchainUpdate {
                     toolchain: self.name().to_string(),
                     success: true,
@@ -182,7 +436,7 @@ impl<'a> Toolchain<'a> {
                 match self.telemetry.log_telemetry(te) {
                     Ok(_) => Ok(us),
                     Err(e) => {
-                        (self.cfg.notify_handler)(Notification::TelemetryCleanupError(&e));
+                        (self.cfg.notify_handler)(Notification::TelemetryError(&e));
                         Ok(us)
                     }
                 }
@@ -193,22 +447,90 @@ impl<'a> Toolchain<'a> {
                     success: true,
                 };
                 let _ = self.telemetry.log_telemetry(te).map_err(|xe| {
-                    (self.cfg.notify_handler)(Notification::TelemetryCleanupError(&xe));
+                    (self.cfg.notify_handler)(Notification::TelemetryError(&xe));
                 });
                 Err(e)
             }
         }
     }
 
+    /// The release page an install would currently use
+    pub fn release_url(&self) -> Result<String> {
+        Ok(dist::toolchain_url(self.download_cfg(), &self.desc()?)?)
+    }
+    /// The installer an install from the release page `release` would
+    /// download for `target`, or its variant `variant`, without downloading
+    /// it
+    pub fn installer_url(
+        &self,
+        release: &str,
+        target: Option<&str>,
+        variant: Option<&str>,
+    ) -> Result<String> {
+        Ok(dist::installer_url(
+            self.download_cfg(),
+            &self.desc()?,
+            release,
+            target,
+            variant,
+        )?)
+    }
+    /// The installers for `target` on the release page `release`, the
+    /// primary one first and then its variants
+    pub fn installer_assets(&self, release: &str, target: Option<&str>) -> Result<Vec<Asset>> {
+        Ok(dist::installer_assets(
+            self.download_cfg(),
+            &self.desc()?,
+            release,
+            target,
+        )?)
+    }
+
     pub fn install_from_dist_if_not_installed(&self) -> Result<UpdateStatus> {
         let update_hash = self.update_hash()?;
         self.install_if_not_installed(InstallMethod::Dist(
             &self.desc()?,
             update_hash.as_ref().map(|p| &**p),
             self.download_cfg(),
-            false,
+            DistOptions::default(),
         ))
     }
+    /// The release page this toolchain was last installed or updated from,
+    /// if known
+    pub fn installed_release(&self) -> Result<Option<String>> {
+        match self.update_hash()? {
+            Some(ref hash_file) if utils::is_file(hash_file) => {
+                Ok(Some(utils::read_file("update hash", hash_file)?))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// The release page an install would use, or `None` if the toolchain is
+    /// installed already from its latest release. See
+    /// `dist::release_to_install`.
+    pub fn release_to_install(&self) -> Result<Option<String>> {
+        let installed = self.installed_release()?;
+        Ok(dist::release_to_install(
+            &self.desc()?,
+            self.exists(),
+            installed.as_deref(),
+            |desc| dist::toolchain_url(self.download_cfg(), desc),
+        )?)
+    }
+    /// The newer release page a tracking toolchain would update to, without
+    /// updating it
+    pub fn newer_release(&self) -> Result<Option<String>> {
+        if !self.is_tracking() {
+            return Ok(None);
+        }
+        let installed = match self.installed_release()? {
+            Some(installed) => installed,
+            None => return Ok(None),
+        };
+        Ok(dist::newer_release(&self.desc()?, &installed, |desc| {
+            dist::toolchain_url(self.download_cfg(), desc)
+        })?)
+    }
     pub fn is_tracking(&self) -> bool {
         ToolchainDesc::from_str(&self.name)
             .ok()
@@ -217,12 +539,7 @@ impl<'a> Toolchain<'a> {
     }
 
     pub fn install_from_dir(&self, src: &Path, link: bool) -> Result<()> {
-        let mut pathbuf = PathBuf::from(src);
-
-        pathbuf.push("bin");
-        utils::assert_is_directory(&pathbuf)?;
-        pathbuf.push(format!("lean{}", EXE_SUFFIX));
-        utils::assert_is_file(&pathbuf)?;
+        validate_layout(src)?;
 
         if link {
             self.install(InstallMethod::Link(&utils::to_absolute(src)?))?;
@@ -233,13 +550,38 @@ impl<'a> Toolchain<'a> {
         Ok(())
     }
 
+    /// Links the toolchain to the directory `src`, which must hold a
+    /// toolchain. A toolchain of the same name is only replaced if `force`,
+    /// and a linked one in one step, so that running it never finds it
+    /// missing.
+    pub fn link(&self, src: &Path, force: bool) -> Result<()> {
+        validate_layout(src)?;
+        if self.exists() && !force {
+            return Err(ErrorKind::ToolchainLinkExists(self.name.to_owned()).into());
+        }
+        if self.read_only || !self.is_symlink() {
+            return self.install_from_dir(src, true);
+        }
+
+        (self.cfg.notify_handler)(Notification::UpdatingToolchain(&self.name));
+        utils::replace_symlink_dir(&utils::to_absolute(src)?, &self.path, &|n| {
+            (self.cfg.notify_handler)(n.into())
+        })?;
+        (self.cfg.notify_handler)(Notification::InstalledToolchain(&self.name));
+        Ok(())
+    }
+
     pub fn create_command<T: AsRef<OsStr>>(&self, binary: T) -> Result<Command> {
         if !self.exists() {
             return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
         }
+        if let Some(target) = self.foreign_target()? {
+            return Err(ErrorKind::ForeignToolchain(self.name.to_owned(), target).into());
+        }
 
         let bin_path = self.binary_file(&binary);
         let path = if utils::is_file(&bin_path) {
+            self.check_arch(&bin_path)?;
             &bin_path
         } else {
             let recursion_count = env::var("LEAN_RECURSION_COUNT")
@@ -266,24 +608,98 @@ impl<'a> Toolchain<'a> {
         Ok(cmd)
     }
 
+    /// Fails before spawning `binary` if it was built for another
+    /// architecture, which the OS would only report confusingly
+    fn check_arch(&self, binary: &Path) -> Result<()> {
+        let archs = match arch::read_binary_archs(binary) {
+            Ok(archs) => archs,
+            // Let spawning it report the problem
+            Err(_) => return Ok(()),
+        };
+        if arch::can_run_here(&archs) {
+            return Ok(());
+        }
+        Err(ErrorKind::ToolchainArchMismatch {
+            expected: env::consts::ARCH.to_owned(),
+            found: archs.join(", "),
+        }
+        .into())
+    }
+
+    /// A command running `program`, which need not be part of the
+    /// toolchain, with the toolchain's environment along with the
+    /// `LAKE_HOME` that build scripts expect and its library added to
+    /// `LEAN_PATH` as `lean_path` says
+    pub fn create_exec_command<T: AsRef<OsStr>>(
+        &self,
+        program: T,
+        lean_path: PathVarMode,
+    ) -> Result<Command> {
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+        if let Some(target) = self.foreign_target()? {
+            return Err(ErrorKind::ForeignToolchain(self.name.to_owned(), target).into());
+        }
+
+        let mut cmd = Command::new(program);
+        for (name, value) in self.exec_env_vars(lean_path) {
+            cmd.env(name, value);
+        }
+        env_var::inc("LEAN_RECURSION_COUNT", &mut cmd);
+        Ok(cmd)
+    }
+
+    /// The environment variables set for programs run with `elan exec` and
+    /// printed by `elan env`: those of `env_vars` along with `LAKE_HOME` and
+    /// the toolchain's library added to `LEAN_PATH` as `lean_path` says
+    pub fn exec_env_vars(&self, lean_path: PathVarMode) -> Vec<(&'static str, OsString)> {
+        let mut vars = self.env_vars();
+        let lib_dir = self.path.join("lib").join("lean");
+        let old = env::var_os("LEAN_PATH");
+        if let Some(value) = path_var::combined_path(lean_path, old.as_deref(), vec![lib_dir]) {
+            vars.push(("LEAN_PATH", value));
+        }
+        vars.push(("LAKE_HOME", self.path.clone().into_os_string()));
+        vars
+    }
+
     fn set_env(&self, cmd: &mut Command) {
-        self.set_ldpath(cmd);
+        for (name, value) in self.env_vars() {
+            cmd.env(name, value);
+        }
 
+        env_var::inc("LEAN_RECURSION_COUNT", cmd);
+    }
+
+    /// The environment variables set for commands run with this toolchain,
+    /// not including the recursion counter
+    pub fn env_vars(&self) -> Vec<(&'static str, OsString)> {
+        let mut vars = self.path_vars();
+
+        vars.push(("ELAN_TOOLCHAIN", OsString::from(&self.name)));
+        // Lake looks for Lean in LEAN_SYSROOT and LEAN before PATH, and
+        // `lake env` exports both, so override whatever an outer `lake env`
+        // of another toolchain left behind for `lake env lean` and friends.
+        vars.push(("LEAN_SYSROOT", self.path.clone().into_os_string()));
+        vars.push(("LEAN", self.binary_file("lean").into_os_string()));
         // Because elan and leanpkg use slightly different
         // definitions of leanpkg home (elan doesn't read HOME on
         // windows), we must set it here to ensure leanpkg and
         // elan agree.
-        if let Ok(elan_home) = utils::elan_home() {
-            cmd.env("ELAN_HOME", &elan_home);
-        }
+        vars.push(("ELAN_HOME", self.cfg.elan_dir.clone().into_os_string()));
 
-        env_var::inc("LEAN_RECURSION_COUNT", cmd);
-
-        cmd.env("ELAN_TOOLCHAIN", &self.name);
-        cmd.env("ELAN_HOME", &self.cfg.elan_dir);
+        vars
     }
 
     pub fn set_ldpath(&self, cmd: &mut Command) {
+        for (name, value) in self.path_vars() {
+            cmd.env(name, value);
+        }
+    }
+
+    fn path_vars(&self) -> Vec<(&'static str, OsString)> {
+        let mut vars = vec![];
         let new_path = self.path.join("lib");
 
         #[cfg(not(target_os = "macos"))]
@@ -294,22 +710,26 @@ impl<'a> Toolchain<'a> {
         mod sysenv {
             pub const LOADER_PATH: &'static str = "DYLD_LIBRARY_PATH";
         }
-        env_var::prepend_path(sysenv::LOADER_PATH, vec![new_path.clone()], cmd);
+        if let Some(value) = env_var::prepended_path(sysenv::LOADER_PATH, vec![new_path]) {
+            vars.push((sysenv::LOADER_PATH, value));
+        }
 
-        // Prepend ELAN_HOME/bin to the PATH variable so that we're sure to run
-        // leanpkg/lean via the proxy bins. There is no fallback case for if the
-        // proxy bins don't exist. We'll just be running whatever happens to
-        // be on the PATH.
-        let mut path_entries = vec![];
-        if let Ok(elan_home) = utils::elan_home() {
-            path_entries.push(elan_home.join("bin").to_path_buf());
+        // Prepend the toolchain's bin dir to the PATH variable so that tools
+        // like lake that shell out to lean/leanc find the same toolchain,
+        // then ELAN_HOME/bin so that anything else the toolchain doesn't ship
+        // runs via the proxy bins. There is no fallback case for if the proxy
+        // bins don't exist. We'll just be running whatever happens to be on
+        // the PATH.
+        let mut path_entries = vec![self.path.join("bin")];
+        if let Ok(bin_dir) = utils::elan_bin_dir() {
+            path_entries.push(bin_dir);
         }
 
-        if cfg!(target_os = "windows") {
-            path_entries.push(self.path.join("bin"));
+        if let Some(value) = env_var::prepended_path("PATH", path_entries) {
+            vars.push(("PATH", value));
         }
 
-        env_var::prepend_path("PATH", path_entries, cmd);
+        vars
     }
 
     pub fn doc_path(&self, relative: &str) -> Result<PathBuf> {
@@ -320,6 +740,9 @@ impl<'a> Toolchain<'a> {
         for part in parts {
             doc_dir.push(part);
         }
+        if !utils::is_directory(&doc_dir) {
+            return Err(ErrorKind::ToolchainDocsNotInstalled(self.name.clone()).into());
+        }
         doc_dir.push(relative);
 
         Ok(doc_dir)
@@ -339,6 +762,20 @@ impl<'a> Toolchain<'a> {
             Ok(())
         })?)
     }
+    /// Names this toolchain in the `lean-toolchain` file of the directory
+    /// `path`, keeping any lines after the toolchain
+    pub fn make_toolchain_file(&self, path: &Path) -> Result<()> {
+        let file = path.join("lean-toolchain");
+        let existing = if utils::is_file(&file) {
+            Some(utils::read_file("toolchain", &file)?)
+        } else {
+            None
+        };
+        let contents = utils::toolchain_file_contents(existing.as_deref(), &self.name);
+        utils::write_file_atomic("toolchain", &file, &contents)?;
+        (self.cfg.notify_handler)(Notification::WroteToolchainFile(&file, &self.name));
+        Ok(())
+    }
 
     pub fn binary_file<T: AsRef<OsStr>>(&self, binary: T) -> PathBuf {
         let binary = if let Some(binary_str) = binary.as_ref().to_str() {
@@ -0,0 +1,158 @@
+#![cfg(unix)]
+
+extern crate elan;
+extern crate elan_dist;
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use elan::command::{run_command_capture_errors, run_command_for_dir};
+use elan::{Cfg, ErrorKind, Notification};
+use elan_dist::release_manifest;
+use support::{write_script, Env};
+
+/// The configuration of `env`'s home, ignoring notifications
+fn cfg(env: &Env) -> Cfg {
+    Cfg::from_home(env.home.clone(), Arc::new(|_| {})).unwrap()
+}
+
+#[test]
+fn is_toolchain_installed() {
+    let env = Env::new();
+    let cfg = cfg(&env);
+    // Where an install of the toolchain would put lean
+    let lean = cfg
+        .get_toolchain("leanprover/lean4:v4.1.0", false)
+        .unwrap()
+        .binary_file("lean");
+    fs::create_dir_all(lean.parent().unwrap()).unwrap();
+    write_script(&lean, "true");
+    assert!(cfg
+        .is_toolchain_installed("leanprover/lean4:v4.1.0")
+        .unwrap());
+    assert!(!cfg
+        .is_toolchain_installed("leanprover/lean4:v4.2.0")
+        .unwrap());
+    assert!(cfg.is_toolchain_installed("lean4:v4:1:0").is_err());
+
+    // An install missing lean itself is not complete
+    fs::remove_file(lean).unwrap();
+    assert!(!cfg
+        .is_toolchain_installed("leanprover/lean4:v4.1.0")
+        .unwrap());
+}
+
+#[test]
+fn captured_run_returns_error_codes() {
+    let env = Env::new();
+    env.link_toolchain(
+        "fake",
+        &[(
+            "lean",
+            "echo 'a.lean:1:0: error [E0001]' >&2; echo '[E0007]'; \
+             echo 'a.lean:2:0: error [E0042]' >&2; exit 1",
+        )],
+    );
+
+    let capture = |cfg: &Cfg| {
+        let cmd = cfg
+            .get_toolchain("fake", false)
+            .unwrap()
+            .create_command("lean")
+            .unwrap();
+        run_command_capture_errors(cmd, &["a.lean"], cfg).unwrap()
+    };
+    let run = capture(&cfg(&env));
+    assert_eq!(run.output.status.code(), Some(1));
+    assert_eq!(run.output.stdout, b"[E0007]\n");
+    // Only those on stderr, as telemetry records them
+    assert_eq!(run.error_codes, vec!["E0001", "E0042"]);
+
+    // With the same custom pattern as telemetry
+    fs::write(
+        env.home.join("settings.toml"),
+        "version = \"12\"\ntelemetry_error_pattern = \"error \\\\[(?P<error>E004\\\\d)\\\\]\"\n",
+    )
+    .unwrap();
+    let run = capture(&cfg(&env));
+    assert_eq!(run.error_codes, vec!["E0042"]);
+}
+
+#[test]
+fn command_finished_carries_the_exit_code() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "exit 3")]);
+    env.elan_ok(&["set", "telemetry", "true"]);
+
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let handler = finished.clone();
+    let cfg = Cfg::from_home(
+        env.home.clone(),
+        Arc::new(move |n: Notification| {
+            if let Notification::CommandFinished {
+                tool, exit_code, ..
+            } = n
+            {
+                handler.lock().unwrap().push((tool.to_owned(), exit_code));
+            }
+        }),
+    )
+    .unwrap();
+    assert!(cfg.telemetry_enabled().unwrap());
+
+    let cmd = cfg
+        .get_toolchain("fake", false)
+        .unwrap()
+        .create_command("lean")
+        .unwrap();
+    assert_eq!(
+        run_command_for_dir(cmd, "lean", &["a.lean"], &cfg).unwrap(),
+        3
+    );
+    assert_eq!(*finished.lock().unwrap(), vec![("lean".to_owned(), 3)]);
+}
+
+const CHOICES: [&str; 2] = ["stable", "nightly"];
+
+#[test]
+fn no_default_toolchain_offers_to_install_one() {
+    let env = Env::new();
+    let stable = env.release("stable.tar.gz", "4.0.0");
+    let nightly = env.release("nightly.tar.gz", "4.1.0-nightly");
+    let manifest = env.manifest(&[
+        ("stable", "stable.tar.gz", &stable),
+        ("nightly", "nightly.tar.gz", &nightly),
+    ]);
+    release_manifest::set_release_manifest(Some(manifest.to_str().unwrap().to_owned()));
+    let cfg = cfg(&env);
+
+    // Not asking at all, without a terminal to ask on
+    let mut asked = Vec::new();
+    let e = cfg
+        .create_command_for_dir_or_pick(&env.cwd, "lean", &CHOICES, false, &b"2\n"[..], &mut asked)
+        .unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::NoDefaultToolchain), "{}", e);
+    assert!(asked.is_empty());
+
+    // Declined
+    let e = cfg
+        .create_command_for_dir_or_pick(&env.cwd, "lean", &CHOICES, true, &b"n\n"[..], &mut asked)
+        .unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::NoDefaultToolchain), "{}", e);
+    assert!(String::from_utf8(asked).unwrap().contains("2) nightly"));
+    assert_eq!(cfg.get_default().unwrap(), None);
+
+    let mut cmd = cfg
+        .create_command_for_dir_or_pick(&env.cwd, "lean", &CHOICES, true, &b"2\n"[..], Vec::new())
+        .unwrap();
+    assert_eq!(cfg.get_default().unwrap().as_deref(), Some("nightly"));
+    let output = cmd.arg("--version").output().unwrap();
+    assert_eq!(output.stdout, b"Lean (version 4.1.0-nightly)\n");
+}
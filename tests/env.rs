@@ -0,0 +1,100 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use support::{stderr, stdout, Env};
+
+/// The variables `sh` sees after evaluating `script`, one per line
+fn eval_sh(env: &Env, script: &str, vars: &[&str]) -> Vec<String> {
+    let print: Vec<String> = vars
+        .iter()
+        .map(|var| format!("printf '%s\\n' \"${}\"", var))
+        .collect();
+    let output = env.run("sh", &["-c", &format!("{}\n{}", script, print.join("\n"))]);
+    assert!(output.status.success(), "sh failed: {}", stderr(&output));
+    stdout(&output).lines().map(|l| l.to_owned()).collect()
+}
+
+#[test]
+fn sh_format_exports_quoted_lean_path() {
+    let env = Env::with_home("it's a \"home\" with spaces");
+    env.link_toolchain("fake", &[("lean", "exit 0")]);
+
+    let script = env.elan_ok(&["env", "--toolchain", "fake", "--format", "sh"]);
+    for line in script.lines() {
+        assert!(
+            line.starts_with("export ") && line.ends_with(';'),
+            "not an export: {}",
+            line
+        );
+    }
+    assert!(script.contains("export LEAN_PATH="));
+    assert!(script.contains("export LAKE_HOME="));
+
+    let toolchain = env.home.join("toolchains").join("fake");
+    let lib = toolchain.join("lib").join("lean");
+    assert_eq!(
+        eval_sh(&env, &script, &["LEAN_PATH", "LAKE_HOME", "ELAN_TOOLCHAIN"]),
+        vec![
+            lib.display().to_string(),
+            toolchain.display().to_string(),
+            "fake".to_owned(),
+        ]
+    );
+    let path = eval_sh(&env, &script, &["PATH"]).remove(0);
+    assert!(path.starts_with(&toolchain.join("bin").display().to_string()));
+}
+
+#[test]
+fn exec_runs_programs_in_the_toolchain_environment() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "exit 0")]);
+    let output = env.elan(&[
+        "exec",
+        "fake",
+        "--",
+        "sh",
+        "-c",
+        "printf '%s\\n' \"$LEAN_PATH\" \"$LAKE_HOME\" \"$ELAN_TOOLCHAIN\" \"${PATH%%:*}\"; exit 3",
+    ]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+
+    let toolchain = env.home.join("toolchains").join("fake");
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "{}\n{}\nfake\n{}\n",
+            toolchain.join("lib").join("lean").display(),
+            toolchain.display(),
+            toolchain.join("bin").display()
+        )
+    );
+}
+
+#[test]
+fn exec_prepends_the_toolchain_to_lean_path_as_set() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "exit 0")]);
+    env.elan_ok(&["set", "lean-path", "prepend"]);
+    let output = env
+        .cmd("elan")
+        .args(["exec", "fake", "--", "sh", "-c", "echo \"$LEAN_PATH\""])
+        .env("LEAN_PATH", "/elsewhere")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let lib = env
+        .home
+        .join("toolchains")
+        .join("fake")
+        .join("lib")
+        .join("lean");
+    assert_eq!(stdout(&output), format!("{}:/elsewhere\n", lib.display()));
+}
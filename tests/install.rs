@@ -0,0 +1,282 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use support::{sha256, stderr, stdout, toolchain_archive, Env};
+
+/// Writes a lockfile to the working directory locking `stable` to the
+/// archive `url` with the SHA-256 `sha256`
+fn lock_stable(env: &Env, url: &str, sha256: &str) {
+    fs::write(
+        env.cwd.join("elan.lock"),
+        format!(
+            "version = \"1\"\n\n[toolchains.stable]\nrelease = \"{}\"\nsha256 = \"{}\"\n",
+            url, sha256
+        ),
+    )
+    .unwrap();
+}
+
+#[test]
+fn locked_install_records_the_archive() {
+    let env = Env::new();
+    let archive = toolchain_archive(&[("lean", "echo \"Lean (version 4.0.0)\"")]);
+    fs::write(env.path("lean.tar.gz"), &archive).unwrap();
+    let manifest = env.manifest(&[("stable", "lean.tar.gz", &sha256(&archive))]);
+
+    env.elan_ok(&[
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "stable".as_ref(),
+        "--locked".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ]);
+    let lock = fs::read_to_string(env.cwd.join("elan.lock")).unwrap();
+    assert!(
+        lock.contains(&format!("sha256 = \"{}\"", sha256(&archive))),
+        "{}",
+        lock
+    );
+    let output = env.run("lean", &["+stable", "--version"]);
+    assert_eq!(
+        output.stdout,
+        b"Lean (version 4.0.0)\n",
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn locked_checksum_is_checked_before_downloading() {
+    let env = Env::new();
+    // The archive does not even exist, so it must not be downloaded
+    let manifest = env.manifest(&[("stable", "lean.tar.gz", &"1".repeat(64))]);
+    let url = format!("file://{}", env.path("lean.tar.gz").display());
+    lock_stable(&env, &url, &"2".repeat(64));
+
+    let output = env.elan(&[
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "stable".as_ref(),
+        "--locked".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("checksum failed"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!env.home.join("toolchains").join("stable").exists());
+}
+
+/// The version the `lean` of `toolchain` prints
+fn lean_version(env: &Env, toolchain: &str) -> String {
+    let output = env.run("lean", &[&format!("+{}", toolchain), "--version"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn update_skips_pinned_toolchains() {
+    let env = Env::new();
+    let stable = env.release("stable-1.tar.gz", "4.0.0");
+    let nightly = env.release("nightly-1.tar.gz", "4.1.0-nightly-1");
+    let manifest = env.manifest(&[
+        ("stable", "stable-1.tar.gz", &stable),
+        ("nightly", "nightly-1.tar.gz", &nightly),
+    ]);
+    env.elan_ok(&[
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "stable".as_ref(),
+        "nightly".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ]);
+    env.elan_ok(&["toolchain", "pin", "nightly"]);
+
+    // Both channels have moved on since
+    let stable = env.release("stable-2.tar.gz", "4.0.1");
+    let nightly = env.release("nightly-2.tar.gz", "4.1.0-nightly-2");
+    let manifest = env.manifest(&[
+        ("stable", "stable-2.tar.gz", &stable),
+        ("nightly", "nightly-2.tar.gz", &nightly),
+    ]);
+    let update = [
+        "update".as_ref(),
+        "--no-self-update".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ];
+    env.elan_ok(&update);
+    assert_eq!(lean_version(&env, "stable"), "Lean (version 4.0.1)\n");
+    assert_eq!(
+        lean_version(&env, "nightly"),
+        "Lean (version 4.1.0-nightly-1)\n"
+    );
+
+    env.elan_ok(&["toolchain", "unpin", "nightly"]);
+    env.elan_ok(&update);
+    assert_eq!(
+        lean_version(&env, "nightly"),
+        "Lean (version 4.1.0-nightly-2)\n"
+    );
+}
+
+#[test]
+fn verify_only_goes_on_past_toolchains_without_a_record() {
+    let env = Env::new();
+    let sha = env.release("lean.tar.gz", "4.0.0");
+    let manifest = env.manifest(&[("stable", "lean.tar.gz", &sha)]);
+    env.elan_ok(&[
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "stable".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ]);
+    // Linked toolchains were not installed from a release
+    env.link_toolchain("fake", &[("lean", "true")]);
+
+    let verify = ["toolchain", "install", "fake", "stable", "--verify-only"];
+    let output = env.elan(&verify);
+    assert!(!output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "fake: no record of installed files\nstable: ok\n"
+    );
+    assert!(
+        stderr(&output).contains("toolchain 'fake' failed verification"),
+        "{}",
+        stderr(&output)
+    );
+
+    let lean = env
+        .home
+        .join("toolchains")
+        .join("stable")
+        .join("bin")
+        .join("lean");
+    fs::write(&lean, "#!/bin/sh\necho tampered\n").unwrap();
+    let output = env.elan(&["toolchain", "install", "stable", "--verify-only"]);
+    assert!(!output.status.success());
+    assert!(
+        stdout(&output).starts_with("stable: 1 problem(s)\n"),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn print_install_dir_prints_only_the_toolchain_directory() {
+    let env = Env::new();
+    let sha = env.release("lean.tar.gz", "4.0.0");
+    let manifest = env.manifest(&[("stable", "lean.tar.gz", &sha)]);
+    let install = [
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "stable".as_ref(),
+        "--print-install-dir".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ];
+    let dir = env.elan_ok(&install);
+    let dir = Path::new(dir.strip_suffix('\n').unwrap());
+    assert!(dir.is_absolute());
+    assert!(dir.join("bin").join("lean").is_file());
+
+    // Without the archive, as an installed toolchain is not installed again
+    fs::remove_file(env.path("lean.tar.gz")).unwrap();
+    assert_eq!(env.elan_ok(&install), format!("{}\n", dir.display()));
+}
+
+#[test]
+fn quiet_install_prints_only_failures() {
+    let env = Env::new();
+    let sha = env.release("lean.tar.gz", "4.0.0");
+    let manifest = env.manifest(&[("stable", "lean.tar.gz", &sha)]);
+    let install = |toolchain: &str| {
+        env.elan(&[
+            "toolchain".as_ref(),
+            "install".as_ref(),
+            toolchain.as_ref(),
+            "--quiet".as_ref(),
+            "--manifest".as_ref(),
+            manifest.as_os_str(),
+        ])
+    };
+
+    let output = install("stable");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "");
+
+    let output = install("nightly");
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "");
+    assert!(
+        stderr(&output).starts_with("error: ")
+            && stderr(&output).contains("does not list the toolchain 'nightly'"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+/// Every path under `dir` with its modification time
+fn snapshot(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        entries.push((path.clone(), metadata.modified().unwrap()));
+        if metadata.is_dir() {
+            entries.extend(snapshot(&path));
+        }
+    }
+    entries.sort();
+    entries
+}
+
+#[test]
+fn if_needed_install_of_an_installed_version_does_nothing() {
+    let env = Env::new();
+    let sha = env.release("lean.tar.gz", "4.1.0");
+    let manifest = env.manifest(&[("leanprover/lean4:v4.1.0", "lean.tar.gz", &sha)]);
+    let install = [
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "leanprover/lean4:v4.1.0".as_ref(),
+        "--if-needed".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ];
+    env.elan_ok(&install);
+    assert_eq!(
+        lean_version(&env, "leanprover/lean4:v4.1.0"),
+        "Lean (version 4.1.0)\n"
+    );
+
+    let before = snapshot(&env.home);
+    // Long enough for any write to show in the modification times
+    thread::sleep(Duration::from_millis(20));
+    fs::remove_file(env.path("lean.tar.gz")).unwrap();
+    let output = env.elan(&install);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "");
+    assert_eq!(snapshot(&env.home), before);
+}
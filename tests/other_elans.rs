@@ -0,0 +1,138 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use support::{stderr, write_script, Env};
+
+/// A simulated install of another elan in the directory `name` of `env`, as
+/// its home with `toolchains` and `bin/elan`
+fn other_install(env: &Env, name: &str) -> PathBuf {
+    let home = env.path(name);
+    fs::create_dir_all(home.join("toolchains")).unwrap();
+    fs::create_dir_all(home.join("bin")).unwrap();
+    home
+}
+
+/// The warnings of `elan <args>` run with the default home of the user home
+/// `user_home`, with `other_home`'s elan next on `PATH`
+fn warnings_of(
+    env: &Env,
+    args: &[&str],
+    user_home: &Path,
+    other_home: &Path,
+    elan_home: Option<&Path>,
+) -> String {
+    let path = env::join_paths(
+        [env.bin.clone(), other_home.join("bin")]
+            .iter()
+            .cloned()
+            .chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+    )
+    .unwrap();
+    let mut cmd = env.cmd("elan");
+    cmd.args(args).env("PATH", path).env("HOME", user_home);
+    match elan_home {
+        Some(home) => cmd.env("ELAN_HOME", home),
+        None => cmd.env_remove("ELAN_HOME"),
+    };
+    let output = cmd.output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    stderr(&output)
+}
+
+/// The warnings of `elan show`, as `warnings_of`
+fn warnings(env: &Env, user_home: &Path, other_home: &Path, elan_home: Option<&Path>) -> String {
+    warnings_of(env, &["show"], user_home, other_home, elan_home)
+}
+
+#[test]
+fn other_elans_with_a_home_of_their_own_are_warned_about() {
+    let env = Env::new();
+    let user_home = env.path("user");
+    let ours = user_home.join(".elan");
+    fs::create_dir_all(ours.join("toolchains")).unwrap();
+
+    // An elan whose wrapper gives it a home of its own
+    let other = other_install(&env, "other");
+    let binary = other.join("bin").join("elan");
+    write_script(
+        &binary,
+        &format!(
+            "[ \"$1 $2\" = \"show home\" ] && echo '{}'",
+            other.display()
+        ),
+    );
+    let warned = format!(
+        "warning: another elan at '{}' appears to use the elan home '{}' instead of '{}'",
+        binary.display(),
+        other.display(),
+        ours.display()
+    );
+    let stderr = warnings(&env, &user_home, &other, None);
+    assert!(stderr.contains(&warned), "{}", stderr);
+
+    // Both elans see the same `ELAN_HOME`
+    let stderr = warnings(&env, &user_home, &other, Some(&env.home));
+    assert!(!stderr.contains("another elan"), "{}", stderr);
+
+    // A copy of this elan that merely sits in another home uses the same
+    // default home, as it says when asked
+    let copy = other_install(&env, "copy");
+    fs::copy(
+        env!("CARGO_BIN_EXE_elan-init"),
+        copy.join("bin").join("elan"),
+    )
+    .unwrap();
+    let stderr = warnings(&env, &user_home, &copy, None);
+    assert!(!stderr.contains("another elan"), "{}", stderr);
+}
+
+#[test]
+fn other_elans_are_asked_once_and_not_for_long() {
+    let env = Env::new();
+    let user_home = env.path("user");
+    let ours = user_home.join(".elan");
+    fs::create_dir_all(ours.join("toolchains")).unwrap();
+
+    // An elan that counts how often it is asked, and never answers
+    let other = other_install(&env, "other");
+    let asked = env.path("asked");
+    write_script(
+        &other.join("bin").join("elan"),
+        &format!("echo >> '{}'\nexec sleep 60", asked.display()),
+    );
+    let times_asked = || {
+        fs::read_to_string(&asked)
+            .unwrap_or_default()
+            .lines()
+            .count()
+    };
+
+    // Scripted commands don't ask at all
+    let stderr = warnings_of(&env, &["toolchain", "list"], &user_home, &other, None);
+    assert!(!stderr.contains("another elan"), "{}", stderr);
+    assert_eq!(times_asked(), 0);
+
+    // It is given up on, and its home guessed from where it is
+    let start = Instant::now();
+    let stderr = warnings(&env, &user_home, &other, None);
+    assert!(start.elapsed() < Duration::from_secs(30));
+    assert!(stderr.contains("another elan"), "{}", stderr);
+    assert_eq!(times_asked(), 1);
+
+    // What it took for its home is remembered
+    let stderr = warnings(&env, &user_home, &other, None);
+    assert!(stderr.contains("another elan"), "{}", stderr);
+    assert_eq!(times_asked(), 1);
+}
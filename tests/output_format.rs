@@ -0,0 +1,47 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use support::{stderr, Env};
+
+/// The stderr of a failing `elan run` with the variables `vars` set and
+/// the extra arguments `args` before the subcommand
+fn failed_run(env: &Env, vars: &[(&str, &str)], args: &[&str]) -> String {
+    let mut cmd = env.cmd("elan");
+    cmd.args(args).args(["run", "nope", "lean"]);
+    for &(var, value) in vars {
+        cmd.env(var, value);
+    }
+    let output = cmd.output().unwrap();
+    assert!(!output.status.success());
+    stderr(&output)
+}
+
+#[test]
+fn github_actions_get_errors_as_workflow_commands() {
+    let env = Env::new();
+    let command = "::error::toolchain 'nope' is not installed\n";
+    let plain = "error: toolchain 'nope' is not installed\n";
+
+    let stderr = failed_run(&env, &[("ELAN_GITHUB_ACTIONS", "1")], &[]);
+    assert!(stderr.starts_with(command), "{}", stderr);
+    let stderr = failed_run(&env, &[("GITHUB_ACTIONS", "true")], &[]);
+    assert!(stderr.starts_with(command), "{}", stderr);
+    let stderr = failed_run(&env, &[], &["--output-format", "github"]);
+    assert!(stderr.starts_with(command), "{}", stderr);
+
+    let stderr = failed_run(&env, &[], &[]);
+    assert!(stderr.starts_with(plain), "{}", stderr);
+    let stderr = failed_run(
+        &env,
+        &[("GITHUB_ACTIONS", "true"), ("ELAN_GITHUB_ACTIONS", "0")],
+        &[],
+    );
+    assert!(stderr.starts_with(plain), "{}", stderr);
+}
@@ -0,0 +1,72 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+
+use support::{stderr, Env};
+
+#[test]
+fn override_set_with_path_applies_inside_that_directory() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "echo fake")]);
+    env.link_toolchain("other", &[("lean", "echo other")]);
+    env.elan_ok(&["default", "other"]);
+    let project = env.path("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+
+    // Relative to the current directory, which the override is not for
+    env.elan_ok(&["override", "set", "fake", "--path", "../project"]);
+    let recorded = fs::canonicalize(&project).unwrap();
+    assert!(
+        env.settings().contains(recorded.to_str().unwrap()),
+        "{}",
+        env.settings()
+    );
+    let output = env.run("lean", &["--version"]);
+    assert_eq!(output.stdout, b"other\n", "{}", stderr(&output));
+    let output = env
+        .cmd("lean")
+        .arg("--version")
+        .current_dir(project.join("src"))
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"fake\n", "{}", stderr(&output));
+
+    let output = env.elan(&["override", "set", "fake", "--path", "../missing"]);
+    assert!(!output.status.success());
+    assert!(!env.settings().contains("missing"), "{}", env.settings());
+}
+
+#[test]
+fn why_lists_every_reference() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "true")]);
+    env.link_toolchain("other", &[("lean", "true")]);
+    env.elan_ok(&["default", "fake"]);
+    let project = env.path("project");
+    fs::create_dir_all(&project).unwrap();
+    env.elan_ok(&[
+        "override".as_ref(),
+        "set".as_ref(),
+        "fake".as_ref(),
+        "--path".as_ref(),
+        project.as_os_str(),
+    ]);
+
+    assert_eq!(
+        env.elan_ok(&["why", "fake"]),
+        format!(
+            "'fake' is in use because:\n  it is the default toolchain\n  \
+             directory override for '{}'\n",
+            fs::canonicalize(&project).unwrap().display()
+        )
+    );
+    assert_eq!(env.elan_ok(&["why", "other"]), "'other': no references\n");
+}
@@ -0,0 +1,148 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use support::{stderr, stdout, Env};
+
+/// A binary printing each of its arguments on a line of its own
+const PRINT_ARGS: &str = "for a in \"$@\"; do echo \"$a\"; done";
+
+#[test]
+fn response_files_are_expanded_for_lean_only() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", PRINT_ARGS), ("lake", PRINT_ARGS)]);
+    env.elan_ok(&["default", "fake"]);
+    fs::write(
+        env.cwd.join("args.rsp"),
+        "-DmaxHeartbeats=0 \"My File.lean\"\n-o out\n",
+    )
+    .unwrap();
+
+    let output = env.run("lean", &["--make", "@args.rsp"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "--make\n-DmaxHeartbeats=0\nMy File.lean\n-o\nout\n"
+    );
+
+    let output = env.run("lake", &["build", "@args.rsp"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "build\n@args.rsp\n");
+}
+
+#[test]
+fn missing_and_non_executable_binaries_are_explained() {
+    let env = Env::new();
+    let dir = env.link_toolchain("fake", &[("lean", "true")]);
+    env.elan_ok(&["default", "fake"]);
+
+    // Neither in the toolchain nor anywhere else on `PATH`
+    let output = env.elan(&["run", "fake", "leanchecker"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("toolchain 'fake' does not have the binary `leanchecker`"),
+        "{}",
+        stderr(&output)
+    );
+
+    fs::set_permissions(
+        dir.join("bin").join("lean"),
+        fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+    let lean = env
+        .home
+        .join("toolchains")
+        .join("fake")
+        .join("bin")
+        .join("lean");
+    let output = env.run("lean", &["--version"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains(&format!(
+            "'{}' is not executable; you may need to run `chmod +x '{}'`",
+            lean.display(),
+            lean.display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn no_auto_install_refuses_missing_toolchains() {
+    let env = Env::new();
+    fs::write(env.cwd.join("lean-toolchain"), "leanprover/lean4:v4.9.0\n").unwrap();
+    let not_installed = "toolchain 'leanprover/lean4:v4.9.0' is not installed";
+
+    let output = env
+        .cmd("lean")
+        .arg("--version")
+        .env("ELAN_NO_AUTO_INSTALL", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains(not_installed),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        !stderr(&output).contains("downloading"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = env.elan(&["--no-auto-install", "which", "lean"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains(not_installed),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        !env.home.join("toolchains").exists()
+            || fs::read_dir(env.home.join("toolchains")).unwrap().count() == 0
+    );
+}
+
+#[test]
+fn lake_finds_the_lean_of_its_toolchain() {
+    let env = Env::new();
+    let other = env.link_toolchain("other", &[("lean", "echo other")]);
+    env.link_toolchain(
+        "fake",
+        &[
+            ("lean", "echo fake"),
+            // Records the lean it found, as `lake env lean` would run it
+            ("lake", "echo \"$LEAN_SYSROOT\"; \"$LEAN\""),
+        ],
+    );
+    env.elan_ok(&["default", "fake"]);
+
+    // As left behind by the `lake env` of another toolchain
+    let output = env
+        .cmd("lake")
+        .args(["env", "lean"])
+        .env("LEAN_SYSROOT", &other)
+        .env("LEAN", other.join("bin").join("lean"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "{}\nfake\n",
+            env.home.join("toolchains").join("fake").display()
+        )
+    );
+}
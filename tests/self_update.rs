@@ -0,0 +1,83 @@
+#![cfg(unix)]
+
+extern crate elan_dist;
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use support::{sha256, stderr, write_script, Env};
+
+/// Publishes elan `version` under the update root `root`, as an archive
+/// whose `elan-init` prints that version, and returns the archive's path
+fn publish(root: &Path, version: &str) -> PathBuf {
+    let contents = format!("#!/bin/sh\necho 'elan {}'\n", version);
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o755);
+    header.set_size(contents.len() as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "elan-init", contents.as_bytes())
+        .unwrap();
+    let archive = builder.into_inner().unwrap().finish().unwrap();
+
+    let dir = root.join(format!("v{}", version));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("elan-{}.tar.gz", elan_dist::dist::host_triple()));
+    fs::write(&path, archive).unwrap();
+    path
+}
+
+#[test]
+fn self_update_keeps_elan_unless_the_download_is_verified() {
+    let env = Env::new();
+    let bin = env.home.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    write_script(&bin.join("elan"), "echo 'elan 0.0.1'");
+
+    let root = env.path("releases");
+    let archive = publish(&root, "9.9.9");
+    let index = env.path("index.json");
+    fs::write(&index, r#"{"leanprover/elan": {"latest": "v9.9.9"}}"#).unwrap();
+    let update = || {
+        let output = env
+            .cmd("elan")
+            .args(["self", "update"])
+            .env("ELAN_RELEASE_INDEX", &index)
+            .env("ELAN_UPDATE_ROOT", format!("file://{}", root.display()))
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        // Neither the installed elan nor the update's setup were touched
+        assert_eq!(
+            fs::read_to_string(bin.join("elan")).unwrap(),
+            "#!/bin/sh\necho 'elan 0.0.1'\n"
+        );
+        assert!(!bin.join("elan-init").exists());
+        stderr(&output)
+    };
+
+    // No checksum is published
+    let stderr = update();
+    assert!(
+        stderr.contains("no checksum is published for"),
+        "{}",
+        stderr
+    );
+
+    // One for another archive
+    let checksum = archive.with_extension("gz.sha256");
+    fs::write(&checksum, format!("{}  elan.tar.gz\n", sha256(b"other"))).unwrap();
+    let stderr = update();
+    assert!(stderr.contains("checksum failed"), "{}", stderr);
+}
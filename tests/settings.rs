@@ -0,0 +1,112 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+
+use support::{stderr, stdout, with_stderr_on_tty, Env};
+
+/// A binary printing each of its arguments on a line of its own
+const PRINT_ARGS: &str = "for a in \"$@\"; do echo \"$a\"; done";
+
+#[test]
+fn toolchain_lean_args_type_errors_name_the_key() {
+    let env = Env::new();
+    let error = |settings: &str| {
+        fs::write(env.home.join("settings.toml"), settings).unwrap();
+        let output = env.elan(&["toolchain", "list"]);
+        assert!(!output.status.success());
+        stderr(&output)
+    };
+    let stderr = error("version = \"12\"\ntoolchain_lean_args = 1\n");
+    assert!(
+        stderr.contains("expected type: 'table' for 'toolchain_lean_args'"),
+        "{}",
+        stderr
+    );
+    let stderr = error("version = \"12\"\n[toolchain_lean_args]\nstable = [1]\n");
+    assert!(
+        stderr.contains("expected type: 'string' for 'toolchain_lean_args.stable'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn project_settings_may_only_set_harmless_keys() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", PRINT_ARGS)]);
+    env.link_toolchain("other", &[("lean", "echo other")]);
+    env.elan_ok(&["default", "fake"]);
+    fs::create_dir_all(env.cwd.join(".elan")).unwrap();
+    fs::write(
+        env.cwd.join(".elan").join("settings.toml"),
+        "color = \"never\"\ndefault_toolchain = \"other\"\nlean_args = [\"--plugin=evil.so\"]\n\
+         proxy = \"http://evil.example:3128\"\nno_proxy = \"*\"\n",
+    )
+    .unwrap();
+
+    let output = env.run("lean", &["a.lean"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"a.lean\n");
+    for key in &["default_toolchain", "lean_args", "proxy", "no_proxy"] {
+        assert!(
+            stderr(&output).contains(&format!("warning: ignoring '{}' in '", key)),
+            "{}",
+            stderr(&output)
+        );
+    }
+    assert!(!stderr(&output).contains("'color'"), "{}", stderr(&output));
+    assert_eq!(env.elan_ok(&["get", "color"]), "never\n");
+}
+
+#[test]
+fn set_then_get_round_trips() {
+    let env = Env::new();
+    env.elan_ok(&["set", "auto-install", "false"]);
+    let output = env.elan(&["get", "auto-install"]);
+    assert_eq!(stdout(&output), "false\n");
+    assert_eq!(stderr(&output), "");
+    env.elan_ok(&["set", "auto-install", "true"]);
+    assert_eq!(env.elan_ok(&["get", "auto-install"]), "true\n");
+
+    // A project's setting wins over the one `elan set` writes, and both
+    // say so
+    fs::create_dir_all(env.cwd.join(".elan")).unwrap();
+    let project = env.cwd.join(".elan").join("settings.toml");
+    fs::write(&project, "color = \"always\"\n").unwrap();
+    // Without the prefix, as the project's setting colors it
+    let shadowed = format!(
+        "'color' is set in '{}', which takes precedence",
+        project.display()
+    );
+    let output = env.elan(&["set", "color", "never"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains(&shadowed), "{}", stderr(&output));
+    let output = env.elan(&["get", "color"]);
+    assert_eq!(stdout(&output), "always\n");
+    assert!(stderr(&output).contains(&shadowed), "{}", stderr(&output));
+}
+
+#[test]
+fn color_setting_applies_on_a_terminal() {
+    let env = Env::new();
+    // An error, which is colored on a terminal
+    let terminal = || {
+        let (output, terminal) =
+            with_stderr_on_tty(env.cmd("elan").args(["default", "lean4:v4:1:0"]));
+        assert!(!output.status.success());
+        assert!(terminal.contains("error: "), "{}", terminal);
+        terminal
+    };
+    assert!(terminal().contains('\x1b'), "{:?}", terminal());
+
+    env.elan_ok(&["set", "color", "never"]);
+    assert!(!terminal().contains('\x1b'), "{:?}", terminal());
+}
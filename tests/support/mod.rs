@@ -0,0 +1,292 @@
+//! Runs the built elan, and the proxies it installs, on an elan home of
+//! their own, with toolchains that are shell scripts linked into it
+// Not every test uses every helper
+#![allow(dead_code)]
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::ptr;
+use std::thread;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+/// Variables of the environment running the tests that would change how
+/// elan behaves
+const CLEARED_VARS: &[&str] = &[
+    "ELAN_TOOLCHAIN",
+    "ELAN_NO_AUTO_INSTALL",
+    "ELAN_NO_COLOR_INJECT",
+    "ELAN_NO_TELEMETRY_COLOR",
+    "ELAN_TELEMETRY_PASSIVE",
+    "ELAN_RELEASE_INDEX",
+    "ELAN_RELEASE_MANIFEST",
+    "ELAN_GITHUB_ACTIONS",
+    "GITHUB_ACTIONS",
+    "LEAN_PATH",
+    "LEAN_SYSROOT",
+    "LEAN",
+    "LAKE_HOME",
+    "LEAN_RECURSION_COUNT",
+];
+
+pub struct Env {
+    dir: TempDir,
+    /// `ELAN_HOME` of the commands run
+    pub home: PathBuf,
+    /// Where `elan` and the proxies are, first on `PATH`
+    pub bin: PathBuf,
+    /// The directory commands are run in
+    pub cwd: PathBuf,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::with_home("home")
+    }
+
+    /// An environment whose home is the directory `name` of its own
+    pub fn with_home(name: &str) -> Self {
+        let dir = tempfile::Builder::new()
+            .prefix("elan-test")
+            .tempdir()
+            .unwrap();
+        let home = dir.path().join(name);
+        let bin = dir.path().join("bin");
+        let cwd = dir.path().join("work");
+        for dir in &[&home, &bin, &cwd] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let elan = Path::new(env!("CARGO_BIN_EXE_elan-init"));
+        for name in &["elan", "lean", "lake"] {
+            std::os::unix::fs::symlink(elan, bin.join(name)).unwrap();
+        }
+        Env {
+            dir,
+            home,
+            bin,
+            cwd,
+        }
+    }
+
+    /// A path within the environment's own directory
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// `program` run as elan runs it, from `bin` if it is there
+    pub fn cmd(&self, program: &str) -> Command {
+        let program = match self.bin.join(program) {
+            ref path if path.exists() => path.clone(),
+            _ => PathBuf::from(program),
+        };
+        let mut cmd = Command::new(program);
+        for var in CLEARED_VARS {
+            cmd.env_remove(var);
+        }
+        let path = env::var_os("PATH").unwrap_or_default();
+        let path = env::join_paths(
+            Some(self.bin.clone())
+                .into_iter()
+                .chain(env::split_paths(&path)),
+        )
+        .unwrap();
+        cmd.env("PATH", path)
+            .env("ELAN_HOME", &self.home)
+            // Keeps the user's own settings out
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("RUST_BACKTRACE", "0")
+            .current_dir(&self.cwd);
+        cmd
+    }
+
+    pub fn run<S: AsRef<OsStr>>(&self, program: &str, args: &[S]) -> Output {
+        self.cmd(program).args(args).output().unwrap()
+    }
+
+    pub fn elan<S: AsRef<OsStr>>(&self, args: &[S]) -> Output {
+        self.run("elan", args)
+    }
+
+    /// Runs elan with `args` and `input` as its stdin, answering its
+    /// questions
+    pub fn elan_with_input<S: AsRef<OsStr>>(&self, args: &[S], input: &str) -> Output {
+        let mut child = self
+            .cmd("elan")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs elan with `args`, failing the test unless it succeeds, and
+    /// returns its stdout
+    pub fn elan_ok<S: AsRef<OsStr>>(&self, args: &[S]) -> String {
+        let output = self.elan(args);
+        assert!(output.status.success(), "elan failed: {}", stderr(&output));
+        stdout(&output)
+    }
+
+    /// Writes a toolchain whose binaries are the shell scripts `binaries`,
+    /// as pairs of a name and the script's body, outside of the home and
+    /// returns its directory
+    pub fn toolchain_dir(&self, dir: &str, binaries: &[(&str, &str)]) -> PathBuf {
+        let dir = self.path(dir);
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::create_dir_all(dir.join("lib").join("lean")).unwrap();
+        for &(name, body) in binaries {
+            write_script(&dir.join("bin").join(name), body);
+        }
+        dir
+    }
+
+    /// Links a toolchain `name` made as `toolchain_dir` does
+    pub fn link_toolchain(&self, name: &str, binaries: &[(&str, &str)]) -> PathBuf {
+        let dir = self.toolchain_dir(&format!("{}-src", name), binaries);
+        self.elan_ok(&[
+            OsStr::new("toolchain"),
+            OsStr::new("link"),
+            OsStr::new(name),
+            dir.as_os_str(),
+        ]);
+        dir
+    }
+
+    /// Writes the release archive `file` of a toolchain whose `lean` prints
+    /// `version` as its version, and returns its SHA-256
+    pub fn release(&self, file: &str, version: &str) -> String {
+        let lean = format!("echo \"Lean (version {})\"", version);
+        let archive = toolchain_archive(&[("lean", &lean)]);
+        fs::write(self.path(file), &archive).unwrap();
+        sha256(&archive)
+    }
+
+    /// Writes a release manifest listing `entries`, each a toolchain name,
+    /// an archive URL relative to the manifest and its SHA-256, for this
+    /// platform, and returns its path
+    pub fn manifest(&self, entries: &[(&str, &str, &str)]) -> PathBuf {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|&(name, url, sha256)| {
+                format!(
+                    r#"{{ "name": "{}", "target": "{}", "url": "{}", "sha256": "{}" }}"#,
+                    name,
+                    host_target(),
+                    url,
+                    sha256
+                )
+            })
+            .collect();
+        let path = self.path("manifest.json");
+        fs::write(&path, format!("[{}]", entries.join(",\n"))).unwrap();
+        path
+    }
+
+    pub fn settings(&self) -> String {
+        fs::read_to_string(self.home.join("settings.toml")).unwrap_or_default()
+    }
+}
+
+/// Runs `cmd` with its stderr on a pseudo-terminal, returning its output
+/// along with what it wrote to the terminal
+pub fn with_stderr_on_tty(cmd: &mut Command) -> (Output, String) {
+    let (mut master, mut slave) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(opened, 0);
+    let (terminal, tty) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+    let reader = thread::spawn(move || {
+        let mut seen = Vec::new();
+        // Fails once the command, the last to hold the other end, exits
+        let _ = (&terminal).read_to_end(&mut seen);
+        String::from_utf8_lossy(&seen).into_owned()
+    });
+
+    let output = cmd
+        .env("TERM", "xterm")
+        .env_remove("NO_COLOR")
+        .stderr(tty)
+        .output()
+        .unwrap();
+    // Drops the command's handle on the terminal
+    cmd.stderr(Stdio::null());
+    (output, reader.join().unwrap())
+}
+
+/// Writes an executable shell script
+pub fn write_script(path: &Path, body: &str) {
+    fs::write(path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A release archive of a toolchain whose binaries are the shell scripts
+/// `binaries`, under a top directory as Lean's releases have
+pub fn toolchain_archive(binaries: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for &(name, body) in binaries {
+        let contents = format!("#!/bin/sh\n{}\n", body);
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o755);
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("lean-4.0.0/bin/{}", name),
+                contents.as_bytes(),
+            )
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+/// The name release assets use for this platform
+pub fn host_target() -> String {
+    let os = match env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    match env::consts::ARCH {
+        "aarch64" => format!("{}_aarch64", os),
+        _ => os.to_owned(),
+    }
+}
+
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
@@ -0,0 +1,396 @@
+extern crate elan;
+extern crate elan_utils;
+extern crate flate2;
+extern crate libc;
+extern crate serde_json;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+extern crate time;
+
+#[cfg(unix)]
+mod support;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use elan::telemetry::{LogMessage, Telemetry, TelemetryEvent};
+use elan::telemetry_analysis::{parse_since, TelemetryAnalysis};
+use elan_utils::semaphore::Semaphore;
+use time::{Duration, OffsetDateTime};
+
+fn lean_run(exit_code: i32) -> TelemetryEvent {
+    TelemetryEvent::LeanRun {
+        duration_ms: 1234,
+        exit_code,
+        errors: Some(vec!["E0001".to_owned(), "E0002".to_owned()]),
+        color_forced: false,
+    }
+}
+
+/// The exit codes of the `LeanRun` events logged in `dir`
+fn logged_exit_codes(dir: &Path) -> Vec<i32> {
+    let mut codes: Vec<i32> = TelemetryAnalysis::new(dir.to_owned())
+        .import_telemery()
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| match event {
+            TelemetryEvent::LeanRun {
+                exit_code, errors, ..
+            } => {
+                assert_eq!(errors, Some(vec!["E0001".to_owned(), "E0002".to_owned()]));
+                Some(exit_code)
+            }
+            _ => None,
+        })
+        .collect();
+    codes.sort();
+    codes
+}
+
+#[test]
+fn compressed_lean_run_is_read_back() {
+    let dir = tempfile::Builder::new()
+        .prefix("elan-telemetry")
+        .tempdir()
+        .unwrap();
+    let telemetry = Telemetry::new(dir.path().to_owned(), true);
+    telemetry.log_telemetry(lean_run(1)).unwrap();
+    telemetry.log_telemetry(lean_run(2)).unwrap();
+
+    let files: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    assert!(files[0].to_str().unwrap().ends_with(".json.gz"));
+    assert_eq!(fs::read(&files[0]).unwrap()[..2], [0x1f, 0x8b]);
+    assert_eq!(logged_exit_codes(dir.path()), vec![1, 2]);
+
+    // Uncompressed logs, such as those of earlier days, are still read
+    let plain = Telemetry::new(dir.path().to_owned(), false);
+    plain.log_telemetry(lean_run(3)).unwrap();
+    assert_eq!(logged_exit_codes(dir.path()), vec![1, 2, 3]);
+}
+
+/// Turns telemetry on in the settings elan has written for `env`
+#[cfg(unix)]
+fn enable_telemetry(env: &support::Env) {
+    let settings = env.settings();
+    let settings = if settings.contains("telemetry = false") {
+        settings.replace("telemetry = false", "telemetry = true")
+    } else {
+        format!("telemetry = true\n{}", settings)
+    };
+    fs::write(env.home.join("settings.toml"), settings).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn lean_exit_code_survives_unwritable_telemetry() {
+    let env = support::Env::new();
+    env.link_toolchain("fake", &[("lean", "echo ran; exit 3")]);
+    env.elan_ok(&["default", "fake"]);
+    enable_telemetry(&env);
+    // Nothing can be created in place of a file
+    fs::remove_dir_all(env.home.join("telemetry")).ok();
+    fs::write(env.home.join("telemetry"), "").unwrap();
+
+    let output = env.run("lean", &["foo.lean"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"ran\n");
+    assert!(output.stderr.is_empty(), "{}", support::stderr(&output));
+
+    // The failure is only reported when asked for
+    let output = env.elan(&["--verbose", "run", "fake", "lean", "foo.lean"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = support::stderr(&output);
+    assert_eq!(
+        stderr.matches("unable to record telemetry").count(),
+        1,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn every_error_code_on_a_line_is_recorded() {
+    let env = support::Env::new();
+    env.link_toolchain(
+        "fake",
+        &[(
+            "lean",
+            "echo 'a.lean:1:0: error [E0001] and [E0002]' >&2; exit 1",
+        )],
+    );
+    env.elan_ok(&["default", "fake"]);
+    enable_telemetry(&env);
+
+    let output = env.run("lean", &["a.lean"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        output.stderr,
+        b"a.lean:1:0: error [E0001] and [E0002]\n".to_vec()
+    );
+    let events = TelemetryAnalysis::new(env.home.join("telemetry"))
+        .import_telemery()
+        .unwrap();
+    let errors: Vec<_> = events
+        .into_iter()
+        .filter_map(|event| match event {
+            TelemetryEvent::LeanRun { errors, .. } => Some(errors),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        vec![Some(vec!["E0001".to_owned(), "E0002".to_owned()])]
+    );
+}
+
+#[test]
+fn parse_since_durations_and_dates() {
+    let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+    assert_eq!(parse_since("3d", now).unwrap(), now - Duration::days(3));
+    assert_eq!(parse_since("12h", now).unwrap(), now - Duration::hours(12));
+    assert_eq!(
+        parse_since("30m", now).unwrap(),
+        now - Duration::minutes(30)
+    );
+    assert_eq!(
+        parse_since("2023-11-14", now).unwrap(),
+        OffsetDateTime::from_unix_timestamp(1_699_920_000).unwrap()
+    );
+    for bad in &[
+        "",
+        "d",
+        "3w",
+        "-3d",
+        "3.5h",
+        "2023-11",
+        "2023-13-01",
+        "2023-02-30",
+        "recently",
+    ] {
+        assert!(parse_since(bad, now).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn analyze_since_skips_older_events() {
+    let env = support::Env::new();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let dir = env.home.join("telemetry");
+    fs::create_dir_all(&dir).unwrap();
+    let run = |time: i64| {
+        format!(
+            r#"{{"log_time_s":{},"event":{{"LeanRun":{{"duration_ms":10,"exit_code":0,"errors":null}}}},"version":1}}"#,
+            time
+        )
+    };
+    fs::write(
+        dir.join("log-2023-11-14.json"),
+        format!(
+            "{}\n{}\n{}\n",
+            run(now - 7 * 86400),
+            run(now - 3600),
+            run(now)
+        ),
+    )
+    .unwrap();
+
+    let compiles = |args: &[&str]| {
+        let stdout = env.elan_ok(args);
+        stdout
+            .lines()
+            .find(|l| l.trim().starts_with("Total compiles:"))
+            .map(|l| l.trim().to_owned())
+            .unwrap()
+    };
+    assert_eq!(compiles(&["telemetry", "analyze"]), "Total compiles: 3");
+    assert_eq!(
+        compiles(&["telemetry", "analyze", "--since", "1d"]),
+        "Total compiles: 2"
+    );
+    assert_eq!(
+        compiles(&["telemetry", "analyze", "--since", "30m"]),
+        "Total compiles: 1"
+    );
+    let output = env.elan(&["telemetry", "analyze", "--since", "last week"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn transforms_drop_and_enrich_events() {
+    let dir = tempfile::Builder::new()
+        .prefix("elan-telemetry")
+        .tempdir()
+        .unwrap();
+    let mut telemetry = Telemetry::new(dir.path().to_owned(), false);
+    telemetry.set_transform(Arc::new(|_| None));
+    telemetry.log_telemetry(lean_run(1)).unwrap();
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    telemetry.set_transform(Arc::new(|mut message: LogMessage| {
+        message.set_field("machine_id", "abc");
+        Some(message)
+    }));
+    telemetry.log_telemetry(lean_run(2)).unwrap();
+    let files: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    let stored = fs::read_to_string(&files[0]).unwrap();
+    let messages: Vec<LogMessage> = stored
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].get_field("machine_id"), Some("abc"));
+    assert_eq!(logged_exit_codes(dir.path()), vec![2]);
+}
+
+#[test]
+fn concurrent_writes_lose_no_events() {
+    let dir = tempfile::Builder::new()
+        .prefix("elan-telemetry")
+        .tempdir()
+        .unwrap();
+    // Compressed events are written in several parts, and so would be
+    // garbled by writes in between
+    let mut telemetry = Telemetry::new(dir.path().to_owned(), true);
+    telemetry.set_limiter(Arc::new(Semaphore::new(3)));
+    let telemetry = Arc::new(telemetry);
+
+    let threads: Vec<_> = (0..16)
+        .map(|i| {
+            let telemetry = telemetry.clone();
+            thread::spawn(move || {
+                for j in 0..4 {
+                    telemetry.log_telemetry(lean_run(i * 4 + j)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(logged_exit_codes(dir.path()), (0..64).collect::<Vec<_>>());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+/// Whether each `Download` event logged in `dir` was of an auto-install
+fn logged_downloads(dir: &Path) -> Vec<(String, bool)> {
+    TelemetryAnalysis::new(dir.to_owned())
+        .import_telemery()
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| match event {
+            TelemetryEvent::Download {
+                toolchain,
+                bytes,
+                auto,
+                ..
+            } => {
+                assert!(bytes > 0);
+                Some((toolchain, auto))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn download_events_are_read_back() {
+    let dir = tempfile::Builder::new()
+        .prefix("elan-telemetry")
+        .tempdir()
+        .unwrap();
+    let telemetry = Telemetry::new(dir.path().to_owned(), false);
+    for &(toolchain, auto) in &[("stable", false), ("nightly", true)] {
+        let download = TelemetryEvent::Download {
+            toolchain: toolchain.to_owned(),
+            bytes: 1024,
+            duration_ms: 10,
+            auto,
+        };
+        telemetry.log_telemetry(download).unwrap();
+    }
+    assert_eq!(
+        logged_downloads(dir.path()),
+        vec![("stable".to_owned(), false), ("nightly".to_owned(), true)]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn auto_installs_log_auto_downloads() {
+    let env = support::Env::new();
+    let sha = env.release("lean.tar.gz", "4.1.0");
+    let manifest = env.manifest(&[
+        ("leanprover/lean4:v4.1.0", "lean.tar.gz", &sha),
+        ("leanprover/lean4:v4.2.0", "lean.tar.gz", &sha),
+    ]);
+    env.elan_ok(&["set", "telemetry", "true"]);
+    env.elan_ok(&[
+        "toolchain".as_ref(),
+        "install".as_ref(),
+        "leanprover/lean4:v4.1.0".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ]);
+
+    fs::write(env.cwd.join("lean-toolchain"), "leanprover/lean4:v4.2.0\n").unwrap();
+    let output = env
+        .cmd("lean")
+        .arg("--version")
+        .env("ELAN_RELEASE_MANIFEST", &manifest)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", support::stderr(&output));
+    assert_eq!(output.stdout, b"Lean (version 4.1.0)\n");
+
+    assert_eq!(
+        logged_downloads(&env.home.join("telemetry")),
+        vec![
+            ("leanprover/lean4:v4.1.0".to_owned(), false),
+            ("leanprover/lean4:v4.2.0".to_owned(), true),
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn lean_run_records_whether_color_was_forced() {
+    let env = support::Env::new();
+    // Prints its arguments to stderr, so on the terminal when there is one
+    env.link_toolchain("fake", &[("lean", "echo \"$@\" >&2")]);
+    env.elan_ok(&["default", "fake"]);
+    env.elan_ok(&["set", "telemetry", "true"]);
+    let color_forced = || -> Vec<bool> {
+        TelemetryAnalysis::new(env.home.join("telemetry"))
+            .import_telemery()
+            .unwrap()
+            .into_iter()
+            .filter_map(|event| match event {
+                TelemetryEvent::LeanRun { color_forced, .. } => Some(color_forced),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let (output, terminal) = support::with_stderr_on_tty(env.cmd("lean").arg("a.lean"));
+    assert!(output.status.success(), "{}", terminal);
+    assert!(terminal.contains("--color"), "{}", terminal);
+    assert_eq!(color_forced(), vec![true]);
+
+    let output = env.run("lean", &["a.lean"]);
+    assert_eq!(output.stderr, b"a.lean\n");
+    assert_eq!(color_forced(), vec![true, false]);
+}
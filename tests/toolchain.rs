@@ -0,0 +1,223 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use support::{stderr, Env};
+
+/// A `.tar.gz` of `entries`, each a name, type, link target and contents,
+/// written as is since `tar::Header` refuses unsafe names
+fn raw_tar_gz(entries: &[(&str, tar::EntryType, &str, &[u8])]) -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for &(name, entry_type, link, contents) in entries {
+        let mut header = tar::Header::new_old();
+        {
+            let old = header.as_old_mut();
+            old.name[..name.len()].copy_from_slice(name.as_bytes());
+            old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+        }
+        header.set_entry_type(entry_type);
+        header.set_mode(0o755);
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder.append(&header, contents).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn import_rejects_archive_escaping_through_symlink() {
+    let env = Env::new();
+    let outside = env.path("outside");
+    fs::create_dir_all(&outside).unwrap();
+    let archive = env.path("hostile.tar.gz");
+    fs::write(
+        &archive,
+        raw_tar_gz(&[
+            (
+                "stable/bin/lean",
+                tar::EntryType::Regular,
+                "",
+                b"#!/bin/sh\n",
+            ),
+            (
+                "stable/lib",
+                tar::EntryType::Symlink,
+                outside.to_str().unwrap(),
+                b"",
+            ),
+            ("stable/lib/evil", tar::EntryType::Regular, "", b"evil"),
+        ]),
+    )
+    .unwrap();
+
+    let output = env.elan(&["toolchain".as_ref(), "import".as_ref(), archive.as_os_str()]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("refusing to extract archive entry 'stable/lib'"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!outside.join("evil").exists());
+    assert!(!env.home.join("toolchains").join("stable").exists());
+}
+
+#[test]
+fn export_then_import_round_trip() {
+    let env = Env::new();
+    env.link_toolchain("src", &[("lean", "echo \"Lean (version 4.0.0)\"")]);
+    let archive = env.path("src.tar.gz");
+    env.elan_ok(&[
+        "toolchain".as_ref(),
+        "export".as_ref(),
+        "src".as_ref(),
+        archive.as_os_str(),
+    ]);
+
+    let other = Env::new();
+    other.elan_ok(&[
+        "toolchain".as_ref(),
+        "import".as_ref(),
+        archive.as_os_str(),
+        "copy".as_ref(),
+    ]);
+    let output = other.run("lean", &["+copy", "--version"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"Lean (version 4.0.0)\n");
+}
+
+#[test]
+fn uninstalling_the_default_asks_first() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "true")]);
+    env.link_toolchain("other", &[("lean", "true")]);
+    env.elan_ok(&["default", "fake"]);
+    let fake = env.home.join("toolchains").join("fake");
+
+    // Declined, or with no answer at all
+    for input in &["n\n", ""] {
+        let output = env.elan_with_input(&["toolchain", "uninstall", "fake"], input);
+        assert!(
+            stderr(&output).contains("'fake' is the default toolchain"),
+            "{}",
+            stderr(&output)
+        );
+        assert!(fs::symlink_metadata(&fake).is_ok());
+        assert!(env.settings().contains("default_toolchain = \"fake\""));
+    }
+
+    // Confirmed, with another default chosen in its stead
+    let output = env.elan_with_input(&["toolchain", "uninstall", "fake"], "y\nother\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fs::symlink_metadata(&fake).is_err());
+    assert!(env.settings().contains("default_toolchain = \"other\""));
+
+    let output = env.elan(&["toolchain", "uninstall", "other", "-y"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("the default toolchain 'other' is no longer installed"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn system_toolchains_are_used_but_never_changed() {
+    let env = Env::new();
+    let dir = env.toolchain_dir("system", &[("lean", "echo system")]);
+    let register = |name: &str| format!("[system_toolchains]\n{} = \"{}\"\n", name, dir.display());
+    fs::write(
+        env.home.join("settings.toml"),
+        format!("version = \"12\"\n{}", register("sys")),
+    )
+    .unwrap();
+    env.elan_ok(&["set", "telemetry", "true"]);
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let output = env.run("lean", &["+sys", "--version"]);
+    assert_eq!(output.stdout, b"system\n", "{}", stderr(&output));
+    let output = env.elan(&["toolchain", "uninstall", "sys", "-y"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("toolchain 'sys' is a system toolchain"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(dir.join("bin").join("lean").is_file());
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // Neither the user's settings nor a project's may register one
+    let config = env.path("config").join("elan");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("settings.toml"), register("user")).unwrap();
+    fs::create_dir_all(env.cwd.join(".elan")).unwrap();
+    fs::write(
+        env.cwd.join(".elan").join("settings.toml"),
+        register("project"),
+    )
+    .unwrap();
+    for name in &["user", "project"] {
+        let output = env.run("lean", &[&format!("+{}", name), "--version"]);
+        assert_ne!(output.stdout, b"system\n");
+    }
+    let output = env.run("lean", &["+sys", "--version"]);
+    assert_eq!(output.stdout, b"system\n", "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!(
+            "warning: ignoring 'system_toolchains' in '{}'",
+            config.join("settings.toml").display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        stderr(&output).contains(&format!(
+            "warning: ignoring 'system_toolchains' in '{}', as a project's settings",
+            env.cwd.join(".elan").join("settings.toml").display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn link_force_repoints_an_existing_link() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "echo old")]);
+    env.elan_ok(&["default", "fake"]);
+    let new = env.toolchain_dir("new", &[("lean", "echo new")]);
+    let link = |force: bool| {
+        let mut args = vec![
+            "toolchain".as_ref(),
+            "link".as_ref(),
+            "fake".as_ref(),
+            new.as_os_str(),
+        ];
+        if force {
+            args.push("--force".as_ref());
+        }
+        env.elan(&args)
+    };
+
+    let output = link(false);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("toolchain 'fake' already exists; pass `--force`"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(env.run("lean", &["--version"]).stdout, b"old\n");
+
+    let output = link(true);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(env.run("lean", &["--version"]).stdout, b"new\n");
+}
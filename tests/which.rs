@@ -0,0 +1,161 @@
+#![cfg(unix)]
+
+extern crate flate2;
+extern crate libc;
+extern crate sha2;
+extern crate tar;
+extern crate tempfile;
+
+mod support;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use support::{stderr, stdout, with_stderr_on_tty, Env};
+
+#[test]
+fn which_all_marks_missing_binaries() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "true"), ("lake", "true")]);
+    env.elan_ok(&["default", "fake"]);
+    // Others linked into the home are listed after the known ones
+    fs::create_dir_all(env.home.join("bin")).unwrap();
+    fs::write(env.home.join("bin").join("lean-extra"), "").unwrap();
+
+    let stdout = env.elan_ok(&["which", "--all"]);
+    let lines: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    let bin = env.home.join("toolchains").join("fake").join("bin");
+    let path = |name: &str| bin.join(name).to_str().unwrap().to_owned();
+    assert!(lines.contains(&vec!["lean", &path("lean")]), "{}", stdout);
+    assert!(lines.contains(&vec!["lake", &path("lake")]), "{}", stdout);
+    assert!(lines.contains(&vec!["leanc", "missing"]), "{}", stdout);
+    assert_eq!(lines.last(), Some(&vec!["lean-extra", "missing"]));
+}
+
+#[test]
+fn show_active_toolchain_prints_only_the_name() {
+    let env = Env::new();
+    env.link_toolchain("fake", &[("lean", "true")]);
+    fs::write(env.cwd.join("lean-toolchain"), "fake\n").unwrap();
+    let output = env.elan(&["show", "active-toolchain"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"fake\n");
+    assert!(output.stderr.is_empty());
+    assert_eq!(env.elan_ok(&["show", "--format", "name"]), "fake\n");
+
+    // Naming a toolchain is no reason to install it
+    fs::write(env.cwd.join("lean-toolchain"), "leanprover/lean4:v4.9.0\n").unwrap();
+    let output = env.elan(&["show", "active-toolchain"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"leanprover/lean4:v4.9.0\n");
+    assert!(output.stderr.is_empty());
+    assert_eq!(
+        fs::read_dir(env.home.join("toolchains")).unwrap().count(),
+        1
+    );
+}
+
+/// Serves `body` over HTTP to one request, pausing halfway for longer than
+/// a second so that the download shows its progress
+fn serve_slowly(body: Vec<u8>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let mut stream = listener.incoming().next().unwrap().unwrap();
+        let mut line = String::new();
+        {
+            let mut reader = BufReader::new(&mut stream);
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+        }
+        let half = body.len() / 2;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body[..half]).unwrap();
+        stream.flush().unwrap();
+        thread::sleep(Duration::from_millis(1500));
+        stream.write_all(&body[half..]).unwrap();
+    });
+    port
+}
+
+#[test]
+fn which_prints_only_the_path_while_installing() {
+    let env = Env::new();
+    let sha = env.release("lean.tar.gz", "4.9.0");
+    let port = serve_slowly(fs::read(env.path("lean.tar.gz")).unwrap());
+    let url = format!("http://127.0.0.1:{}/lean.tar.gz", port);
+    let manifest = env.manifest(&[("leanprover/lean4:v4.9.0", &url, &sha)]);
+    fs::write(env.cwd.join("lean-toolchain"), "leanprover/lean4:v4.9.0\n").unwrap();
+
+    // Progress is only drawn on a terminal, so stderr is one
+    let (output, terminal) = with_stderr_on_tty(
+        env.cmd("elan")
+            .args(["which", "lean"])
+            .env("ELAN_RELEASE_MANIFEST", &manifest),
+    );
+    assert!(output.status.success(), "{}", terminal);
+    let lean = env
+        .home
+        .join("toolchains")
+        .join("leanprover--lean4---v4.9.0")
+        .join("bin")
+        .join("lean");
+    assert_eq!(stdout(&output), format!("{}\n", lean.display()));
+    // The install and the progress of its download went to the terminal
+    assert!(terminal.contains("downloading"), "{}", terminal);
+    assert!(terminal.contains("(100 %)"), "{}", terminal);
+}
+
+#[test]
+fn show_home_prints_the_directories_of_elan_home() {
+    let env = Env::with_home("custom home");
+    let show = |args: &[&str]| {
+        let output = env.elan(&[&["show", "home"], args].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(output.stderr.is_empty(), "{}", stderr(&output));
+        stdout(&output)
+    };
+    assert_eq!(show(&[]), format!("{}\n", env.home.display()));
+    assert_eq!(
+        show(&["--toolchains"]),
+        format!("{}\n", env.home.join("toolchains").display())
+    );
+    assert_eq!(
+        show(&["--bin"]),
+        format!("{}\n", env.home.join("bin").display())
+    );
+}
+
+#[test]
+fn show_home_resolves_a_relative_elan_home_in_the_working_directory() {
+    let env = Env::new();
+    let show = |args: &[&str]| {
+        let output = env
+            .cmd("elan")
+            .args(["show", "home"])
+            .args(args)
+            .env("ELAN_HOME", "custom-elan-home")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output)
+    };
+    let home = env.cwd.join("custom-elan-home");
+    assert_eq!(show(&[]), format!("{}\n", home.display()));
+    assert_eq!(
+        show(&["--bin"]),
+        format!("{}\n", home.join("bin").display())
+    );
+}